@@ -130,6 +130,13 @@
 //!     wasm_bench_free(bench_api);
 //! }
 //! ```
+//!
+//! # Hostcall timers
+//!
+//! Benchmarks which also want to time each call they make into the host, such
+//! as to WASI, can create the engine with
+//! [`wasm_bench_create_with_hostcall_timers`] instead, passing a
+//! [`WasmBenchHostcallTimers`] alongside the usual configuration.
 
 mod unsafe_send_sync;
 
@@ -137,8 +144,9 @@ use crate::unsafe_send_sync::UnsafeSendSync;
 use anyhow::{anyhow, Context, Result};
 use std::os::raw::{c_int, c_void};
 use std::slice;
+use std::sync::Arc;
 use std::{env, path::PathBuf};
-use wasmtime::{Config, Engine, Instance, Linker, Module, Store};
+use wasmtime::{Config, Engine, HostcallInstrumentation, Instance, Linker, Module, Store};
 use wasmtime_wasi::{sync::WasiCtxBuilder, WasiCtx};
 
 pub type ExitCode = c_int;
@@ -192,6 +200,25 @@ pub struct WasmBenchConfig {
     pub execution_end: extern "C" fn(*mut u8),
 }
 
+/// The (optional) functions to start and stop performance timers/counters
+/// around each call from Wasm into the host, such as to WASI.
+///
+/// This is separate from [`WasmBenchConfig`] so that the layout of the latter
+/// stays the same for existing users of [`wasm_bench_create`].
+#[repr(C)]
+pub struct WasmBenchHostcallTimers {
+    pub timer: *mut u8,
+    pub start: Option<HostcallTimerFn>,
+    pub end: Option<HostcallTimerFn>,
+}
+
+/// A function to start or stop a hostcall timer. It is called with the
+/// `timer` pointer of [`WasmBenchHostcallTimers`], followed by the UTF-8
+/// module and name of the function being called, e.g.
+/// `wasi_snapshot_preview1` and `fd_write`. Both are only valid for the
+/// duration of the call. Calls to the `bench` functions are not included.
+pub type HostcallTimerFn = extern "C" fn(*mut u8, *const u8, usize, *const u8, usize);
+
 impl WasmBenchConfig {
     fn working_dir(&self) -> Result<PathBuf> {
         let working_dir =
@@ -241,6 +268,25 @@ impl WasmBenchConfig {
 pub extern "C" fn wasm_bench_create(
     config: WasmBenchConfig,
     out_bench_ptr: *mut *mut c_void,
+) -> ExitCode {
+    create(config, None, out_bench_ptr)
+}
+
+/// Like [`wasm_bench_create`], but also calls the `hostcall_timers` around
+/// each call the benchmark makes into the host.
+#[no_mangle]
+pub extern "C" fn wasm_bench_create_with_hostcall_timers(
+    config: WasmBenchConfig,
+    hostcall_timers: WasmBenchHostcallTimers,
+    out_bench_ptr: *mut *mut c_void,
+) -> ExitCode {
+    create(config, Some(hostcall_timers), out_bench_ptr)
+}
+
+fn create(
+    config: WasmBenchConfig,
+    hostcall_timers: Option<WasmBenchHostcallTimers>,
+    out_bench_ptr: *mut *mut c_void,
 ) -> ExitCode {
     let result = (|| -> Result<_> {
         let working_dir = config.working_dir()?;
@@ -267,6 +313,7 @@ pub extern "C" fn wasm_bench_create(
             config.execution_timer,
             config.execution_start,
             config.execution_end,
+            hostcall_timers,
             move || {
                 let mut cx = WasiCtxBuilder::new();
 
@@ -391,6 +438,44 @@ struct HostState {
     wasi_crypto: wasmtime_wasi_crypto::WasiCryptoCtx,
 }
 
+/// Calls the `hostcall_{start,end}` functions around every hostcall.
+struct HostcallTimers {
+    timer: UnsafeSendSync<*mut u8>,
+    start: Option<HostcallTimerFn>,
+    end: Option<HostcallTimerFn>,
+}
+
+impl HostcallInstrumentation for HostcallTimers {
+    fn instrument(&self, module: &str, _name: &str) -> bool {
+        module != "bench"
+    }
+
+    fn enter(&self, module: &str, name: &str) -> u64 {
+        if let Some(start) = self.start {
+            start(
+                *self.timer.get(),
+                module.as_ptr(),
+                module.len(),
+                name.as_ptr(),
+                name.len(),
+            );
+        }
+        0
+    }
+
+    fn exit(&self, module: &str, name: &str, _entered: u64, _trapped: bool) {
+        if let Some(end) = self.end {
+            end(
+                *self.timer.get(),
+                module.as_ptr(),
+                module.len(),
+                name.as_ptr(),
+                name.len(),
+            );
+        }
+    }
+}
+
 impl BenchState {
     fn new(
         compilation_timer: *mut u8,
@@ -402,11 +487,20 @@ impl BenchState {
         execution_timer: *mut u8,
         execution_start: extern "C" fn(*mut u8),
         execution_end: extern "C" fn(*mut u8),
+        hostcall_timers: Option<WasmBenchHostcallTimers>,
         make_wasi_cx: impl FnMut() -> Result<WasiCtx> + 'static,
     ) -> Result<Self> {
         // NB: do not configure a code cache.
         let mut config = Config::new();
         config.wasm_simd(true);
+        if let Some(timers) = hostcall_timers {
+            config.hostcall_instrumentation(Arc::new(HostcallTimers {
+                // Safe for the same reason as the execution timer below.
+                timer: unsafe { UnsafeSendSync::new(timers.timer) },
+                start: timers.start,
+                end: timers.end,
+            }));
+        }
         let engine = Engine::new(&config)?;
         let mut linker = Linker::<HostState>::new(&engine);
 
@@ -511,3 +605,115 @@ impl BenchState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+
+    extern "C" fn noop(_: *mut u8) {}
+
+    extern "C" fn hostcall_start(
+        timer: *mut u8,
+        module_ptr: *const u8,
+        module_len: usize,
+        name_ptr: *const u8,
+        name_len: usize,
+    ) {
+        record(timer, "start", module_ptr, module_len, name_ptr, name_len);
+    }
+
+    extern "C" fn hostcall_end(
+        timer: *mut u8,
+        module_ptr: *const u8,
+        module_len: usize,
+        name_ptr: *const u8,
+        name_len: usize,
+    ) {
+        record(timer, "end", module_ptr, module_len, name_ptr, name_len);
+    }
+
+    fn record(
+        timer: *mut u8,
+        event: &str,
+        module_ptr: *const u8,
+        module_len: usize,
+        name_ptr: *const u8,
+        name_len: usize,
+    ) {
+        let calls = unsafe { &mut *(timer as *mut Vec<String>) };
+        let module = unsafe { slice::from_raw_parts(module_ptr, module_len) };
+        let name = unsafe { slice::from_raw_parts(name_ptr, name_len) };
+        calls.push(format!(
+            "{} {}::{}",
+            event,
+            std::str::from_utf8(module).unwrap(),
+            std::str::from_utf8(name).unwrap(),
+        ));
+    }
+
+    #[test]
+    fn hostcall_timers() {
+        let working_dir = env::current_dir().unwrap().display().to_string();
+        let stdout_path = "./stdout.log";
+        let stderr_path = "./stderr.log";
+        let config = WasmBenchConfig {
+            working_dir_ptr: working_dir.as_ptr(),
+            working_dir_len: working_dir.len(),
+            stdout_path_ptr: stdout_path.as_ptr(),
+            stdout_path_len: stdout_path.len(),
+            stderr_path_ptr: stderr_path.as_ptr(),
+            stderr_path_len: stderr_path.len(),
+            stdin_path_ptr: ptr::null(),
+            stdin_path_len: 0,
+            compilation_timer: ptr::null_mut(),
+            compilation_start: noop,
+            compilation_end: noop,
+            instantiation_timer: ptr::null_mut(),
+            instantiation_start: noop,
+            instantiation_end: noop,
+            execution_timer: ptr::null_mut(),
+            execution_start: noop,
+            execution_end: noop,
+        };
+        let mut calls = Vec::<String>::new();
+        let hostcall_timers = WasmBenchHostcallTimers {
+            timer: &mut calls as *mut Vec<String> as *mut u8,
+            start: Some(hostcall_start),
+            end: Some(hostcall_end),
+        };
+
+        let mut bench_api = ptr::null_mut();
+        let code = wasm_bench_create_with_hostcall_timers(config, hostcall_timers, &mut bench_api);
+        assert_eq!(code, OK);
+
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func $bench_start (import "bench" "start"))
+                (func $bench_end (import "bench" "end"))
+                (func $sched_yield (import "wasi_snapshot_preview1" "sched_yield") (result i32))
+                (func $start (export "_start")
+                    call $bench_start
+                    call $sched_yield
+                    drop
+                    call $bench_end
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        assert_eq!(wasm_bench_compile(bench_api, wasm.as_ptr(), wasm.len()), OK);
+        assert_eq!(wasm_bench_instantiate(bench_api), OK);
+        assert_eq!(wasm_bench_execute(bench_api), OK);
+        wasm_bench_free(bench_api);
+
+        assert_eq!(
+            calls,
+            [
+                "start wasi_snapshot_preview1::sched_yield",
+                "end wasi_snapshot_preview1::sched_yield",
+            ]
+        );
+    }
+}
@@ -546,6 +546,10 @@ mod test {
                 fn out_of_gas(&mut self) -> Result<(), anyhow::Error> {
                     Ok(())
                 }
+                fn libcall_enter(&mut self, _name: &'static str) -> Option<u64> {
+                    None
+                }
+                fn libcall_exit(&mut self, _name: &'static str, _entered: u64, _trapped: bool) {}
             }
             struct MockModuleInfo;
             impl crate::ModuleInfoLookup for MockModuleInfo {
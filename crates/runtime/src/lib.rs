@@ -119,4 +119,12 @@ pub unsafe trait Store {
     /// is returned that's raised as a trap. Otherwise wasm execution will
     /// continue as normal.
     fn out_of_gas(&mut self) -> Result<(), Error>;
+    /// Callback invoked as wasm calls into the runtime library function
+    /// `name`, e.g. `memory.grow`. Returns `None` if libcalls aren't being
+    /// instrumented, and otherwise a value to pass to `libcall_exit`.
+    fn libcall_enter(&mut self, name: &'static str) -> Option<u64>;
+    /// Callback invoked just before a libcall for which `libcall_enter`
+    /// returned `Some(entered)` returns to wasm, or raises a trap if
+    /// `trapped` is set.
+    fn libcall_exit(&mut self, name: &'static str, entered: u64, trapped: bool);
 }
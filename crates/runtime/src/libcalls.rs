@@ -55,17 +55,47 @@
 //!       // Wasm!)
 //!   }
 //!   ```
+//!
+//! * Library functions which take a `vmctx` report themselves to the store's
+//!   libcall instrumentation with a `LibcallProbe`. As with drops, the probe
+//!   must be exited before raising a trap, since nothing after that runs.
 
 use crate::externref::VMExternRef;
 use crate::instance::Instance;
 use crate::table::{Table, TableElementType};
 use crate::traphandlers::{raise_lib_trap, resume_panic, Trap};
 use crate::vmcontext::{VMCallerCheckedAnyfunc, VMContext};
+use crate::Store;
 use backtrace::Backtrace;
 use std::mem;
 use std::ptr::{self, NonNull};
 use wasmtime_environ::{DataIndex, ElemIndex, GlobalIndex, MemoryIndex, TableIndex, TrapCode};
 
+/// Reports a call to a library function to the store's instrumentation, if
+/// libcalls are being instrumented.
+struct LibcallProbe {
+    store: *mut dyn Store,
+    name: &'static str,
+    entered: Option<u64>,
+}
+
+impl LibcallProbe {
+    unsafe fn enter(vmctx: *mut VMContext, name: &'static str) -> LibcallProbe {
+        let store = (*vmctx).instance().store();
+        LibcallProbe {
+            store,
+            name,
+            entered: (*store).libcall_enter(name),
+        }
+    }
+
+    unsafe fn exit(self, trapped: bool) {
+        if let Some(entered) = self.entered {
+            (*self.store).libcall_exit(self.name, entered, trapped);
+        }
+    }
+}
+
 const TOINT_32: f32 = 1.0 / f32::EPSILON;
 const TOINT_64: f64 = 1.0 / f64::EPSILON;
 
@@ -190,13 +220,16 @@ pub unsafe extern "C" fn wasmtime_memory32_grow(
     delta: u64,
     memory_index: u32,
 ) -> usize {
+    let probe = LibcallProbe::enter(vmctx, "memory32_grow");
     // Memory grow can invoke user code provided in a ResourceLimiter{,Async},
     // so we need to catch a possible panic
-    match std::panic::catch_unwind(|| {
+    let result = std::panic::catch_unwind(|| {
         let instance = (*vmctx).instance_mut();
         let memory_index = MemoryIndex::from_u32(memory_index);
         instance.memory_grow(memory_index, delta)
-    }) {
+    });
+    probe.exit(!matches!(result, Ok(Ok(_))));
+    match result {
         Ok(Ok(Some(size_in_bytes))) => size_in_bytes / (wasmtime_environ::WASM_PAGE_SIZE as usize),
         Ok(Ok(None)) => usize::max_value(),
         Ok(Err(err)) => crate::traphandlers::raise_user_trap(err),
@@ -213,9 +246,10 @@ pub unsafe extern "C" fn wasmtime_table_grow(
     // or is a `VMExternRef` until we look at the table type.
     init_value: *mut u8,
 ) -> u32 {
+    let probe = LibcallProbe::enter(vmctx, "table_grow");
     // Table grow can invoke user code provided in a ResourceLimiter{,Async},
     // so we need to catch a possible panic
-    match std::panic::catch_unwind(|| {
+    let result = std::panic::catch_unwind(|| {
         let instance = (*vmctx).instance_mut();
         let table_index = TableIndex::from_u32(table_index);
         let element = match instance.table_element_type(table_index) {
@@ -230,7 +264,9 @@ pub unsafe extern "C" fn wasmtime_table_grow(
             }
         };
         instance.table_grow(table_index, delta, element)
-    }) {
+    });
+    probe.exit(!matches!(result, Ok(Ok(_))));
+    match result {
         Ok(Ok(Some(r))) => r,
         Ok(Ok(None)) => -1_i32 as u32,
         Ok(Err(err)) => crate::traphandlers::raise_user_trap(err),
@@ -248,6 +284,7 @@ pub unsafe extern "C" fn wasmtime_table_fill(
     val: *mut u8,
     len: u32,
 ) {
+    let probe = LibcallProbe::enter(vmctx, "table_fill");
     let result = {
         let instance = (*vmctx).instance_mut();
         let table_index = TableIndex::from_u32(table_index);
@@ -267,6 +304,7 @@ pub unsafe extern "C" fn wasmtime_table_fill(
             }
         }
     };
+    probe.exit(result.is_err());
     if let Err(trap) = result {
         raise_lib_trap(trap);
     }
@@ -281,6 +319,7 @@ pub unsafe extern "C" fn wasmtime_table_copy(
     src: u32,
     len: u32,
 ) {
+    let probe = LibcallProbe::enter(vmctx, "table_copy");
     let result = {
         let dst_table_index = TableIndex::from_u32(dst_table_index);
         let src_table_index = TableIndex::from_u32(src_table_index);
@@ -289,6 +328,7 @@ pub unsafe extern "C" fn wasmtime_table_copy(
         let src_table = instance.get_table(src_table_index);
         Table::copy(dst_table, src_table, dst, src, len)
     };
+    probe.exit(result.is_err());
     if let Err(trap) = result {
         raise_lib_trap(trap);
     }
@@ -303,12 +343,14 @@ pub unsafe extern "C" fn wasmtime_table_init(
     src: u32,
     len: u32,
 ) {
+    let probe = LibcallProbe::enter(vmctx, "table_init");
     let result = {
         let table_index = TableIndex::from_u32(table_index);
         let elem_index = ElemIndex::from_u32(elem_index);
         let instance = (*vmctx).instance_mut();
         instance.table_init(table_index, elem_index, dst, src, len)
     };
+    probe.exit(result.is_err());
     if let Err(trap) = result {
         raise_lib_trap(trap);
     }
@@ -316,9 +358,11 @@ pub unsafe extern "C" fn wasmtime_table_init(
 
 /// Implementation of `elem.drop`.
 pub unsafe extern "C" fn wasmtime_elem_drop(vmctx: *mut VMContext, elem_index: u32) {
+    let probe = LibcallProbe::enter(vmctx, "elem_drop");
     let elem_index = ElemIndex::from_u32(elem_index);
     let instance = (*vmctx).instance_mut();
     instance.elem_drop(elem_index);
+    probe.exit(false);
 }
 
 /// Implementation of `memory.copy` for locally defined memories.
@@ -330,12 +374,14 @@ pub unsafe extern "C" fn wasmtime_memory_copy(
     src: u64,
     len: u64,
 ) {
+    let probe = LibcallProbe::enter(vmctx, "memory_copy");
     let result = {
         let src_index = MemoryIndex::from_u32(src_index);
         let dst_index = MemoryIndex::from_u32(dst_index);
         let instance = (*vmctx).instance_mut();
         instance.memory_copy(dst_index, dst, src_index, src, len)
     };
+    probe.exit(result.is_err());
     if let Err(trap) = result {
         raise_lib_trap(trap);
     }
@@ -349,11 +395,13 @@ pub unsafe extern "C" fn wasmtime_memory_fill(
     val: u32,
     len: u64,
 ) {
+    let probe = LibcallProbe::enter(vmctx, "memory_fill");
     let result = {
         let memory_index = MemoryIndex::from_u32(memory_index);
         let instance = (*vmctx).instance_mut();
         instance.memory_fill(memory_index, dst, val as u8, len)
     };
+    probe.exit(result.is_err());
     if let Err(trap) = result {
         raise_lib_trap(trap);
     }
@@ -368,12 +416,14 @@ pub unsafe extern "C" fn wasmtime_memory_init(
     src: u32,
     len: u32,
 ) {
+    let probe = LibcallProbe::enter(vmctx, "memory_init");
     let result = {
         let memory_index = MemoryIndex::from_u32(memory_index);
         let data_index = DataIndex::from_u32(data_index);
         let instance = (*vmctx).instance_mut();
         instance.memory_init(memory_index, data_index, dst, src, len)
     };
+    probe.exit(result.is_err());
     if let Err(trap) = result {
         raise_lib_trap(trap);
     }
@@ -381,9 +431,11 @@ pub unsafe extern "C" fn wasmtime_memory_init(
 
 /// Implementation of `data.drop`.
 pub unsafe extern "C" fn wasmtime_data_drop(vmctx: *mut VMContext, data_index: u32) {
+    let probe = LibcallProbe::enter(vmctx, "data_drop");
     let data_index = DataIndex::from_u32(data_index);
     let instance = (*vmctx).instance_mut();
-    instance.data_drop(data_index)
+    instance.data_drop(data_index);
+    probe.exit(false);
 }
 
 /// Drop a `VMExternRef`.
@@ -400,9 +452,11 @@ pub unsafe extern "C" fn wasmtime_activations_table_insert_with_gc(
     externref: *mut u8,
 ) {
     let externref = VMExternRef::clone_from_raw(externref);
+    let probe = LibcallProbe::enter(vmctx, "activations_table_insert_with_gc");
     let instance = (*vmctx).instance();
     let (activations_table, module_info_lookup) = (*instance.store()).externref_activations_table();
     activations_table.insert_with_gc(externref, module_info_lookup);
+    probe.exit(false);
 }
 
 /// Perform a Wasm `global.get` for `externref` globals.
@@ -410,10 +464,11 @@ pub unsafe extern "C" fn wasmtime_externref_global_get(
     vmctx: *mut VMContext,
     index: u32,
 ) -> *mut u8 {
+    let probe = LibcallProbe::enter(vmctx, "externref_global_get");
     let index = GlobalIndex::from_u32(index);
     let instance = (*vmctx).instance();
     let global = instance.defined_or_imported_global_ptr(index);
-    match (*global).as_externref().clone() {
+    let raw = match (*global).as_externref().clone() {
         None => ptr::null_mut(),
         Some(externref) => {
             let raw = externref.as_raw();
@@ -422,7 +477,9 @@ pub unsafe extern "C" fn wasmtime_externref_global_get(
             activations_table.insert_with_gc(externref, module_info_lookup);
             raw
         }
-    }
+    };
+    probe.exit(false);
+    raw
 }
 
 /// Perform a Wasm `global.set` for `externref` globals.
@@ -437,6 +494,7 @@ pub unsafe extern "C" fn wasmtime_externref_global_set(
         Some(VMExternRef::clone_from_raw(externref))
     };
 
+    let probe = LibcallProbe::enter(vmctx, "externref_global_set");
     let index = GlobalIndex::from_u32(index);
     let instance = (*vmctx).instance();
     let global = instance.defined_or_imported_global_ptr(index);
@@ -447,6 +505,7 @@ pub unsafe extern "C" fn wasmtime_externref_global_set(
     // it observing a halfway-deinitialized value).
     let old = mem::replace((*global).as_externref_mut(), externref);
     drop(old);
+    probe.exit(false);
 }
 
 /// Implementation of `memory.atomic.notify` for locally defined memories.
@@ -552,7 +611,10 @@ unsafe fn validate_atomic_addr(
 
 /// Hook for when an instance runs out of fuel.
 pub unsafe extern "C" fn wasmtime_out_of_gas(vmctx: *mut VMContext) {
-    match (*(*vmctx).instance().store()).out_of_gas() {
+    let probe = LibcallProbe::enter(vmctx, "out_of_gas");
+    let result = (*(*vmctx).instance().store()).out_of_gas();
+    probe.exit(result.is_err());
+    match result {
         Ok(()) => {}
         Err(err) => crate::traphandlers::raise_user_trap(err),
     }
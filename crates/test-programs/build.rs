@@ -43,6 +43,10 @@ mod wasi_tests {
             .expect("generating wasi-cap-std-sync tests");
         test_directory(&mut out, "wasi-tokio", "tokio", &out_dir)
             .expect("generating wasi-tokio tests");
+        test_directory(&mut out, "wasi-virtfs", "virtfs", &out_dir)
+            .expect("generating wasi-virtfs tests");
+        test_directory(&mut out, "wasi-overlay", "overlay", &out_dir)
+            .expect("generating wasi-overlay tests");
     }
 
     fn build_tests(testsuite: &str, out_dir: &Path) -> io::Result<()> {
@@ -175,6 +179,7 @@ mod wasi_tests {
         match testsuite {
             "wasi-cap-std-sync" => cap_std_sync_ignore(name),
             "wasi-virtfs" => virtfs_ignore(name),
+            "wasi-overlay" => overlay_ignore(name),
             "wasi-tokio" => tokio_ignore(name),
             _ => panic!("unknown test suite: {}", testsuite),
         }
@@ -207,46 +212,15 @@ mod wasi_tests {
     fn tokio_ignore(name: &str) -> bool {
         cap_std_sync_ignore(name)
     }
-    /// Virtfs barely works at all and is not suitable for any purpose
+    /// Virtfs runs the tests in a `wasi_common::memfs::MemFs`, whose files
+    /// can't be polled by the cap-std-sync scheduler.
     fn virtfs_ignore(name: &str) -> bool {
-        [
-            "dangling_fd",
-            "dangling_symlink",
-            "directory_seek",
-            "fd_advise",
-            "fd_filestat_set",
-            "fd_flags_set",
-            "fd_readdir",
-            "file_allocate",
-            "file_pread_pwrite",
-            "file_seek_tell",
-            "file_truncation",
-            "file_unbuffered_write",
-            "interesting_paths",
-            "isatty",
-            "nofollow_errors",
-            "path_filestat",
-            "path_link",
-            "path_open_create_existing",
-            "path_open_dirfd_not_dir",
-            "path_open_read_without_rights",
-            "path_rename",
-            "path_rename_dir_trailing_slashes",
-            "path_rename_file_trailing_slashes",
-            "path_symlink_trailing_slashes",
-            "poll_oneoff",
-            "poll_oneoff_stdio",
-            "readlink",
-            "remove_directory_trailing_slashes",
-            "remove_nonempty_directory",
-            "renumber",
-            "symlink_create",
-            "symlink_filestat",
-            "symlink_loop",
-            "truncation_rights",
-            "unlink_file_trailing_slashes",
-        ]
-        .contains(&name)
+        ["poll_oneoff_files", "poll_oneoff_stdio"].contains(&name)
+    }
+    /// Overlay runs the tests in a copy-on-write overlay of the workspace,
+    /// whose upper layer is a `MemFs`, so the same goes for it.
+    fn overlay_ignore(name: &str) -> bool {
+        virtfs_ignore(name)
     }
 
     /// Mark tests which do not require preopens
@@ -271,7 +245,7 @@ mod wasi_tests {
                 "poll_oneoff_stdio" => true,
                 _ => false,
             },
-            "wasi-virtfs" => false,
+            "wasi-virtfs" | "wasi-overlay" => false,
             _ => panic!("unknown test suite {}", testsuite),
         }
     }
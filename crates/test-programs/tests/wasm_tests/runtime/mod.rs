@@ -1,5 +1,7 @@
 pub mod cap_std_sync;
+pub mod overlay;
 pub mod tokio;
+pub mod virtfs;
 
 // Configure the test suite environment.
 // Test programs use these environment variables to determine what behavior
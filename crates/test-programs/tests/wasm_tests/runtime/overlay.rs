@@ -0,0 +1,59 @@
+use anyhow::Context;
+use std::path::Path;
+use wasi_common::pipe::WritePipe;
+use wasmtime::{Engine, Linker, Module, Store};
+use wasmtime_wasi::sync::{add_to_linker, WasiCtxBuilder};
+
+pub fn instantiate(data: &[u8], bin_name: &str, workspace: Option<&Path>) -> anyhow::Result<()> {
+    let stdout = WritePipe::new_in_memory();
+    let stderr = WritePipe::new_in_memory();
+
+    let r = {
+        let engine = Engine::default();
+        let module = Module::new(&engine, &data).context("failed to create wasm module")?;
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker, |cx| cx)?;
+
+        // The upper layer of the overlay is in memory, and behaves the same
+        // on every host, which is the way it behaves on Linux.
+        let mut builder = WasiCtxBuilder::new()
+            .stdout(Box::new(stdout.clone()))
+            .stderr(Box::new(stderr.clone()))
+            .arg(bin_name)?
+            .arg(".")?
+            .env("ERRNO_MODE_UNIX", "1")?;
+
+        if let Some(workspace) = workspace {
+            let lower =
+                cap_std::fs::Dir::open_ambient_dir(workspace, cap_std::ambient_authority())?;
+            builder = builder.preopened_overlay_dir(lower, ".")?;
+        }
+        let ctx = builder.build();
+
+        let mut store = Store::new(&engine, ctx);
+        let instance = linker.instantiate(&mut store, &module)?;
+        let start = instance.get_typed_func::<(), (), _>(&mut store, "_start")?;
+        start.call(&mut store, ()).map_err(anyhow::Error::from)
+    };
+
+    match r {
+        Ok(()) => Ok(()),
+        Err(trap) => {
+            let stdout = stdout
+                .try_into_inner()
+                .expect("sole ref to stdout")
+                .into_inner();
+            if !stdout.is_empty() {
+                println!("guest stdout:\n{}\n===", String::from_utf8_lossy(&stdout));
+            }
+            let stderr = stderr
+                .try_into_inner()
+                .expect("sole ref to stderr")
+                .into_inner();
+            if !stderr.is_empty() {
+                println!("guest stderr:\n{}\n===", String::from_utf8_lossy(&stderr));
+            }
+            Err(trap.context(format!("error while testing Wasm module '{}'", bin_name,)))
+        }
+    }
+}
@@ -2,6 +2,9 @@
 //! terms of `cap_std::fs::{File, Dir}`. These types provide sandboxed access
 //! to the local filesystem on both Unix and Windows.
 //!
+//! It also provides a `WasiNetwork`, `Network`, which connects sockets with
+//! `cap_std::net` to the addresses allowed on the `WasiCtxBuilder`.
+//!
 //! All syscalls are hidden behind the `cap-std` hierarchy, with the lone
 //! exception of the `sched` implementation, which is provided for both unix
 //! and windows in separate modules.
@@ -36,71 +39,77 @@
 pub mod clocks;
 pub mod dir;
 pub mod file;
+pub mod net;
+pub mod overlay;
 pub mod sched;
 pub mod stdio;
 
 pub use cap_std::ambient_authority;
 pub use cap_std::fs::Dir;
+pub use cap_std::net::TcpListener;
 pub use clocks::clocks_ctx;
+pub use net::{Network, Socket};
 pub use sched::sched_ctx;
 
 use cap_rand::RngCore;
+use std::net::SocketAddr;
 use std::path::Path;
-use wasi_common::{table::Table, Error, WasiCtx, WasiFile};
+use wasi_common::{table::Table, Error, ErrorExt, WasiCtx, WasiFile};
 
-pub struct WasiCtxBuilder(WasiCtx);
+pub struct WasiCtxBuilder {
+    ctx: WasiCtx,
+    network: Network,
+}
 
 impl WasiCtxBuilder {
     pub fn new() -> Self {
-        WasiCtxBuilder(WasiCtx::new(
-            random_ctx(),
-            clocks_ctx(),
-            sched_ctx(),
-            Table::new(),
-        ))
+        WasiCtxBuilder {
+            ctx: WasiCtx::new(random_ctx(), clocks_ctx(), sched_ctx(), Table::new()),
+            network: Network::new(),
+        }
     }
     pub fn env(mut self, var: &str, value: &str) -> Result<Self, wasi_common::StringArrayError> {
-        self.0.push_env(var, value)?;
+        self.ctx.push_env(var, value)?;
         Ok(self)
     }
     pub fn envs(mut self, env: &[(String, String)]) -> Result<Self, wasi_common::StringArrayError> {
         for (k, v) in env {
-            self.0.push_env(k, v)?;
+            self.ctx.push_env(k, v)?;
         }
         Ok(self)
     }
     pub fn inherit_env(mut self) -> Result<Self, wasi_common::StringArrayError> {
         for (key, value) in std::env::vars() {
-            self.0.push_env(&key, &value)?;
+            self.ctx.push_env(&key, &value)?;
         }
         Ok(self)
     }
     pub fn arg(mut self, arg: &str) -> Result<Self, wasi_common::StringArrayError> {
-        self.0.push_arg(arg)?;
+        self.ctx.push_arg(arg)?;
         Ok(self)
     }
     pub fn args(mut self, arg: &[String]) -> Result<Self, wasi_common::StringArrayError> {
         for a in arg {
-            self.0.push_arg(&a)?;
+            self.ctx.push_arg(&a)?;
         }
         Ok(self)
     }
     pub fn inherit_args(mut self) -> Result<Self, wasi_common::StringArrayError> {
         for arg in std::env::args() {
-            self.0.push_arg(&arg)?;
+            self.ctx.push_arg(&arg)?;
         }
         Ok(self)
     }
     pub fn stdin(mut self, f: Box<dyn WasiFile>) -> Self {
-        self.0.set_stdin(f);
+        self.ctx.set_stdin(f);
         self
     }
     pub fn stdout(mut self, f: Box<dyn WasiFile>) -> Self {
-        self.0.set_stdout(f);
+        self.ctx.set_stdout(f);
         self
     }
     pub fn stderr(mut self, f: Box<dyn WasiFile>) -> Self {
-        self.0.set_stderr(f);
+        self.ctx.set_stderr(f);
         self
     }
     pub fn inherit_stdin(self) -> Self {
@@ -117,11 +126,58 @@ impl WasiCtxBuilder {
     }
     pub fn preopened_dir(mut self, dir: Dir, guest_path: impl AsRef<Path>) -> Result<Self, Error> {
         let dir = Box::new(crate::dir::Dir::from_cap_std(dir));
-        self.0.push_preopened_dir(dir, guest_path)?;
+        self.ctx.push_preopened_dir(dir, guest_path)?;
+        Ok(self)
+    }
+    /// Preopens a copy-on-write overlay of `dir`: the guest sees what's in
+    /// `dir` and may change it as it likes, but its changes are kept in
+    /// memory and never reach `dir`. See `overlay::OverlayDir`.
+    pub fn preopened_overlay_dir(
+        mut self,
+        dir: Dir,
+        guest_path: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        let dir = Box::new(crate::overlay::OverlayDir::new(dir));
+        self.ctx.push_preopened_dir(dir, guest_path)?;
+        Ok(self)
+    }
+    /// Hands the guest `socket`, such as a `TcpListener` to accept
+    /// connections on, as `fd`. It's made blocking, until the guest sets
+    /// `NONBLOCK` on it.
+    pub fn preopened_socket(mut self, fd: u32, socket: impl Into<Socket>) -> Result<Self, Error> {
+        if self.ctx.table().contains_key(fd) {
+            return Err(Error::invalid_argument().context(format!("fd {} is already in use", fd)));
+        }
+        let socket = socket.into().into_wasi_socket()?;
+        self.ctx.insert_socket(fd, socket);
         Ok(self)
     }
-    pub fn build(self) -> WasiCtx {
-        self.0
+    pub fn hostcall_recorder(
+        mut self,
+        recorder: std::sync::Arc<dyn wasi_common::HostcallRecorder>,
+    ) -> Self {
+        self.ctx.set_hostcall_recorder(recorder);
+        self
+    }
+    pub fn hostcall_log(mut self, log: std::sync::Arc<dyn wasi_common::HostcallLog>) -> Self {
+        self.ctx.set_hostcall_log(log);
+        self
+    }
+    /// Allows the guest to connect sockets to `addr`. Guests can't reach
+    /// any address which isn't allowed.
+    pub fn allow_socket_addr(mut self, addr: SocketAddr) -> Self {
+        self.network.allow_socket_addr(addr);
+        self
+    }
+    /// Allows the guest to connect sockets to the Unix socket at `path`.
+    #[cfg(unix)]
+    pub fn allow_unix_socket(mut self, path: impl AsRef<Path>) -> Self {
+        self.network.allow_unix_socket(path.as_ref());
+        self
+    }
+    pub fn build(mut self) -> WasiCtx {
+        self.ctx.set_network(Box::new(self.network));
+        self.ctx
     }
 }
 
@@ -0,0 +1,685 @@
+use cap_std::net::Pool;
+use std::any::Any;
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+use wasi_common::{
+    file::FileType,
+    socket::{RiFlags, RoFlags, SdFlags, SocketAddress, SocketType, WasiNetwork, WasiSocket},
+    Error, ErrorExt,
+};
+
+/// The addresses a guest may connect sockets to. Nothing is reachable until
+/// it is allowed: IP addresses are checked against a `cap_std::net::Pool`,
+/// and Unix sockets against a list of paths.
+pub struct Network {
+    pool: Pool,
+    #[cfg(unix)]
+    unix_paths: Vec<PathBuf>,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Network {
+            pool: Pool::new(),
+            #[cfg(unix)]
+            unix_paths: Vec::new(),
+        }
+    }
+
+    /// Allows connecting to `addr`.
+    pub fn allow_socket_addr(&mut self, addr: SocketAddr) {
+        self.pool
+            .insert_socket_addr(addr, crate::ambient_authority());
+    }
+
+    /// Allows connecting to the Unix socket at `path`. The guest has to name
+    /// it by the same path.
+    #[cfg(unix)]
+    pub fn allow_unix_socket(&mut self, path: impl Into<PathBuf>) {
+        self.unix_paths.push(path.into());
+    }
+
+    #[cfg(unix)]
+    fn check_unix_path(&self, path: &std::path::Path) -> Result<(), Error> {
+        if self.unix_paths.iter().any(|p| p == path) {
+            Ok(())
+        } else {
+            Err(Error::not_capable().context(format!("{} is not allowed", path.display())))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiNetwork for Network {
+    async fn connect(
+        &self,
+        ty: SocketType,
+        addr: &SocketAddress,
+    ) -> Result<Box<dyn WasiSocket>, Error> {
+        match (ty, addr) {
+            (SocketType::Stream, SocketAddress::Ip(addr)) => {
+                let stream = self.pool.connect_tcp_stream(addr).map_err(pool_error)?;
+                Ok(Box::new(TcpStream(stream)))
+            }
+            (SocketType::Dgram, SocketAddress::Ip(addr)) => {
+                let unspecified = match addr {
+                    SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                };
+                let socket =
+                    cap_std::net::UdpSocket::from_std(std::net::UdpSocket::bind(unspecified)?);
+                self.pool
+                    .connect_udp_socket(&socket, addr)
+                    .map_err(pool_error)?;
+                Ok(Box::new(UdpSocket(socket)))
+            }
+            #[cfg(unix)]
+            (SocketType::Stream, SocketAddress::Unix(path)) => {
+                self.check_unix_path(path)?;
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                Ok(Box::new(UnixStream(
+                    cap_std::os::unix::net::UnixStream::from_std(stream),
+                )))
+            }
+            #[cfg(unix)]
+            (SocketType::Dgram, SocketAddress::Unix(path)) => {
+                self.check_unix_path(path)?;
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Box::new(UnixDatagram(
+                    cap_std::os::unix::net::UnixDatagram::from_std(socket),
+                )))
+            }
+            #[cfg(not(unix))]
+            (_, SocketAddress::Unix(_)) => {
+                Err(Error::not_supported().context("Unix sockets are not supported"))
+            }
+        }
+    }
+}
+
+/// The pool refuses addresses outside of it with a `PermissionDenied` error
+/// of its own, which is a missing capability rather than an OS error.
+fn pool_error(err: io::Error) -> Error {
+    if err.kind() == io::ErrorKind::PermissionDenied && err.raw_os_error().is_none() {
+        Error::not_capable().context(err.to_string())
+    } else {
+        err.into()
+    }
+}
+
+fn to_std_shutdown(how: SdFlags) -> Result<Shutdown, Error> {
+    if how == SdFlags::RD | SdFlags::WR {
+        Ok(Shutdown::Both)
+    } else if how == SdFlags::RD {
+        Ok(Shutdown::Read)
+    } else if how == SdFlags::WR {
+        Ok(Shutdown::Write)
+    } else {
+        Err(Error::invalid_argument().context("shutdown needs RD, WR or both"))
+    }
+}
+
+/// Receives one message into `bufs` with `recv`, which fills a single
+/// buffer, for sockets with no vectored receive.
+fn recv_message(
+    bufs: &mut [IoSliceMut<'_>],
+    recv: impl FnOnce(&mut [u8]) -> io::Result<usize>,
+) -> Result<(u64, RoFlags), Error> {
+    let len = bufs.iter().map(|b| b.len()).sum::<usize>();
+    // One byte more than fits tells whether the message was truncated.
+    let mut message = vec![0; len + 1];
+    let n = recv(&mut message)?;
+    let mut flags = RoFlags::empty();
+    if n > len {
+        flags |= RoFlags::RECV_DATA_TRUNCATED;
+    }
+    let mut rest = &message[..n.min(len)];
+    for buf in bufs.iter_mut() {
+        let k = rest.len().min(buf.len());
+        buf[..k].copy_from_slice(&rest[..k]);
+        rest = &rest[k..];
+    }
+    Ok((n.min(len) as u64, flags))
+}
+
+/// Reads until `bufs` are full or the peer closes the stream, for
+/// `RECV_WAITALL`.
+fn read_to_fill(mut stream: impl Read, bufs: &mut [IoSliceMut<'_>]) -> io::Result<u64> {
+    let mut total = 0;
+    for buf in bufs.iter_mut() {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match stream.read(&mut buf[filled..])? {
+                0 => return Ok(total + filled as u64),
+                n => filled += n,
+            }
+        }
+        total += filled as u64;
+    }
+    Ok(total)
+}
+
+/// Sends `bufs` as one message with `send`, which takes a single buffer.
+fn send_message(
+    bufs: &[IoSlice<'_>],
+    send: impl FnOnce(&[u8]) -> io::Result<usize>,
+) -> Result<u64, Error> {
+    let message = bufs
+        .iter()
+        .flat_map(|b| b.iter().copied())
+        .collect::<Vec<u8>>();
+    Ok(send(&message)? as u64)
+}
+
+/// A socket the host opens for the guest, with
+/// `WasiCtxBuilder::preopened_socket`.
+pub enum Socket {
+    TcpListener(cap_std::net::TcpListener),
+    #[cfg(unix)]
+    UnixListener(cap_std::os::unix::net::UnixListener),
+}
+
+impl Socket {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Socket::TcpListener(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Socket::UnixListener(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Makes this the guest's, which expects it to start out blocking.
+    pub fn into_wasi_socket(self) -> Result<Box<dyn WasiSocket>, Error> {
+        self.set_nonblocking(false)?;
+        Ok(match self {
+            Socket::TcpListener(listener) => Box::new(TcpListener(listener)),
+            #[cfg(unix)]
+            Socket::UnixListener(listener) => Box::new(UnixListener(listener)),
+        })
+    }
+}
+
+impl From<cap_std::net::TcpListener> for Socket {
+    fn from(listener: cap_std::net::TcpListener) -> Self {
+        Socket::TcpListener(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<cap_std::os::unix::net::UnixListener> for Socket {
+    fn from(listener: cap_std::os::unix::net::UnixListener) -> Self {
+        Socket::UnixListener(listener)
+    }
+}
+
+pub struct TcpListener(cap_std::net::TcpListener);
+
+#[async_trait::async_trait]
+impl WasiSocket for TcpListener {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+    async fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        self.0.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+    async fn accept(&self) -> Result<Box<dyn WasiSocket>, Error> {
+        let (stream, _) = self.0.accept()?;
+        Ok(Box::new(TcpStream(stream)))
+    }
+    async fn send<'a>(&self, _bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        Err(Error::not_connected().context("sending on a listening socket"))
+    }
+    async fn recv<'a>(
+        &self,
+        _bufs: &mut [IoSliceMut<'a>],
+        _flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        Err(Error::not_connected().context("receiving on a listening socket"))
+    }
+    async fn shutdown(&self, _how: SdFlags) -> Result<(), Error> {
+        Err(Error::not_connected().context("shutting down a listening socket"))
+    }
+}
+
+pub struct TcpStream(cap_std::net::TcpStream);
+
+#[async_trait::async_trait]
+impl WasiSocket for TcpStream {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+    async fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        self.0.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+    async fn send<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        Ok((&self.0).write_vectored(bufs)? as u64)
+    }
+    async fn recv<'a>(
+        &self,
+        bufs: &mut [IoSliceMut<'a>],
+        flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        if flags.contains(RiFlags::RECV_PEEK) {
+            return recv_message(bufs, |buf| self.0.peek(buf)).map(|(n, _)| (n, RoFlags::empty()));
+        }
+        if !flags.contains(RiFlags::RECV_WAITALL) {
+            return Ok(((&self.0).read_vectored(bufs)? as u64, RoFlags::empty()));
+        }
+        Ok((read_to_fill(&self.0, bufs)?, RoFlags::empty()))
+    }
+    async fn shutdown(&self, how: SdFlags) -> Result<(), Error> {
+        self.0.shutdown(to_std_shutdown(how)?)?;
+        Ok(())
+    }
+}
+
+pub struct UdpSocket(cap_std::net::UdpSocket);
+
+#[async_trait::async_trait]
+impl WasiSocket for UdpSocket {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+    async fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        self.0.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+    async fn send<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        send_message(bufs, |message| self.0.send(message))
+    }
+    async fn recv<'a>(
+        &self,
+        bufs: &mut [IoSliceMut<'a>],
+        flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        if flags.contains(RiFlags::RECV_PEEK) {
+            recv_message(bufs, |buf| self.0.peek(buf))
+        } else {
+            recv_message(bufs, |buf| self.0.recv(buf))
+        }
+    }
+    async fn shutdown(&self, _how: SdFlags) -> Result<(), Error> {
+        Err(Error::not_connected().context("datagram sockets have no connection to shut down"))
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixListener(cap_std::os::unix::net::UnixListener);
+
+#[cfg(unix)]
+#[async_trait::async_trait]
+impl WasiSocket for UnixListener {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+    async fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        self.0.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+    async fn accept(&self) -> Result<Box<dyn WasiSocket>, Error> {
+        let (stream, _) = self.0.accept()?;
+        Ok(Box::new(UnixStream(stream)))
+    }
+    async fn send<'a>(&self, _bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        Err(Error::not_connected().context("sending on a listening socket"))
+    }
+    async fn recv<'a>(
+        &self,
+        _bufs: &mut [IoSliceMut<'a>],
+        _flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        Err(Error::not_connected().context("receiving on a listening socket"))
+    }
+    async fn shutdown(&self, _how: SdFlags) -> Result<(), Error> {
+        Err(Error::not_connected().context("shutting down a listening socket"))
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixStream(cap_std::os::unix::net::UnixStream);
+
+#[cfg(unix)]
+#[async_trait::async_trait]
+impl WasiSocket for UnixStream {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::SocketStream)
+    }
+    async fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        self.0.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+    async fn send<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        Ok((&self.0).write_vectored(bufs)? as u64)
+    }
+    async fn recv<'a>(
+        &self,
+        bufs: &mut [IoSliceMut<'a>],
+        flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        if flags.contains(RiFlags::RECV_PEEK) {
+            return Err(Error::not_supported().context("peeking at a Unix stream"));
+        }
+        if !flags.contains(RiFlags::RECV_WAITALL) {
+            return Ok(((&self.0).read_vectored(bufs)? as u64, RoFlags::empty()));
+        }
+        Ok((read_to_fill(&self.0, bufs)?, RoFlags::empty()))
+    }
+    async fn shutdown(&self, how: SdFlags) -> Result<(), Error> {
+        self.0.shutdown(to_std_shutdown(how)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixDatagram(cap_std::os::unix::net::UnixDatagram);
+
+#[cfg(unix)]
+#[async_trait::async_trait]
+impl WasiSocket for UnixDatagram {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(FileType::SocketDgram)
+    }
+    async fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error> {
+        self.0.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+    async fn send<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        send_message(bufs, |message| self.0.send(message))
+    }
+    async fn recv<'a>(
+        &self,
+        bufs: &mut [IoSliceMut<'a>],
+        flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error> {
+        if flags.contains(RiFlags::RECV_PEEK) {
+            return Err(Error::not_supported().context("peeking at a Unix datagram socket"));
+        }
+        recv_message(bufs, |buf| self.0.recv(buf))
+    }
+    async fn shutdown(&self, _how: SdFlags) -> Result<(), Error> {
+        Err(Error::not_connected().context("datagram sockets have no connection to shut down"))
+    }
+}
+
+#[cfg(unix)]
+use io_lifetimes::{AsFd, BorrowedFd};
+
+macro_rules! wasi_socket_as_fd {
+    ($($ty:ty),*) => {$(
+        #[cfg(unix)]
+        impl AsFd for $ty {
+            fn as_fd(&self) -> BorrowedFd<'_> {
+                self.0.as_fd()
+            }
+        }
+    )*};
+}
+
+wasi_socket_as_fd!(TcpListener, TcpStream, UdpSocket);
+#[cfg(unix)]
+wasi_socket_as_fd!(UnixListener, UnixStream, UnixDatagram);
+
+/// The file descriptor of one of the sockets in this module, for schedulers
+/// to poll it.
+#[cfg(unix)]
+pub fn wasi_socket_fd(socket: &dyn WasiSocket) -> Option<BorrowedFd<'_>> {
+    let a = socket.as_any();
+    if a.is::<TcpListener>() {
+        Some(a.downcast_ref::<TcpListener>().unwrap().as_fd())
+    } else if a.is::<TcpStream>() {
+        Some(a.downcast_ref::<TcpStream>().unwrap().as_fd())
+    } else if a.is::<UdpSocket>() {
+        Some(a.downcast_ref::<UdpSocket>().unwrap().as_fd())
+    } else if a.is::<UnixListener>() {
+        Some(a.downcast_ref::<UnixListener>().unwrap().as_fd())
+    } else if a.is::<UnixStream>() {
+        Some(a.downcast_ref::<UnixStream>().unwrap().as_fd())
+    } else if a.is::<UnixDatagram>() {
+        Some(a.downcast_ref::<UnixDatagram>().unwrap().as_fd())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Network, Socket};
+    use std::io::{IoSlice, IoSliceMut, Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use wasi_common::socket::{
+        RiFlags, RoFlags, SdFlags, SocketAddress, SocketType, WasiNetwork, WasiSocket,
+    };
+    use wasi_common::ErrorKind;
+
+    fn connect(
+        network: &Network,
+        ty: SocketType,
+        addr: SocketAddress,
+    ) -> Result<Box<dyn WasiSocket>, wasi_common::Error> {
+        run(network.connect(ty, &addr))
+    }
+
+    fn assert_not_capable(result: Result<Box<dyn WasiSocket>, wasi_common::Error>) {
+        let err = result.err().expect("connecting should fail");
+        match err.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::NotCapable) => {}
+            _ => panic!("expected a missing capability, got {:?}", err),
+        }
+    }
+
+    fn recv(socket: &dyn WasiSocket, lens: &[usize], flags: RiFlags) -> (Vec<u8>, RoFlags) {
+        let mut bufs = lens.iter().map(|len| vec![0; *len]).collect::<Vec<_>>();
+        let mut slices = bufs
+            .iter_mut()
+            .map(|b| IoSliceMut::new(b))
+            .collect::<Vec<_>>();
+        let (n, flags) = run(socket.recv(&mut slices, flags)).expect("recv");
+        let mut data = bufs.concat();
+        data.truncate(n as usize);
+        (data, flags)
+    }
+
+    #[test]
+    fn tcp_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut network = Network::new();
+        assert_not_capable(connect(
+            &network,
+            SocketType::Stream,
+            SocketAddress::Ip(addr),
+        ));
+        network.allow_socket_addr(addr);
+        let socket = connect(&network, SocketType::Stream, SocketAddress::Ip(addr)).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        let bufs = [IoSlice::new(b"hel"), IoSlice::new(b"lo")];
+        assert_eq!(run(socket.send(&bufs)).unwrap(), 5);
+        let mut received = [0; 5];
+        peer.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"hello");
+
+        peer.write_all(b"world").unwrap();
+        let (peeked, _) = recv(&*socket, &[5], RiFlags::RECV_PEEK | RiFlags::RECV_WAITALL);
+        assert_eq!(peeked, b"world");
+        let (data, flags) = recv(&*socket, &[2, 3], RiFlags::RECV_WAITALL);
+        assert_eq!(data, b"world");
+        assert_eq!(flags, RoFlags::empty());
+
+        run(socket.shutdown(SdFlags::WR)).unwrap();
+        let mut rest = Vec::new();
+        peer.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn tcp_listener_accepts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let socket = Socket::from(cap_std::net::TcpListener::from_std(listener))
+            .into_wasi_socket()
+            .unwrap();
+
+        let mut peer = std::net::TcpStream::connect(addr).unwrap();
+        let connection = run(socket.accept()).unwrap();
+        peer.write_all(b"hello").unwrap();
+        let (data, _) = recv(&*connection, &[5], RiFlags::RECV_WAITALL);
+        assert_eq!(data, b"hello");
+        assert!(run(socket.send(&[IoSlice::new(b"hello")])).is_err());
+
+        // Once nonblocking, accepting with no connection waiting fails
+        // rather than blocking.
+        run(socket.set_nonblocking(true)).unwrap();
+        let err = run(socket.accept()).err().expect("accepting should fail");
+        let err = err.downcast::<std::io::Error>().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn udp_loopback() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = peer.local_addr().unwrap();
+
+        let mut network = Network::new();
+        assert_not_capable(connect(
+            &network,
+            SocketType::Dgram,
+            SocketAddress::Ip(addr),
+        ));
+        network.allow_socket_addr(addr);
+        let socket = connect(&network, SocketType::Dgram, SocketAddress::Ip(addr)).unwrap();
+
+        let bufs = [IoSlice::new(b"ping"), IoSlice::new(b"!")];
+        assert_eq!(run(socket.send(&bufs)).unwrap(), 5);
+        let mut received = [0; 16];
+        let (n, from) = peer.recv_from(&mut received).unwrap();
+        assert_eq!(&received[..n], b"ping!");
+
+        peer.send_to(b"pong", from).unwrap();
+        let (data, flags) = recv(&*socket, &[2, 2], RiFlags::empty());
+        assert_eq!(data, b"pong");
+        assert_eq!(flags, RoFlags::empty());
+
+        // Datagrams which don't fit are cut short, and say so.
+        peer.send_to(b"too long", from).unwrap();
+        let (data, flags) = recv(&*socket, &[3], RiFlags::empty());
+        assert_eq!(data, b"too");
+        assert_eq!(flags, RoFlags::RECV_DATA_TRUNCATED);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_loopback() {
+        use std::os::unix::net::{UnixDatagram, UnixListener};
+
+        let tempdir = tempfile::Builder::new()
+            .prefix("cap-std-sync")
+            .tempdir()
+            .expect("create temporary dir");
+        let stream_path = tempdir.path().join("stream.sock");
+        let dgram_path = tempdir.path().join("dgram.sock");
+        let listener = UnixListener::bind(&stream_path).unwrap();
+        let dgram_peer = UnixDatagram::bind(&dgram_path).unwrap();
+
+        let mut network = Network::new();
+        network.allow_unix_socket(&stream_path);
+        assert_not_capable(connect(
+            &network,
+            SocketType::Dgram,
+            SocketAddress::Unix(dgram_path.clone()),
+        ));
+        network.allow_unix_socket(&dgram_path);
+
+        let stream = connect(
+            &network,
+            SocketType::Stream,
+            SocketAddress::Unix(stream_path.clone()),
+        )
+        .unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        assert_eq!(run(stream.send(&[IoSlice::new(b"hello")])).unwrap(), 5);
+        let mut received = [0; 5];
+        peer.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"hello");
+        peer.write_all(b"world").unwrap();
+        let (data, _) = recv(&*stream, &[5], RiFlags::RECV_WAITALL);
+        assert_eq!(data, b"world");
+
+        let dgram = connect(
+            &network,
+            SocketType::Dgram,
+            SocketAddress::Unix(dgram_path.clone()),
+        )
+        .unwrap();
+        assert_eq!(run(dgram.send(&[IoSlice::new(b"ping")])).unwrap(), 4);
+        let mut received = [0; 16];
+        let n = dgram_peer.recv(&mut received).unwrap();
+        assert_eq!(&received[..n], b"ping");
+    }
+
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+        let mut f = Pin::from(Box::new(future));
+        let waker = dummy_waker();
+        let mut cx = Context::from_waker(&waker);
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(val) => return val,
+            Poll::Pending => panic!("socket futures should complete synchronously"),
+        }
+
+        fn dummy_waker() -> Waker {
+            return unsafe { Waker::from_raw(clone(5 as *const _)) };
+
+            unsafe fn clone(ptr: *const ()) -> RawWaker {
+                assert_eq!(ptr as usize, 5);
+                const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+                RawWaker::new(ptr, &VTABLE)
+            }
+
+            unsafe fn wake(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+
+            unsafe fn wake_by_ref(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+
+            unsafe fn drop(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+        }
+    }
+}
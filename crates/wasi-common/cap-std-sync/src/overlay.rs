@@ -0,0 +1,1091 @@
+//! A copy-on-write overlay of a read-only host directory.
+//!
+//! An `OverlayDir` lets a guest change a host directory without anything it
+//! does reaching the host: it layers a writable, in-memory upper directory
+//! over a `cap_std::fs::Dir` it only ever reads from, the lower directory.
+//!
+//! * A path which is in the upper layer hides the same path in the lower one.
+//!   Directories which are in both are merged.
+//! * A file or symlink of the lower layer is copied up into the upper layer,
+//!   along with the directories above it, the first time it's opened for
+//!   writing or truncated, its times are set, or it's renamed or linked to.
+//!   Until then, opening it opens the host file read-only, and its size,
+//!   flags and times can't be changed through the open file. Renaming a
+//!   directory copies all of it up.
+//! * Removing a path of the lower layer leaves a whiteout behind, which hides
+//!   it. A directory created where one was removed is opaque: nothing of the
+//!   lower layer below it shows through.
+//!
+//! The upper layer is a `wasi_common::memfs::MemFs`, so what the guest wrote
+//! can be looked at, or exported, once it's done. It holds at most
+//! `DEFAULT_CAPACITY` bytes of files unless given another capacity with
+//! `MemFs::set_capacity`. Whiteouts only live as long
+//! as the `OverlayDir`s of the overlay.
+//!
+//! Paths, and the symlinks they pass through, are resolved over the merged
+//! view of both layers, and may no more leave the preopened directory than
+//! they may in a host directory.
+
+use crate::file::{filetype_from, File};
+use cap_fs_ext::{DirEntryExt, FollowSymlinks, MetadataExt, OpenOptionsFollowExt};
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use wasi_common::{
+    dir::{ReaddirCursor, ReaddirEntity, WasiDir},
+    file::{Advice, FdFlags, FileType, Filestat, OFlags, WasiFile},
+    memfs::{MemDir, MemFs},
+    Error, ErrorExt, ErrorKind, SystemTimeSpec,
+};
+
+/// The most symlinks followed while resolving a single path, as on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// How many bytes the files of the upper layer may hold between them, unless
+/// it's given another capacity.
+pub const DEFAULT_CAPACITY: u64 = 1 << 30;
+
+/// A directory of a copy-on-write overlay of a host directory.
+pub struct OverlayDir {
+    overlay: Arc<Overlay>,
+    /// Where this directory is, relative to the root of the overlay. The
+    /// root is the empty path.
+    path: String,
+}
+
+impl OverlayDir {
+    /// Layers an empty in-memory directory over `lower`, which is never
+    /// written to, and returns the root of the overlay.
+    pub fn new(lower: cap_std::fs::Dir) -> Self {
+        let upper = MemFs::new();
+        upper.set_capacity(DEFAULT_CAPACITY);
+        OverlayDir {
+            overlay: Arc::new(Overlay {
+                upper_root: upper.root(),
+                upper,
+                lower,
+                hidden: Mutex::new(Hidden::default()),
+            }),
+            path: String::new(),
+        }
+    }
+
+    /// The upper layer of the overlay, which holds everything the guest
+    /// created or changed.
+    pub fn upper(&self) -> &MemFs {
+        &self.overlay.upper
+    }
+
+    /// Finds the `OverlayDir` another directory of the same overlay is.
+    fn same_overlay<'a>(&self, other: &'a dyn WasiDir) -> Result<&'a OverlayDir, Error> {
+        match other.as_any().downcast_ref::<OverlayDir>() {
+            Some(other) if Arc::ptr_eq(&self.overlay, &other.overlay) => Ok(other),
+            Some(_) => Err(Error::badf().context("directory of another overlay")),
+            None => Err(Error::badf().context("not an overlay directory")),
+        }
+    }
+}
+
+struct Overlay {
+    upper: MemFs,
+    upper_root: MemDir,
+    lower: cap_std::fs::Dir,
+    hidden: Mutex<Hidden>,
+}
+
+/// What of the lower layer is hidden, by path.
+#[derive(Default)]
+struct Hidden {
+    /// Paths which were removed from the lower layer.
+    whiteouts: HashSet<String>,
+    /// Directories of the upper layer which nothing of the lower layer below
+    /// them shows through.
+    opaque: HashSet<String>,
+}
+
+impl Hidden {
+    /// Forgets everything hidden strictly below `path`.
+    fn forget_below(&mut self, path: &str) {
+        self.whiteouts.retain(|p| !is_below(p, path));
+        self.opaque.retain(|p| !is_below(p, path));
+    }
+}
+
+/// Which layer a path was found in.
+#[derive(Clone, Copy, PartialEq)]
+enum Layer {
+    Upper,
+    Lower,
+}
+
+/// Where a path resolved to: the entry `name` of the directory `parent`, or
+/// `parent` itself if there's no name.
+struct Resolved {
+    parent: String,
+    name: Option<String>,
+    trailing_slash: bool,
+}
+
+impl Resolved {
+    fn path(&self) -> String {
+        match &self.name {
+            Some(name) => join(&self.parent, name),
+            None => self.parent.clone(),
+        }
+    }
+}
+
+impl Overlay {
+    /// Whether `path` of the lower layer may show through.
+    fn lower_visible(&self, path: &str) -> bool {
+        let hidden = self.hidden.lock().unwrap();
+        !hidden.whiteouts.contains(path)
+            && ancestors(path)
+                .all(|dir| !hidden.whiteouts.contains(dir) && !hidden.opaque.contains(dir))
+    }
+
+    fn lower_metadata(&self, path: &str) -> Result<Option<cap_std::fs::Metadata>, Error> {
+        if !self.lower_visible(path) {
+            return Ok(None);
+        }
+        match self.lower.symlink_metadata(lower_path(path)) {
+            Ok(meta) => Ok(Some(meta)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn upper_filestat(&self, path: &str) -> Result<Option<Filestat>, Error> {
+        match self
+            .upper_root
+            .get_path_filestat(upper_path(path), false)
+            .await
+        {
+            Ok(stat) => Ok(Some(stat)),
+            Err(e)
+                if matches!(
+                    e.downcast_ref(),
+                    Some(ErrorKind::Noent) | Some(ErrorKind::Notdir)
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Finds which layer `path` is in, if any, without following a symlink
+    /// at its end.
+    async fn locate(&self, path: &str) -> Result<Option<(Layer, FileType)>, Error> {
+        if let Some(stat) = self.upper_filestat(path).await? {
+            return Ok(Some((Layer::Upper, stat.filetype)));
+        }
+        Ok(self
+            .lower_metadata(path)?
+            .map(|meta| (Layer::Lower, filetype_from(&meta.file_type()))))
+    }
+
+    /// Like `locate`, but a trailing slash on anything but a directory is an
+    /// error.
+    async fn locate_resolved(&self, r: &Resolved) -> Result<Option<(Layer, FileType)>, Error> {
+        let found = self.locate(&r.path()).await?;
+        match found {
+            Some((_, filetype)) if r.trailing_slash && filetype != FileType::Directory => {
+                Err(Error::not_dir())
+            }
+            _ => Ok(found),
+        }
+    }
+
+    async fn filestat(&self, path: &str) -> Result<Filestat, Error> {
+        if let Some(stat) = self.upper_filestat(path).await? {
+            return Ok(stat);
+        }
+        match self.lower_metadata(path)? {
+            Some(meta) => Ok(filestat_from(&meta)),
+            None => Err(Error::not_found()),
+        }
+    }
+
+    async fn read_link(&self, path: &str, layer: Layer) -> Result<String, Error> {
+        let target = match layer {
+            Layer::Upper => self.upper_root.read_link(upper_path(path)).await?,
+            Layer::Lower => self.lower.read_link(lower_path(path))?,
+        };
+        target
+            .into_os_string()
+            .into_string()
+            .map_err(|_| Error::illegal_byte_sequence().context("symlink target"))
+    }
+
+    /// Resolves `path` from the directory at `base`, following the symlinks
+    /// it passes through, and the one at its end if `follow` is set.
+    async fn resolve(&self, base: &str, path: &str, follow: bool) -> Result<Resolved, Error> {
+        if path.is_empty() {
+            return Err(Error::not_found());
+        }
+        let trailing_slash = path.ends_with('/');
+        let mut queue = components(path)?;
+        // The directories walked into below `base`, which `..` may not leave.
+        let mut walked: Vec<String> = Vec::new();
+        let mut symlinks = 0;
+        while let Some(name) = queue.pop_front() {
+            if name == ".." {
+                walked
+                    .pop()
+                    .ok_or_else(|| Error::perm().context("path leaves its directory"))?;
+                continue;
+            }
+            let parent = walked.iter().fold(base.to_owned(), |dir, n| join(&dir, n));
+            let last = queue.is_empty();
+            if last && !follow && !trailing_slash {
+                return Ok(Resolved {
+                    parent,
+                    name: Some(name),
+                    trailing_slash,
+                });
+            }
+            let path = join(&parent, &name);
+            match self.locate(&path).await? {
+                Some((layer, FileType::SymbolicLink)) => {
+                    symlinks += 1;
+                    if symlinks > MAX_SYMLINKS {
+                        return Err(Error::symlink_loop());
+                    }
+                    let target = self.read_link(&path, layer).await?;
+                    for component in components(&target)?.into_iter().rev() {
+                        queue.push_front(component);
+                    }
+                }
+                Some((_, FileType::Directory)) if !last => walked.push(name),
+                Some(_) if !last => return Err(Error::not_dir()),
+                None if !last => return Err(Error::not_found()),
+                _ => {
+                    return Ok(Resolved {
+                        parent,
+                        name: Some(name),
+                        trailing_slash,
+                    })
+                }
+            }
+        }
+        Ok(Resolved {
+            parent: walked.iter().fold(base.to_owned(), |dir, n| join(&dir, n)),
+            name: None,
+            trailing_slash,
+        })
+    }
+
+    /// Lists the entries of the directory at `path`, other than `.` and
+    /// `..`, by name.
+    async fn list(&self, path: &str) -> Result<BTreeMap<String, (FileType, u64)>, Error> {
+        let mut entries = BTreeMap::new();
+        if self.upper_filestat(path).await?.is_some() {
+            let dir = self.upper_root.open_dir(false, upper_path(path)).await?;
+            for entity in dir.readdir(ReaddirCursor::from(0)).await? {
+                let entity = entity?;
+                if entity.name != "." && entity.name != ".." {
+                    entries.insert(entity.name, (entity.filetype, entity.inode));
+                }
+            }
+        }
+        match self.lower_metadata(path)? {
+            Some(meta) if meta.is_dir() => {}
+            _ => return Ok(entries),
+        }
+        for entry in self.lower.read_dir(lower_path(path))? {
+            let entry = entry?;
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| Error::illegal_byte_sequence().context("filename"))?;
+            if entries.contains_key(&name) || !self.lower_visible(&join(path, &name)) {
+                continue;
+            }
+            let meta = entry.full_metadata()?;
+            entries.insert(name, (filetype_from(&meta.file_type()), meta.ino()));
+        }
+        Ok(entries)
+    }
+
+    /// Copies `path`, which is only in the lower layer, up into the upper
+    /// layer, along with the directories above it. Only the directory itself
+    /// is copied up for a directory.
+    async fn copy_up(&self, path: &str) -> Result<(), Error> {
+        self.copy_up_parents(path).await?;
+        self.copy_up_one(path).await
+    }
+
+    /// Copies the directories above `path` which are only in the lower layer
+    /// up into the upper layer.
+    async fn copy_up_parents(&self, path: &str) -> Result<(), Error> {
+        for dir in ancestors(path) {
+            if self.upper_filestat(dir).await?.is_none() {
+                self.copy_up_one(dir).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn copy_up_one(&self, path: &str) -> Result<(), Error> {
+        let meta = self.lower_metadata(path)?.ok_or_else(Error::not_found)?;
+        match filetype_from(&meta.file_type()) {
+            FileType::Directory => self.upper.create_dir_all(path)?,
+            FileType::SymbolicLink => {
+                let target = self.read_link(path, Layer::Lower).await?;
+                self.upper.symlink(&target, path)?
+            }
+            FileType::RegularFile => self
+                .upper
+                .write_file(path, self.lower.read(lower_path(path))?)?,
+            _ => return Err(Error::not_supported().context("copying up a special file")),
+        }
+        self.upper_root
+            .set_times(
+                path,
+                meta.accessed().ok().map(SystemTimeSpec::Absolute),
+                meta.modified().ok().map(SystemTimeSpec::Absolute),
+                false,
+            )
+            .await
+    }
+
+    /// Copies everything at and below `path` which is only in the lower
+    /// layer up into the upper layer.
+    async fn copy_up_tree(&self, path: &str) -> Result<(), Error> {
+        let mut todo = vec![path.to_owned()];
+        while let Some(path) = todo.pop() {
+            let (layer, filetype) = self.locate(&path).await?.ok_or_else(Error::not_found)?;
+            if layer == Layer::Lower {
+                self.copy_up(&path).await?;
+            }
+            if filetype == FileType::Directory {
+                for name in self.list(&path).await?.into_keys() {
+                    todo.push(join(&path, &name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the lower layer's `path` show through again after something
+    /// was created at `path` in the upper layer.
+    fn unhide(&self, path: &str, is_dir: bool) {
+        let mut hidden = self.hidden.lock().unwrap();
+        if hidden.whiteouts.remove(path) && is_dir {
+            // Whatever used to be below the removed path stays removed.
+            hidden.opaque.insert(path.to_owned());
+        }
+    }
+
+    /// Hides the lower layer's `path`, if it has one, after it was removed.
+    fn hide(&self, path: &str) -> Result<(), Error> {
+        let in_lower = self.lower_metadata(path)?.is_some();
+        let mut hidden = self.hidden.lock().unwrap();
+        hidden.forget_below(path);
+        hidden.opaque.remove(path);
+        if in_lower {
+            hidden.whiteouts.insert(path.to_owned());
+        }
+        Ok(())
+    }
+
+    fn open_lower(&self, path: &str) -> Result<LowerFile, Error> {
+        let mut opts = cap_std::fs::OpenOptions::new();
+        opts.read(true);
+        opts.follow(FollowSymlinks::No);
+        let f = self.lower.open_with(lower_path(path), &opts)?;
+        Ok(LowerFile(File::from_cap_std(f)))
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for OverlayDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let o = &self.overlay;
+        let r = o
+            .resolve(&self.path, path, symlink_follow || path.ends_with('/'))
+            .await?;
+        let path = r.path();
+        let truncate = oflags.contains(OFlags::TRUNCATE);
+        let created = match o.locate_resolved(&r).await? {
+            Some(_) if oflags.contains(OFlags::CREATE | OFlags::EXCLUSIVE) => {
+                return Err(Error::exist())
+            }
+            Some((_, FileType::SymbolicLink)) => return Err(Error::symlink_loop()),
+            Some((_, FileType::Directory)) if write || truncate => return Err(Error::is_dir()),
+            Some((Layer::Lower, _)) if !write && !truncate => {
+                return Ok(Box::new(o.open_lower(&path)?))
+            }
+            Some((Layer::Lower, _)) => {
+                o.copy_up(&path).await?;
+                false
+            }
+            Some((Layer::Upper, _)) => false,
+            None if oflags.contains(OFlags::CREATE) => {
+                if r.trailing_slash {
+                    return Err(Error::is_dir());
+                }
+                o.copy_up_parents(&path).await?;
+                true
+            }
+            None => return Err(Error::not_found()),
+        };
+        let f = o
+            .upper_root
+            .open_file(false, upper_path(&path), oflags, read, write, fdflags)
+            .await?;
+        if created {
+            o.unhide(&path, false);
+        }
+        Ok(f)
+    }
+
+    async fn open_dir(&self, symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        let r = self
+            .overlay
+            .resolve(&self.path, path, symlink_follow)
+            .await?;
+        match self.overlay.locate_resolved(&r).await? {
+            Some((_, FileType::Directory)) => Ok(Box::new(OverlayDir {
+                overlay: self.overlay.clone(),
+                path: r.path(),
+            })),
+            Some((_, FileType::SymbolicLink)) => Err(Error::symlink_loop()),
+            Some(_) => Err(Error::not_dir()),
+            None => Err(Error::not_found()),
+        }
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<(), Error> {
+        let o = &self.overlay;
+        let r = o.resolve(&self.path, path, false).await?;
+        let path = r.path();
+        if r.name.is_none() || o.locate(&path).await?.is_some() {
+            return Err(Error::exist());
+        }
+        o.copy_up_parents(&path).await?;
+        o.upper_root.create_dir(&path).await?;
+        o.unhide(&path, true);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let o = &self.overlay;
+        let inode = o.filestat(&self.path).await?.inode;
+        let parent_inode = match self.path.rfind('/') {
+            Some(i) => o.filestat(&self.path[..i]).await?.inode,
+            None if self.path.is_empty() => inode,
+            None => o.filestat("").await?.inode,
+        };
+        let dots = vec![
+            (".".to_owned(), (FileType::Directory, inode)),
+            ("..".to_owned(), (FileType::Directory, parent_inode)),
+        ];
+        // Entries are listed by name, so the index of an entry is its cookie.
+        let entities = dots
+            .into_iter()
+            .chain(o.list(&self.path).await?)
+            .enumerate()
+            .skip(u64::from(cursor) as usize)
+            .map(|(ix, (name, (filetype, inode)))| {
+                Ok(ReaddirEntity {
+                    next: ReaddirCursor::from(ix as u64 + 1),
+                    inode,
+                    name,
+                    filetype,
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(entities.into_iter()))
+    }
+
+    async fn symlink(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
+        let o = &self.overlay;
+        let r = o.resolve(&self.path, new_path, false).await?;
+        let path = r.path();
+        if r.name.is_none() || o.locate(&path).await?.is_some() {
+            return Err(Error::exist());
+        }
+        if r.trailing_slash {
+            return Err(Error::not_found());
+        }
+        o.copy_up_parents(&path).await?;
+        o.upper_root.symlink(old_path, &path).await?;
+        o.unhide(&path, false);
+        Ok(())
+    }
+
+    async fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        let o = &self.overlay;
+        let r = o.resolve(&self.path, path, false).await?;
+        if r.name.is_none() {
+            return Err(Error::invalid_argument().context("removing `.` or `..`"));
+        }
+        let path = r.path();
+        let layer = match o.locate(&path).await? {
+            Some((layer, FileType::Directory)) => layer,
+            Some(_) => return Err(Error::not_dir()),
+            None => return Err(Error::not_found()),
+        };
+        if !o.list(&path).await?.is_empty() {
+            return Err(Error::not_empty());
+        }
+        if layer == Layer::Upper {
+            o.upper_root.remove_dir(&path).await?;
+        }
+        o.hide(&path)
+    }
+
+    async fn unlink_file(&self, path: &str) -> Result<(), Error> {
+        let o = &self.overlay;
+        let r = o.resolve(&self.path, path, false).await?;
+        if r.name.is_none() {
+            return Err(Error::is_dir());
+        }
+        let path = r.path();
+        let layer = match o.locate(&path).await? {
+            Some((_, FileType::Directory)) => return Err(Error::is_dir()),
+            Some(_) if r.trailing_slash => return Err(Error::not_dir()),
+            Some((layer, _)) => layer,
+            None => return Err(Error::not_found()),
+        };
+        if layer == Layer::Upper {
+            o.upper_root.unlink_file(&path).await?;
+        }
+        o.hide(&path)
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        let o = &self.overlay;
+        let r = o.resolve(&self.path, path, false).await?;
+        let path = r.path();
+        match o.locate_resolved(&r).await? {
+            Some((layer, FileType::SymbolicLink)) => {
+                Ok(PathBuf::from(o.read_link(&path, layer).await?))
+            }
+            Some(_) => Err(Error::invalid_argument().context("not a symlink")),
+            None => Err(Error::not_found()),
+        }
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.overlay.filestat(&self.path).await
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        let o = &self.overlay;
+        let r = o
+            .resolve(&self.path, path, follow_symlinks || path.ends_with('/'))
+            .await?;
+        match o.locate_resolved(&r).await? {
+            Some(_) => o.filestat(&r.path()).await,
+            None => Err(Error::not_found()),
+        }
+    }
+
+    async fn rename(
+        &self,
+        src_path: &str,
+        dest_dir: &dyn WasiDir,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        let dest_dir = self.same_overlay(dest_dir)?;
+        let o = &self.overlay;
+        let src = o.resolve(&self.path, src_path, false).await?;
+        let dest = o.resolve(&dest_dir.path, dest_path, false).await?;
+        if src.name.is_none() || dest.name.is_none() {
+            return Err(Error::invalid_argument().context("renaming `.` or `..`"));
+        }
+        let (src_layer, src_type) = o
+            .locate_resolved(&src)
+            .await?
+            .ok_or_else(Error::not_found)?;
+        let is_dir = src_type == FileType::Directory;
+        let (src, dest_trailing_slash, dest) = (src.path(), dest.trailing_slash, dest.path());
+
+        // The upper layer can't see what's only in the lower one, so the
+        // destination is checked against the merged view here.
+        match o.locate(&dest).await? {
+            Some((_, FileType::Directory)) if !is_dir => return Err(Error::is_dir()),
+            Some((_, FileType::Directory)) if src != dest && !o.list(&dest).await?.is_empty() => {
+                return Err(Error::not_empty())
+            }
+            Some((_, FileType::Directory)) => {}
+            Some(_) if is_dir => return Err(Error::not_dir()),
+            _ if dest_trailing_slash && !is_dir => return Err(Error::not_dir()),
+            _ => {}
+        }
+        if src == dest {
+            return Ok(());
+        }
+        if is_dir && is_below(&dest, &src) {
+            return Err(Error::invalid_argument().context("moving a directory into itself"));
+        }
+
+        // Everything being moved is brought up into the upper layer first, so
+        // that it can be moved there in one go.
+        if is_dir {
+            o.copy_up_tree(&src).await?;
+        } else if src_layer == Layer::Lower {
+            o.copy_up(&src).await?;
+        }
+        o.copy_up_parents(&dest).await?;
+        o.upper_root.rename(&src, &o.upper_root, &dest).await?;
+
+        o.hide(&src)?;
+        let mut hidden = o.hidden.lock().unwrap();
+        hidden.whiteouts.remove(&dest);
+        hidden.forget_below(&dest);
+        if is_dir {
+            // All of the directory was copied up, so nothing of whatever was
+            // in the lower layer at its new path may show through.
+            hidden.opaque.insert(dest);
+        } else {
+            hidden.opaque.remove(&dest);
+        }
+        Ok(())
+    }
+
+    async fn hard_link(
+        &self,
+        src_path: &str,
+        target_dir: &dyn WasiDir,
+        target_path: &str,
+    ) -> Result<(), Error> {
+        let target_dir = self.same_overlay(target_dir)?;
+        let o = &self.overlay;
+        let src = o.resolve(&self.path, src_path, false).await?;
+        let src_layer = match o.locate_resolved(&src).await? {
+            Some((_, FileType::Directory)) => {
+                return Err(Error::perm().context("hard link to a directory"))
+            }
+            Some((layer, _)) => layer,
+            None => return Err(Error::not_found()),
+        };
+        let target = o.resolve(&target_dir.path, target_path, false).await?;
+        let (src, target_trailing_slash, target) =
+            (src.path(), target.trailing_slash, target.path());
+        if o.locate(&target).await?.is_some() {
+            return Err(Error::exist());
+        }
+        if target_trailing_slash {
+            return Err(Error::not_found());
+        }
+        if src_layer == Layer::Lower {
+            o.copy_up(&src).await?;
+        }
+        o.copy_up_parents(&target).await?;
+        o.upper_root.hard_link(&src, &o.upper_root, &target).await?;
+        o.unhide(&target, false);
+        Ok(())
+    }
+
+    async fn set_times(
+        &self,
+        path: &str,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+        follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        let o = &self.overlay;
+        let r = o
+            .resolve(&self.path, path, follow_symlinks || path.ends_with('/'))
+            .await?;
+        let path = r.path();
+        match o.locate_resolved(&r).await? {
+            Some((Layer::Lower, _)) => o.copy_up(&path).await?,
+            Some((Layer::Upper, _)) => {}
+            None => return Err(Error::not_found()),
+        }
+        o.upper_root
+            .set_times(upper_path(&path), atime, mtime, false)
+            .await
+    }
+}
+
+/// A file of the lower layer, opened for reading. Changing its size, flags or
+/// times would change the host file, even through a read-only descriptor, so
+/// they are refused.
+struct LowerFile(File);
+
+impl LowerFile {
+    fn read_only() -> Error {
+        Error::perm().context("file of the lower layer of an overlay")
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for LowerFile {
+    fn as_any(&self) -> &dyn Any {
+        // The scheduler finds the host file to poll this way.
+        self.0.as_any()
+    }
+    async fn datasync(&self) -> Result<(), Error> {
+        self.0.datasync().await
+    }
+    async fn sync(&self) -> Result<(), Error> {
+        self.0.sync().await
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        self.0.get_filetype().await
+    }
+    async fn get_fdflags(&self) -> Result<FdFlags, Error> {
+        self.0.get_fdflags().await
+    }
+    async fn set_fdflags(&mut self, _flags: FdFlags) -> Result<(), Error> {
+        Err(Self::read_only())
+    }
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+    async fn set_filestat_size(&self, _size: u64) -> Result<(), Error> {
+        Err(Self::read_only())
+    }
+    async fn advise(&self, offset: u64, len: u64, advice: Advice) -> Result<(), Error> {
+        self.0.advise(offset, len, advice).await
+    }
+    async fn allocate(&self, _offset: u64, _len: u64) -> Result<(), Error> {
+        Err(Self::read_only())
+    }
+    async fn set_times(
+        &self,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        Err(Self::read_only())
+    }
+    async fn read_vectored<'a>(&self, bufs: &mut [io::IoSliceMut<'a>]) -> Result<u64, Error> {
+        self.0.read_vectored(bufs).await
+    }
+    async fn read_vectored_at<'a>(
+        &self,
+        bufs: &mut [io::IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.0.read_vectored_at(bufs, offset).await
+    }
+    async fn write_vectored<'a>(&self, _bufs: &[io::IoSlice<'a>]) -> Result<u64, Error> {
+        Err(Error::badf())
+    }
+    async fn write_vectored_at<'a>(
+        &self,
+        _bufs: &[io::IoSlice<'a>],
+        _offset: u64,
+    ) -> Result<u64, Error> {
+        Err(Error::badf())
+    }
+    async fn seek(&self, pos: io::SeekFrom) -> Result<u64, Error> {
+        self.0.seek(pos).await
+    }
+    async fn peek(&self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.0.peek(buf).await
+    }
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        self.0.num_ready_bytes().await
+    }
+    async fn readable(&self) -> Result<(), Error> {
+        self.0.readable().await
+    }
+    async fn writable(&self) -> Result<(), Error> {
+        self.0.writable().await
+    }
+}
+
+fn filestat_from(meta: &cap_std::fs::Metadata) -> Filestat {
+    Filestat {
+        device_id: meta.dev(),
+        inode: meta.ino(),
+        filetype: filetype_from(&meta.file_type()),
+        nlink: meta.nlink(),
+        size: meta.len(),
+        atim: meta.accessed().map(|t| Some(t.into_std())).unwrap_or(None),
+        mtim: meta.modified().map(|t| Some(t.into_std())).unwrap_or(None),
+        ctim: meta.created().map(|t| Some(t.into_std())).unwrap_or(None),
+    }
+}
+
+/// Splits `path` into the names it's made of, leaving out empty names and
+/// `.`.
+fn components(path: &str) -> Result<VecDeque<String>, Error> {
+    if path.starts_with('/') {
+        return Err(Error::perm().context("absolute path"));
+    }
+    if path.contains('\0') {
+        return Err(Error::invalid_argument().context("path contains NUL"));
+    }
+    Ok(path
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(str::to_owned)
+        .collect())
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// The directories `path` is below, from the outermost in, leaving out the
+/// root.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(i, _)| &path[..i])
+}
+
+fn is_below(path: &str, dir: &str) -> bool {
+    path.len() > dir.len() && path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/'
+        || dir.is_empty() && !path.is_empty()
+}
+
+/// Where `path` is in the upper layer, which, like a host directory, doesn't
+/// take an empty path for its root.
+fn upper_path(path: &str) -> &str {
+    if path.is_empty() {
+        "."
+    } else {
+        path
+    }
+}
+
+fn lower_path(path: &str) -> &Path {
+    Path::new(upper_path(path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::OverlayDir;
+    use cap_std::ambient_authority;
+    use std::collections::BTreeSet;
+    use wasi_common::dir::{ReaddirCursor, WasiDir};
+    use wasi_common::file::{FdFlags, OFlags, WasiFile};
+
+    /// Makes a host directory holding `a.txt`, `sub/b.txt` and `sub/deep/c.txt`.
+    fn lower() -> (tempfile::TempDir, cap_std::fs::Dir) {
+        let tempdir = tempfile::Builder::new()
+            .prefix("cap-std-sync")
+            .tempdir()
+            .expect("create temporary dir");
+        std::fs::create_dir_all(tempdir.path().join("sub/deep")).unwrap();
+        std::fs::write(tempdir.path().join("a.txt"), "lower a").unwrap();
+        std::fs::write(tempdir.path().join("sub/b.txt"), "lower b").unwrap();
+        std::fs::write(tempdir.path().join("sub/deep/c.txt"), "lower c").unwrap();
+        let dir = cap_std::fs::Dir::open_ambient_dir(tempdir.path(), ambient_authority())
+            .expect("open ambient temporary dir");
+        (tempdir, dir)
+    }
+
+    fn names(dir: &dyn WasiDir) -> BTreeSet<String> {
+        run(dir.readdir(ReaddirCursor::from(0)))
+            .expect("readdir succeeds")
+            .map(|e| e.expect("readdir entry is valid").name)
+            .filter(|name| name != "." && name != "..")
+            .collect()
+    }
+
+    fn read(dir: &dyn WasiDir, path: &str) -> String {
+        let f = run(dir.open_file(false, path, OFlags::empty(), true, false, FdFlags::empty()))
+            .expect("open for reading");
+        let mut buf = vec![0; 64];
+        let n = run(f.read_vectored(&mut [std::io::IoSliceMut::new(&mut buf)])).unwrap();
+        String::from_utf8(buf[..n as usize].to_vec()).unwrap()
+    }
+
+    fn write(dir: &dyn WasiDir, path: &str, oflags: OFlags, contents: &str) {
+        let f: Box<dyn WasiFile> =
+            run(dir.open_file(false, path, oflags, false, true, FdFlags::empty()))
+                .expect("open for writing");
+        run(f.write_vectored(&[std::io::IoSlice::new(contents.as_bytes())])).unwrap();
+    }
+
+    #[test]
+    fn writes_are_copied_up() {
+        let (tempdir, lower) = lower();
+        let overlay = OverlayDir::new(lower);
+
+        assert_eq!(read(&overlay, "sub/b.txt"), "lower b");
+        write(&overlay, "sub/b.txt", OFlags::TRUNCATE, "upper b");
+        write(&overlay, "sub/new.txt", OFlags::CREATE, "new");
+        assert_eq!(read(&overlay, "sub/b.txt"), "upper b");
+        assert_eq!(read(&overlay, "sub/new.txt"), "new");
+        assert_eq!(
+            names(&*run(overlay.open_dir(false, "sub")).unwrap()),
+            ["b.txt", "deep", "new.txt"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect()
+        );
+
+        // Nothing reached the host, and the changes are in the upper layer.
+        assert_eq!(
+            std::fs::read_to_string(tempdir.path().join("sub/b.txt")).unwrap(),
+            "lower b"
+        );
+        assert!(!tempdir.path().join("sub/new.txt").exists());
+        assert_eq!(overlay.upper().read_file("sub/b.txt").unwrap(), b"upper b");
+        assert!(overlay.upper().read_file("a.txt").is_err());
+    }
+
+    #[test]
+    fn lower_files_stay_unchanged() {
+        let (tempdir, lower) = lower();
+        let overlay = OverlayDir::new(lower);
+        let host = tempdir.path().join("a.txt");
+        let mtime = std::fs::metadata(&host).unwrap().modified().unwrap();
+
+        let f = run(overlay.open_file(
+            false,
+            "a.txt",
+            OFlags::empty(),
+            true,
+            false,
+            FdFlags::empty(),
+        ))
+        .expect("open for reading");
+        let epoch = cap_std::time::SystemTime::from_std(std::time::UNIX_EPOCH);
+        let epoch = Some(wasi_common::SystemTimeSpec::Absolute(epoch));
+        assert!(run(f.set_times(None, epoch)).is_err());
+        assert!(run(f.set_filestat_size(0)).is_err());
+        assert!(run(f.allocate(0, 4096)).is_err());
+        assert!(run(f.write_vectored(&[std::io::IoSlice::new(b"x")])).is_err());
+        let mut buf = [0; 7];
+        run(f.read_vectored(&mut [std::io::IoSliceMut::new(&mut buf)])).unwrap();
+        assert_eq!(&buf, b"lower a");
+
+        let meta = std::fs::metadata(&host).unwrap();
+        assert_eq!(meta.modified().unwrap(), mtime);
+        assert_eq!(meta.len(), 7);
+    }
+
+    #[test]
+    fn upper_layer_has_a_capacity() {
+        let (_tempdir, lower) = lower();
+        let overlay = OverlayDir::new(lower);
+        overlay.upper().set_capacity(8);
+        let f = run(overlay.open_file(
+            false,
+            "a.txt",
+            OFlags::empty(),
+            false,
+            true,
+            FdFlags::empty(),
+        ))
+        .expect("open for writing");
+        assert!(run(f.write_vectored_at(&[std::io::IoSlice::new(b"x")], 1 << 40)).is_err());
+        assert!(run(f.set_filestat_size(9)).is_err());
+        run(f.set_filestat_size(8)).unwrap();
+    }
+
+    #[test]
+    fn removals_leave_whiteouts() {
+        let (tempdir, lower) = lower();
+        let overlay = OverlayDir::new(lower);
+
+        run(overlay.unlink_file("a.txt")).unwrap();
+        assert!(run(overlay.get_path_filestat("a.txt", false)).is_err());
+        assert!(run(overlay.remove_dir("sub")).is_err(), "sub isn't empty");
+        run(overlay.unlink_file("sub/deep/c.txt")).unwrap();
+        run(overlay.remove_dir("sub/deep")).unwrap();
+        run(overlay.unlink_file("sub/b.txt")).unwrap();
+        run(overlay.remove_dir("sub")).unwrap();
+        assert!(names(&overlay).is_empty());
+
+        // A directory made where one was removed doesn't show what was in it.
+        run(overlay.create_dir("sub")).unwrap();
+        assert!(names(&*run(overlay.open_dir(false, "sub")).unwrap()).is_empty());
+        write(&overlay, "a.txt", OFlags::CREATE, "again");
+        assert_eq!(read(&overlay, "a.txt"), "again");
+
+        assert!(tempdir.path().join("a.txt").exists());
+        assert!(tempdir.path().join("sub/deep/c.txt").exists());
+    }
+
+    #[test]
+    fn renames_and_symlinks() {
+        let (tempdir, lower) = lower();
+        let overlay = OverlayDir::new(lower);
+
+        run(overlay.rename("sub", &overlay, "moved")).unwrap();
+        assert!(run(overlay.get_path_filestat("sub", false)).is_err());
+        assert_eq!(read(&overlay, "moved/deep/c.txt"), "lower c");
+
+        run(overlay.symlink("moved/deep", "link")).unwrap();
+        assert_eq!(read(&overlay, "link/c.txt"), "lower c");
+        assert!(run(overlay.symlink("..", "escape")).is_ok());
+        assert!(run(overlay.open_dir(true, "escape")).is_err());
+
+        run(overlay.rename("a.txt", &overlay, "moved/a.txt")).unwrap();
+        assert_eq!(read(&overlay, "moved/a.txt"), "lower a");
+        assert_eq!(
+            names(&overlay),
+            ["escape", "link", "moved"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect()
+        );
+
+        assert!(tempdir.path().join("sub/b.txt").exists());
+        assert!(tempdir.path().join("a.txt").exists());
+        assert!(!tempdir.path().join("moved").exists());
+    }
+
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+        let mut f = Pin::from(Box::new(future));
+        let waker = dummy_waker();
+        let mut cx = Context::from_waker(&waker);
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(val) => return val,
+            Poll::Pending => {
+                panic!("Cannot wait on pending future: must enable wiggle \"async\" future and execute on an async Store")
+            }
+        }
+
+        fn dummy_waker() -> Waker {
+            return unsafe { Waker::from_raw(clone(5 as *const _)) };
+
+            unsafe fn clone(ptr: *const ()) -> RawWaker {
+                assert_eq!(ptr as usize, 5);
+                const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+                RawWaker::new(ptr, &VTABLE)
+            }
+
+            unsafe fn wake(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+
+            unsafe fn wake_by_ref(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+
+            unsafe fn drop(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+        }
+    }
+}
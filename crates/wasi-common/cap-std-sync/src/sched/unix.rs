@@ -31,6 +31,12 @@ pub async fn poll_oneoff<'a>(poll: &mut Poll<'a>) -> Result<(), Error> {
                 )?;
                 pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::OUT));
             }
+            Subscription::SocketRead(s) => {
+                let fd = crate::net::wasi_socket_fd(s.socket).ok_or(
+                    Error::invalid_argument().context("read subscription socket downcast failed"),
+                )?;
+                pollfds.push(PollFd::from_borrowed_fd(fd, PollFlags::IN));
+            }
             Subscription::MonotonicClock { .. } => unreachable!(),
         }
     }
@@ -58,6 +64,20 @@ pub async fn poll_oneoff<'a>(poll: &mut Poll<'a>) -> Result<(), Error> {
     if ready > 0 {
         for (rwsub, pollfd) in poll.rw_subscriptions().zip(pollfds.into_iter()) {
             let revents = pollfd.revents();
+            if let Subscription::SocketRead(sub) = rwsub {
+                // There's no count of the bytes, or the connections, which
+                // are ready on a socket.
+                if revents.contains(PollFlags::NVAL) {
+                    sub.error(Error::badf());
+                } else if revents.contains(PollFlags::ERR) {
+                    sub.error(Error::io());
+                } else if revents.contains(PollFlags::HUP) {
+                    sub.complete(1, RwEventFlags::HANGUP);
+                } else if revents.contains(PollFlags::IN) {
+                    sub.complete(1, RwEventFlags::empty());
+                }
+                continue;
+            }
             let (nbytes, rwsub) = match rwsub {
                 Subscription::Read(sub) => {
                     let ready = sub.file.num_ready_bytes().await?;
@@ -79,6 +79,10 @@ pub async fn poll_oneoff_<'a>(
                     );
                 }
             }
+            Subscription::SocketRead(_) => {
+                return Err(Error::not_supported()
+                    .context("socket subscriptions are not supported on windows"));
+            }
             Subscription::MonotonicClock { .. } => unreachable!(),
         }
     }
@@ -2,11 +2,15 @@ use crate::clocks::WasiClocks;
 use crate::dir::{DirCaps, DirEntry, WasiDir};
 use crate::file::{FileCaps, FileEntry, WasiFile};
 use crate::sched::WasiSched;
+use crate::socket::{SocketEntry, WasiNetwork, WasiSocket};
 use crate::string_array::{StringArray, StringArrayError};
 use crate::table::Table;
 use crate::Error;
 use cap_rand::RngCore;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wiggle::replay::HostcallLog;
+use wiggle::timing::HostcallRecorder;
 
 pub struct WasiCtx {
     pub args: StringArray,
@@ -15,6 +19,9 @@ pub struct WasiCtx {
     pub clocks: WasiClocks,
     pub sched: Box<dyn WasiSched>,
     pub table: Table,
+    pub network: Option<Box<dyn WasiNetwork>>,
+    pub hostcall_recorder: Option<Arc<dyn HostcallRecorder>>,
+    pub hostcall_log: Option<Arc<dyn HostcallLog>>,
 }
 
 impl WasiCtx {
@@ -31,6 +38,9 @@ impl WasiCtx {
             clocks,
             sched,
             table,
+            network: None,
+            hostcall_recorder: None,
+            hostcall_log: None,
         };
         s.set_stdin(Box::new(crate::pipe::ReadPipe::new(std::io::empty())));
         s.set_stdout(Box::new(crate::pipe::WritePipe::new(std::io::sink())));
@@ -57,6 +67,13 @@ impl WasiCtx {
         );
     }
 
+    /// Hands the guest an already open socket as `fd`, such as one
+    /// listening for connections for it to accept.
+    pub fn insert_socket(&mut self, fd: u32, socket: Box<dyn WasiSocket>) {
+        self.table()
+            .insert_at(fd, Box::new(SocketEntry::open(socket)));
+    }
+
     pub fn table(&mut self) -> &mut Table {
         &mut self.table
     }
@@ -70,6 +87,26 @@ impl WasiCtx {
         Ok(())
     }
 
+    /// Records the hostcalls made through this context with `recorder`
+    /// rather than the process-wide `wiggle::timing::recorder`. As each
+    /// `Store` has its own context, this records the WASI calls of a single
+    /// `Store`.
+    pub fn set_hostcall_recorder(&mut self, recorder: Arc<dyn HostcallRecorder>) {
+        self.hostcall_recorder = Some(recorder);
+    }
+
+    /// Records the hostcalls made through this context to `log`, or, if
+    /// `log` is a `wiggle::replay::LogReplayer`, serves them from it without
+    /// touching the host at all.
+    pub fn set_hostcall_log(&mut self, log: Arc<dyn HostcallLog>) {
+        self.hostcall_log = Some(log);
+    }
+
+    /// Lets the guest connect sockets to whatever `network` allows.
+    pub fn set_network(&mut self, network: Box<dyn WasiNetwork>) {
+        self.network = Some(network);
+    }
+
     pub fn set_stdin(&mut self, f: Box<dyn WasiFile>) {
         self.insert_file(0, f, FileCaps::all());
     }
@@ -69,6 +69,27 @@ pub enum ErrorKind {
     /// Errno::Spipe: Invalid seek
     #[error("Spipe: Invalid seek")]
     Spipe,
+    /// Errno::Notconn: The socket is not connected.
+    #[error("Notconn: The socket is not connected")]
+    Notconn,
+    /// Errno::Isdir: Is a directory.
+    #[error("Isdir: Is a directory")]
+    Isdir,
+    /// Errno::Notempty: Directory not empty.
+    #[error("Notempty: Directory not empty")]
+    Notempty,
+    /// Errno::Loop: Too many levels of symbolic links.
+    #[error("Loop: Too many levels of symbolic links")]
+    Loop,
+    /// Errno::Perm: Operation not permitted.
+    #[error("Perm: Operation not permitted")]
+    Perm,
+    /// Errno::Fbig: File too large.
+    #[error("Fbig: File too large")]
+    Fbig,
+    /// Errno::Nospc: No space left on device.
+    #[error("Nospc: No space left on device")]
+    Nospc,
     /// Errno::NotCapable: Not capable
     #[error("Not capable")]
     NotCapable,
@@ -89,6 +110,13 @@ pub trait ErrorExt {
     fn overflow() -> Self;
     fn range() -> Self;
     fn seek_pipe() -> Self;
+    fn not_connected() -> Self;
+    fn is_dir() -> Self;
+    fn not_empty() -> Self;
+    fn symlink_loop() -> Self;
+    fn perm() -> Self;
+    fn file_too_big() -> Self;
+    fn no_space() -> Self;
     fn not_capable() -> Self;
 }
 
@@ -135,6 +163,27 @@ impl ErrorExt for Error {
     fn seek_pipe() -> Self {
         ErrorKind::Spipe.into()
     }
+    fn not_connected() -> Self {
+        ErrorKind::Notconn.into()
+    }
+    fn is_dir() -> Self {
+        ErrorKind::Isdir.into()
+    }
+    fn not_empty() -> Self {
+        ErrorKind::Notempty.into()
+    }
+    fn symlink_loop() -> Self {
+        ErrorKind::Loop.into()
+    }
+    fn perm() -> Self {
+        ErrorKind::Perm.into()
+    }
+    fn file_too_big() -> Self {
+        ErrorKind::Fbig.into()
+    }
+    fn no_space() -> Self {
+        ErrorKind::Nospc.into()
+    }
     fn not_capable() -> Self {
         ErrorKind::NotCapable.into()
     }
@@ -15,20 +15,30 @@
 //! of types defined directly in the crate's source code (I decided it should
 //! NOT those generated by the `wiggle` proc macros, see snapshot architecture
 //! below), as well as the `cap_std::time` family of types.  And, importantly,
-//! `wasi-common` itself provides no implementation of `WasiDir`, and only two
-//! trivial implementations of `WasiFile` on the `crate::pipe::{ReadPipe,
-//! WritePipe}` types, which in turn just delegate to `std::io::{Read,
-//! Write}`. In order for `wasi-common` to access the local filesystem at all,
+//! `wasi-common` itself provides no implementation of `WasiDir` besides the
+//! in-memory filesystem in `crate::memfs`, and only two trivial
+//! implementations of `WasiFile` on the `crate::pipe::{ReadPipe, WritePipe}`
+//! types, which in turn just delegate to `std::io::{Read, Write}`. In order
+//! for `wasi-common` to access the local filesystem at all,
 //! you need to provide `WasiFile` and `WasiDir` impls through either the new
 //! `wasi-cap-std-sync` crate found at `crates/wasi-common/cap-std-sync` - see
 //! the section on that crate below - or by providing your own implementation
 //! from elsewhere.
 //!
+//! Sockets are a third resource type, `Box<dyn WasiSocket>`, which the
+//! `sock_*` functions, as well as `fd_read`, `fd_write` and `fd_close`,
+//! operate on. A socket is only connected through the `WasiNetwork` given to
+//! `WasiCtx::set_network`, which decides the addresses a guest may reach;
+//! without one, no socket connects to anything. Listening sockets are handed
+//! to the guest already open, with `WasiCtx::insert_socket`.
+//!
 //! This design makes it possible for `wasi-common` embedders to statically
 //! reason about access to the local filesystem by examining what impls are
 //! linked into an application. We found that this separation of concerns also
-//! makes it pretty enjoyable to write alternative implementations, e.g. a
-//! virtual filesystem (which will land in a future PR).
+//! makes it pretty enjoyable to write alternative implementations, e.g. the
+//! virtual filesystem in `crate::memfs`, which keeps a tree of files entirely
+//! in memory and can be seeded from, and exported back out to, a tar archive
+//! or a host directory.
 //!
 //! ## Traits for the rest of WASI's features
 //!
@@ -55,10 +65,12 @@ mod ctx;
 pub mod dir;
 mod error;
 pub mod file;
+pub mod memfs;
 pub mod pipe;
 pub mod random;
 pub mod sched;
 pub mod snapshots;
+pub mod socket;
 mod string_array;
 pub mod table;
 
@@ -69,5 +81,8 @@ pub use dir::WasiDir;
 pub use error::{Context, Error, ErrorExt, ErrorKind};
 pub use file::WasiFile;
 pub use sched::{Poll, WasiSched};
+pub use socket::{WasiNetwork, WasiSocket};
 pub use string_array::StringArrayError;
 pub use table::Table;
+pub use wiggle::replay::HostcallLog;
+pub use wiggle::timing::HostcallRecorder;
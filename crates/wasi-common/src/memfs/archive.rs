@@ -0,0 +1,427 @@
+//! Moving the contents of a `MemFs` in from, and back out to, tar archives and
+//! host directories.
+
+use super::{components, DirNode, Fs, Kind, MemFs, Target, ROOT};
+use crate::{Error, ErrorExt};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+impl MemFs {
+    /// Creates a filesystem holding the contents of a tar archive.
+    ///
+    /// Regular files, directories, symlinks and hard links are unpacked along
+    /// with their modification times, and any other kind of entry is skipped.
+    /// As with `tar -x`, a later entry for the same path replaces an earlier
+    /// one. Entries with absolute paths, or paths which leave the archive
+    /// with `..`, are rejected.
+    pub fn from_tar(archive: impl Read) -> Result<MemFs, Error> {
+        let fs = MemFs::new();
+        fs.lock().unpack_tar(archive)?;
+        Ok(fs)
+    }
+
+    /// Creates a filesystem holding a copy of a host directory.
+    ///
+    /// Regular files, directories and symlinks are copied along with their
+    /// access and modification times, and anything else is skipped. Files
+    /// which are hard links to each other on the host are copied separately.
+    pub fn from_host_dir(dir: &cap_std::fs::Dir) -> Result<MemFs, Error> {
+        let fs = MemFs::new();
+        fs.lock().copy_host_dir(ROOT, dir)?;
+        Ok(fs)
+    }
+
+    /// Writes the contents of the filesystem out as a tar archive, in which
+    /// files that are hard links to each other are stored once.
+    pub fn write_tar(&self, out: impl Write) -> Result<(), Error> {
+        let fs = self.lock();
+        let mut builder = tar::Builder::new(out);
+        fs.archive_dir(ROOT, "", &mut builder, &mut HashMap::new())?;
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    /// Copies the contents of the filesystem into a host directory, replacing
+    /// files, and merging into directories, which are already there.
+    ///
+    /// Hard links are kept, but timestamps are those of the copy.
+    pub fn export_to_host_dir(&self, dir: &cap_std::fs::Dir) -> Result<(), Error> {
+        let fs = self.lock();
+        fs.export_dir(ROOT, "", dir, dir, &mut HashMap::new())
+    }
+}
+
+impl Fs {
+    fn unpack_tar(&mut self, archive: impl Read) -> Result<(), Error> {
+        let mut archive = tar::Archive::new(archive);
+        // Directory times are set last, so that unpacking their contents
+        // doesn't change them.
+        let mut dir_times = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = archive_path(&entry.path_bytes())?;
+            let (parent, name) = match path.rsplit_once('/') {
+                Some((parent, name)) => (parent, name),
+                None if path.is_empty() => continue,
+                None => ("", path.as_str()),
+            };
+            let dir = self.create_dir_all(ROOT, parent)?;
+            let target = Target {
+                dir,
+                name: Some(name.to_owned()),
+                trailing_slash: false,
+            };
+            let mtime = UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?);
+            let ino = match entry.header().entry_type() {
+                tar::EntryType::Directory => match self.target_ino(&target)? {
+                    Some(ino) if self.node(ino).is_dir() => ino,
+                    _ => {
+                        self.make_way(&target)?;
+                        self.create(&target, Kind::Dir(DirNode::new(dir)))?
+                    }
+                },
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data)?;
+                    self.make_way(&target)?;
+                    self.create(&target, Kind::File(data))?
+                }
+                tar::EntryType::Symlink => {
+                    let link = entry.link_name_bytes().unwrap_or_default();
+                    let link = std::str::from_utf8(&link)?.to_owned();
+                    self.make_way(&target)?;
+                    self.create(&target, Kind::Symlink(link))?
+                }
+                tar::EntryType::Link => {
+                    let link = entry.link_name_bytes().unwrap_or_default();
+                    let src = self.lookup(ROOT, &archive_path(&link)?, false)?;
+                    if self.node(src).is_dir() {
+                        return Err(
+                            Error::perm().context(format!("{}: hard link to a directory", path))
+                        );
+                    }
+                    // A link to the entry it replaces is already in place,
+                    // and making way for it would free what it links to.
+                    if self.entry(dir, name) != Some(src) {
+                        self.make_way(&target)?;
+                        let now = self.now();
+                        self.link(dir, name.to_owned(), src, now);
+                    }
+                    src
+                }
+                _ => continue,
+            };
+            if self.node(ino).is_dir() {
+                dir_times.push((ino, mtime));
+            } else {
+                let node = self.node_mut(ino);
+                node.atim = mtime;
+                node.mtim = mtime;
+            }
+        }
+        for (ino, mtime) in dir_times {
+            let node = self.node_mut(ino);
+            node.atim = mtime;
+            node.mtim = mtime;
+        }
+        Ok(())
+    }
+
+    /// Removes whatever is at `target`, so that an archive entry can take its
+    /// place.
+    fn make_way(&mut self, target: &Target) -> Result<(), Error> {
+        let name = target.name.as_deref().expect("archive entries are named");
+        if let Some(ino) = self.entry(target.dir, name) {
+            if let Kind::Dir(dir) = &self.node(ino).kind {
+                if !dir.entries.is_empty() {
+                    return Err(Error::not_empty().context(name.to_owned()));
+                }
+            }
+            let now = self.now();
+            self.unlink(target.dir, name, now);
+        }
+        Ok(())
+    }
+
+    fn archive_dir<W: Write>(
+        &self,
+        dir: u64,
+        prefix: &str,
+        builder: &mut tar::Builder<W>,
+        archived: &mut HashMap<u64, String>,
+    ) -> Result<(), Error> {
+        for (name, entry) in self.dir(dir)?.entries.iter() {
+            let path = format!("{}{}", prefix, name);
+            let node = self.node(entry.ino);
+            let mut header = tar::Header::new_gnu();
+            let mtime = node
+                .mtim
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            header.set_mtime(mtime);
+            header.set_size(0);
+            match &node.kind {
+                Kind::Dir(_) => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(0o755);
+                    builder.append_data(&mut header, format!("{}/", path), io::empty())?;
+                    self.archive_dir(entry.ino, &format!("{}/", path), builder, archived)?;
+                }
+                Kind::File(_) if archived.contains_key(&entry.ino) => {
+                    header.set_entry_type(tar::EntryType::Link);
+                    header.set_mode(0o644);
+                    builder.append_link(&mut header, &path, &archived[&entry.ino])?;
+                }
+                Kind::File(data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_mode(0o644);
+                    header.set_size(data.len() as u64);
+                    builder.append_data(&mut header, &path, &data[..])?;
+                    if node.nlink > 1 {
+                        archived.insert(entry.ino, path);
+                    }
+                }
+                Kind::Symlink(target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_mode(0o777);
+                    builder.append_link(&mut header, &path, target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_host_dir(&mut self, dir: u64, host: &cap_std::fs::Dir) -> Result<(), Error> {
+        for entry in host.entries()? {
+            let entry = entry?;
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| Error::illegal_byte_sequence().context("filename"))?;
+            let target = Target {
+                dir,
+                name: Some(name.clone()),
+                trailing_slash: false,
+            };
+            let file_type = entry.file_type()?;
+            let ino = if file_type.is_dir() {
+                let ino = self.create(&target, Kind::Dir(DirNode::new(dir)))?;
+                self.copy_host_dir(ino, &entry.open_dir()?)?;
+                ino
+            } else if file_type.is_file() {
+                let mut data = Vec::new();
+                entry.open()?.read_to_end(&mut data)?;
+                self.create(&target, Kind::File(data))?
+            } else if file_type.is_symlink() {
+                let link = host
+                    .read_link(&name)?
+                    .into_os_string()
+                    .into_string()
+                    .map_err(|_| Error::illegal_byte_sequence().context("symlink target"))?;
+                self.create(&target, Kind::Symlink(link))?
+            } else {
+                continue;
+            };
+            let meta = entry.metadata()?;
+            let node = self.node_mut(ino);
+            if let Ok(atime) = meta.accessed() {
+                node.atim = atime.into_std();
+            }
+            if let Ok(mtime) = meta.modified() {
+                node.mtim = mtime.into_std();
+            }
+        }
+        Ok(())
+    }
+
+    fn export_dir(
+        &self,
+        dir: u64,
+        prefix: &str,
+        host: &cap_std::fs::Dir,
+        host_root: &cap_std::fs::Dir,
+        exported: &mut HashMap<u64, String>,
+    ) -> Result<(), Error> {
+        for (name, entry) in self.dir(dir)?.entries.iter() {
+            let path = format!("{}{}", prefix, name);
+            match &self.node(entry.ino).kind {
+                Kind::Dir(_) => {
+                    match host.create_dir(name) {
+                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                        result => result?,
+                    }
+                    let sub = host.open_dir(name)?;
+                    self.export_dir(entry.ino, &format!("{}/", path), &sub, host_root, exported)?;
+                }
+                Kind::File(_) if exported.contains_key(&entry.ino) => {
+                    remove_host_file(host, name)?;
+                    host_root.hard_link(&exported[&entry.ino], host_root, &path)?;
+                }
+                Kind::File(data) => {
+                    // Writing over what's there would write through a host
+                    // symlink or into every host hard link of it.
+                    remove_host_file(host, name)?;
+                    host.write(name, data)?;
+                    exported.insert(entry.ino, path);
+                }
+                Kind::Symlink(target) => {
+                    remove_host_file(host, name)?;
+                    #[cfg(not(windows))]
+                    host.symlink(target, name)?;
+                    #[cfg(windows)]
+                    host.symlink_file(target, name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn remove_host_file(host: &cap_std::fs::Dir, name: &str) -> Result<(), Error> {
+    match host.remove_file(name) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// The path of an archive entry, relative to the root of the filesystem.
+fn archive_path(path: &[u8]) -> Result<String, Error> {
+    let path = std::str::from_utf8(path)?;
+    let names = components(path)?;
+    if names.iter().any(|name| name == "..") {
+        return Err(Error::perm().context(format!("{}: path leads outside of the archive", path)));
+    }
+    Ok(names.into_iter().collect::<Vec<_>>().join("/"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn populated() -> MemFs {
+        let fs = MemFs::new();
+        fs.create_dir_all("dir/empty").unwrap();
+        fs.write_file("dir/file", "contents").unwrap();
+        fs.hard_link("dir/file", "link").unwrap();
+        fs.symlink("dir/file", "symlink").unwrap();
+        let long = "a-rather-long-name-".repeat(8);
+        fs.write_file(&long, "long").unwrap();
+        fs
+    }
+
+    fn check(fs: &MemFs) {
+        let long = "a-rather-long-name-".repeat(8);
+        assert_eq!(fs.read_file("dir/file").unwrap(), b"contents");
+        assert_eq!(fs.read_file("symlink").unwrap(), b"contents");
+        assert_eq!(fs.read_file(&long).unwrap(), b"long");
+        let lock = fs.lock();
+        let file = lock.lookup(ROOT, "dir/file", false).unwrap();
+        assert_eq!(lock.lookup(ROOT, "link", false).unwrap(), file);
+        assert!(lock
+            .dir(lock.lookup(ROOT, "dir/empty", false).unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    fn tar_round_trip() {
+        let fs = populated();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        {
+            let mut lock = fs.lock();
+            let ino = lock.lookup(ROOT, "dir", false).unwrap();
+            lock.node_mut(ino).mtim = mtime;
+        }
+        let mut archive = Vec::new();
+        fs.write_tar(&mut archive).unwrap();
+
+        let copy = MemFs::from_tar(&archive[..]).unwrap();
+        check(&copy);
+        let lock = copy.lock();
+        let ino = lock.lookup(ROOT, "dir", false).unwrap();
+        assert_eq!(lock.node(ino).mtim, mtime);
+    }
+
+    #[test]
+    fn tar_paths_stay_inside_the_archive() {
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        // `Builder` refuses to write a path like this one, so it's put in
+        // the header directly.
+        header.as_old_mut().name[..9].copy_from_slice(b"a/../../b");
+        header.set_cksum();
+        archive.append(&header, io::empty()).unwrap();
+        let archive = archive.into_inner().unwrap();
+        let err = MemFs::from_tar(&archive[..]).err().unwrap();
+        assert!(matches!(err.downcast_ref(), Some(crate::ErrorKind::Perm)));
+    }
+
+    #[test]
+    fn tar_hard_link_to_itself() {
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_entry_type(tar::EntryType::Regular);
+        archive.append_data(&mut header, "a", &b"data"[..]).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Link);
+        archive.append_link(&mut header, "a", "a").unwrap();
+        let archive = archive.into_inner().unwrap();
+
+        let fs = MemFs::from_tar(&archive[..]).unwrap();
+        assert_eq!(fs.read_file("a").unwrap(), b"data");
+        let lock = fs.lock();
+        let ino = lock.lookup(ROOT, "a", false).unwrap();
+        assert_eq!(lock.node(ino).nlink, 1);
+    }
+
+    // Creating symlinks on Windows needs privileges tests don't have.
+    #[cfg(not(windows))]
+    #[test]
+    fn export_replaces_host_links() {
+        let tempdir = tempfile::Builder::new()
+            .prefix("memfs")
+            .tempdir()
+            .expect("create temporary dir");
+        let host = cap_std::fs::Dir::open_ambient_dir(tempdir.path(), cap_std::ambient_authority())
+            .expect("open ambient temporary dir");
+        host.write("target", "target").unwrap();
+        host.write("linked", "linked").unwrap();
+        host.symlink("target", "symlink").unwrap();
+        host.hard_link("linked", &host, "hard_link").unwrap();
+
+        let fs = MemFs::new();
+        fs.write_file("symlink", "new").unwrap();
+        fs.write_file("hard_link", "new").unwrap();
+        fs.export_to_host_dir(&host).unwrap();
+        assert_eq!(host.read("symlink").unwrap(), b"new");
+        assert_eq!(host.read("hard_link").unwrap(), b"new");
+        assert_eq!(host.read("target").unwrap(), b"target");
+        assert_eq!(host.read("linked").unwrap(), b"linked");
+    }
+
+    // Creating symlinks on Windows needs privileges tests don't have.
+    #[cfg(not(windows))]
+    #[test]
+    fn host_dir_round_trip() {
+        let tempdir = tempfile::Builder::new()
+            .prefix("memfs")
+            .tempdir()
+            .expect("create temporary dir");
+        let host = cap_std::fs::Dir::open_ambient_dir(tempdir.path(), cap_std::ambient_authority())
+            .expect("open ambient temporary dir");
+        populated().export_to_host_dir(&host).unwrap();
+        assert_eq!(host.read("link").unwrap(), b"contents");
+        // Exporting again replaces what's there.
+        populated().export_to_host_dir(&host).unwrap();
+
+        let copy = MemFs::from_host_dir(&host).unwrap();
+        assert_eq!(copy.read_file("link").unwrap(), b"contents");
+        let lock = copy.lock();
+        let ino = lock.lookup(ROOT, "symlink", false).unwrap();
+        assert!(matches!(&lock.node(ino).kind, Kind::Symlink(t) if t == "dir/file"));
+    }
+}
@@ -0,0 +1,249 @@
+use super::{DirNode, Kind, MemFile, MemFs};
+use crate::dir::{ReaddirCursor, ReaddirEntity, WasiDir};
+use crate::file::{FdFlags, FileType, Filestat, OFlags, WasiFile};
+use crate::{Error, ErrorExt, SystemTimeSpec};
+use std::any::Any;
+use std::path::PathBuf;
+
+/// A directory of a `MemFs`.
+pub struct MemDir {
+    fs: MemFs,
+    ino: u64,
+}
+
+impl MemDir {
+    pub(super) fn open(fs: &MemFs, ino: u64) -> MemDir {
+        fs.lock().open(ino);
+        MemDir {
+            fs: fs.clone(),
+            ino,
+        }
+    }
+
+    /// The filesystem this directory is in.
+    pub fn fs(&self) -> &MemFs {
+        &self.fs
+    }
+
+    /// Finds the `MemDir` another directory of the same filesystem is.
+    fn same_fs<'a>(&self, other: &'a dyn WasiDir) -> Result<&'a MemDir, Error> {
+        match other.as_any().downcast_ref::<MemDir>() {
+            Some(other) if self.fs.same_fs(&other.fs) => Ok(other),
+            Some(_) => Err(Error::badf().context("directory of another memfs")),
+            None => Err(Error::badf().context("not a memfs directory")),
+        }
+    }
+}
+
+impl Drop for MemDir {
+    fn drop(&mut self) {
+        self.fs.lock().close(self.ino);
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiDir for MemDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let mut fs = self.fs.lock();
+        let target = fs.resolve(self.ino, path, symlink_follow || path.ends_with('/'))?;
+        let ino = match fs.target_ino(&target)? {
+            Some(_) if oflags.contains(OFlags::CREATE | OFlags::EXCLUSIVE) => {
+                return Err(Error::exist())
+            }
+            Some(ino) => {
+                let truncate = oflags.contains(OFlags::TRUNCATE);
+                match &fs.node(ino).kind {
+                    Kind::Symlink(_) => return Err(Error::symlink_loop()),
+                    Kind::Dir(_) if write || truncate => return Err(Error::is_dir()),
+                    Kind::File(_) if truncate => {
+                        let now = fs.now();
+                        fs.resize(ino, 0)?;
+                        let node = fs.node_mut(ino);
+                        node.mtim = now;
+                        node.ctim = now;
+                    }
+                    _ => {}
+                }
+                ino
+            }
+            None if oflags.contains(OFlags::CREATE) => {
+                if target.trailing_slash {
+                    return Err(Error::is_dir());
+                }
+                fs.create(&target, Kind::File(Vec::new()))?
+            }
+            None => return Err(Error::not_found()),
+        };
+        drop(fs);
+        Ok(Box::new(MemFile::open(&self.fs, ino, read, write, fdflags)))
+    }
+
+    async fn open_dir(&self, symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        let ino = {
+            let fs = self.fs.lock();
+            let ino = fs.lookup(self.ino, path, symlink_follow)?;
+            match fs.node(ino).kind {
+                Kind::Dir(_) => ino,
+                Kind::Symlink(_) => return Err(Error::symlink_loop()),
+                Kind::File(_) => return Err(Error::not_dir()),
+            }
+        };
+        Ok(Box::new(MemDir::open(&self.fs, ino)))
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<(), Error> {
+        let mut fs = self.fs.lock();
+        let target = fs.resolve(self.ino, path, false)?;
+        fs.create(&target, Kind::Dir(DirNode::new(target.dir)))?;
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let fs = self.fs.lock();
+        let dir = fs.dir(self.ino)?;
+        let dots = vec![
+            (0, self.ino, ".".to_owned(), FileType::Directory),
+            (1, dir.parent, "..".to_owned(), FileType::Directory),
+        ];
+        let mut entries = dir
+            .entries
+            .iter()
+            .map(|(name, e)| (e.cookie, e.ino, name.clone(), fs.node(e.ino).filetype()))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(cookie, ..)| *cookie);
+        let cursor = u64::from(cursor);
+        let entities = dots
+            .into_iter()
+            .chain(entries)
+            .filter(|(cookie, ..)| *cookie >= cursor)
+            .map(|(cookie, inode, name, filetype)| {
+                Ok(ReaddirEntity {
+                    next: ReaddirCursor::from(cookie + 1),
+                    inode,
+                    name,
+                    filetype,
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(entities.into_iter()))
+    }
+
+    async fn symlink(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
+        let mut fs = self.fs.lock();
+        let target = fs.resolve(self.ino, new_path, false)?;
+        fs.create(&target, Kind::Symlink(old_path.to_owned()))?;
+        Ok(())
+    }
+
+    async fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        let mut fs = self.fs.lock();
+        let target = fs.resolve(self.ino, path, false)?;
+        let name = match &target.name {
+            Some(name) => name,
+            None => return Err(Error::invalid_argument().context("removing `.` or `..`")),
+        };
+        let ino = fs.entry(target.dir, name).ok_or_else(Error::not_found)?;
+        match &fs.node(ino).kind {
+            Kind::Dir(dir) if !dir.entries.is_empty() => return Err(Error::not_empty()),
+            Kind::Dir(_) => {}
+            _ => return Err(Error::not_dir()),
+        }
+        let now = fs.now();
+        fs.unlink(target.dir, name, now);
+        Ok(())
+    }
+
+    async fn unlink_file(&self, path: &str) -> Result<(), Error> {
+        let mut fs = self.fs.lock();
+        let target = fs.resolve(self.ino, path, false)?;
+        let name = match &target.name {
+            Some(name) => name,
+            None => return Err(Error::is_dir()),
+        };
+        let ino = fs.entry(target.dir, name).ok_or_else(Error::not_found)?;
+        if fs.node(ino).is_dir() {
+            return Err(Error::is_dir());
+        }
+        if target.trailing_slash {
+            return Err(Error::not_dir());
+        }
+        let now = fs.now();
+        fs.unlink(target.dir, name, now);
+        Ok(())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        let fs = self.fs.lock();
+        let ino = fs.lookup(self.ino, path, false)?;
+        match &fs.node(ino).kind {
+            Kind::Symlink(target) => Ok(PathBuf::from(target)),
+            _ => Err(Error::invalid_argument().context("not a symlink")),
+        }
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(self.fs.lock().filestat(self.ino))
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        let fs = self.fs.lock();
+        let ino = fs.lookup(self.ino, path, follow_symlinks)?;
+        Ok(fs.filestat(ino))
+    }
+
+    async fn rename(
+        &self,
+        src_path: &str,
+        dest_dir: &dyn WasiDir,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        let dest_dir = self.same_fs(dest_dir)?;
+        self.fs
+            .lock()
+            .rename(self.ino, src_path, dest_dir.ino, dest_path)
+    }
+
+    async fn hard_link(
+        &self,
+        src_path: &str,
+        target_dir: &dyn WasiDir,
+        target_path: &str,
+    ) -> Result<(), Error> {
+        let target_dir = self.same_fs(target_dir)?;
+        self.fs
+            .lock()
+            .hard_link(self.ino, src_path, target_dir.ino, target_path)
+    }
+
+    async fn set_times(
+        &self,
+        path: &str,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+        follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        let mut fs = self.fs.lock();
+        let ino = fs.lookup(self.ino, path, follow_symlinks)?;
+        fs.set_times(ino, atime, mtime);
+        Ok(())
+    }
+}
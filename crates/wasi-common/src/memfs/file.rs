@@ -0,0 +1,241 @@
+use super::{Kind, MemFs};
+use crate::file::{Advice, FdFlags, FileType, Filestat, WasiFile};
+use crate::{Error, ErrorExt, SystemTimeSpec};
+use std::any::Any;
+use std::convert::TryFrom;
+use std::io::{self, SeekFrom};
+use std::sync::Mutex;
+
+/// An open file of a `MemFs`.
+///
+/// Like a host file opened through `cap-std`, a `MemFile` opened for neither
+/// reading nor writing can still be read.
+pub struct MemFile {
+    fs: MemFs,
+    ino: u64,
+    position: Mutex<u64>,
+    readable: bool,
+    writable: bool,
+    flags: FdFlags,
+}
+
+impl MemFile {
+    pub(super) fn open(fs: &MemFs, ino: u64, read: bool, write: bool, flags: FdFlags) -> MemFile {
+        fs.lock().open(ino);
+        MemFile {
+            fs: fs.clone(),
+            ino,
+            position: Mutex::new(0),
+            readable: read || !write,
+            writable: write,
+            flags,
+        }
+    }
+
+    /// The filesystem this file is in.
+    pub fn fs(&self) -> &MemFs {
+        &self.fs
+    }
+
+    /// Reads from the file at `offset` into `bufs`, returning how much was
+    /// read.
+    fn read_at(&self, bufs: &mut [io::IoSliceMut<'_>], offset: u64) -> Result<u64, Error> {
+        if !self.readable {
+            return Err(Error::badf());
+        }
+        let mut fs = self.fs.lock();
+        let now = fs.now();
+        let node = fs.node_mut(self.ino);
+        let data = match &node.kind {
+            Kind::File(data) => data,
+            _ => return Err(Error::is_dir()),
+        };
+        let mut pos = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let start = pos;
+        for buf in bufs {
+            let n = buf.len().min(data.len() - pos);
+            buf[..n].copy_from_slice(&data[pos..pos + n]);
+            pos += n;
+        }
+        node.atim = now;
+        Ok((pos - start) as u64)
+    }
+
+    /// Writes `bufs` to the file at `offset`, or at its end if `offset` is
+    /// `None`, returning how much was written and where it ended.
+    fn write_at(&self, bufs: &[io::IoSlice<'_>], offset: Option<u64>) -> Result<(u64, u64), Error> {
+        if !self.writable {
+            return Err(Error::badf());
+        }
+        let mut fs = self.fs.lock();
+        let now = fs.now();
+        let size = match &fs.node(self.ino).kind {
+            Kind::File(data) => data.len(),
+            _ => return Err(Error::is_dir()),
+        };
+        let start = match offset {
+            Some(offset) => usize::try_from(offset).map_err(|_| Error::file_too_big())?,
+            None => size,
+        };
+        let len = bufs.iter().map(|b| b.len()).sum::<usize>();
+        let end = start.checked_add(len).ok_or_else(Error::file_too_big)?;
+        if end > size {
+            fs.resize(self.ino, end)?;
+        }
+        let node = fs.node_mut(self.ino);
+        let data = match &mut node.kind {
+            Kind::File(data) => data,
+            _ => unreachable!("checked to be a file"),
+        };
+        let mut pos = start;
+        for buf in bufs {
+            data[pos..pos + buf.len()].copy_from_slice(buf);
+            pos += buf.len();
+        }
+        node.mtim = now;
+        node.ctim = now;
+        Ok((len as u64, end as u64))
+    }
+
+    fn resize(&self, size: u64) -> Result<(), Error> {
+        if !self.writable {
+            return Err(Error::badf());
+        }
+        let size = usize::try_from(size).map_err(|_| Error::file_too_big())?;
+        let mut fs = self.fs.lock();
+        let now = fs.now();
+        fs.resize(self.ino, size)?;
+        let node = fs.node_mut(self.ino);
+        node.mtim = now;
+        node.ctim = now;
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        match &self.fs.lock().node(self.ino).kind {
+            Kind::File(data) => data.len() as u64,
+            _ => 0,
+        }
+    }
+}
+
+impl Drop for MemFile {
+    fn drop(&mut self) {
+        self.fs.lock().close(self.ino);
+    }
+}
+
+#[wiggle::async_trait]
+impl WasiFile for MemFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    async fn datasync(&self) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn sync(&self) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn get_filetype(&self) -> Result<FileType, Error> {
+        Ok(self.fs.lock().node(self.ino).filetype())
+    }
+    async fn get_fdflags(&self) -> Result<FdFlags, Error> {
+        Ok(self.flags)
+    }
+    async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        // Everything is synchronized with memory as soon as it's written, so
+        // the sync family of flags has nothing left to do.
+        self.flags = flags;
+        Ok(())
+    }
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(self.fs.lock().filestat(self.ino))
+    }
+    async fn set_filestat_size(&self, size: u64) -> Result<(), Error> {
+        self.resize(size)
+    }
+    async fn advise(&self, _offset: u64, _len: u64, _advice: Advice) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn allocate(&self, offset: u64, len: u64) -> Result<(), Error> {
+        let end = offset.checked_add(len).ok_or_else(Error::file_too_big)?;
+        if end > self.len() {
+            self.resize(end)?;
+        }
+        Ok(())
+    }
+    async fn set_times(
+        &self,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) -> Result<(), Error> {
+        self.fs.lock().set_times(self.ino, atime, mtime);
+        Ok(())
+    }
+    async fn read_vectored<'a>(&self, bufs: &mut [io::IoSliceMut<'a>]) -> Result<u64, Error> {
+        let mut position = self.position.lock().unwrap();
+        let n = self.read_at(bufs, *position)?;
+        *position += n;
+        Ok(n)
+    }
+    async fn read_vectored_at<'a>(
+        &self,
+        bufs: &mut [io::IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.read_at(bufs, offset)
+    }
+    async fn write_vectored<'a>(&self, bufs: &[io::IoSlice<'a>]) -> Result<u64, Error> {
+        let mut position = self.position.lock().unwrap();
+        let offset = if self.flags.contains(FdFlags::APPEND) {
+            None
+        } else {
+            Some(*position)
+        };
+        let (n, end) = self.write_at(bufs, offset)?;
+        *position = end;
+        Ok(n)
+    }
+    async fn write_vectored_at<'a>(
+        &self,
+        bufs: &[io::IoSlice<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        let (n, _) = self.write_at(bufs, Some(offset))?;
+        Ok(n)
+    }
+    async fn seek(&self, pos: SeekFrom) -> Result<u64, Error> {
+        let mut position = self.position.lock().unwrap();
+        let new = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => offset_by(*position, delta),
+            SeekFrom::End(delta) => offset_by(self.len(), delta),
+        };
+        *position = new.ok_or_else(|| Error::invalid_argument().context("seek out of range"))?;
+        Ok(*position)
+    }
+    async fn peek(&self, buf: &mut [u8]) -> Result<u64, Error> {
+        let position = *self.position.lock().unwrap();
+        self.read_at(&mut [io::IoSliceMut::new(buf)], position)
+    }
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        let position = *self.position.lock().unwrap();
+        Ok(self.len().saturating_sub(position))
+    }
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn offset_by(base: u64, delta: i64) -> Option<u64> {
+    if delta < 0 {
+        base.checked_sub(delta.unsigned_abs())
+    } else {
+        base.checked_add(delta as u64)
+    }
+}
@@ -0,0 +1,1002 @@
+//! An in-memory filesystem.
+//!
+//! A `MemFs` is a tree of files, directories and symlinks held entirely in
+//! host memory. Its root is a `WasiDir`, so it can be given to a guest with
+//! `WasiCtx::push_preopened_dir` in place of a host directory, and it can be
+//! seeded from, and exported back out to, a tar archive or a host directory:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), wasi_common::Error> {
+//! use wasi_common::memfs::MemFs;
+//! # let mut ctx: wasi_common::WasiCtx = todo!();
+//! let fs = MemFs::from_tar(std::fs::File::open("input.tar")?)?;
+//! ctx.push_preopened_dir(Box::new(fs.root()), "/data")?;
+//! // ... run the guest, then keep what it left behind:
+//! fs.write_tar(std::fs::File::create("output.tar")?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Paths are resolved the way `cap-std` resolves them in a host directory:
+//! neither a path nor any symlink it passes through may be absolute, or use
+//! `..` to leave the directory the path is resolved in.
+//!
+//! As on POSIX, a file or directory which is removed while it is open lives
+//! on until its last `MemFile` or `MemDir` is dropped. The cookies `readdir`
+//! hands out are stable: each entry gets the next cookie of its directory when
+//! it is added, so entries added or removed between calls never cause others
+//! to be skipped or listed twice.
+//!
+//! Nothing limits how much a `MemFs` holds unless it is given a capacity with
+//! `MemFs::set_capacity`, which a filesystem written to by an untrusted guest
+//! should have.
+//!
+//! Files of a `MemFs` are always ready for reading and writing, but the
+//! `wasi-cap-std-sync` scheduler can only wait on host files, so they can't
+//! be subscribed to in `poll_oneoff` there.
+
+mod archive;
+mod dir;
+mod file;
+
+pub use self::dir::MemDir;
+pub use self::file::MemFile;
+
+use crate::clocks::WasiSystemClock;
+use crate::file::{FileType, Filestat};
+use crate::{Error, ErrorExt, SystemTimeSpec};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// The inode of the root directory.
+const ROOT: u64 = 1;
+
+/// The most symlinks followed while resolving a single path, as on Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// The longest name a directory entry may have, as on Linux.
+const MAX_NAME: usize = 255;
+
+/// Each `MemFs` gets its own device id, so that inodes are only compared
+/// within one filesystem.
+static NEXT_DEVICE: AtomicU64 = AtomicU64::new(1);
+
+/// An in-memory filesystem.
+///
+/// Cloning a `MemFs` gives another handle to the same filesystem, so an
+/// embedder can keep one to look at, or export, what a guest wrote to the
+/// filesystem after the guest is done with it.
+#[derive(Clone)]
+pub struct MemFs(Arc<Mutex<Fs>>);
+
+impl MemFs {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        MemFs(Arc::new(Mutex::new(Fs::new())))
+    }
+
+    /// Takes the timestamps of changes to the filesystem from `clock`,
+    /// rather than from the host's clock.
+    pub fn set_clock(&self, clock: Box<dyn WasiSystemClock>) {
+        self.lock().clock = Some(clock);
+    }
+
+    /// Limits the contents of all the files in the filesystem to `bytes`
+    /// between them. Writes and resizes which would take a file past it fail
+    /// with `EFBIG`, and those which would take the filesystem past it fail
+    /// with `ENOSPC`. Files already over the limit can still shrink.
+    pub fn set_capacity(&self, bytes: u64) {
+        self.lock().capacity = bytes;
+    }
+
+    /// Opens the root directory of the filesystem, for preopening it to a
+    /// guest.
+    pub fn root(&self) -> MemDir {
+        MemDir::open(self, ROOT)
+    }
+
+    /// Creates a directory at `path`, along with any of its parents which
+    /// don't exist yet.
+    pub fn create_dir_all(&self, path: &str) -> Result<(), Error> {
+        self.lock().create_dir_all(ROOT, path).map(|_| ())
+    }
+
+    /// Writes `contents` to the file at `path`, creating it if it doesn't
+    /// exist and replacing its contents if it does. The file's parent
+    /// directory must already exist.
+    pub fn write_file(&self, path: &str, contents: impl Into<Vec<u8>>) -> Result<(), Error> {
+        self.lock()
+            .write_file(ROOT, path, contents.into())
+            .map(|_| ())
+    }
+
+    /// Reads the contents of the file at `path`.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let fs = self.lock();
+        let ino = fs.lookup(ROOT, path, true)?;
+        match &fs.node(ino).kind {
+            Kind::File(data) => Ok(data.clone()),
+            _ => Err(Error::is_dir()),
+        }
+    }
+
+    /// Creates a symlink at `path` which points to `target`.
+    pub fn symlink(&self, target: &str, path: &str) -> Result<(), Error> {
+        let mut fs = self.lock();
+        let at = fs.resolve(ROOT, path, false)?;
+        fs.create(&at, Kind::Symlink(target.to_owned())).map(|_| ())
+    }
+
+    /// Creates a hard link at `path` to the file at `src`.
+    pub fn hard_link(&self, src: &str, path: &str) -> Result<(), Error> {
+        self.lock().hard_link(ROOT, src, ROOT, path)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Fs> {
+        self.0.lock().unwrap()
+    }
+
+    fn same_fs(&self, other: &MemFs) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Default for MemFs {
+    fn default() -> Self {
+        MemFs::new()
+    }
+}
+
+struct Fs {
+    device_id: u64,
+    nodes: HashMap<u64, Node>,
+    next_inode: u64,
+    clock: Option<Box<dyn WasiSystemClock>>,
+    /// The most bytes the files may hold between them.
+    capacity: u64,
+    /// The bytes the files hold between them, including removed files which
+    /// are still open.
+    used: u64,
+}
+
+struct Node {
+    kind: Kind,
+    /// The number of directory entries naming this node, which is zero once
+    /// it has been removed.
+    nlink: u64,
+    /// The number of open `MemFile`s and `MemDir`s of this node, which keep
+    /// it alive after it is removed.
+    handles: u64,
+    atim: SystemTime,
+    mtim: SystemTime,
+    ctim: SystemTime,
+}
+
+enum Kind {
+    File(Vec<u8>),
+    Dir(DirNode),
+    Symlink(String),
+}
+
+struct DirNode {
+    parent: u64,
+    entries: BTreeMap<String, Entry>,
+    /// The readdir cookie of the next entry added. Cookies 0 and 1 are `.`
+    /// and `..`.
+    next_cookie: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    ino: u64,
+    cookie: u64,
+}
+
+/// Where a path leads: to the entry `name` of the directory `dir`, which may
+/// not exist yet, or to `dir` itself when `name` is `None`.
+struct Target {
+    dir: u64,
+    name: Option<String>,
+    /// The path ended in a slash, so it must name a directory.
+    trailing_slash: bool,
+}
+
+impl Node {
+    fn new(kind: Kind, now: SystemTime) -> Node {
+        Node {
+            kind,
+            nlink: 0,
+            handles: 0,
+            atim: now,
+            mtim: now,
+            ctim: now,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.kind, Kind::Dir(_))
+    }
+
+    fn filetype(&self) -> FileType {
+        match self.kind {
+            Kind::File(_) => FileType::RegularFile,
+            Kind::Dir(_) => FileType::Directory,
+            Kind::Symlink(_) => FileType::SymbolicLink,
+        }
+    }
+}
+
+impl DirNode {
+    fn new(parent: u64) -> DirNode {
+        DirNode {
+            parent,
+            entries: BTreeMap::new(),
+            next_cookie: 2,
+        }
+    }
+}
+
+impl Fs {
+    fn new() -> Fs {
+        let now = SystemTime::now();
+        let mut root = Node::new(Kind::Dir(DirNode::new(ROOT)), now);
+        root.nlink = 1;
+        let mut nodes = HashMap::new();
+        nodes.insert(ROOT, root);
+        Fs {
+            device_id: NEXT_DEVICE.fetch_add(1, Ordering::Relaxed),
+            nodes,
+            next_inode: ROOT + 1,
+            clock: None,
+            capacity: u64::MAX,
+            used: 0,
+        }
+    }
+
+    fn now(&self) -> SystemTime {
+        match &self.clock {
+            Some(clock) => clock.now(clock.resolution()).into_std(),
+            None => SystemTime::now(),
+        }
+    }
+
+    fn node(&self, ino: u64) -> &Node {
+        self.nodes.get(&ino).expect("open or linked inode")
+    }
+
+    fn node_mut(&mut self, ino: u64) -> &mut Node {
+        self.nodes.get_mut(&ino).expect("open or linked inode")
+    }
+
+    /// Makes room for a file to go from `old` to `new` bytes, failing if
+    /// that would go past the capacity of the filesystem.
+    fn reserve(&mut self, old: usize, new: usize) -> Result<(), Error> {
+        let (old, new) = (old as u64, new as u64);
+        if new <= old {
+            self.used -= old - new;
+            return Ok(());
+        }
+        if new > self.capacity {
+            return Err(Error::file_too_big());
+        }
+        match self.used.checked_add(new - old) {
+            Some(used) if used <= self.capacity => {
+                self.used = used;
+                Ok(())
+            }
+            _ => Err(Error::no_space()),
+        }
+    }
+
+    /// Resizes the file `ino` to `size` bytes, within the capacity of the
+    /// filesystem.
+    fn resize(&mut self, ino: u64, size: usize) -> Result<(), Error> {
+        let len = match &self.node(ino).kind {
+            Kind::File(data) => data.len(),
+            _ => return Err(Error::is_dir()),
+        };
+        self.reserve(len, size)?;
+        if let Kind::File(data) = &mut self.node_mut(ino).kind {
+            data.resize(size, 0);
+        }
+        Ok(())
+    }
+
+    fn dir(&self, ino: u64) -> Result<&DirNode, Error> {
+        match &self.node(ino).kind {
+            Kind::Dir(dir) => Ok(dir),
+            _ => Err(Error::not_dir()),
+        }
+    }
+
+    fn dir_mut(&mut self, ino: u64) -> Result<&mut DirNode, Error> {
+        match &mut self.node_mut(ino).kind {
+            Kind::Dir(dir) => Ok(dir),
+            _ => Err(Error::not_dir()),
+        }
+    }
+
+    fn entry(&self, dir: u64, name: &str) -> Option<u64> {
+        self.dir(dir).ok()?.entries.get(name).map(|e| e.ino)
+    }
+
+    /// Resolves `path` relative to the directory `start`, following symlinks
+    /// along the way, and the last component too if `follow` is set.
+    fn resolve(&self, start: u64, path: &str, follow: bool) -> Result<Target, Error> {
+        if path.is_empty() {
+            return Err(Error::not_found());
+        }
+        let mut trailing_slash = path.ends_with('/');
+        let mut rest = components(path)?;
+        // The directories walked through from `start`, for `..` to go back to.
+        let mut walked = vec![start];
+        let mut symlinks = 0;
+        while let Some(name) = rest.pop_front() {
+            let dir = *walked.last().unwrap();
+            if name == ".." {
+                if walked.len() == 1 {
+                    return Err(Error::perm().context("path leads outside of its directory"));
+                }
+                walked.pop();
+                continue;
+            }
+            let last = rest.is_empty();
+            let ino = match self.dir(dir)?.entries.get(&name) {
+                Some(entry) => entry.ino,
+                None if last => {
+                    return Ok(Target {
+                        dir,
+                        name: Some(name),
+                        trailing_slash,
+                    })
+                }
+                None => return Err(Error::not_found()),
+            };
+            match &self.node(ino).kind {
+                Kind::Symlink(target) if !last || follow => {
+                    symlinks += 1;
+                    if symlinks > MAX_SYMLINKS {
+                        return Err(Error::symlink_loop());
+                    }
+                    if target.is_empty() {
+                        return Err(Error::not_found());
+                    }
+                    if last {
+                        trailing_slash |= target.ends_with('/');
+                    }
+                    for component in components(target)?.into_iter().rev() {
+                        rest.push_front(component);
+                    }
+                }
+                Kind::Dir(_) if !last => walked.push(ino),
+                _ if last => {
+                    return Ok(Target {
+                        dir,
+                        name: Some(name),
+                        trailing_slash,
+                    })
+                }
+                _ => return Err(Error::not_dir()),
+            }
+        }
+        Ok(Target {
+            dir: *walked.last().unwrap(),
+            name: None,
+            trailing_slash,
+        })
+    }
+
+    /// The node a resolved path names, if it exists. A path with a trailing
+    /// slash may only name a directory.
+    fn target_ino(&self, target: &Target) -> Result<Option<u64>, Error> {
+        let ino = match &target.name {
+            None => target.dir,
+            Some(name) => match self.entry(target.dir, name) {
+                Some(ino) => ino,
+                None => return Ok(None),
+            },
+        };
+        if target.trailing_slash && !self.node(ino).is_dir() {
+            return Err(Error::not_dir());
+        }
+        Ok(Some(ino))
+    }
+
+    /// Looks up the node at `path`, which must exist. A trailing slash
+    /// follows a symlink at the end of the path, as it does on POSIX.
+    fn lookup(&self, start: u64, path: &str, follow: bool) -> Result<u64, Error> {
+        let follow = follow || path.ends_with('/');
+        let target = self.resolve(start, path, follow)?;
+        self.target_ino(&target)?.ok_or_else(Error::not_found)
+    }
+
+    /// Checks that a new entry, for a directory if `is_dir` is set, can be
+    /// added at `target`, and returns its name.
+    fn new_entry<'a>(&self, target: &'a Target, is_dir: bool) -> Result<&'a str, Error> {
+        let name = match &target.name {
+            Some(name) => name,
+            None => return Err(Error::exist()),
+        };
+        if let Some(ino) = self.entry(target.dir, name) {
+            if target.trailing_slash && !self.node(ino).is_dir() {
+                return Err(Error::not_dir());
+            }
+            return Err(Error::exist());
+        }
+        if target.trailing_slash && !is_dir {
+            return Err(Error::not_found());
+        }
+        if name.len() > MAX_NAME {
+            return Err(Error::name_too_long());
+        }
+        if self.node(target.dir).nlink == 0 {
+            return Err(Error::not_found().context("directory was removed"));
+        }
+        Ok(name)
+    }
+
+    /// Adds a new node to the filesystem at `target`, which must not exist.
+    fn create(&mut self, target: &Target, kind: Kind) -> Result<u64, Error> {
+        let name = self
+            .new_entry(target, matches!(kind, Kind::Dir(_)))?
+            .to_owned();
+        if let Kind::File(data) = &kind {
+            self.reserve(0, data.len())?;
+        }
+        let ino = self.next_inode;
+        self.next_inode += 1;
+        let now = self.now();
+        self.nodes.insert(ino, Node::new(kind, now));
+        self.link(target.dir, name, ino, now);
+        Ok(ino)
+    }
+
+    /// Adds an entry for `ino` to the directory `dir`.
+    fn link(&mut self, dir: u64, name: String, ino: u64, now: SystemTime) {
+        let dir_node = self.node_mut(dir);
+        dir_node.mtim = now;
+        dir_node.ctim = now;
+        match &mut dir_node.kind {
+            Kind::Dir(d) => {
+                let cookie = d.next_cookie;
+                d.next_cookie += 1;
+                d.entries.insert(name, Entry { ino, cookie });
+            }
+            _ => unreachable!("linked into a directory"),
+        }
+        let node = self.node_mut(ino);
+        node.nlink += 1;
+        node.ctim = now;
+        if let Kind::Dir(d) = &mut node.kind {
+            d.parent = dir;
+        }
+    }
+
+    /// Removes the entry `name` from the directory `dir`, freeing what it
+    /// named if nothing else refers to it.
+    fn unlink(&mut self, dir: u64, name: &str, now: SystemTime) {
+        let dir_node = self.node_mut(dir);
+        dir_node.mtim = now;
+        dir_node.ctim = now;
+        let ino = match &mut dir_node.kind {
+            Kind::Dir(d) => d.entries.remove(name).expect("unlinked entry exists").ino,
+            _ => unreachable!("unlinked from a directory"),
+        };
+        let node = self.node_mut(ino);
+        node.nlink -= 1;
+        node.ctim = now;
+        self.collect(ino);
+    }
+
+    /// Frees the node `ino` if it has no links and isn't open.
+    fn collect(&mut self, ino: u64) {
+        let node = self.node(ino);
+        if ino != ROOT && node.nlink == 0 && node.handles == 0 {
+            if let Some(Node {
+                kind: Kind::File(data),
+                ..
+            }) = self.nodes.remove(&ino)
+            {
+                self.used -= data.len() as u64;
+            }
+        }
+    }
+
+    fn open(&mut self, ino: u64) {
+        self.node_mut(ino).handles += 1;
+    }
+
+    fn close(&mut self, ino: u64) {
+        self.node_mut(ino).handles -= 1;
+        self.collect(ino);
+    }
+
+    fn create_dir_all(&mut self, start: u64, path: &str) -> Result<u64, Error> {
+        let mut dir = start;
+        for name in components(path)? {
+            let target = Target {
+                dir,
+                name: Some(name),
+                trailing_slash: true,
+            };
+            dir = match self.target_ino(&target)? {
+                Some(ino) => ino,
+                None => self.create(&target, Kind::Dir(DirNode::new(dir)))?,
+            };
+        }
+        Ok(dir)
+    }
+
+    fn write_file(&mut self, start: u64, path: &str, contents: Vec<u8>) -> Result<u64, Error> {
+        let target = self.resolve(start, path, true)?;
+        let ino = match self.target_ino(&target)? {
+            Some(ino) => ino,
+            None => self.create(&target, Kind::File(Vec::new()))?,
+        };
+        let len = match &self.node(ino).kind {
+            Kind::File(data) => data.len(),
+            _ => return Err(Error::is_dir()),
+        };
+        self.reserve(len, contents.len())?;
+        let now = self.now();
+        let node = self.node_mut(ino);
+        node.kind = Kind::File(contents);
+        node.mtim = now;
+        node.ctim = now;
+        Ok(ino)
+    }
+
+    fn hard_link(
+        &mut self,
+        src_start: u64,
+        src_path: &str,
+        dest_start: u64,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        let ino = self.lookup(src_start, src_path, false)?;
+        if self.node(ino).is_dir() {
+            return Err(Error::perm().context("hard link to a directory"));
+        }
+        let target = self.resolve(dest_start, dest_path, false)?;
+        let name = self.new_entry(&target, false)?.to_owned();
+        let now = self.now();
+        self.link(target.dir, name, ino, now);
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        src_start: u64,
+        src_path: &str,
+        dest_start: u64,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        let src = self.resolve(src_start, src_path, false)?;
+        let dest = self.resolve(dest_start, dest_path, false)?;
+        let (src_name, dest_name) = match (&src.name, &dest.name) {
+            (Some(src_name), Some(dest_name)) => (src_name.clone(), dest_name.clone()),
+            _ => return Err(Error::invalid_argument().context("rename of `.` or `..`")),
+        };
+        let ino = self
+            .entry(src.dir, &src_name)
+            .ok_or_else(Error::not_found)?;
+        let is_dir = self.node(ino).is_dir();
+        if (src.trailing_slash || dest.trailing_slash) && !is_dir {
+            return Err(Error::not_dir());
+        }
+        let replaced = self.entry(dest.dir, &dest_name);
+        if let Some(replaced) = replaced {
+            if replaced == ino {
+                return Ok(());
+            }
+            match &self.node(replaced).kind {
+                Kind::Dir(d) if is_dir && !d.entries.is_empty() => return Err(Error::not_empty()),
+                Kind::Dir(_) if !is_dir => return Err(Error::is_dir()),
+                Kind::Dir(_) => {}
+                _ if is_dir => return Err(Error::not_dir()),
+                _ => {}
+            }
+        }
+        if self.node(dest.dir).nlink == 0 {
+            return Err(Error::not_found().context("directory was removed"));
+        }
+        if is_dir {
+            // A directory can't be moved into itself.
+            let mut ancestor = dest.dir;
+            while ancestor != ROOT {
+                if ancestor == ino {
+                    return Err(Error::invalid_argument().context("rename into itself"));
+                }
+                ancestor = self.dir(ancestor)?.parent;
+            }
+        }
+        let now = self.now();
+        if replaced.is_some() {
+            self.unlink(dest.dir, &dest_name, now);
+        }
+        // Moving the entry keeps the node's link count, but dropping it from
+        // its old directory and linking it into the new one adds one on the
+        // way, which is taken back here.
+        self.dir_mut(src.dir)?.entries.remove(&src_name);
+        let src_dir = self.node_mut(src.dir);
+        src_dir.mtim = now;
+        src_dir.ctim = now;
+        self.link(dest.dir, dest_name, ino, now);
+        self.node_mut(ino).nlink -= 1;
+        Ok(())
+    }
+
+    fn filestat(&self, ino: u64) -> Filestat {
+        let node = self.node(ino);
+        let (size, nlink) = match &node.kind {
+            Kind::File(data) => (data.len() as u64, node.nlink),
+            Kind::Symlink(target) => (target.len() as u64, node.nlink),
+            // A directory is linked from its parent, by its own `.`, and by
+            // the `..` of each of its subdirectories.
+            Kind::Dir(dir) if node.nlink > 0 => {
+                let subdirs = dir
+                    .entries
+                    .values()
+                    .filter(|e| self.node(e.ino).is_dir())
+                    .count();
+                (0, 2 + subdirs as u64)
+            }
+            Kind::Dir(_) => (0, 0),
+        };
+        Filestat {
+            device_id: self.device_id,
+            inode: ino,
+            filetype: node.filetype(),
+            nlink,
+            size,
+            atim: Some(node.atim),
+            mtim: Some(node.mtim),
+            ctim: Some(node.ctim),
+        }
+    }
+
+    fn set_times(
+        &mut self,
+        ino: u64,
+        atime: Option<SystemTimeSpec>,
+        mtime: Option<SystemTimeSpec>,
+    ) {
+        let now = self.now();
+        let time = |spec| match spec {
+            SystemTimeSpec::SymbolicNow => now,
+            SystemTimeSpec::Absolute(t) => cap_std::time::SystemTime::into_std(t),
+        };
+        let node = self.node_mut(ino);
+        if let Some(atime) = atime {
+            node.atim = time(atime);
+        }
+        if let Some(mtime) = mtime {
+            node.mtim = time(mtime);
+        }
+        node.ctim = now;
+    }
+}
+
+/// Splits a relative path into the names it is made of, leaving out empty
+/// components and `.`, which don't move the path anywhere.
+fn components(path: &str) -> Result<VecDeque<String>, Error> {
+    if path.starts_with('/') {
+        return Err(Error::perm().context("absolute path"));
+    }
+    if path.contains('\0') {
+        return Err(Error::invalid_argument().context("path contains a NUL"));
+    }
+    Ok(path
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dir::{ReaddirCursor, WasiDir};
+    use crate::file::{FdFlags, OFlags, WasiFile};
+    use crate::snapshots::preview_1::types::Errno;
+    use std::io::{IoSlice, IoSliceMut, SeekFrom};
+
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+        let mut f = Pin::from(Box::new(future));
+        let waker = dummy_waker();
+        let mut cx = Context::from_waker(&waker);
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(val) => return val,
+            Poll::Pending => panic!("memfs futures should complete synchronously"),
+        }
+
+        fn dummy_waker() -> Waker {
+            return unsafe { Waker::from_raw(clone(5 as *const _)) };
+
+            unsafe fn clone(ptr: *const ()) -> RawWaker {
+                assert_eq!(ptr as usize, 5);
+                const VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+                RawWaker::new(ptr, &VTABLE)
+            }
+
+            unsafe fn wake(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+
+            unsafe fn wake_by_ref(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+
+            unsafe fn drop(ptr: *const ()) {
+                assert_eq!(ptr as usize, 5);
+            }
+        }
+    }
+
+    fn errno(err: Error) -> crate::snapshots::preview_1::types::Errno {
+        std::convert::TryFrom::try_from(err).unwrap()
+    }
+
+    fn open(dir: &dyn WasiDir, path: &str, oflags: OFlags) -> Result<Box<dyn WasiFile>, Error> {
+        run(dir.open_file(false, path, oflags, true, true, FdFlags::empty()))
+    }
+
+    fn names(dir: &dyn WasiDir, cookie: u64) -> Vec<(String, u64)> {
+        run(dir.readdir(ReaddirCursor::from(cookie)))
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                (e.name, u64::from(e.next))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_and_write_files() {
+        let fs = MemFs::new();
+        let root = fs.root();
+        let f = open(&root, "file", OFlags::CREATE).unwrap();
+        assert_eq!(
+            run(f.write_vectored(&[IoSlice::new(b"hello "), IoSlice::new(b"world")])).unwrap(),
+            11
+        );
+        assert_eq!(run(f.seek(SeekFrom::Start(6))).unwrap(), 6);
+        let mut buf = [0; 16];
+        let n = run(f.read_vectored(&mut [IoSliceMut::new(&mut buf)])).unwrap();
+        assert_eq!(&buf[..n as usize], b"world");
+        run(f.write_vectored_at(&[IoSlice::new(b"!")], 14)).unwrap();
+        assert_eq!(fs.read_file("file").unwrap(), b"hello world\0\0\0!");
+        run(f.set_filestat_size(5)).unwrap();
+        assert_eq!(fs.read_file("file").unwrap(), b"hello");
+
+        let appending =
+            run(root.open_file(false, "file", OFlags::empty(), false, true, FdFlags::APPEND))
+                .unwrap();
+        run(appending.write_vectored(&[IoSlice::new(b"!")])).unwrap();
+        assert_eq!(fs.read_file("file").unwrap(), b"hello!");
+
+        let err = open(&root, "file", OFlags::CREATE | OFlags::EXCLUSIVE)
+            .err()
+            .unwrap();
+        assert_eq!(errno(err), Errno::Exist);
+        open(&root, "file", OFlags::TRUNCATE).unwrap();
+        assert_eq!(fs.read_file("file").unwrap(), b"");
+    }
+
+    #[test]
+    fn capacity() {
+        let fs = MemFs::new();
+        fs.set_capacity(16);
+        fs.write_file("full", vec![0; 10]).unwrap();
+        let root = fs.root();
+        let f = open(&root, "file", OFlags::CREATE).unwrap();
+
+        let err = run(f.write_vectored_at(&[IoSlice::new(b"x")], 1 << 40))
+            .err()
+            .unwrap();
+        assert_eq!(errno(err), Errno::Fbig);
+        let err = run(f.set_filestat_size(u64::MAX)).err().unwrap();
+        assert_eq!(errno(err), Errno::Fbig);
+        let err = run(f.allocate(0, 7)).err().unwrap();
+        assert_eq!(errno(err), Errno::Nospc);
+        run(f.allocate(0, 6)).unwrap();
+        let err = run(f.write_vectored(&[IoSlice::new(b"1234567")]))
+            .err()
+            .unwrap();
+        assert_eq!(errno(err), Errno::Nospc);
+        assert_eq!(fs.read_file("file").unwrap(), [0; 6]);
+
+        // Space comes back once a file is gone, but not while it's open.
+        run(root.unlink_file("file")).unwrap();
+        let err = fs.write_file("other", vec![0; 7]).err().unwrap();
+        assert_eq!(errno(err), Errno::Nospc);
+        drop(f);
+        fs.write_file("other", vec![0; 6]).unwrap();
+        open(&root, "other", OFlags::TRUNCATE).unwrap();
+        fs.write_file("full", vec![0; 16]).unwrap();
+    }
+
+    #[test]
+    fn paths_stay_inside_their_directory() {
+        let fs = MemFs::new();
+        fs.create_dir_all("a/b").unwrap();
+        fs.write_file("a/b/file", "contents").unwrap();
+        fs.symlink("../..", "a/b/up").unwrap();
+        fs.symlink("/etc", "a/abs").unwrap();
+        fs.symlink("loop", "loop").unwrap();
+
+        let root = fs.root();
+        let a = run(root.open_dir(false, "a")).unwrap();
+        assert!(open(&*a, "b/../b/./file", OFlags::empty()).is_ok());
+        assert!(open(&root, "a/b/up/a/b/file", OFlags::empty()).is_ok());
+        for path in &["..", "b/../..", "b/up", "abs", "/a"] {
+            let err = run(a.open_dir(true, path)).err().unwrap();
+            assert_eq!(errno(err), Errno::Perm, "{}", path);
+        }
+        let err = run(root.open_dir(true, "loop")).err().unwrap();
+        assert_eq!(errno(err), Errno::Loop);
+        let err = run(root.open_dir(false, "a/b/up")).err().unwrap();
+        assert_eq!(errno(err), Errno::Loop);
+        let err = open(&root, "a/b/file/", OFlags::empty()).err().unwrap();
+        assert_eq!(errno(err), Errno::Notdir);
+    }
+
+    #[test]
+    fn readdir_cookies_are_stable() {
+        let fs = MemFs::new();
+        for name in &["b", "a", "c"] {
+            fs.write_file(name, "").unwrap();
+        }
+        let root = fs.root();
+        let all = names(&root, 0);
+        let listed: Vec<_> = all.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(listed, [".", "..", "b", "a", "c"]);
+
+        // Carry on after "b", with "a" removed and "d" added meanwhile.
+        let after_b = all[2].1;
+        run(root.unlink_file("a")).unwrap();
+        fs.write_file("d", "").unwrap();
+        let rest: Vec<_> = names(&root, after_b).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(rest, ["c", "d"]);
+    }
+
+    #[test]
+    fn links_and_removal() {
+        let fs = MemFs::new();
+        fs.write_file("file", "contents").unwrap();
+        fs.hard_link("file", "link").unwrap();
+        let root = fs.root();
+        let stat = run(root.get_path_filestat("link", false)).unwrap();
+        assert_eq!(stat.nlink, 2);
+        assert_eq!(
+            stat.inode,
+            run(root.get_path_filestat("file", false)).unwrap().inode
+        );
+
+        // An open file outlives its last link.
+        let f = open(&root, "file", OFlags::empty()).unwrap();
+        run(root.unlink_file("file")).unwrap();
+        run(root.unlink_file("link")).unwrap();
+        assert_eq!(run(f.get_filestat()).unwrap().nlink, 0);
+        let mut buf = [0; 8];
+        run(f.read_vectored(&mut [IoSliceMut::new(&mut buf)])).unwrap();
+        assert_eq!(&buf, b"contents");
+        let ino = stat.inode;
+        assert!(fs.lock().nodes.contains_key(&ino));
+        drop(f);
+        assert!(!fs.lock().nodes.contains_key(&ino));
+
+        fs.create_dir_all("dir/sub").unwrap();
+        let err = run(root.remove_dir("dir")).err().unwrap();
+        assert_eq!(errno(err), Errno::Notempty);
+        let err = run(root.unlink_file("dir")).err().unwrap();
+        assert_eq!(errno(err), Errno::Isdir);
+        let err = run(root.hard_link("dir", &root, "dir2")).err().unwrap();
+        assert_eq!(errno(err), Errno::Perm);
+        run(root.remove_dir("dir/sub/")).unwrap();
+        run(root.remove_dir("dir")).unwrap();
+    }
+
+    #[test]
+    fn rename_semantics() {
+        let fs = MemFs::new();
+        fs.create_dir_all("a/b").unwrap();
+        fs.create_dir_all("empty").unwrap();
+        fs.write_file("a/file", "1").unwrap();
+        fs.write_file("other", "2").unwrap();
+        let root = fs.root();
+
+        let err = run(root.rename("a", &root, "a/b/c")).err().unwrap();
+        assert_eq!(errno(err), Errno::Inval);
+        let err = run(root.rename("other", &root, "a")).err().unwrap();
+        assert_eq!(errno(err), Errno::Isdir);
+        let err = run(root.rename("a", &root, "other")).err().unwrap();
+        assert_eq!(errno(err), Errno::Notdir);
+        let err = run(root.rename("other", &root, "moved/")).err().unwrap();
+        assert_eq!(errno(err), Errno::Notdir);
+
+        // Files replace files, and directories replace empty directories.
+        run(root.rename("other", &root, "a/file")).unwrap();
+        assert_eq!(fs.read_file("a/file").unwrap(), b"2");
+        run(root.rename("a/", &root, "empty")).unwrap();
+        assert_eq!(fs.read_file("empty/file").unwrap(), b"2");
+        let parent = run(root.get_path_filestat("empty", false)).unwrap();
+        assert_eq!(parent.nlink, 3);
+        let b = run(root.open_dir(false, "empty/b")).unwrap();
+        let dotdot = run(b.readdir(ReaddirCursor::from(1)))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!((dotdot.name.as_str(), dotdot.inode), ("..", parent.inode));
+
+        // Renames between directories opened separately.
+        let sub = run(root.open_dir(false, "empty")).unwrap();
+        run(sub.rename("file", &root, "file")).unwrap();
+        assert_eq!(fs.read_file("file").unwrap(), b"2");
+        let other = MemFs::new();
+        let err = run(root.rename("file", &other.root(), "file"))
+            .err()
+            .unwrap();
+        assert_eq!(errno(err), Errno::Badf);
+    }
+
+    #[test]
+    fn timestamps() {
+        use crate::clocks::WasiSystemClock;
+        use cap_std::time::Duration;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        struct Ticks(AtomicU64);
+        impl WasiSystemClock for Ticks {
+            fn resolution(&self) -> Duration {
+                Duration::from_secs(1)
+            }
+            fn now(&self, _precision: Duration) -> cap_std::time::SystemTime {
+                let secs = self.0.fetch_add(1, Ordering::Relaxed);
+                cap_std::time::SystemTime::from_std(
+                    std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs),
+                )
+            }
+        }
+        let at = |secs| Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs));
+
+        let fs = MemFs::new();
+        fs.set_clock(Box::new(Ticks(AtomicU64::new(100))));
+        let root = fs.root();
+        let f = open(&root, "file", OFlags::CREATE).unwrap();
+        let stat = run(f.get_filestat()).unwrap();
+        assert_eq!(
+            (stat.atim, stat.mtim, stat.ctim),
+            (at(100), at(100), at(100))
+        );
+        assert_eq!(run(root.get_filestat()).unwrap().mtim, at(100));
+
+        run(f.write_vectored(&[IoSlice::new(b"x")])).unwrap();
+        let stat = run(f.get_filestat()).unwrap();
+        assert_eq!(
+            (stat.atim, stat.mtim, stat.ctim),
+            (at(100), at(101), at(101))
+        );
+
+        run(f.read_vectored_at(&mut [IoSliceMut::new(&mut [0; 1])], 0)).unwrap();
+        assert_eq!(run(f.get_filestat()).unwrap().atim, at(102));
+
+        run(root.set_times(
+            "file",
+            None,
+            Some(SystemTimeSpec::Absolute(
+                cap_std::time::SystemTime::from_std(at(7).unwrap()),
+            )),
+            false,
+        ))
+        .unwrap();
+        let stat = run(f.get_filestat()).unwrap();
+        assert_eq!((stat.atim, stat.mtim, stat.ctim), (at(102), at(7), at(103)));
+    }
+}
@@ -1,12 +1,14 @@
 use crate::clocks::WasiMonotonicClock;
 use crate::file::WasiFile;
+use crate::socket::WasiSocket;
 use crate::Error;
 use cap_std::time::Instant;
 pub mod subscription;
 pub use cap_std::time::Duration;
 
 pub use subscription::{
-    MonotonicClockSubscription, RwEventFlags, RwSubscription, Subscription, SubscriptionResult,
+    MonotonicClockSubscription, RwEventFlags, RwSubscription, SocketSubscription, Subscription,
+    SubscriptionResult,
 };
 
 #[wiggle::async_trait]
@@ -64,6 +66,12 @@ impl<'a> Poll<'a> {
         self.subs
             .push((Subscription::Write(RwSubscription::new(file)), ud));
     }
+    pub fn subscribe_socket_read(&mut self, socket: &'a dyn WasiSocket, ud: Userdata) {
+        self.subs.push((
+            Subscription::SocketRead(SocketSubscription::new(socket)),
+            ud,
+        ));
+    }
     pub fn results(self) -> Vec<(SubscriptionResult, Userdata)> {
         self.subs
             .into_iter()
@@ -84,7 +92,9 @@ impl<'a> Poll<'a> {
     }
     pub fn rw_subscriptions<'b>(&'b mut self) -> impl Iterator<Item = &'b mut Subscription<'a>> {
         self.subs.iter_mut().filter_map(|(s, _ud)| match s {
-            Subscription::Read { .. } | Subscription::Write { .. } => Some(s),
+            Subscription::Read { .. }
+            | Subscription::Write { .. }
+            | Subscription::SocketRead { .. } => Some(s),
             _ => None,
         })
     }
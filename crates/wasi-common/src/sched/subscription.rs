@@ -1,5 +1,6 @@
 use crate::clocks::WasiMonotonicClock;
 use crate::file::WasiFile;
+use crate::socket::WasiSocket;
 use crate::Error;
 use bitflags::bitflags;
 use cap_std::time::{Duration, Instant};
@@ -30,6 +31,31 @@ impl<'a> RwSubscription<'a> {
     }
 }
 
+/// A subscription to a socket becoming readable, which for a listening
+/// socket means it has a connection to accept.
+pub struct SocketSubscription<'a> {
+    pub socket: &'a dyn WasiSocket,
+    status: Option<Result<(u64, RwEventFlags), Error>>,
+}
+
+impl<'a> SocketSubscription<'a> {
+    pub fn new(socket: &'a dyn WasiSocket) -> Self {
+        Self {
+            socket,
+            status: None,
+        }
+    }
+    pub fn complete(&mut self, size: u64, flags: RwEventFlags) {
+        self.status = Some(Ok((size, flags)))
+    }
+    pub fn error(&mut self, error: Error) {
+        self.status = Some(Err(error))
+    }
+    pub fn result(&mut self) -> Option<Result<(u64, RwEventFlags), Error>> {
+        self.status.take()
+    }
+}
+
 pub struct MonotonicClockSubscription<'a> {
     pub clock: &'a dyn WasiMonotonicClock,
     pub deadline: Instant,
@@ -55,6 +81,7 @@ impl<'a> MonotonicClockSubscription<'a> {
 pub enum Subscription<'a> {
     Read(RwSubscription<'a>),
     Write(RwSubscription<'a>),
+    SocketRead(SocketSubscription<'a>),
     MonotonicClock(MonotonicClockSubscription<'a>),
 }
 
@@ -70,6 +97,7 @@ impl SubscriptionResult {
         match s {
             Subscription::Read(mut s) => s.result().map(SubscriptionResult::Read),
             Subscription::Write(mut s) => s.result().map(SubscriptionResult::Write),
+            Subscription::SocketRead(mut s) => s.result().map(SubscriptionResult::Read),
             Subscription::MonotonicClock(s) => s.result().map(SubscriptionResult::MonotonicClock),
         }
     }
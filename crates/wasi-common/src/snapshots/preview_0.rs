@@ -13,7 +13,6 @@ use std::io::{IoSlice, IoSliceMut};
 use std::ops::Deref;
 use tracing::debug;
 use wiggle::GuestPtr;
-use wiggle::timing::{start_timer, stop_timer, push_result};
 
 
 wiggle::from_witx!({
@@ -21,6 +20,8 @@ wiggle::from_witx!({
     errors: { errno => Error },
     async: *,
     wasmtime: false,
+    instrument: true,
+    hostcall_log: true,
 });
 
 impl wiggle::GuestErrorType for types::Errno {
@@ -339,6 +340,14 @@ convert_flags_bidirectional!(
 // performing the no-op type conversions along the way.
 #[wiggle::async_trait]
 impl wasi_unstable::WasiUnstable for WasiCtx {
+    fn hostcall_recorder(&self) -> Option<&dyn wiggle::timing::HostcallRecorder> {
+        self.hostcall_recorder.as_deref().map(|r| r as _)
+    }
+
+    fn hostcall_log(&self) -> Option<std::sync::Arc<dyn wiggle::replay::HostcallLog>> {
+        self.hostcall_log.clone()
+    }
+
     async fn args_get<'a>(
         &mut self,
         argv: &GuestPtr<'a, GuestPtr<'a, u8>>,
@@ -482,6 +491,7 @@ impl wasi_unstable::WasiUnstable for WasiCtx {
             .collect();
 
         let bytes_read = f.read_vectored(&mut ioslices).await?;
+        wiggle::timing::report_payload(bytes_read, u32::try_from(ioslices.len())?);
         Ok(types::Size::try_from(bytes_read)?)
     }
 
@@ -511,6 +521,7 @@ impl wasi_unstable::WasiUnstable for WasiCtx {
             .collect();
 
         let bytes_read = f.read_vectored_at(&mut ioslices, offset).await?;
+        wiggle::timing::report_payload(bytes_read, u32::try_from(ioslices.len())?);
         Ok(types::Size::try_from(bytes_read)?)
     }
 
@@ -536,6 +547,7 @@ impl wasi_unstable::WasiUnstable for WasiCtx {
             .map(|s| IoSlice::new(s.deref()))
             .collect();
         let bytes_written = f.write_vectored(&ioslices).await?;
+        wiggle::timing::report_payload(bytes_written, u32::try_from(ioslices.len())?);
 
         Ok(types::Size::try_from(bytes_written)?)
     }
@@ -565,6 +577,7 @@ impl wasi_unstable::WasiUnstable for WasiCtx {
             .map(|s| IoSlice::new(s.deref()))
             .collect();
         let bytes_written = f.write_vectored_at(&ioslices, offset).await?;
+        wiggle::timing::report_payload(bytes_written, u32::try_from(ioslices.len())?);
 
         Ok(types::Size::try_from(bytes_written)?)
     }
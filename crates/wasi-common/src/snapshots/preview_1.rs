@@ -8,6 +8,10 @@ use crate::{
         subscription::{RwEventFlags, SubscriptionResult},
         Poll, Userdata,
     },
+    socket::{
+        AddressFamily, RiFlags, RoFlags, SdFlags, SocketAddress, SocketEntry, SocketType,
+        TableSocketExt, WasiSocket,
+    },
     Error, ErrorExt, ErrorKind, SystemTimeSpec, WasiCtx,
 };
 use anyhow::Context;
@@ -15,10 +19,10 @@ use cap_std::time::{Duration, SystemClock};
 use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
 use std::io::{IoSlice, IoSliceMut};
+use std::net::{Ipv4Addr, SocketAddrV4, SocketAddrV6};
 use std::ops::{Deref, DerefMut};
 use tracing::debug;
 use wiggle::GuestPtr;
-use wiggle::timing::{start_timer, stop_timer, push_result}; 
 
 wiggle::from_witx!({
     witx: ["$WASI_ROOT/phases/snapshot/witx/wasi_snapshot_preview1.witx"],
@@ -27,7 +31,10 @@ wiggle::from_witx!({
     // keeping that set the same in this macro and the wasmtime_wiggle / lucet_wiggle macros is
     // tedious, and there is no cost to having a sync function be async in this case.
     async: *,
-    wasmtime: false
+    wasmtime: false,
+    instrument: true,
+    guest_memory: { wasi_snapshot_preview1::sock_connect: true },
+    hostcall_log: true,
 });
 
 impl wiggle::GuestErrorType for types::Errno {
@@ -84,6 +91,13 @@ impl From<ErrorKind> for types::Errno {
             ErrorKind::Overflow => Errno::Overflow,
             ErrorKind::Range => Errno::Range,
             ErrorKind::Spipe => Errno::Spipe,
+            ErrorKind::Notconn => Errno::Notconn,
+            ErrorKind::Isdir => Errno::Isdir,
+            ErrorKind::Notempty => Errno::Notempty,
+            ErrorKind::Loop => Errno::Loop,
+            ErrorKind::Perm => Errno::Perm,
+            ErrorKind::Fbig => Errno::Fbig,
+            ErrorKind::Nospc => Errno::Nospc,
             ErrorKind::NotCapable => Errno::Notcapable,
         }
     }
@@ -141,6 +155,20 @@ impl TryFrom<std::io::Error> for types::Errno {
                 Some(Error::OVERFLOW) => Some(types::Errno::Overflow),
                 Some(Error::ILSEQ) => Some(types::Errno::Ilseq),
                 Some(Error::NOTSUP) => Some(types::Errno::Notsup),
+                Some(Error::AGAIN) => Some(types::Errno::Again),
+                Some(Error::CONNREFUSED) => Some(types::Errno::Connrefused),
+                Some(Error::CONNRESET) => Some(types::Errno::Connreset),
+                Some(Error::CONNABORTED) => Some(types::Errno::Connaborted),
+                Some(Error::NOTCONN) => Some(types::Errno::Notconn),
+                Some(Error::ISCONN) => Some(types::Errno::Isconn),
+                Some(Error::ADDRINUSE) => Some(types::Errno::Addrinuse),
+                Some(Error::ADDRNOTAVAIL) => Some(types::Errno::Addrnotavail),
+                Some(Error::AFNOSUPPORT) => Some(types::Errno::Afnosupport),
+                Some(Error::NETUNREACH) => Some(types::Errno::Netunreach),
+                Some(Error::HOSTUNREACH) => Some(types::Errno::Hostunreach),
+                Some(Error::TIMEDOUT) => Some(types::Errno::Timedout),
+                Some(Error::MSGSIZE) => Some(types::Errno::Msgsize),
+                Some(Error::DESTADDRREQ) => Some(types::Errno::Destaddrreq),
                 _ => None,
             }
         }
@@ -183,6 +211,15 @@ impl TryFrom<std::io::Error> for types::Errno {
                 std::io::ErrorKind::PermissionDenied => Ok(types::Errno::Perm),
                 std::io::ErrorKind::AlreadyExists => Ok(types::Errno::Exist),
                 std::io::ErrorKind::InvalidInput => Ok(types::Errno::Ilseq),
+                std::io::ErrorKind::ConnectionRefused => Ok(types::Errno::Connrefused),
+                std::io::ErrorKind::ConnectionReset => Ok(types::Errno::Connreset),
+                std::io::ErrorKind::ConnectionAborted => Ok(types::Errno::Connaborted),
+                std::io::ErrorKind::NotConnected => Ok(types::Errno::Notconn),
+                std::io::ErrorKind::AddrInUse => Ok(types::Errno::Addrinuse),
+                std::io::ErrorKind::AddrNotAvailable => Ok(types::Errno::Addrnotavail),
+                std::io::ErrorKind::BrokenPipe => Ok(types::Errno::Pipe),
+                std::io::ErrorKind::WouldBlock => Ok(types::Errno::Again),
+                std::io::ErrorKind::TimedOut => Ok(types::Errno::Timedout),
                 _ => Err(anyhow::anyhow!(err).context(format!("Unknown OS error"))),
             },
         }
@@ -191,6 +228,14 @@ impl TryFrom<std::io::Error> for types::Errno {
 
 #[wiggle::async_trait]
 impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
+    fn hostcall_recorder(&self) -> Option<&dyn wiggle::timing::HostcallRecorder> {
+        self.hostcall_recorder.as_deref().map(|r| r as _)
+    }
+
+    fn hostcall_log(&self) -> Option<std::sync::Arc<dyn wiggle::replay::HostcallLog>> {
+        self.hostcall_log.clone()
+    }
+
     async fn args_get<'b>(
         &mut self,
         argv: &GuestPtr<'b, GuestPtr<'b, u8>>,
@@ -288,7 +333,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
         if !table.contains_key(fd) {
             return Err(Error::badf().context("key not in table"));
         }
-        // fd_close must close either a File or a Dir handle
+        // fd_close must close a File, Dir or socket handle
         if table.is::<FileEntry>(fd) {
             let _ = table.delete(fd);
         } else if table.is::<DirEntry>(fd) {
@@ -299,8 +344,10 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             }
             drop(dir_entry);
             let _ = table.delete(fd);
+        } else if table.is::<SocketEntry>(fd) {
+            let _ = table.delete(fd);
         } else {
-            return Err(Error::badf().context("key does not refer to file or directory"));
+            return Err(Error::badf().context("key does not refer to file, directory or socket"));
         }
 
         Ok(())
@@ -326,6 +373,18 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             let dir_entry: &DirEntry = table.get(fd)?;
             let dir_fdstat = dir_entry.get_dir_fdstat();
             Ok(types::Fdstat::from(&dir_fdstat))
+        } else if table.is::<SocketEntry>(fd) {
+            let socket_entry: &SocketEntry = table.get(fd)?;
+            Ok(types::Fdstat {
+                fs_filetype: types::Filetype::from(&socket_entry.get_filetype().await?),
+                fs_flags: types::Fdflags::from(socket_entry.get_fdflags()),
+                fs_rights_base: types::Rights::FD_READ
+                    | types::Rights::FD_WRITE
+                    | types::Rights::FD_FDSTAT_SET_FLAGS
+                    | types::Rights::POLL_FD_READWRITE
+                    | types::Rights::SOCK_SHUTDOWN,
+                fs_rights_inheriting: types::Rights::empty(),
+            })
         } else {
             Err(Error::badf())
         }
@@ -336,6 +395,13 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
         fd: types::Fd,
         flags: types::Fdflags,
     ) -> Result<(), Error> {
+        if self.table().is::<SocketEntry>(u32::from(fd)) {
+            return self
+                .table()
+                .get_socket_mut(u32::from(fd))?
+                .set_fdflags(FdFlags::from(flags))
+                .await;
+        }
         self.table()
             .get_file_mut(u32::from(fd))?
             .get_cap_mut(FileCaps::FDSTAT_SET_FLAGS)?
@@ -442,6 +508,10 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
         fd: types::Fd,
         iovs: &types::IovecArray<'a>,
     ) -> Result<types::Size, Error> {
+        if self.table().is::<SocketEntry>(u32::from(fd)) {
+            let (bytes_read, _) = self.sock_recv(fd, iovs, types::Riflags::empty()).await?;
+            return Ok(bytes_read);
+        }
         let table = self.table();
         let f = table.get_file(u32::from(fd))?.get_cap(FileCaps::READ)?;
 
@@ -460,6 +530,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             .collect();
 
         let bytes_read = f.read_vectored(&mut ioslices).await?;
+        wiggle::timing::report_payload(bytes_read, u32::try_from(ioslices.len())?);
         Ok(types::Size::try_from(bytes_read)?)
     }
 
@@ -489,6 +560,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             .collect();
 
         let bytes_read = f.read_vectored_at(&mut ioslices, offset).await?;
+        wiggle::timing::report_payload(bytes_read, u32::try_from(ioslices.len())?);
         Ok(types::Size::try_from(bytes_read)?)
     }
 
@@ -497,6 +569,9 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
         fd: types::Fd,
         ciovs: &types::CiovecArray<'a>,
     ) -> Result<types::Size, Error> {
+        if self.table().is::<SocketEntry>(u32::from(fd)) {
+            return self.sock_send(fd, ciovs, 0).await;
+        }
         let table = self.table();
         let f = table.get_file(u32::from(fd))?.get_cap(FileCaps::WRITE)?;
 
@@ -514,6 +589,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             .map(|s| IoSlice::new(s.deref()))
             .collect();
         let bytes_written = f.write_vectored(&ioslices).await?;
+        wiggle::timing::report_payload(bytes_written, u32::try_from(ioslices.len())?);
 
         Ok(types::Size::try_from(bytes_written)?)
     }
@@ -543,6 +619,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             .map(|s| IoSlice::new(s.deref()))
             .collect();
         let bytes_written = f.write_vectored_at(&ioslices, offset).await?;
+        wiggle::timing::report_payload(bytes_written, u32::try_from(ioslices.len())?);
 
         Ok(types::Size::try_from(bytes_written)?)
     }
@@ -678,6 +755,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             // If the dirent struct wasnt compied entirely, return that we filled the buffer, which
             // tells libc that we're not at EOF.
             if dirent_copy_len < dirent_len {
+                wiggle::timing::report_payload(u64::from(buf_len), 1);
                 return Ok(buf_len);
             }
 
@@ -693,12 +771,14 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
             // tells libc that we're not at EOF
 
             if name_copy_len < name_len {
+                wiggle::timing::report_payload(u64::from(buf_len), 1);
                 return Ok(buf_len);
             }
 
             buf = buf.add(name_copy_len)?;
             bufused += name_copy_len;
         }
+        wiggle::timing::report_payload(u64::from(bufused), 1);
         Ok(bufused)
     }
 
@@ -872,6 +952,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
         }
         let mut buf = buf.as_array(link_len as u32).as_slice_mut()?;
         buf.copy_from_slice(link_bytes);
+        wiggle::timing::report_payload(link_len as u64, 1);
         Ok(link_len as types::Size)
     }
 
@@ -975,6 +1056,7 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
         // We need these refmuts to outlive Poll, which will hold the &mut dyn WasiFile inside
         let mut read_refs: Vec<(&dyn WasiFile, Userdata)> = Vec::new();
         let mut write_refs: Vec<(&dyn WasiFile, Userdata)> = Vec::new();
+        let mut socket_read_refs: Vec<(&dyn WasiSocket, Userdata)> = Vec::new();
         let mut poll = Poll::new();
 
         let subs = subs.as_array(nsubscriptions);
@@ -1019,10 +1101,15 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
                     } else {
                         sub_fds.insert(fd);
                     }
-                    let file_ref = table
-                        .get_file(u32::from(fd))?
-                        .get_cap(FileCaps::POLL_READWRITE)?;
-                    read_refs.push((file_ref, sub.userdata.into()));
+                    if table.is::<SocketEntry>(u32::from(fd)) {
+                        let socket = table.get_socket(u32::from(fd))?.get_open()?;
+                        socket_read_refs.push((socket, sub.userdata.into()));
+                    } else {
+                        let file_ref = table
+                            .get_file(u32::from(fd))?
+                            .get_cap(FileCaps::POLL_READWRITE)?;
+                        read_refs.push((file_ref, sub.userdata.into()));
+                    }
                 }
                 types::SubscriptionU::FdWrite(writesub) => {
                     let fd = writesub.file_descriptor;
@@ -1046,6 +1133,9 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
         for (f, ud) in write_refs.iter_mut() {
             poll.subscribe_write(*f, *ud);
         }
+        for (s, ud) in socket_read_refs.iter_mut() {
+            poll.subscribe_socket_read(*s, *ud);
+        }
 
         self.sched.poll_oneoff(&mut poll).await?;
 
@@ -1147,39 +1237,180 @@ impl wasi_snapshot_preview1::WasiSnapshotPreview1 for WasiCtx {
     ) -> Result<(), Error> {
         let mut buf = buf.as_array(buf_len).as_slice_mut()?;
         self.random.try_fill_bytes(buf.deref_mut())?;
+        wiggle::timing::report_payload(u64::from(buf_len), 1);
         Ok(())
     }
 
+    async fn sock_accept(
+        &mut self,
+        fd: types::Fd,
+        flags: types::Fdflags,
+    ) -> Result<types::Fd, Error> {
+        let table = self.table();
+        let connection = table
+            .get_socket(u32::from(fd))?
+            .accept(FdFlags::from(flags))
+            .await?;
+        let fd = table.push(Box::new(connection))?;
+        Ok(types::Fd::from(fd))
+    }
+
     async fn sock_recv<'a>(
         &mut self,
-        _fd: types::Fd,
-        _ri_data: &types::IovecArray<'a>,
-        _ri_flags: types::Riflags,
+        fd: types::Fd,
+        ri_data: &types::IovecArray<'a>,
+        ri_flags: types::Riflags,
     ) -> Result<(types::Size, types::Roflags), Error> {
-        Err(Error::trap("sock_recv unsupported"))
+        let table = self.table();
+        let socket = table.get_socket(u32::from(fd))?.get_open()?;
+
+        let mut guest_slices: Vec<wiggle::GuestSliceMut<u8>> = ri_data
+            .iter()
+            .map(|iov_ptr| {
+                let iov_ptr = iov_ptr?;
+                let iov: types::Iovec = iov_ptr.read()?;
+                Ok(iov.buf.as_array(iov.buf_len).as_slice_mut()?)
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let mut ioslices: Vec<IoSliceMut> = guest_slices
+            .iter_mut()
+            .map(|s| IoSliceMut::new(&mut *s))
+            .collect();
+
+        let (bytes_read, roflags) = socket.recv(&mut ioslices, RiFlags::from(&ri_flags)).await?;
+        wiggle::timing::report_payload(bytes_read, u32::try_from(ioslices.len())?);
+        Ok((
+            types::Size::try_from(bytes_read)?,
+            types::Roflags::from(&roflags),
+        ))
     }
 
     async fn sock_send<'a>(
         &mut self,
-        _fd: types::Fd,
-        _si_data: &types::CiovecArray<'a>,
+        fd: types::Fd,
+        si_data: &types::CiovecArray<'a>,
         _si_flags: types::Siflags,
     ) -> Result<types::Size, Error> {
-        Err(Error::trap("sock_send unsupported"))
+        let table = self.table();
+        let socket = table.get_socket(u32::from(fd))?.get_open()?;
+
+        let guest_slices: Vec<wiggle::GuestSlice<u8>> = si_data
+            .iter()
+            .map(|iov_ptr| {
+                let iov_ptr = iov_ptr?;
+                let iov: types::Ciovec = iov_ptr.read()?;
+                Ok(iov.buf.as_array(iov.buf_len).as_slice()?)
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let ioslices: Vec<IoSlice> = guest_slices
+            .iter()
+            .map(|s| IoSlice::new(s.deref()))
+            .collect();
+        let bytes_written = socket.send(&ioslices).await?;
+        wiggle::timing::report_payload(bytes_written, u32::try_from(ioslices.len())?);
+
+        Ok(types::Size::try_from(bytes_written)?)
     }
 
-    async fn sock_shutdown(&mut self, _fd: types::Fd, _how: types::Sdflags) -> Result<(), Error> {
-        Err(Error::trap("sock_shutdown unsupported"))
+    async fn sock_shutdown(&mut self, fd: types::Fd, how: types::Sdflags) -> Result<(), Error> {
+        self.table()
+            .get_socket(u32::from(fd))?
+            .get_open()?
+            .shutdown(SdFlags::from(&how))
+            .await
     }
 
-    async fn socket(&mut self, _domain: u32, _ty: u32, _protocol: u32) -> Result<u32, Error> {
-        Err(Error::trap("socket unsupported"))
+    async fn socket(&mut self, domain: u32, ty: u32, protocol: u32) -> Result<u32, Error> {
+        let family = match domain {
+            AF_INET => AddressFamily::Inet4,
+            AF_INET6 => AddressFamily::Inet6,
+            AF_UNIX => AddressFamily::Unix,
+            _ => return Err(Error::not_supported().context(format!("address family {}", domain))),
+        };
+        let ty = match ty {
+            SOCK_STREAM => SocketType::Stream,
+            SOCK_DGRAM => SocketType::Dgram,
+            _ => return Err(Error::invalid_argument().context(format!("socket type {}", ty))),
+        };
+        match (family, ty, protocol) {
+            (_, _, 0) => {}
+            (AddressFamily::Inet4 | AddressFamily::Inet6, SocketType::Stream, IPPROTO_TCP) => {}
+            (AddressFamily::Inet4 | AddressFamily::Inet6, SocketType::Dgram, IPPROTO_UDP) => {}
+            _ => {
+                return Err(Error::invalid_argument()
+                    .context(format!("protocol {} for a {:?} socket", protocol, ty)))
+            }
+        }
+        self.table().push(Box::new(SocketEntry::new(family, ty)))
     }
 
-    async fn sock_connect(&mut self, _fd: u32, _addr: u32, _addrlen: u32) -> Result<(), Error> {
-        Err(Error::trap("sock_connect unsupported"))
+    async fn sock_connect(&mut self, fd: u32, addr: u32, addrlen: u32) -> Result<(), Error> {
+        // The address is a plain pointer in the witx signature, so it's read
+        // from the memory this hostcall is scoped to.
+        let addr = wiggle::with_guest_memory(|mem| read_sockaddr(mem, addr, addrlen))
+            .ok_or_else(|| Error::trap("sock_connect called without guest memory"))??;
+        let network = self.network.as_deref();
+        self.table.get_socket_mut(fd)?.connect(network, &addr).await
+    }
+}
+
+// The `domain` and `ty` arguments of `socket`, and the families of the
+// addresses passed to `sock_connect`, as numbered by wasi-libc.
+const AF_INET: u32 = 1;
+const AF_INET6: u32 = 2;
+const AF_UNIX: u32 = 3;
+const SOCK_DGRAM: u32 = 5;
+const SOCK_STREAM: u32 = 6;
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+
+/// Reads the `sockaddr` of `addrlen` bytes at `addr`. It starts with the
+/// address family as a little-endian `u16`, followed by the port in network
+/// byte order and the address for `AF_INET` and `AF_INET6`, as in
+/// `sockaddr_in` and `sockaddr_in6`, or by a nul-terminated path for
+/// `AF_UNIX`.
+fn read_sockaddr(
+    mem: &dyn wiggle::GuestMemory,
+    addr: u32,
+    addrlen: u32,
+) -> Result<SocketAddress, Error> {
+    let bytes = GuestPtr::<u8>::new(mem, addr)
+        .as_array(addrlen)
+        .as_slice()?;
+    let too_short = || Error::invalid_argument().context("socket address is too short");
+    let family = match bytes.get(..2) {
+        Some(b) => u32::from(u16::from_le_bytes([b[0], b[1]])),
+        None => return Err(too_short()),
+    };
+    match family {
+        AF_INET => {
+            let b = bytes.get(..8).ok_or_else(too_short)?;
+            let port = u16::from_be_bytes([b[2], b[3]]);
+            let ip = Ipv4Addr::new(b[4], b[5], b[6], b[7]);
+            Ok(SocketAddress::Ip(SocketAddrV4::new(ip, port).into()))
+        }
+        AF_INET6 => {
+            let b = bytes.get(..28).ok_or_else(too_short)?;
+            let port = u16::from_be_bytes([b[2], b[3]]);
+            let flowinfo = u32::from_be_bytes(b[4..8].try_into().unwrap());
+            let ip = <[u8; 16]>::try_from(&b[8..24]).unwrap();
+            let scope_id = u32::from_le_bytes(b[24..28].try_into().unwrap());
+            Ok(SocketAddress::Ip(
+                SocketAddrV6::new(ip.into(), port, flowinfo, scope_id).into(),
+            ))
+        }
+        AF_UNIX => {
+            let path = &bytes[2..];
+            let path = path.split(|b| *b == 0).next().unwrap_or_default();
+            if path.is_empty() {
+                return Err(Error::invalid_argument().context("empty socket path"));
+            }
+            Ok(SocketAddress::Unix(std::str::from_utf8(path)?.into()))
+        }
+        _ => Err(Error::not_supported().context(format!("address family {}", family))),
     }
-    
 }
 
 impl From<types::Advice> for Advice {
@@ -1511,6 +1742,42 @@ impl From<&OFlags> for types::Oflags {
         out
     }
 }
+impl From<&types::Riflags> for RiFlags {
+    fn from(flags: &types::Riflags) -> RiFlags {
+        let mut out = RiFlags::empty();
+        if flags.contains(types::Riflags::RECV_PEEK) {
+            out = out | RiFlags::RECV_PEEK;
+        }
+        if flags.contains(types::Riflags::RECV_WAITALL) {
+            out = out | RiFlags::RECV_WAITALL;
+        }
+        out
+    }
+}
+
+impl From<&RoFlags> for types::Roflags {
+    fn from(flags: &RoFlags) -> types::Roflags {
+        let mut out = types::Roflags::empty();
+        if flags.contains(RoFlags::RECV_DATA_TRUNCATED) {
+            out = out | types::Roflags::RECV_DATA_TRUNCATED;
+        }
+        out
+    }
+}
+
+impl From<&types::Sdflags> for SdFlags {
+    fn from(flags: &types::Sdflags) -> SdFlags {
+        let mut out = SdFlags::empty();
+        if flags.contains(types::Sdflags::RD) {
+            out = out | SdFlags::RD;
+        }
+        if flags.contains(types::Sdflags::WR) {
+            out = out | SdFlags::WR;
+        }
+        out
+    }
+}
+
 impl From<Filestat> for types::Filestat {
     fn from(stat: Filestat) -> types::Filestat {
         types::Filestat {
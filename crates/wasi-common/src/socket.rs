@@ -0,0 +1,224 @@
+use crate::file::{FdFlags, FileType};
+use crate::{Error, ErrorExt};
+use bitflags::bitflags;
+use std::any::Any;
+use std::io::{IoSlice, IoSliceMut};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// A connected or listening socket.
+#[wiggle::async_trait]
+pub trait WasiSocket: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    async fn get_filetype(&self) -> Result<FileType, Error>;
+    async fn set_nonblocking(&self, nonblocking: bool) -> Result<(), Error>;
+    /// Accepts a connection, if this is a listening socket.
+    async fn accept(&self) -> Result<Box<dyn WasiSocket>, Error> {
+        Err(Error::invalid_argument().context("socket is not listening"))
+    }
+    async fn send<'a>(&self, bufs: &[IoSlice<'a>]) -> Result<u64, Error>;
+    async fn recv<'a>(
+        &self,
+        bufs: &mut [IoSliceMut<'a>],
+        flags: RiFlags,
+    ) -> Result<(u64, RoFlags), Error>;
+    async fn shutdown(&self, how: SdFlags) -> Result<(), Error>;
+}
+
+/// The authority to connect sockets. A `WasiCtx` without one can create
+/// sockets, but never connect them to anything.
+#[wiggle::async_trait]
+pub trait WasiNetwork: Send + Sync {
+    /// Connects a new socket of type `ty` to `addr`, failing with
+    /// `Error::not_capable` if `addr` isn't one the guest may reach.
+    async fn connect(
+        &self,
+        ty: SocketType,
+        addr: &SocketAddress,
+    ) -> Result<Box<dyn WasiSocket>, Error>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressFamily {
+    Inet4,
+    Inet6,
+    Unix,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SocketType {
+    Stream,
+    Dgram,
+}
+
+impl From<SocketType> for FileType {
+    fn from(ty: SocketType) -> FileType {
+        match ty {
+            SocketType::Stream => FileType::SocketStream,
+            SocketType::Dgram => FileType::SocketDgram,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketAddress {
+    Ip(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl SocketAddress {
+    pub fn family(&self) -> AddressFamily {
+        match self {
+            SocketAddress::Ip(SocketAddr::V4(_)) => AddressFamily::Inet4,
+            SocketAddress::Ip(SocketAddr::V6(_)) => AddressFamily::Inet6,
+            SocketAddress::Unix(_) => AddressFamily::Unix,
+        }
+    }
+}
+
+bitflags! {
+    pub struct RiFlags: u32 {
+        const RECV_PEEK    = 0b1;
+        const RECV_WAITALL = 0b10;
+    }
+}
+
+bitflags! {
+    pub struct RoFlags: u32 {
+        const RECV_DATA_TRUNCATED = 0b1;
+    }
+}
+
+bitflags! {
+    pub struct SdFlags: u32 {
+        const RD = 0b1;
+        const WR = 0b10;
+    }
+}
+
+pub(crate) trait TableSocketExt {
+    fn get_socket(&self, fd: u32) -> Result<&SocketEntry, Error>;
+    fn get_socket_mut(&mut self, fd: u32) -> Result<&mut SocketEntry, Error>;
+}
+impl TableSocketExt for crate::table::Table {
+    fn get_socket(&self, fd: u32) -> Result<&SocketEntry, Error> {
+        self.get(fd)
+    }
+    fn get_socket_mut(&mut self, fd: u32) -> Result<&mut SocketEntry, Error> {
+        self.get_mut(fd)
+    }
+}
+
+/// A socket in the table, which only has a `WasiSocket` once it is
+/// connected, or when it was handed to the guest already open.
+pub(crate) struct SocketEntry {
+    flags: FdFlags,
+    state: SocketState,
+}
+
+enum SocketState {
+    Unconnected(AddressFamily, SocketType),
+    Open(Box<dyn WasiSocket>),
+}
+
+impl SocketEntry {
+    pub fn new(family: AddressFamily, ty: SocketType) -> Self {
+        SocketEntry {
+            flags: FdFlags::empty(),
+            state: SocketState::Unconnected(family, ty),
+        }
+    }
+
+    pub fn open(socket: Box<dyn WasiSocket>) -> Self {
+        SocketEntry {
+            flags: FdFlags::empty(),
+            state: SocketState::Open(socket),
+        }
+    }
+
+    pub async fn get_filetype(&self) -> Result<FileType, Error> {
+        match &self.state {
+            SocketState::Unconnected(_, ty) => Ok((*ty).into()),
+            SocketState::Open(socket) => socket.get_filetype().await,
+        }
+    }
+
+    pub fn get_fdflags(&self) -> FdFlags {
+        self.flags
+    }
+
+    pub async fn set_fdflags(&mut self, flags: FdFlags) -> Result<(), Error> {
+        check_socket_fdflags(flags)?;
+        if let SocketState::Open(socket) = &self.state {
+            socket
+                .set_nonblocking(flags.contains(FdFlags::NONBLOCK))
+                .await?;
+        }
+        self.flags = flags;
+        Ok(())
+    }
+
+    pub async fn connect(
+        &mut self,
+        network: Option<&dyn WasiNetwork>,
+        addr: &SocketAddress,
+    ) -> Result<(), Error> {
+        let (family, ty) = match self.state {
+            SocketState::Unconnected(family, ty) => (family, ty),
+            SocketState::Open(_) => {
+                return Err(Error::invalid_argument().context("socket is already connected"))
+            }
+        };
+        if addr.family() != family {
+            return Err(Error::invalid_argument().context(format!(
+                "{:?} address for a {:?} socket",
+                addr.family(),
+                family
+            )));
+        }
+        let network =
+            network.ok_or_else(|| Error::not_capable().context("no network is available"))?;
+        let socket = network.connect(ty, addr).await?;
+        if self.flags.contains(FdFlags::NONBLOCK) {
+            socket.set_nonblocking(true).await?;
+        }
+        self.state = SocketState::Open(socket);
+        Ok(())
+    }
+
+    /// Accepts a connection on this listening socket, as a new entry with
+    /// `flags`.
+    pub async fn accept(&self, flags: FdFlags) -> Result<SocketEntry, Error> {
+        check_socket_fdflags(flags)?;
+        let listener = match &self.state {
+            SocketState::Unconnected(..) => {
+                return Err(Error::invalid_argument().context("socket is not listening"))
+            }
+            SocketState::Open(socket) => socket,
+        };
+        let socket = listener.accept().await?;
+        socket
+            .set_nonblocking(flags.contains(FdFlags::NONBLOCK))
+            .await?;
+        Ok(SocketEntry {
+            flags,
+            state: SocketState::Open(socket),
+        })
+    }
+
+    pub fn get_open(&self) -> Result<&dyn WasiSocket, Error> {
+        match &self.state {
+            SocketState::Open(socket) => Ok(&**socket),
+            SocketState::Unconnected(..) => Err(Error::not_connected()),
+        }
+    }
+}
+
+/// Sockets only have the `NONBLOCK` flag.
+fn check_socket_fdflags(flags: FdFlags) -> Result<(), Error> {
+    if flags.difference(FdFlags::NONBLOCK).is_empty() {
+        Ok(())
+    } else {
+        Err(Error::invalid_argument().context("sockets only support the NONBLOCK flag"))
+    }
+}
@@ -91,6 +91,17 @@ impl WasiCtxBuilder {
         self.0.push_preopened_dir(dir, guest_path)?;
         Ok(self)
     }
+    pub fn hostcall_recorder(
+        mut self,
+        recorder: std::sync::Arc<dyn wasi_common::HostcallRecorder>,
+    ) -> Self {
+        self.0.set_hostcall_recorder(recorder);
+        self
+    }
+    pub fn hostcall_log(mut self, log: std::sync::Arc<dyn wasi_common::HostcallLog>) -> Self {
+        self.0.set_hostcall_log(log);
+        self
+    }
     pub fn build(self) -> WasiCtx {
         self.0
     }
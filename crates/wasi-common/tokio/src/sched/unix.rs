@@ -1,12 +1,14 @@
 use std::future::Future;
+use std::os::unix::io::AsRawFd;
 use std::pin::Pin;
 use std::task::{Context, Poll as FPoll};
+use tokio::io::{unix::AsyncFd, Interest};
 use wasi_common::{
     sched::{
         subscription::{RwEventFlags, Subscription},
         Poll,
     },
-    Context as _, Error,
+    Context as _, Error, ErrorExt,
 };
 
 struct FirstReady<'a, T>(Vec<Pin<Box<dyn Future<Output = T> + Send + 'a>>>);
@@ -75,6 +77,22 @@ pub async fn poll_oneoff<'a>(poll: &mut Poll<'a>) -> Result<(), Error> {
                     Ok(())
                 });
             }
+            Subscription::SocketRead(s) => {
+                let rawfd = wasi_cap_std_sync::net::wasi_socket_fd(s.socket)
+                    .ok_or(
+                        Error::invalid_argument()
+                            .context("read subscription socket downcast failed"),
+                    )?
+                    .as_raw_fd();
+                futures.push(async move {
+                    let asyncfd = AsyncFd::with_interest(rawfd, Interest::READABLE)?;
+                    let _ = asyncfd.readable().await.context("socket readable future")?;
+                    // There's no count of the bytes, or the connections,
+                    // which are ready on a socket.
+                    s.complete(1, RwEventFlags::empty());
+                    Ok(())
+                });
+            }
             Subscription::MonotonicClock { .. } => unreachable!(),
         }
     }
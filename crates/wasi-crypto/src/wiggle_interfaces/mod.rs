@@ -2,6 +2,7 @@ pub use wasi_crypto::CryptoCtx as WasiCryptoCtx;
 
 wiggle::from_witx!({
     witx: ["$CARGO_MANIFEST_DIR/spec/witx/wasi_ephemeral_crypto.witx"],
+    instrument: true,
 });
 
 pub mod wasi_modules {
@@ -1,5 +1,6 @@
 use crate::memory::MemoryCreator;
 use crate::trampoline::MemoryCreatorProxy;
+use crate::HostcallInstrumentation;
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::cmp;
@@ -94,6 +95,8 @@ pub struct Config {
     pub(crate) cache_config: CacheConfig,
     pub(crate) profiler: Arc<dyn ProfilingAgent>,
     pub(crate) mem_creator: Option<Arc<dyn RuntimeMemoryCreator>>,
+    pub(crate) hostcall_instrumentation: Option<Arc<dyn HostcallInstrumentation>>,
+    pub(crate) instrument_libcalls: bool,
     pub(crate) allocation_strategy: InstanceAllocationStrategy,
     pub(crate) max_wasm_stack: usize,
     pub(crate) features: WasmFeatures,
@@ -118,6 +121,8 @@ impl Config {
             cache_config: CacheConfig::new_cache_disabled(),
             profiler: Arc::new(NullProfilerAgent),
             mem_creator: None,
+            hostcall_instrumentation: None,
+            instrument_libcalls: false,
             allocation_strategy: InstanceAllocationStrategy::OnDemand,
             max_wasm_stack: 1 << 20,
             wasm_backtrace_details_env_used: false,
@@ -726,6 +731,38 @@ impl Config {
         self
     }
 
+    /// Sets the instrumentation told about every call from WebAssembly into a
+    /// host function.
+    ///
+    /// See [`HostcallInstrumentation`] for more information.
+    ///
+    /// By default no instrumentation is installed, and calls into host
+    /// functions carry no extra overhead.
+    pub fn hostcall_instrumentation(
+        &mut self,
+        instrumentation: Arc<dyn HostcallInstrumentation>,
+    ) -> &mut Self {
+        self.hostcall_instrumentation = Some(instrumentation);
+        self
+    }
+
+    /// Configures whether calls from WebAssembly into the runtime's own
+    /// library functions are reported to the
+    /// [`hostcall_instrumentation`](Config::hostcall_instrumentation) too.
+    ///
+    /// These are the functions compiled code calls to implement instructions
+    /// such as `memory.grow`, `table.grow`, `memory.copy` and `memory.fill`,
+    /// along with garbage collection of `externref`s and running out of fuel.
+    /// They are reported under the module `"runtime"`, with names such as
+    /// `"memory32_grow"`, and [`HostcallInstrumentation::instrument`] is not
+    /// asked about them.
+    ///
+    /// This is `false` by default.
+    pub fn instrument_libcalls(&mut self, enable: bool) -> &mut Self {
+        self.instrument_libcalls = enable;
+        self
+    }
+
     /// Sets the instance allocation strategy to use.
     ///
     /// When using the pooling instance allocation strategy, all linear memories
@@ -1112,6 +1149,8 @@ impl Clone for Config {
             profiler: self.profiler.clone(),
             features: self.features.clone(),
             mem_creator: self.mem_creator.clone(),
+            hostcall_instrumentation: self.hostcall_instrumentation.clone(),
+            instrument_libcalls: self.instrument_libcalls,
             allocation_strategy: self.allocation_strategy.clone(),
             max_wasm_stack: self.max_wasm_stack,
             wasm_backtrace_details_env_used: self.wasm_backtrace_details_env_used,
@@ -1153,7 +1192,12 @@ impl fmt::Debug for Config {
                 "guard_before_linear_memory",
                 &self.tunables.guard_before_linear_memory,
             )
-            .field("parallel_compilation", &self.parallel_compilation);
+            .field("parallel_compilation", &self.parallel_compilation)
+            .field(
+                "hostcall_instrumentation",
+                &self.hostcall_instrumentation.is_some(),
+            )
+            .field("instrument_libcalls", &self.instrument_libcalls);
         #[cfg(compiler)]
         {
             f.field("compiler", &self.compiler);
@@ -236,7 +236,10 @@ macro_rules! for_each_function_signature {
     };
 }
 
+mod instrumentation;
 mod typed;
+pub use instrumentation::HostcallInstrumentation;
+pub(crate) use instrumentation::HostcallProbe;
 pub use typed::*;
 
 macro_rules! generate_wrap_async_func {
@@ -342,7 +345,8 @@ impl Func {
         func: impl Fn(Caller<'_, T>, *mut ValRaw) -> Result<(), Trap> + Send + Sync + 'static,
     ) -> Self {
         let store = store.as_context_mut().0;
-        let host = HostFunc::new_unchecked(store.engine(), ty, func);
+        let probe = HostcallProbe::anonymous(store.engine());
+        let host = HostFunc::new_unchecked(store.engine(), ty, func, probe);
         host.into_func(store)
     }
 
@@ -664,7 +668,8 @@ impl Func {
         // part of this unsafety is about matching the `T` to a `Store<T>`,
         // which is done through the `AsContextMut` bound above.
         unsafe {
-            let host = HostFunc::wrap(store.engine(), func);
+            let probe = HostcallProbe::anonymous(store.engine());
+            let host = HostFunc::wrap(store.engine(), func, probe);
             host.into_func(store)
         }
     }
@@ -1601,7 +1606,17 @@ for_each_function_signature!(impl_host_abi);
 /// as an implementation detail of this crate.
 pub trait IntoFunc<T, Params, Results>: Send + Sync + 'static {
     #[doc(hidden)]
-    fn into_func(self, engine: &Engine) -> (InstanceHandle, VMTrampoline);
+    fn into_func(
+        self,
+        engine: &Engine,
+        probe: Option<Arc<HostcallProbe>>,
+    ) -> (InstanceHandle, VMTrampoline);
+}
+
+/// The host state of a function created from an [`IntoFunc`].
+struct WrappedFunc<F> {
+    func: F,
+    probe: Option<Arc<HostcallProbe>>,
 }
 
 /// A structure representing the caller's context when creating a function
@@ -1794,12 +1809,12 @@ macro_rules! impl_into_func {
             $($args: WasmTy,)*
             R: WasmRet,
         {
-            fn into_func(self, engine: &Engine) -> (InstanceHandle, VMTrampoline) {
+            fn into_func(self, engine: &Engine, probe: Option<Arc<HostcallProbe>>) -> (InstanceHandle, VMTrampoline) {
                 let f = move |_: Caller<'_, T>, $($args:$args),*| {
                     self($($args),*)
                 };
 
-                f.into_func(engine)
+                f.into_func(engine, probe)
             }
         }
 
@@ -1810,7 +1825,7 @@ macro_rules! impl_into_func {
             $($args: WasmTy,)*
             R: WasmRet,
         {
-            fn into_func(self, engine: &Engine) -> (InstanceHandle, VMTrampoline) {
+            fn into_func(self, engine: &Engine, probe: Option<Arc<HostcallProbe>>) -> (InstanceHandle, VMTrampoline) {
                 /// This shim is called by Wasm code, constructs a `Caller`,
                 /// calls the wrapped host function, and returns the translated
                 /// result back to Wasm.
@@ -1841,13 +1856,16 @@ macro_rules! impl_into_func {
                     // destructors. As a result anything requiring a destructor
                     // should be part of this block, and the long-jmp-ing
                     // happens after the block in handling `CallResult`.
+                    let state = (*vmctx).host_state();
+                    // Double-check ourselves in debug mode, but we control
+                    // the `Any` here so an unsafe downcast should also
+                    // work.
+                    debug_assert!(state.is::<WrappedFunc<F>>());
+                    let state = &*(state as *const _ as *const WrappedFunc<F>);
+                    let entered = state.probe.as_ref().map(|probe| probe.enter());
+
                     let result = Caller::with(caller_vmctx, |mut caller| {
-                        let state = (*vmctx).host_state();
-                        // Double-check ourselves in debug mode, but we control
-                        // the `Any` here so an unsafe downcast should also
-                        // work.
-                        debug_assert!(state.is::<F>());
-                        let func = &*(state as *const _ as *const F);
+                        let func = &state.func;
 
                         let ret = {
                             panic::catch_unwind(AssertUnwindSafe(|| {
@@ -1892,6 +1910,10 @@ macro_rules! impl_into_func {
                         }
                     });
 
+                    if let (Some(probe), Some(entered)) = (&state.probe, entered) {
+                        probe.exit(entered, !matches!(result, CallResult::Ok(_)));
+                    }
+
                     match result {
                         CallResult::Ok(val) => val,
                         CallResult::Trap(trap) => raise_user_trap(trap),
@@ -1953,7 +1975,7 @@ macro_rules! impl_into_func {
                             0,
                         ),
                         shared_signature_id,
-                        Box::new(self),
+                        Box::new(WrappedFunc { func: self, probe }),
                     )
                     .expect("failed to create raw function")
                 };
@@ -1998,12 +2020,16 @@ impl HostFunc {
         engine: &Engine,
         ty: FuncType,
         func: impl Fn(Caller<'_, T>, &[Val], &mut [Val]) -> Result<(), Trap> + Send + Sync + 'static,
+        probe: Option<Arc<HostcallProbe>>,
     ) -> Self {
         let ty_clone = ty.clone();
         unsafe {
-            HostFunc::new_unchecked(engine, ty, move |caller, values| {
-                Func::invoke(caller, &ty_clone, values, &func)
-            })
+            HostFunc::new_unchecked(
+                engine,
+                ty,
+                move |caller, values| Func::invoke(caller, &ty_clone, values, &func),
+                probe,
+            )
         }
     }
 
@@ -2013,9 +2039,15 @@ impl HostFunc {
         engine: &Engine,
         ty: FuncType,
         func: impl Fn(Caller<'_, T>, *mut ValRaw) -> Result<(), Trap> + Send + Sync + 'static,
+        probe: Option<Arc<HostcallProbe>>,
     ) -> Self {
         let func = move |caller_vmctx, values: *mut ValRaw| unsafe {
-            Caller::<T>::with(caller_vmctx, |caller| func(caller, values))
+            let entered = probe.as_ref().map(|probe| probe.enter());
+            let result = Caller::<T>::with(caller_vmctx, |caller| func(caller, values));
+            if let (Some(probe), Some(entered)) = (&probe, entered) {
+                probe.exit(entered, result.is_err());
+            }
+            result
         };
         let (instance, trampoline) = crate::trampoline::create_function(&ty, func, engine)
             .expect("failed to create function");
@@ -2026,8 +2058,9 @@ impl HostFunc {
     pub fn wrap<T, Params, Results>(
         engine: &Engine,
         func: impl IntoFunc<T, Params, Results>,
+        probe: Option<Arc<HostcallProbe>>,
     ) -> Self {
-        let (instance, trampoline) = func.into_func(engine);
+        let (instance, trampoline) = func.into_func(engine, probe);
         HostFunc::_new(engine, instance, trampoline)
     }
 
@@ -0,0 +1,81 @@
+use crate::Engine;
+use std::sync::Arc;
+
+/// Observes calls from WebAssembly into host functions.
+///
+/// An implementation is installed with
+/// [`Config::hostcall_instrumentation`](crate::Config::hostcall_instrumentation)
+/// and is then told about every call into a host function created with that
+/// configuration, whether through [`Linker::func_wrap`](crate::Linker::func_wrap),
+/// [`Linker::func_new`](crate::Linker::func_new), [`Func::wrap`](crate::Func::wrap),
+/// [`Func::new`](crate::Func::new) or the C API.
+///
+/// Calls are identified by the module and name the function was defined under
+/// in a [`Linker`](crate::Linker). Functions created directly through `Func`
+/// have no name, and are reported under the module `""` and the name
+/// `"<anonymous>"`.
+pub trait HostcallInstrumentation: Send + Sync {
+    /// Whether calls to `module::name` should be observed at all.
+    ///
+    /// This is asked once, when the host function is defined, which allows
+    /// skipping functions which are already instrumented some other way.
+    /// Defaults to `true`.
+    fn instrument(&self, module: &str, name: &str) -> bool {
+        let _ = (module, name);
+        true
+    }
+
+    /// Called as soon as WebAssembly has called into `module::name`, before
+    /// its arguments are converted. The returned value, typically a
+    /// timestamp, is passed back to [`HostcallInstrumentation::exit`].
+    fn enter(&self, module: &str, name: &str) -> u64;
+
+    /// Called just before control returns to WebAssembly, after the host
+    /// function's results have been converted. `trapped` is set if the call
+    /// is returning with a trap rather than results.
+    fn exit(&self, module: &str, name: &str, entered: u64, trapped: bool);
+}
+
+/// The name a host function is reported under, along with the
+/// instrumentation to report it to.
+///
+/// This is only `pub` because it appears in [`IntoFunc`](crate::IntoFunc),
+/// and is not reachable from outside the crate.
+#[doc(hidden)]
+pub struct HostcallProbe {
+    instrumentation: Arc<dyn HostcallInstrumentation>,
+    module: String,
+    name: String,
+}
+
+impl HostcallProbe {
+    /// Returns a probe for `module::name` if `engine` is configured with
+    /// instrumentation which wants to observe it.
+    pub(crate) fn new(engine: &Engine, module: &str, name: &str) -> Option<Arc<HostcallProbe>> {
+        let instrumentation = engine.config().hostcall_instrumentation.as_ref()?;
+        if !instrumentation.instrument(module, name) {
+            return None;
+        }
+        Some(Arc::new(HostcallProbe {
+            instrumentation: instrumentation.clone(),
+            module: module.to_string(),
+            name: name.to_string(),
+        }))
+    }
+
+    /// A probe for a host function created without a name.
+    pub(crate) fn anonymous(engine: &Engine) -> Option<Arc<HostcallProbe>> {
+        HostcallProbe::new(engine, "", "<anonymous>")
+    }
+
+    #[inline]
+    pub(crate) fn enter(&self) -> u64 {
+        self.instrumentation.enter(&self.module, &self.name)
+    }
+
+    #[inline]
+    pub(crate) fn exit(&self, entered: u64, trapped: bool) {
+        self.instrumentation
+            .exit(&self.module, &self.name, entered, trapped)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::func::HostFunc;
+use crate::func::{HostFunc, HostcallProbe};
 use crate::instance::{InstanceData, InstancePre};
 use crate::store::StoreOpaque;
 use crate::{
@@ -309,7 +309,8 @@ impl<T> Linker<T> {
         ty: FuncType,
         func: impl Fn(Caller<'_, T>, &[Val], &mut [Val]) -> Result<(), Trap> + Send + Sync + 'static,
     ) -> Result<&mut Self> {
-        let func = HostFunc::new(&self.engine, ty, func);
+        let probe = HostcallProbe::new(&self.engine, module, name);
+        let func = HostFunc::new(&self.engine, ty, func, probe);
         let key = self.import_key(module, Some(name));
         self.insert(key, Definition::HostFunc(Arc::new(func)))?;
         Ok(self)
@@ -327,7 +328,8 @@ impl<T> Linker<T> {
         ty: FuncType,
         func: impl Fn(Caller<'_, T>, *mut ValRaw) -> Result<(), Trap> + Send + Sync + 'static,
     ) -> Result<&mut Self> {
-        let func = HostFunc::new_unchecked(&self.engine, ty, func);
+        let probe = HostcallProbe::new(&self.engine, module, name);
+        let func = HostFunc::new_unchecked(&self.engine, ty, func, probe);
         let key = self.import_key(module, Some(name));
         self.insert(key, Definition::HostFunc(Arc::new(func)))?;
         Ok(self)
@@ -431,7 +433,8 @@ impl<T> Linker<T> {
         name: &str,
         func: impl IntoFunc<T, Params, Args>,
     ) -> Result<&mut Self> {
-        let func = HostFunc::wrap(&self.engine, func);
+        let probe = HostcallProbe::new(&self.engine, module, name);
+        let func = HostFunc::wrap(&self.engine, func, probe);
         let key = self.import_key(module, Some(name));
         self.insert(key, Definition::HostFunc(Arc::new(func)))?;
         Ok(self)
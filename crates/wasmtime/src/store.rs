@@ -1535,6 +1535,21 @@ unsafe impl<T> wasmtime_runtime::Store for StoreInner<T> {
         <StoreOpaque>::vminterrupts(self)
     }
 
+    fn libcall_enter(&mut self, name: &'static str) -> Option<u64> {
+        let config = self.engine().config();
+        if !config.instrument_libcalls {
+            return None;
+        }
+        let instrumentation = config.hostcall_instrumentation.as_ref()?;
+        Some(instrumentation.enter("runtime", name))
+    }
+
+    fn libcall_exit(&mut self, name: &'static str, entered: u64, trapped: bool) {
+        if let Some(instrumentation) = &self.engine().config().hostcall_instrumentation {
+            instrumentation.exit("runtime", name, entered, trapped);
+        }
+    }
+
     fn externref_activations_table(
         &mut self,
     ) -> (
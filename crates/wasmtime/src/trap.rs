@@ -301,6 +301,40 @@ impl Trap {
     }
 }
 
+/// Returns the WebAssembly frames on the current thread's stack, innermost
+/// first, stopping after `max_frames`.
+///
+/// These are the same frames [`Trap::trace`] would report, but captured
+/// without trapping, which is useful to find out which WebAssembly functions
+/// led to a call into the host. Only frames of modules created in this
+/// process are found, so this returns nothing when not called from within a
+/// host function.
+///
+/// Walking the stack is slow compared to most host functions, so profilers
+/// calling this on every host call should expect it to show up in their
+/// timings.
+pub fn wasm_backtrace(max_frames: usize) -> Vec<FrameInfo> {
+    let mut frames = Vec::new();
+    if max_frames == 0 {
+        return frames;
+    }
+    GlobalModuleRegistry::with(|registry| {
+        backtrace::trace(|frame| {
+            let pc = frame.ip() as usize;
+            if pc == 0 {
+                return true;
+            }
+            // Every frame here is a return address, so look up the call
+            // instruction before it as in `Trap::new_with_trace`.
+            if let Some((info, _, _)) = registry.lookup_frame_info(pc - 1) {
+                frames.push(info);
+            }
+            frames.len() < max_frames
+        });
+    });
+    frames
+}
+
 impl fmt::Debug for Trap {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Trap")
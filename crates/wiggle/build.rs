@@ -0,0 +1,30 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    // Performance counters are read with `rdpmc` through the `asm!` macro,
+    // which is only stable as of Rust 1.59. Older compilers, such as the
+    // nightly the docs are built with, get `#[cfg(stable_asm)]` left unset
+    // and read the counters with a syscall instead.
+    if rustc_minor_version().map_or(false, |minor| minor >= 59) {
+        println!("cargo:rustc-cfg=stable_asm");
+    }
+    println!("cargo:rustc-check-cfg=cfg(stable_asm)");
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// The minor version of the compiler, from `rustc --version`. A nightly may
+/// predate the stabilizations of the release it leads up to, so it counts
+/// as the release before.
+fn rustc_minor_version() -> Option<u32> {
+    let rustc = env::var_os("RUSTC")?;
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    let version = output.split_whitespace().nth(1)?;
+    let minor: u32 = version.split('.').nth(1)?.parse().ok()?;
+    if version.contains('-') {
+        minor.checked_sub(1)
+    } else {
+        Some(minor)
+    }
+}
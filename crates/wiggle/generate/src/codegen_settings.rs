@@ -1,4 +1,4 @@
-use crate::config::{AsyncConf, ErrorConf};
+use crate::config::{AsyncConf, ErrorConf, InstrumentConf};
 use anyhow::{anyhow, Error};
 use proc_macro2::TokenStream;
 use quote::quote;
@@ -12,24 +12,56 @@ pub struct CodegenSettings {
     pub errors: ErrorTransform,
     pub async_: AsyncConf,
     pub wasmtime: bool,
+    pub instrument: InstrumentConf,
+    pub guest_memory: InstrumentConf,
+    pub hostcall_log: InstrumentConf,
+    pub timing: bool,
 }
 impl CodegenSettings {
     pub fn new(
         error_conf: &ErrorConf,
         async_: &AsyncConf,
+        instrument: &InstrumentConf,
+        guest_memory: &InstrumentConf,
+        hostcall_log: &InstrumentConf,
         doc: &Document,
         wasmtime: bool,
+        timing: bool,
     ) -> Result<Self, Error> {
         let errors = ErrorTransform::new(error_conf, doc)?;
         Ok(Self {
             errors,
             async_: async_.clone(),
             wasmtime,
+            instrument: instrument.clone(),
+            guest_memory: guest_memory.clone(),
+            hostcall_log: hostcall_log.clone(),
+            timing,
         })
     }
     pub fn get_async(&self, module: &Module, func: &InterfaceFunc) -> Asyncness {
         self.async_.get(module.name.as_str(), func.name.as_str())
     }
+    /// Whether the abi function for `func` is timed. This is always `false`
+    /// unless wiggle was built with the `timing` feature.
+    pub fn get_instrument(&self, module: &Module, func: &InterfaceFunc) -> bool {
+        self.timing
+            && self
+                .instrument
+                .get(module.name.as_str(), func.name.as_str())
+    }
+    /// Whether the trait method for `func` is called in a
+    /// `wiggle::with_guest_memory` scope.
+    pub fn get_guest_memory(&self, module: &Module, func: &InterfaceFunc) -> bool {
+        self.guest_memory
+            .get(module.name.as_str(), func.name.as_str())
+    }
+    /// Whether the abi function for `func` records its calls to, or replays
+    /// them from, the module trait's `hostcall_log`.
+    pub fn get_hostcall_log(&self, module: &Module, func: &InterfaceFunc) -> bool {
+        self.hostcall_log
+            .get(module.name.as_str(), func.name.as_str())
+    }
 }
 
 pub struct ErrorTransform {
@@ -15,6 +15,9 @@ pub struct Config {
     pub errors: ErrorConf,
     pub async_: AsyncConf,
     pub wasmtime: bool,
+    pub instrument: InstrumentConf,
+    pub guest_memory: InstrumentConf,
+    pub hostcall_log: InstrumentConf,
 }
 
 mod kw {
@@ -24,6 +27,9 @@ mod kw {
     syn::custom_keyword!(errors);
     syn::custom_keyword!(target);
     syn::custom_keyword!(wasmtime);
+    syn::custom_keyword!(instrument);
+    syn::custom_keyword!(guest_memory);
+    syn::custom_keyword!(hostcall_log);
 }
 
 #[derive(Debug, Clone)]
@@ -32,6 +38,9 @@ pub enum ConfigField {
     Error(ErrorConf),
     Async(AsyncConf),
     Wasmtime(bool),
+    Instrument(InstrumentConf),
+    GuestMemory(InstrumentConf),
+    HostcallLog(InstrumentConf),
 }
 
 impl Parse for ConfigField {
@@ -67,6 +76,18 @@ impl Parse for ConfigField {
             input.parse::<kw::wasmtime>()?;
             input.parse::<Token![:]>()?;
             Ok(ConfigField::Wasmtime(input.parse::<syn::LitBool>()?.value))
+        } else if lookahead.peek(kw::instrument) {
+            input.parse::<kw::instrument>()?;
+            input.parse::<Token![:]>()?;
+            Ok(ConfigField::Instrument(input.parse()?))
+        } else if lookahead.peek(kw::guest_memory) {
+            input.parse::<kw::guest_memory>()?;
+            input.parse::<Token![:]>()?;
+            Ok(ConfigField::GuestMemory(input.parse()?))
+        } else if lookahead.peek(kw::hostcall_log) {
+            input.parse::<kw::hostcall_log>()?;
+            input.parse::<Token![:]>()?;
+            Ok(ConfigField::HostcallLog(input.parse()?))
         } else {
             Err(lookahead.error())
         }
@@ -79,6 +100,9 @@ impl Config {
         let mut errors = None;
         let mut async_ = None;
         let mut wasmtime = None;
+        let mut instrument = None;
+        let mut guest_memory = None;
+        let mut hostcall_log = None;
         for f in fields {
             match f {
                 ConfigField::Witx(c) => {
@@ -105,6 +129,24 @@ impl Config {
                     }
                     wasmtime = Some(c);
                 }
+                ConfigField::Instrument(c) => {
+                    if instrument.is_some() {
+                        return Err(Error::new(err_loc, "duplicate `instrument` field"));
+                    }
+                    instrument = Some(c);
+                }
+                ConfigField::GuestMemory(c) => {
+                    if guest_memory.is_some() {
+                        return Err(Error::new(err_loc, "duplicate `guest_memory` field"));
+                    }
+                    guest_memory = Some(c);
+                }
+                ConfigField::HostcallLog(c) => {
+                    if hostcall_log.is_some() {
+                        return Err(Error::new(err_loc, "duplicate `hostcall_log` field"));
+                    }
+                    hostcall_log = Some(c);
+                }
             }
         }
         Ok(Config {
@@ -114,6 +156,9 @@ impl Config {
             errors: errors.take().unwrap_or_default(),
             async_: async_.take().unwrap_or_default(),
             wasmtime: wasmtime.unwrap_or(true),
+            instrument: instrument.take().unwrap_or_default(),
+            guest_memory: guest_memory.take().unwrap_or_default(),
+            hostcall_log: hostcall_log.take().unwrap_or_default(),
         })
     }
 
@@ -457,6 +502,91 @@ impl Parse for AsyncConfField {
     }
 }
 
+#[derive(Clone, Default, Debug)]
+/// Modules and funcs whose generated abi functions are timed with
+/// `wiggle::timing`, or, for the `guest_memory` field, which run their trait
+/// method in a `wiggle::with_guest_memory` scope, or, for the `hostcall_log`
+/// field, which can be recorded to and replayed from a
+/// `wiggle::replay::HostcallLog`. The most specific entry wins, and anything
+/// not mentioned is left out.
+pub struct InstrumentConf {
+    all: bool,
+    modules: HashMap<String, bool>,
+    functions: HashMap<(String, String), bool>,
+}
+
+impl InstrumentConf {
+    pub fn get(&self, module: &str, function: &str) -> bool {
+        self.functions
+            .get(&(module.to_owned(), function.to_owned()))
+            .or_else(|| self.modules.get(module))
+            .copied()
+            .unwrap_or(self.all)
+    }
+}
+
+impl Parse for InstrumentConf {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let lookahead = input.lookahead1();
+        if lookahead.peek(syn::LitBool) {
+            Ok(InstrumentConf {
+                all: input.parse::<syn::LitBool>()?.value,
+                ..Default::default()
+            })
+        } else if lookahead.peek(syn::token::Brace) {
+            let content;
+            let _ = braced!(content in input);
+            let items: Punctuated<InstrumentConfField, Token![,]> =
+                content.parse_terminated(Parse::parse)?;
+            let mut conf = InstrumentConf::default();
+            for i in items {
+                let module_name = i.module_name.to_string();
+                let prev = match i.function_name {
+                    Some(f) => conf
+                        .functions
+                        .insert((module_name, f.to_string()), i.enabled),
+                    None => conf.modules.insert(module_name, i.enabled),
+                };
+                if prev.is_some() {
+                    return Err(Error::new(i.err_loc, "duplicate entry"));
+                }
+            }
+            Ok(conf)
+        } else {
+            Err(lookahead.error())
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct InstrumentConfField {
+    pub module_name: Ident,
+    pub function_name: Option<Ident>,
+    pub enabled: bool,
+    pub err_loc: Span,
+}
+
+impl Parse for InstrumentConfField {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let err_loc = input.span();
+        let module_name = input.parse::<Ident>()?;
+        let function_name = if input.peek(Token![::]) {
+            let _doublecolon: Token![::] = input.parse()?;
+            Some(input.parse::<Ident>()?)
+        } else {
+            None
+        };
+        let _colon: Token![:] = input.parse()?;
+        let enabled = input.parse::<syn::LitBool>()?.value;
+        Ok(InstrumentConfField {
+            module_name,
+            function_name,
+            enabled,
+            err_loc,
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct WasmtimeConfig {
     pub c: Config,
@@ -545,6 +675,18 @@ impl Parse for WasmtimeConfigField {
                 blocking: true,
                 functions: input.parse()?,
             })))
+        } else if lookahead.peek(kw::guest_memory) {
+            input.parse::<kw::guest_memory>()?;
+            input.parse::<Token![:]>()?;
+            Ok(WasmtimeConfigField::Core(ConfigField::GuestMemory(
+                input.parse()?,
+            )))
+        } else if lookahead.peek(kw::hostcall_log) {
+            input.parse::<kw::hostcall_log>()?;
+            input.parse::<Token![:]>()?;
+            Ok(WasmtimeConfigField::Core(ConfigField::HostcallLog(
+                input.parse()?,
+            )))
         } else {
             Err(lookahead.error())
         }
@@ -41,10 +41,14 @@ fn _define_func(
     let param_names = (0..wasm_params.len())
         .map(|i| Ident::new(&format!("arg{}", i), Span::call_site()))
         .collect::<Vec<_>>();
-    let abi_params = wasm_params.iter().zip(&param_names).map(|(arg, name)| {
-        let wasm = names.wasm_type(*arg);
-        quote!(#name : #wasm)
-    });
+    let abi_params = wasm_params
+        .iter()
+        .zip(&param_names)
+        .map(|(arg, name)| {
+            let wasm = names.wasm_type(*arg);
+            quote!(#name : #wasm)
+        })
+        .collect::<Vec<_>>();
 
     let abi_ret = match wasm_results.len() {
         0 => quote!(()),
@@ -55,6 +59,7 @@ fn _define_func(
         _ => unimplemented!(),
     };
 
+    let instrument = settings.get_instrument(module, func);
     let mut body = TokenStream::new();
     let mut bounds = vec![names.trait_name(&module.name)];
     func.call_interface(
@@ -69,6 +74,7 @@ fn _define_func(
             module,
             funcname: func.name.as_str(),
             settings,
+            instrument,
             bounds: &mut bounds,
         },
     );
@@ -83,48 +89,228 @@ fn _define_func(
             function = #func_name
         );
     );
-    if settings.get_async(&module, &func).is_sync() {
-        (
-            quote!(
-                #[allow(unreachable_code)] // deals with warnings in noreturn functions
-                pub fn #ident(
-                    ctx: &mut (impl #(#bounds)+*),
-                    memory: &dyn #rt::GuestMemory,
-                    #(#abi_params),*
-                ) -> Result<#abi_ret, #rt::Trap> {
-                    use super::{start_timer, stop_timer, push_result};
-                    use std::convert::TryFrom as _;
-                    let __start_ts = start_timer();
-                    #mk_span
-                    _span.in_scope(|| {
-                      #body
-                    })
+
+    // Functions whose result is an `expected` with an error type lower that
+    // error to an i32, which is what gets reported to the recorder.
+    let err_ty = func.results.get(0).and_then(|r| match &**r.tref.type_() {
+        witx::Type::Variant(v) => v
+            .as_expected()
+            .and_then(|(_, err)| err.map(|err| names.type_ref(err, anon_lifetime()))),
+        _ => None,
+    });
+    let hostcall_result = match err_ty {
+        Some(err_ty) if wasm_results.len() == 1 => quote! {
+            match &__ret {
+                Ok(e) if *e == <#err_ty as #rt::GuestErrorType>::success() as i32 => {
+                    #rt::timing::HostcallResult::Ok
                 }
-            ),
-            bounds,
-        )
+                Ok(e) => #rt::timing::HostcallResult::Errno(*e),
+                Err(_) => #rt::timing::HostcallResult::Trap,
+            }
+        },
+        _ => quote! {
+            match &__ret {
+                Ok(_) => #rt::timing::HostcallResult::Ok,
+                Err(_) => #rt::timing::HostcallResult::Trap,
+            }
+        },
+    };
+    let trait_name = names.trait_name(&module.name);
+    let timed_ident = names.timed_func(&func.name);
+    let asyncness = settings.get_async(&module, &func);
+
+    // Functions with a `hostcall_log` either replay their result from the
+    // log without running at all, or run against a `LoggedCall` which
+    // notes the guest memory they touch, and hand it back to the log along
+    // with their result.
+    let hostcall_log = settings.get_hostcall_log(module, func);
+    let logged = |call: TokenStream| {
+        if !hostcall_log {
+            return call;
+        }
+        quote! {
+            let __log = #trait_name::hostcall_log(&*ctx);
+            let __call = match &__log {
+                Some(__log) => match __log.begin(
+                    #mod_name,
+                    #func_name,
+                    &[#(#rt::replay::LogValue::to_bits(#param_names)),*],
+                    memory,
+                ) {
+                    #rt::replay::Begin::Call(call) => Some(call),
+                    #rt::replay::Begin::Replayed(ret) => {
+                        return ret.map(#rt::replay::LogValue::from_bits);
+                    }
+                },
+                None => None,
+            };
+            let memory: &dyn #rt::GuestMemory = match &__call {
+                Some(call) => call,
+                None => memory,
+            };
+            let __ret: Result<#abi_ret, #rt::Trap> = #call;
+            if let (Some(__log), Some(__call)) = (__log, __call) {
+                __log.end(__call, __ret.as_ref().map(|r| #rt::replay::LogValue::to_bits(*r)));
+            }
+            __ret
+        }
+    };
+    let sync_body = logged(quote!(_span.in_scope(|| { #body })));
+    let async_body = if hostcall_log {
+        logged(quote!(async { #body }.await))
+    } else {
+        body.clone()
+    };
+    let timed_async_body = logged(quote!(async { #body }.await));
+    // With timing available, every function says whether it is instrumented
+    // and has a `#timed_ident` for the `wasmtime` integration to call, so the
+    // integration follows this `instrument` setting rather than its own.
+    let instrumented_ident = names.instrumented_func(&func.name);
+    let timing_hooks = if !settings.timing {
+        quote!()
+    } else if instrument {
+        quote! {
+            #[doc(hidden)]
+            pub const #instrumented_ident: bool = true;
+        }
+    } else if asyncness.is_sync() {
+        quote! {
+            #[doc(hidden)]
+            pub const #instrumented_ident: bool = false;
+
+            /// The abi function, which isn't instrumented and so leaves
+            /// `__timer` alone.
+            #[doc(hidden)]
+            pub fn #timed_ident(
+                ctx: &mut (impl #(#bounds)+*),
+                memory: &dyn #rt::GuestMemory,
+                _: &mut #rt::timing::HostcallTimer,
+                #(#abi_params),*
+            ) -> Result<#abi_ret, #rt::Trap> {
+                #ident(ctx, memory, #(#param_names),*)
+            }
+        }
     } else {
-        (
-            quote!(
-                #[allow(unreachable_code)] // deals with warnings in noreturn functions
-                pub fn #ident<'a>(
-                    ctx: &'a mut (impl #(#bounds)+*),
-                    memory: &'a dyn #rt::GuestMemory,
-                    #(#abi_params),*
-                ) -> impl std::future::Future<Output = Result<#abi_ret, #rt::Trap>> + 'a {
-                    use super::{start_timer, stop_timer, push_result};
-                    use std::convert::TryFrom as _;
-                    use #rt::tracing::Instrument as _;
-                    let __start_ts = start_timer();
-                    #mk_span
-                    async move {
-                        #body
-                    }.instrument(_span)
+        quote! {
+            #[doc(hidden)]
+            pub const #instrumented_ident: bool = false;
+
+            /// The abi function, which isn't instrumented and so leaves
+            /// `__timer` alone.
+            #[doc(hidden)]
+            pub fn #timed_ident<'a>(
+                ctx: &'a mut (impl #(#bounds)+*),
+                memory: &'a dyn #rt::GuestMemory,
+                _: &'a mut #rt::timing::HostcallTimer,
+                #(#abi_params),*
+            ) -> impl std::future::Future<Output = Result<#abi_ret, #rt::Trap>> + 'a {
+                #ident(ctx, memory, #(#param_names),*)
+            }
+        }
+    };
+    let tokens = match (asyncness.is_sync(), instrument) {
+        (true, false) => quote!(
+            #[allow(unreachable_code)] // deals with warnings in noreturn functions
+            pub fn #ident(
+                ctx: &mut (impl #(#bounds)+*),
+                memory: &dyn #rt::GuestMemory,
+                #(#abi_params),*
+            ) -> Result<#abi_ret, #rt::Trap> {
+                use std::convert::TryFrom as _;
+                #mk_span
+                #sync_body
+            }
+        ),
+        (true, true) => quote!(
+            pub fn #ident(
+                ctx: &mut (impl #(#bounds)+*),
+                memory: &dyn #rt::GuestMemory,
+                #(#abi_params),*
+            ) -> Result<#abi_ret, #rt::Trap> {
+                static __SAMPLER: #rt::timing::Sampler = #rt::timing::Sampler::new();
+                let mut __timer = #rt::timing::HostcallTimer::start(&__SAMPLER);
+                let __ret = #timed_ident(&mut *ctx, memory, &mut __timer, #(#param_names),*);
+                __timer.finish(#trait_name::hostcall_recorder(&*ctx), #mod_name, #func_name);
+                __ret
+            }
+
+            /// The body of the abi function, which stamps each phase of the
+            /// call on `__timer` but leaves it to the caller to finish it.
+            #[doc(hidden)]
+            #[allow(unreachable_code)] // deals with warnings in noreturn functions
+            pub fn #timed_ident(
+                ctx: &mut (impl #(#bounds)+*),
+                memory: &dyn #rt::GuestMemory,
+                __timer: &mut #rt::timing::HostcallTimer,
+                #(#abi_params),*
+            ) -> Result<#abi_ret, #rt::Trap> {
+                use std::convert::TryFrom as _;
+                __timer.lift();
+                #mk_span
+                let __ret: Result<#abi_ret, #rt::Trap> = { #sync_body };
+                __timer.lowered(#hostcall_result);
+                __ret
+            }
+        ),
+        (false, false) => quote!(
+            #[allow(unreachable_code)] // deals with warnings in noreturn functions
+            pub fn #ident<'a>(
+                ctx: &'a mut (impl #(#bounds)+*),
+                memory: &'a dyn #rt::GuestMemory,
+                #(#abi_params),*
+            ) -> impl std::future::Future<Output = Result<#abi_ret, #rt::Trap>> + 'a {
+                use std::convert::TryFrom as _;
+                use #rt::tracing::Instrument as _;
+                #mk_span
+                async move {
+                    #async_body
+                }.instrument(_span)
+            }
+        ),
+        (false, true) => quote!(
+            pub fn #ident<'a>(
+                ctx: &'a mut (impl #(#bounds)+*),
+                memory: &'a dyn #rt::GuestMemory,
+                #(#abi_params),*
+            ) -> impl std::future::Future<Output = Result<#abi_ret, #rt::Trap>> + 'a {
+                static __SAMPLER: #rt::timing::Sampler = #rt::timing::Sampler::new();
+                let mut __timer = #rt::timing::HostcallTimer::start(&__SAMPLER);
+                async move {
+                    let __sampled = __timer.sampled();
+                    let (__ret, __polls) = #rt::timing::Polled::new(
+                        #timed_ident(&mut *ctx, memory, &mut __timer, #(#param_names),*),
+                        __sampled,
+                    ).await;
+                    __timer.polled(__polls);
+                    __timer.finish(#trait_name::hostcall_recorder(&*ctx), #mod_name, #func_name);
+                    __ret
                 }
-            ),
-            bounds,
-        )
-    }
+            }
+
+            /// The body of the abi function, which stamps each phase of the
+            /// call on `__timer` but leaves it to the caller to finish it.
+            #[doc(hidden)]
+            #[allow(unreachable_code)] // deals with warnings in noreturn functions
+            pub fn #timed_ident<'a>(
+                ctx: &'a mut (impl #(#bounds)+*),
+                memory: &'a dyn #rt::GuestMemory,
+                __timer: &'a mut #rt::timing::HostcallTimer,
+                #(#abi_params),*
+            ) -> impl std::future::Future<Output = Result<#abi_ret, #rt::Trap>> + 'a {
+                use std::convert::TryFrom as _;
+                use #rt::tracing::Instrument as _;
+                #mk_span
+                async move {
+                    __timer.lift();
+                    let __ret: Result<#abi_ret, #rt::Trap> = { #timed_async_body };
+                    __timer.lowered(#hostcall_result);
+                    __ret
+                }.instrument(_span)
+            }
+        ),
+    };
+    let tokens = quote!(#tokens #timing_hooks);
+    (tokens, bounds)
 }
 
 struct Rust<'a> {
@@ -137,6 +323,7 @@ struct Rust<'a> {
     module: &'a witx::Module,
     funcname: &'a str,
     settings: &'a CodegenSettings,
+    instrument: bool,
     bounds: &'a mut Vec<Ident>,
 }
 
@@ -267,23 +454,38 @@ impl witx::Bindgen for Rust<'_> {
 
                 let trait_name = self.names.trait_name(&self.module.name);
                 let ident = self.names.func(&func.name);
-                if self.settings.get_async(&self.module, &func).is_sync() {
-                    self.src.extend(quote! {
+                if self.instrument {
+                    self.src.extend(quote!(__timer.call();));
+                }
+                let scoped = self.settings.get_guest_memory(self.module, func);
+                match (self.settings.get_async(self.module, func).is_sync(), scoped) {
+                    (true, false) => self.src.extend(quote! {
                         let ret = #trait_name::#ident(ctx, #(#args),*);
-                    })
-                } else {
-                    self.src.extend(quote! {
+                    }),
+                    (true, true) => self.src.extend(quote! {
+                        let ret = #rt::with_memory_scope(memory, || {
+                            #trait_name::#ident(ctx, #(#args),*)
+                        });
+                    }),
+                    (false, false) => self.src.extend(quote! {
                         let ret = #trait_name::#ident(ctx, #(#args),*).await;
-                    })
-                };
+                    }),
+                    (false, true) => self.src.extend(quote! {
+                        let ret = #rt::MemoryScoped::new(
+                            memory,
+                            #trait_name::#ident(ctx, #(#args),*),
+                        ).await;
+                    }),
+                }
                 self.src.extend(quote! {
                     #rt::tracing::event!(
                         #rt::tracing::Level::TRACE,
                         result = #rt::tracing::field::debug(&ret),
                     );
-                    let __end_ts = stop_timer();
-                    push_result(stringify!(#ident), __start_ts, __end_ts);
                 });
+                if self.instrument {
+                    self.src.extend(quote!(__timer.call_end();));
+                }
 
                 if func.results.len() > 0 {
                     results.push(quote!(ret));
@@ -92,6 +92,18 @@ pub fn define_module_trait(names: &Names, m: &Module, settings: &CodegenSettings
         #[#rt::async_trait]
         pub trait #traitname {
             #(#traitmethods)*
+
+            /// The recorder for hostcalls made through this context, or
+            /// `None` to use the process-wide `wiggle::timing::recorder`.
+            fn hostcall_recorder(&self) -> Option<&dyn #rt::timing::HostcallRecorder> {
+                None
+            }
+
+            /// The log which hostcalls made through this context are recorded
+            /// to, or replayed from, or `None` to run them as usual.
+            fn hostcall_log(&self) -> Option<std::sync::Arc<dyn #rt::replay::HostcallLog>> {
+                None
+            }
         }
     }
 }
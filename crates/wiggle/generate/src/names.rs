@@ -152,6 +152,18 @@ impl Names {
         escape_id(id, NamingConvention::SnakeCase)
     }
 
+    /// The hidden function holding the body of an instrumented abi function,
+    /// which the `wasmtime` integration calls with its own timer.
+    pub fn timed_func(&self, id: &Id) -> Ident {
+        format_ident!("__timed_{}", self.func(id))
+    }
+
+    /// The hidden constant telling the `wasmtime` integration whether an abi
+    /// function is instrumented.
+    pub fn instrumented_func(&self, id: &Id) -> Ident {
+        format_ident!("__INSTRUMENTED_{}", id.as_str().to_shouty_snake_case())
+    }
+
     /// Convert a parameter name from its [`Id`][witx] name to its Rust [`Ident`][id] representation.
     ///
     /// [id]: https://docs.rs/proc-macro2/*/proc_macro2/struct.Ident.html
@@ -23,7 +23,14 @@ pub fn link_module(
     let mut bounds = HashSet::new();
     for f in module.funcs() {
         let asyncness = settings.async_.get(module.name.as_str(), f.name.as_str());
-        bodies.push(generate_func(&module, &f, names, target_path, asyncness));
+        bodies.push(generate_func(
+            &module,
+            &f,
+            names,
+            target_path,
+            asyncness,
+            settings.timing,
+        ));
         let bound = func_bounds(names, module, &f, settings);
         for b in bound {
             bounds.insert(b);
@@ -69,6 +76,7 @@ fn generate_func(
     names: &Names,
     target_path: Option<&syn::Path>,
     asyncness: Asyncness,
+    timing: bool,
 ) -> TokenStream {
     let rt = names.runtime_mod();
 
@@ -105,33 +113,100 @@ fn generate_func(
         quote!(.await)
     };
 
-    let abi_func = if let Some(target_path) = target_path {
-        quote!( #target_path::#module_ident::#field_ident )
-    } else {
-        quote!( #field_ident )
+    let module_path = match target_path {
+        Some(target_path) => quote!( #target_path::#module_ident:: ),
+        None => quote!(),
     };
-
-    let body = quote! {
-        let mem = match caller.get_export("memory") {
-            Some(#rt::wasmtime_crate::Extern::Memory(m)) => m,
-            _ => {
-                return Err(#rt::wasmtime_crate::Trap::new("missing required memory export"));
-            }
+    let body = |instrument: bool| {
+        let abi_func = if instrument {
+            let timed_ident = names.timed_func(&func.name);
+            quote!( #module_path #timed_ident )
+        } else {
+            quote!( #module_path #field_ident )
+        };
+        // Instrumented functions are timed from here, rather than from the abi
+        // function, so that the transitions between wasm and the abi function
+        // are measured too.
+        let (start_timer, timer_arg, finish_timer) = if instrument {
+            let trait_name = names.trait_name(&module.name);
+            (
+                quote! {
+                    static __SAMPLER: #rt::timing::Sampler = #rt::timing::Sampler::new();
+                    let mut __timer = #rt::timing::HostcallTimer::start(&__SAMPLER);
+                },
+                quote!(&mut __timer,),
+                quote!( __timer.finish(#module_path #trait_name::hostcall_recorder(&*ctx), #module_str, #field_str); ),
+            )
+        } else {
+            (quote!(), quote!(), quote!())
+        };
+        let call = quote!( #abi_func(&mut *ctx, &mem, #timer_arg #(#arg_names),*) );
+        // The futures of async hostcalls are wrapped to measure the time they
+        // spend running rather than suspended.
+        let call = if !instrument {
+            quote!( #call #await_ )
+        } else if asyncness.is_sync() {
+            call
+        } else {
+            quote! {{
+                let sampled = __timer.sampled();
+                let (ret, polls) = #rt::timing::Polled::new(#call, sampled).await;
+                __timer.polled(polls);
+                ret
+            }}
         };
-        let (mem , ctx) = mem.data_and_store_mut(&mut caller);
-        let ctx = get_cx(ctx);
-        let mem = #rt::wasmtime::WasmtimeGuestMemory::new(mem);
-        match #abi_func(ctx, &mem #(, #arg_names)*) #await_ {
-            Ok(r) => Ok(<#ret_ty>::from(r)),
-            Err(#rt::Trap::String(err)) => Err(#rt::wasmtime_crate::Trap::new(err)),
-            Err(#rt::Trap::I32Exit(err)) => Err(#rt::wasmtime_crate::Trap::i32_exit(err)),
+        quote! {
+            #start_timer
+            let mem = match caller.get_export("memory") {
+                Some(#rt::wasmtime_crate::Extern::Memory(m)) => m,
+                _ => {
+                    return Err(#rt::wasmtime_crate::Trap::new("missing required memory export"));
+                }
+            };
+            let (mem , ctx) = mem.data_and_store_mut(&mut caller);
+            let ctx = get_cx(ctx);
+            let mem = #rt::wasmtime::WasmtimeGuestMemory::new(mem);
+            let result = match #call {
+                Ok(r) => Ok(<#ret_ty>::from(r)),
+                Err(#rt::Trap::String(err)) => Err(#rt::wasmtime_crate::Trap::new(err)),
+                Err(#rt::Trap::I32Exit(err)) => Err(#rt::wasmtime_crate::Trap::i32_exit(err)),
+            };
+            #finish_timer
+            result
         }
     };
 
+    // Whether a function is instrumented is up to the abi function's
+    // `from_witx!`, which says so in a constant next to it. Instrumented
+    // functions tell any `HostcallInstrumentation` installed in the engine
+    // that they already time themselves.
+    let (body, mark_instrumented) = if timing {
+        let instrumented_ident = names.instrumented_func(&func.name);
+        let timed = body(true);
+        let untimed = body(false);
+        (
+            quote! {
+                if #module_path #instrumented_ident {
+                    #timed
+                } else {
+                    #untimed
+                }
+            },
+            quote! {
+                if #module_path #instrumented_ident {
+                    #rt::timing::mark_instrumented(#module_str, #field_str);
+                }
+            },
+        )
+    } else {
+        (body(false), quote!())
+    };
+
     match asyncness {
         Asyncness::Async => {
             let wrapper = format_ident!("func_wrap{}_async", params.len());
             quote! {
+                #mark_instrumented
                 linker.#wrapper(
                     #module_str,
                     #field_str,
@@ -144,6 +219,7 @@ fn generate_func(
 
         Asyncness::Blocking => {
             quote! {
+                #mark_instrumented
                 linker.func_wrap(
                     #module_str,
                     #field_str,
@@ -157,6 +233,7 @@ fn generate_func(
 
         Asyncness::Sync => {
             quote! {
+                #mark_instrumented
                 linker.func_wrap(
                     #module_str,
                     #field_str,
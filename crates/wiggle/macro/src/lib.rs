@@ -41,6 +41,21 @@ use syn::parse_macro_input;
 ///   WebAssembly execution by trapping.
 /// * Optional: `async` takes a set of witx modules and functions which are
 ///   made Rust `async` functions in the module trait.
+/// * Optional: `instrument` selects the abi-level functions which time each
+///   call and report it to a `wiggle::timing::HostcallRecorder`. It is either
+///   `true`/`false` for every function, or a map like
+///   `{ example: true, example::int_float_args: false }` where a function
+///   entry overrides its module's. Nothing is instrumented unless wiggle is
+///   built with the `timing` feature. A `wasmtime_integration!` of these
+///   functions times the same functions this selects.
+/// * Optional: `guest_memory` takes a map in the same form as `instrument`,
+///   selecting the functions whose module trait methods can reach the memory
+///   of the call with `wiggle::with_guest_memory`. This is for functions
+///   which pass guest pointers as plain integers.
+/// * Optional: `hostcall_log` takes a map in the same form as `instrument`,
+///   selecting the functions whose calls are recorded to, or replayed from,
+///   the `wiggle::replay::HostcallLog` the module trait's `hostcall_log`
+///   method returns.
 ///
 /// ## Example
 ///
@@ -151,8 +166,12 @@ pub fn from_witx(args: TokenStream) -> TokenStream {
     let settings = wiggle_generate::CodegenSettings::new(
         &config.errors,
         &config.async_,
+        &config.instrument,
+        &config.guest_memory,
+        &config.hostcall_log,
         &doc,
         cfg!(feature = "wasmtime") && config.wasmtime,
+        cfg!(feature = "timing"),
     )
     .expect("validating codegen settings");
 
@@ -184,6 +203,9 @@ pub fn async_trait(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// Arguments are provided using struct syntax e.g. `{ arg_name: value }`.
 ///
 /// * `target`: The path of the module where the Wiggle implementation is defined.
+///
+/// The functions the target's `from_witx!` instruments are timed from their
+/// wasm entry, so there's no `instrument` argument here.
 #[proc_macro]
 pub fn wasmtime_integration(args: TokenStream) -> TokenStream {
     let config = parse_macro_input!(args as wiggle_generate::WasmtimeConfig);
@@ -193,8 +215,12 @@ pub fn wasmtime_integration(args: TokenStream) -> TokenStream {
     let settings = wiggle_generate::CodegenSettings::new(
         &config.c.errors,
         &config.c.async_,
+        &config.c.instrument,
+        &config.c.guest_memory,
+        &config.c.hostcall_log,
         &doc,
         cfg!(feature = "wasmtime"),
+        cfg!(feature = "timing"),
     )
     .expect("validating codegen settings");
 
@@ -16,15 +16,18 @@ pub use bitflags;
 pub use witx;
 
 pub mod borrow;
+pub mod replay;
 pub mod timing;
 mod error;
 mod guest_type;
+mod memory_scope;
 mod region;
 
 pub extern crate tracing;
 
 pub use error::GuestError;
 pub use guest_type::{GuestErrorType, GuestType, GuestTypeTransparent};
+pub use memory_scope::{with_guest_memory, with_memory_scope, MemoryScoped};
 pub use region::Region;
 
 pub mod async_trait_crate {
@@ -0,0 +1,77 @@
+//! Access to the guest memory of the hostcall in progress.
+//!
+//! Most hostcalls receive their guest memory through `GuestPtr` arguments,
+//! but some witx signatures pass pointers as plain integers, which leaves
+//! the trait method with no memory to dereference them in. Functions named
+//! in the `guest_memory` field of `from_witx!` run their trait method inside
+//! a scope in which [`with_guest_memory`] yields the memory of the call.
+
+use crate::GuestMemory;
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+thread_local! {
+    static MEMORY: Cell<Option<*const (dyn GuestMemory + 'static)>> = Cell::new(None);
+}
+
+/// Calls `f` with the guest memory of the hostcall in progress, or returns
+/// `None` if the hostcall wasn't generated with access to its memory.
+pub fn with_guest_memory<R>(f: impl FnOnce(&dyn GuestMemory) -> R) -> Option<R> {
+    let mem = MEMORY.with(|m| m.get())?;
+    // Safety: the pointer is only set while `with_memory_scope` or a
+    // `MemoryScoped` future holds the borrow of the memory it came from, and
+    // `f` can't keep the reference beyond this call.
+    Some(f(unsafe { &*mem }))
+}
+
+/// Sets the current guest memory to `mem` for the duration of the returned
+/// guard, restoring the previous one when it is dropped.
+fn enter(mem: &dyn GuestMemory) -> ScopeGuard {
+    // Safety: the lifetime is erased only while the guard lives, which the
+    // callers keep shorter than the borrow of `mem`.
+    let mem: *const (dyn GuestMemory + '_) = mem;
+    let mem: *const (dyn GuestMemory + 'static) = unsafe { std::mem::transmute(mem) };
+    ScopeGuard(MEMORY.with(|m| m.replace(Some(mem))))
+}
+
+struct ScopeGuard(Option<*const (dyn GuestMemory + 'static)>);
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        MEMORY.with(|m| m.set(self.0));
+    }
+}
+
+/// Runs `f` with `mem` as the memory yielded by [`with_guest_memory`].
+pub fn with_memory_scope<R>(mem: &dyn GuestMemory, f: impl FnOnce() -> R) -> R {
+    let _guard = enter(mem);
+    f()
+}
+
+/// A future which makes `mem` the memory yielded by [`with_guest_memory`]
+/// whenever the inner future is polled.
+pub struct MemoryScoped<'a, F> {
+    mem: &'a dyn GuestMemory,
+    future: F,
+}
+
+impl<'a, F: Future> MemoryScoped<'a, F> {
+    /// Wraps `future` so that it runs in the scope of `mem`.
+    pub fn new(mem: &'a dyn GuestMemory, future: F) -> Self {
+        MemoryScoped { mem, future }
+    }
+}
+
+impl<F: Future> Future for MemoryScoped<'_, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // Safety: `future` is structurally pinned; it is never moved out of
+        // `self`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let _guard = enter(this.mem);
+        unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx)
+    }
+}
@@ -0,0 +1,686 @@
+//! Deterministic record and replay of hostcalls.
+//!
+//! Functions named in the `hostcall_log` field of `from_witx!` consult the
+//! [`HostcallLog`] their module trait's `hostcall_log` method returns before
+//! they run. A [`LogRecorder`] lets the call go ahead against a
+//! [`LoggedCall`], which notes every region of guest memory the call touches,
+//! and then writes the arguments, the memory the call read, the memory it
+//! wrote and its result to a compact binary log. A [`LogReplayer`] serves the
+//! same calls from that log without running them at all: it checks that each
+//! call matches the one recorded, writes the recorded bytes back into guest
+//! memory and returns the recorded result. The first call which doesn't match
+//! traps with an error naming its index in the log.
+//!
+//! The log is a header followed by one record per call, with integers
+//! encoded as unsigned LEB128:
+//!
+//! * the index of the call's `module::func` name, followed by the two names
+//!   if this is the first call to the function;
+//! * the number of arguments, and the bits of each;
+//! * the number of regions read, and the offset and bytes of each;
+//! * the number of regions written, and the offset and bytes of each;
+//! * a tag of 0 followed by the bits of the result, 1 followed by the code of
+//!   an `I32Exit` trap, or 2 followed by the message of any other trap.
+
+use crate::{GuestError, GuestMemory, GuestPtr, Region, Trap};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+const MAGIC: &[u8; 8] = b"\0wiggle\x01";
+
+/// Where hostcalls are recorded to, or replayed from.
+pub trait HostcallLog: Send + Sync {
+    /// Starts a call to `module::func` with the raw wasm `args`, made with
+    /// guest memory `memory`.
+    fn begin<'a>(
+        &self,
+        module: &'static str,
+        func: &'static str,
+        args: &[u64],
+        memory: &'a dyn GuestMemory,
+    ) -> Begin<'a>;
+
+    /// Finishes a call which `begin` let go ahead, with the bits of its
+    /// result.
+    fn end(&self, call: LoggedCall<'_>, ret: Result<u64, &Trap>);
+}
+
+/// How a hostcall proceeds, as decided by [`HostcallLog::begin`].
+pub enum Begin<'a> {
+    /// Run the call against this memory, and then pass it to
+    /// [`HostcallLog::end`].
+    Call(LoggedCall<'a>),
+    /// Don't run the call, and return this result instead.
+    Replayed(Result<u64, Trap>),
+}
+
+/// Conversion between the wasm values of arguments and results and the bits
+/// the log stores.
+pub trait LogValue: Sized {
+    fn to_bits(self) -> u64;
+    fn from_bits(bits: u64) -> Self;
+}
+
+impl LogValue for () {
+    fn to_bits(self) -> u64 {
+        0
+    }
+    fn from_bits(_: u64) -> Self {}
+}
+
+macro_rules! int_log_values {
+    ($($ty:ty => $unsigned:ty,)*) => {$(
+        impl LogValue for $ty {
+            fn to_bits(self) -> u64 {
+                self as $unsigned as u64
+            }
+            fn from_bits(bits: u64) -> Self {
+                bits as $unsigned as $ty
+            }
+        }
+    )*};
+}
+
+int_log_values! {
+    i32 => u32,
+    u32 => u32,
+    i64 => u64,
+    u64 => u64,
+}
+
+impl LogValue for f32 {
+    fn to_bits(self) -> u64 {
+        u64::from(f32::to_bits(self))
+    }
+    fn from_bits(bits: u64) -> Self {
+        f32::from_bits(bits as u32)
+    }
+}
+
+impl LogValue for f64 {
+    fn to_bits(self) -> u64 {
+        f64::to_bits(self)
+    }
+    fn from_bits(bits: u64) -> Self {
+        f64::from_bits(bits)
+    }
+}
+
+/// A region of guest memory a call read or wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// The guest memory a call touched, as worked out by
+/// [`LoggedCall::effects`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryEffects {
+    /// The regions the call touched but left unchanged, with their contents.
+    pub reads: Vec<MemoryAccess>,
+    /// The bytes the call changed, with their new contents.
+    pub writes: Vec<MemoryAccess>,
+}
+
+/// A hostcall in progress, whose guest memory accesses are noted.
+///
+/// Every `GuestPtr` dereference validates its region with the memory first,
+/// so the first time the call touches a region, `LoggedCall` keeps a copy of
+/// its contents from before the call changed any of it.
+pub struct LoggedCall<'a> {
+    module: &'static str,
+    func: &'static str,
+    args: Vec<u64>,
+    memory: &'a dyn GuestMemory,
+    touched: Mutex<Vec<(Region, Vec<u8>)>>,
+}
+
+impl<'a> LoggedCall<'a> {
+    pub fn new(
+        module: &'static str,
+        func: &'static str,
+        args: &[u64],
+        memory: &'a dyn GuestMemory,
+    ) -> Self {
+        LoggedCall {
+            module,
+            func,
+            args: args.to_vec(),
+            memory,
+            touched: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn module(&self) -> &'static str {
+        self.module
+    }
+
+    pub fn func(&self) -> &'static str {
+        self.func
+    }
+
+    pub fn args(&self) -> &[u64] {
+        &self.args
+    }
+
+    /// Splits the memory touched so far into what was only read and what
+    /// was written, comparing it with its contents from before the call.
+    pub fn effects(&self) -> MemoryEffects {
+        let mut touched = self.touched.lock().unwrap().clone();
+        touched.sort_by_key(|(region, _)| region.start);
+
+        // Overlapping regions agree on the bytes they share, which all date
+        // from before the call, so they can be merged.
+        let mut merged: Vec<(u32, Vec<u8>)> = Vec::new();
+        for (region, bytes) in touched {
+            if let Some((start, before)) = merged.last_mut() {
+                let end = u64::from(*start) + before.len() as u64;
+                if u64::from(region.start) < end {
+                    let shared = (end - u64::from(region.start)) as usize;
+                    if shared < bytes.len() {
+                        before.extend_from_slice(&bytes[shared..]);
+                    }
+                    continue;
+                }
+            }
+            merged.push((region.start, bytes));
+        }
+
+        let mut effects = MemoryEffects::default();
+        for (offset, before) in merged {
+            let after = self.snapshot(offset, before.len() as u32);
+            if after == before {
+                effects.reads.push(MemoryAccess {
+                    offset,
+                    bytes: before,
+                });
+                continue;
+            }
+            let prefix = before
+                .iter()
+                .zip(&after)
+                .take_while(|(a, b)| a == b)
+                .count();
+            let suffix = before[prefix..]
+                .iter()
+                .rev()
+                .zip(after[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            effects.writes.push(MemoryAccess {
+                offset: offset + prefix as u32,
+                bytes: after[prefix..after.len() - suffix].to_vec(),
+            });
+        }
+        effects
+    }
+
+    /// The current contents of `len` bytes at `offset`, which the call has
+    /// already validated.
+    fn snapshot(&self, offset: u32, len: u32) -> Vec<u8> {
+        match self.memory.validate_size_align(offset, 1, len) {
+            Ok(ptr) => {
+                let mut bytes = Vec::with_capacity(len as usize);
+                // Safety: the region was just validated to lie within guest
+                // memory, and `bytes` was allocated to hold all of it. The
+                // hostcall may well hold a mutable borrow of the region,
+                // such as through `GuestPtr::as_slice_mut`, so it's copied
+                // through raw pointers without ever creating a reference to
+                // it.
+                unsafe {
+                    std::ptr::copy_nonoverlapping(ptr, bytes.as_mut_ptr(), len as usize);
+                    bytes.set_len(len as usize);
+                }
+                bytes
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn touch(&self, offset: u32, len: u32) {
+        if len == 0 {
+            return;
+        }
+        let region = Region { start: offset, len };
+        let mut touched = self.touched.lock().unwrap();
+        if touched.iter().any(|(r, _)| {
+            r.start <= offset
+                && u64::from(offset) + u64::from(len) <= u64::from(r.start) + u64::from(r.len)
+        }) {
+            return;
+        }
+        // Bytes this region shares with ones touched earlier may have changed
+        // since, so take them from the earlier copies instead.
+        let mut bytes = self.snapshot(offset, len);
+        for (r, before) in touched.iter() {
+            if !r.overlaps(region) {
+                continue;
+            }
+            let start = offset.max(r.start);
+            let end = (u64::from(offset) + u64::from(len))
+                .min(u64::from(r.start) + u64::from(r.len)) as u32;
+            bytes[(start - offset) as usize..(end - offset) as usize]
+                .copy_from_slice(&before[(start - r.start) as usize..(end - r.start) as usize]);
+        }
+        touched.push((region, bytes));
+    }
+}
+
+unsafe impl GuestMemory for LoggedCall<'_> {
+    fn base(&self) -> (*mut u8, u32) {
+        self.memory.base()
+    }
+    fn validate_size_align(
+        &self,
+        offset: u32,
+        align: usize,
+        len: u32,
+    ) -> Result<*mut u8, GuestError> {
+        let ptr = self.memory.validate_size_align(offset, align, len)?;
+        self.touch(offset, len);
+        Ok(ptr)
+    }
+    fn has_outstanding_borrows(&self) -> bool {
+        self.memory.has_outstanding_borrows()
+    }
+    fn is_mut_borrowed(&self, r: Region) -> bool {
+        self.memory.is_mut_borrowed(r)
+    }
+    fn is_shared_borrowed(&self, r: Region) -> bool {
+        self.memory.is_shared_borrowed(r)
+    }
+    fn mut_borrow(&self, r: Region) -> Result<crate::BorrowHandle, GuestError> {
+        self.memory.mut_borrow(r)
+    }
+    fn shared_borrow(&self, r: Region) -> Result<crate::BorrowHandle, GuestError> {
+        self.memory.shared_borrow(r)
+    }
+    fn mut_unborrow(&self, h: crate::BorrowHandle) {
+        self.memory.mut_unborrow(h)
+    }
+    fn shared_unborrow(&self, h: crate::BorrowHandle) {
+        self.memory.shared_unborrow(h)
+    }
+}
+
+/// One call in the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    module: String,
+    func: String,
+    args: Vec<u64>,
+    effects: MemoryEffects,
+    ret: Result<u64, Trap>,
+}
+
+/// A [`HostcallLog`] which runs every call and writes it to a log.
+///
+/// Write errors are held on to, and no more calls are written after one;
+/// [`LogRecorder::flush`] reports it.
+pub struct LogRecorder {
+    state: Mutex<RecorderState>,
+}
+
+struct RecorderState {
+    out: BufWriter<Box<dyn Write + Send>>,
+    names: HashMap<(&'static str, &'static str), u64>,
+    buf: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+impl LogRecorder {
+    /// Records calls to `out`.
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        let mut out = BufWriter::new(Box::new(out) as Box<dyn Write + Send>);
+        let error = out.write_all(MAGIC).err();
+        LogRecorder {
+            state: Mutex::new(RecorderState {
+                out,
+                names: HashMap::new(),
+                buf: Vec::new(),
+                error,
+            }),
+        }
+    }
+
+    /// Records calls to a new file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(LogRecorder::new(File::create(path)?))
+    }
+
+    /// Flushes the calls recorded so far, or returns the error which stopped
+    /// the recording.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        state.out.flush()
+    }
+}
+
+impl HostcallLog for LogRecorder {
+    fn begin<'a>(
+        &self,
+        module: &'static str,
+        func: &'static str,
+        args: &[u64],
+        memory: &'a dyn GuestMemory,
+    ) -> Begin<'a> {
+        Begin::Call(LoggedCall::new(module, func, args, memory))
+    }
+
+    fn end(&self, call: LoggedCall<'_>, ret: Result<u64, &Trap>) {
+        let effects = call.effects();
+        let mut state = self.state.lock().unwrap();
+        if state.error.is_some() {
+            return;
+        }
+        let state = &mut *state;
+        let mut buf = std::mem::take(&mut state.buf);
+        buf.clear();
+
+        let next = state.names.len() as u64;
+        let id = *state.names.entry((call.module, call.func)).or_insert(next);
+        write_uleb(&mut buf, id);
+        if id == next {
+            write_bytes(&mut buf, call.module.as_bytes());
+            write_bytes(&mut buf, call.func.as_bytes());
+        }
+        write_uleb(&mut buf, call.args.len() as u64);
+        for arg in &call.args {
+            write_uleb(&mut buf, *arg);
+        }
+        for accesses in [&effects.reads, &effects.writes].iter() {
+            write_uleb(&mut buf, accesses.len() as u64);
+            for access in accesses.iter() {
+                write_uleb(&mut buf, u64::from(access.offset));
+                write_bytes(&mut buf, &access.bytes);
+            }
+        }
+        match ret {
+            Ok(bits) => {
+                buf.push(0);
+                write_uleb(&mut buf, bits);
+            }
+            Err(Trap::I32Exit(code)) => {
+                buf.push(1);
+                write_uleb(&mut buf, u64::from(*code as u32));
+            }
+            Err(Trap::String(msg)) => {
+                buf.push(2);
+                write_bytes(&mut buf, msg.as_bytes());
+            }
+        }
+
+        let mut result = state.out.write_all(&buf);
+        // A trap ends the instance, so make sure the log is complete before
+        // the embedder gets to see it.
+        if ret.is_err() {
+            result = result.and_then(|()| state.out.flush());
+        }
+        state.error = result.err();
+        state.buf = buf;
+    }
+}
+
+/// A [`HostcallLog`] which serves every call from a log written by a
+/// [`LogRecorder`], without running any of them.
+pub struct LogReplayer {
+    state: Mutex<ReplayerState>,
+}
+
+struct ReplayerState {
+    input: BufReader<Box<dyn Read + Send>>,
+    names: Vec<(String, String)>,
+    index: u64,
+}
+
+impl LogReplayer {
+    /// Replays the calls recorded in `input`.
+    pub fn new(input: impl Read + Send + 'static) -> io::Result<Self> {
+        let mut input = BufReader::new(Box::new(input) as Box<dyn Read + Send>);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a hostcall log",
+            ));
+        }
+        Ok(LogReplayer {
+            state: Mutex::new(ReplayerState {
+                input,
+                names: Vec::new(),
+                index: 0,
+            }),
+        })
+    }
+
+    /// Replays the calls recorded in the file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        LogReplayer::new(File::open(path)?)
+    }
+
+    /// The number of calls replayed so far.
+    pub fn calls(&self) -> u64 {
+        self.state.lock().unwrap().index
+    }
+}
+
+impl HostcallLog for LogReplayer {
+    fn begin<'a>(
+        &self,
+        module: &'static str,
+        func: &'static str,
+        args: &[u64],
+        memory: &'a dyn GuestMemory,
+    ) -> Begin<'a> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.index;
+        state.index += 1;
+        let diverged = |msg: String| {
+            Begin::Replayed(Err(Trap::String(format!(
+                "hostcall replay diverged at call {}: {}",
+                index, msg
+            ))))
+        };
+
+        let record = match state.read_record() {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                return diverged(format!(
+                    "the log ends before this call to {}::{}",
+                    module, func
+                ))
+            }
+            Err(e) => return diverged(format!("failed to read the log: {}", e)),
+        };
+        if record.module != module || record.func != func {
+            return diverged(format!(
+                "expected a call to {}::{}, got {}::{}",
+                record.module, record.func, module, func
+            ));
+        }
+        if record.args != args {
+            return diverged(format!(
+                "{}::{} was called with arguments {:?}, but recorded with {:?}",
+                module, func, args, record.args
+            ));
+        }
+        for read in record.effects.reads.iter() {
+            let ptr = GuestPtr::<[u8]>::new(memory, (read.offset, read.bytes.len() as u32));
+            let matches = match ptr.as_slice() {
+                Ok(bytes) => *bytes == read.bytes[..],
+                Err(_) => false,
+            };
+            if !matches {
+                return diverged(format!(
+                    "{}::{} read different guest memory at {:#x}",
+                    module, func, read.offset
+                ));
+            }
+        }
+        for write in record.effects.writes.iter() {
+            let ptr = GuestPtr::<[u8]>::new(memory, (write.offset, write.bytes.len() as u32));
+            if let Err(e) = ptr.copy_from_slice(&write.bytes) {
+                return diverged(format!(
+                    "{}::{} failed to write guest memory at {:#x}: {}",
+                    module, func, write.offset, e
+                ));
+            }
+        }
+        Begin::Replayed(record.ret)
+    }
+
+    fn end(&self, _call: LoggedCall<'_>, _ret: Result<u64, &Trap>) {
+        unreachable!("replayed hostcalls never run")
+    }
+}
+
+impl ReplayerState {
+    /// Reads the next record, or `None` at the end of the log.
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let id = match read_uleb(&mut self.input) {
+            Ok(id) => id,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if id == self.names.len() as u64 {
+            let module = read_string(&mut self.input)?;
+            let func = read_string(&mut self.input)?;
+            self.names.push((module, func));
+        }
+        let (module, func) = self
+            .names
+            .get(id as usize)
+            .cloned()
+            .ok_or_else(|| invalid_data("unknown function index"))?;
+
+        let input = &mut self.input;
+        let args = (0..read_uleb(input)?)
+            .map(|_| read_uleb(input))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut accesses = || -> io::Result<Vec<MemoryAccess>> {
+            (0..read_uleb(input)?)
+                .map(|_| {
+                    Ok(MemoryAccess {
+                        offset: read_u32(input)?,
+                        bytes: read_bytes(input)?,
+                    })
+                })
+                .collect()
+        };
+        let effects = MemoryEffects {
+            reads: accesses()?,
+            writes: accesses()?,
+        };
+        let mut tag = [0];
+        input.read_exact(&mut tag)?;
+        let ret = match tag[0] {
+            0 => Ok(read_uleb(input)?),
+            1 => Err(Trap::I32Exit(read_u32(input)? as i32)),
+            2 => Err(Trap::String(read_string(input)?)),
+            _ => return Err(invalid_data("unknown result tag")),
+        };
+        Ok(Some(Record {
+            module,
+            func,
+            args,
+            effects,
+            ret,
+        }))
+    }
+}
+
+fn write_uleb(buf: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_uleb(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn read_uleb(input: &mut impl Read) -> io::Result<u64> {
+    let mut val = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        val |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(val);
+        }
+    }
+    Err(invalid_data("integer too long"))
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let val = read_uleb(input)?;
+    if val > u64::from(u32::MAX) {
+        return Err(invalid_data("integer out of range"));
+    }
+    Ok(val as u32)
+}
+
+fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_uleb(input)?;
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_string(input: &mut impl Read) -> io::Result<String> {
+    String::from_utf8(read_bytes(input)?).map_err(|_| invalid_data("name is not utf-8"))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uleb_round_trips() {
+        for val in [0, 0x7f, 0x80, 0x3fff, 0x4000, u64::MAX].iter() {
+            let mut buf = Vec::new();
+            write_uleb(&mut buf, *val);
+            assert_eq!(read_uleb(&mut &buf[..]).unwrap(), *val);
+        }
+    }
+
+    #[test]
+    fn truncated_log_ends_replay() {
+        let mut log = MAGIC.to_vec();
+        write_uleb(&mut log, 0);
+        write_bytes(&mut log, b"m");
+        let replayer = LogReplayer::new(io::Cursor::new(log)).unwrap();
+        let mut state = replayer.state.lock().unwrap();
+        assert_eq!(
+            state.read_record().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(LogReplayer::new(&b"not a log"[..]).is_err());
+    }
+}
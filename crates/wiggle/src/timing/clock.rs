@@ -0,0 +1,372 @@
+//! The clock used to timestamp hostcalls.
+//!
+//! On x86_64 hosts with an invariant TSC the clock reads the time stamp
+//! counter directly, serialized with `cpuid`/`rdtscp` so that the measured
+//! region cannot be reordered around the timestamps. The TSC frequency is
+//! taken, in order of preference, from the `WIGGLE_TSC_FREQUENCY` environment
+//! variable, CPUID leaf 0x15 (or 0x16 if the crystal frequency is not
+//! enumerated), and finally a short calibration against the monotonic clock.
+//!
+//! Everywhere else, or when the TSC is not invariant, ticks are nanoseconds
+//! read from `clock_gettime(CLOCK_MONOTONIC_RAW)` (or `Instant` on hosts which
+//! lack it).
+
+use once_cell::sync::OnceCell;
+use std::fmt;
+use std::time::Duration;
+
+/// Environment variable which, when set to a frequency in Hz, overrides the
+/// detected TSC frequency.
+pub const TSC_FREQUENCY_ENV: &str = "WIGGLE_TSC_FREQUENCY";
+
+/// How long `Clock::detect` spins when it has to measure the TSC frequency
+/// itself.
+const CALIBRATION_PERIOD: Duration = Duration::from_millis(20);
+
+/// Where the ticks returned by a `Clock` come from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The x86_64 time stamp counter.
+    Tsc(FrequencySource),
+    /// The host's monotonic clock, with one tick per nanosecond.
+    Monotonic,
+}
+
+/// How the frequency of a TSC clock was determined.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrequencySource {
+    /// Provided by the embedder or through `WIGGLE_TSC_FREQUENCY`.
+    Override,
+    /// Read from CPUID leaf 0x15 or 0x16.
+    Cpuid,
+    /// Measured against the monotonic clock at startup.
+    Calibrated,
+}
+
+impl fmt::Display for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClockSource::Tsc(FrequencySource::Override) => write!(f, "tsc (override)"),
+            ClockSource::Tsc(FrequencySource::Cpuid) => write!(f, "tsc (cpuid)"),
+            ClockSource::Tsc(FrequencySource::Calibrated) => write!(f, "tsc (calibrated)"),
+            ClockSource::Monotonic => write!(f, "monotonic"),
+        }
+    }
+}
+
+/// A source of timestamps along with the frequency needed to turn them into
+/// nanoseconds.
+#[derive(Copy, Clone, Debug)]
+pub struct Clock {
+    source: ClockSource,
+    frequency_hz: u64,
+}
+
+impl Clock {
+    /// Picks the best clock available on this host.
+    ///
+    /// This honors `WIGGLE_TSC_FREQUENCY`, and falls back to the monotonic
+    /// clock when there is no invariant TSC.
+    pub fn detect() -> Clock {
+        if !tsc::is_invariant() {
+            return Clock::monotonic();
+        }
+        if let Some(hz) = std::env::var(TSC_FREQUENCY_ENV)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .filter(|hz| *hz > 0)
+        {
+            return Clock {
+                source: ClockSource::Tsc(FrequencySource::Override),
+                frequency_hz: hz,
+            };
+        }
+        if let Some(hz) = tsc::cpuid_frequency() {
+            return Clock {
+                source: ClockSource::Tsc(FrequencySource::Cpuid),
+                frequency_hz: hz,
+            };
+        }
+        Clock {
+            source: ClockSource::Tsc(FrequencySource::Calibrated),
+            frequency_hz: tsc::calibrate(CALIBRATION_PERIOD),
+        }
+    }
+
+    /// A TSC clock running at the given frequency, or `None` if this host has
+    /// no invariant TSC.
+    pub fn tsc(frequency_hz: u64) -> Option<Clock> {
+        if frequency_hz == 0 || !tsc::is_invariant() {
+            return None;
+        }
+        Some(Clock {
+            source: ClockSource::Tsc(FrequencySource::Override),
+            frequency_hz,
+        })
+    }
+
+    /// A clock backed by `CLOCK_MONOTONIC_RAW`, ticking in nanoseconds.
+    pub fn monotonic() -> Clock {
+        Clock {
+            source: ClockSource::Monotonic,
+            frequency_hz: 1_000_000_000,
+        }
+    }
+
+    /// Where this clock's ticks come from.
+    pub fn source(&self) -> ClockSource {
+        self.source
+    }
+
+    /// The number of ticks per second.
+    pub fn frequency_hz(&self) -> u64 {
+        self.frequency_hz
+    }
+
+    /// Converts a tick delta into nanoseconds.
+    pub fn ticks_to_nanos(&self, ticks: u64) -> f64 {
+        match self.source {
+            ClockSource::Monotonic => ticks as f64,
+            ClockSource::Tsc(_) => ticks as f64 * 1e9 / self.frequency_hz as f64,
+        }
+    }
+
+    /// Takes a timestamp at the start of a measured region.
+    #[inline]
+    pub fn start(&self) -> u64 {
+        match self.source {
+            ClockSource::Tsc(_) => tsc::start(),
+            ClockSource::Monotonic => monotonic_now(),
+        }
+    }
+
+    /// Takes a timestamp at the end of a measured region.
+    #[inline]
+    pub fn stop(&self) -> u64 {
+        match self.source {
+            ClockSource::Tsc(_) => tsc::stop(),
+            ClockSource::Monotonic => monotonic_now(),
+        }
+    }
+}
+
+static CLOCK: OnceCell<Clock> = OnceCell::new();
+
+/// The process-wide clock used by the hostcall instrumentation.
+///
+/// The clock is detected on first use.
+pub fn clock() -> &'static Clock {
+    CLOCK.get_or_init(Clock::detect)
+}
+
+/// Installs the process-wide clock.
+///
+/// This must happen before the first hostcall is timed; if a clock has already
+/// been chosen the given one is handed back as the error.
+pub fn set_clock(clock: Clock) -> Result<(), Clock> {
+    CLOCK.set(clock)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[inline]
+fn monotonic_now() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // CLOCK_MONOTONIC_RAW is unaffected by NTP slewing, which matters for
+    // deltas this short.
+    let rc = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_RAW, &mut ts) };
+    debug_assert_eq!(rc, 0);
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[inline]
+fn monotonic_now() -> u64 {
+    static EPOCH: OnceCell<std::time::Instant> = OnceCell::new();
+    let epoch = EPOCH.get_or_init(std::time::Instant::now);
+    epoch.elapsed().as_nanos() as u64
+}
+
+#[cfg(target_arch = "x86_64")]
+mod tsc {
+    use super::monotonic_now;
+    use core::arch::x86_64::{__cpuid, __cpuid_count, __rdtscp, _rdtsc};
+    use std::time::{Duration, Instant};
+
+    pub fn is_invariant() -> bool {
+        unsafe {
+            // Leaf 0x80000007 EDX bit 8: the TSC runs at a constant rate in
+            // all ACPI P-, C- and T-states.
+            if __cpuid(0x8000_0000).eax < 0x8000_0007 {
+                return false;
+            }
+            __cpuid(0x8000_0007).edx & (1 << 8) != 0
+        }
+    }
+
+    pub fn cpuid_frequency() -> Option<u64> {
+        unsafe {
+            let max_leaf = __cpuid(0).eax;
+            if max_leaf >= 0x15 {
+                let leaf = __cpuid(0x15);
+                // EAX/EBX is the TSC/crystal ratio, ECX the crystal in Hz.
+                if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+                    return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64);
+                }
+            }
+            if max_leaf >= 0x16 {
+                // Processor base frequency in MHz, which matches the TSC on
+                // parts that enumerate 0x15 without the crystal frequency.
+                let base_mhz = __cpuid(0x16).eax & 0xffff;
+                if base_mhz != 0 {
+                    return Some(base_mhz as u64 * 1_000_000);
+                }
+            }
+            None
+        }
+    }
+
+    pub fn calibrate(period: Duration) -> u64 {
+        let begin = Instant::now();
+        let (ns0, tsc0) = (monotonic_now(), start());
+        while begin.elapsed() < period {
+            std::hint::spin_loop();
+        }
+        let (ns1, tsc1) = (monotonic_now(), stop());
+        ((tsc1 - tsc0) as u128 * 1_000_000_000 / (ns1 - ns0).max(1) as u128) as u64
+    }
+
+    #[inline]
+    pub fn start() -> u64 {
+        unsafe {
+            __cpuid_count(0, 0);
+            _rdtsc() as u64
+        }
+    }
+
+    #[inline]
+    pub fn stop() -> u64 {
+        unsafe {
+            let mut junk: u32 = 0;
+            let ans: u64 = __rdtscp(&mut junk);
+            __cpuid_count(0, 0);
+            ans
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod tsc {
+    use std::time::Duration;
+
+    pub fn is_invariant() -> bool {
+        false
+    }
+
+    pub fn cpuid_frequency() -> Option<u64> {
+        None
+    }
+
+    pub fn calibrate(_period: Duration) -> u64 {
+        unreachable!()
+    }
+
+    pub fn start() -> u64 {
+        unreachable!()
+    }
+
+    pub fn stop() -> u64 {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_monotonic(clock: &Clock) {
+        let mut last = clock.start();
+        for _ in 0..10_000 {
+            let start = clock.start();
+            let stop = clock.stop();
+            assert!(
+                start >= last,
+                "{}: {} went back to {}",
+                clock.source,
+                last,
+                start
+            );
+            assert!(
+                stop >= start,
+                "{}: {} went back to {}",
+                clock.source,
+                start,
+                stop
+            );
+            last = stop;
+        }
+    }
+
+    #[test]
+    fn monotonic_clock_never_goes_back() {
+        assert_monotonic(&Clock::monotonic());
+    }
+
+    #[test]
+    fn detected_clock_never_goes_back() {
+        assert_monotonic(&Clock::detect());
+    }
+
+    #[test]
+    fn ticks_convert_to_nanoseconds() {
+        assert_eq!(Clock::monotonic().ticks_to_nanos(1234), 1234.0);
+        let clock = Clock {
+            source: ClockSource::Tsc(FrequencySource::Override),
+            frequency_hz: 2_500_000_000,
+        };
+        assert_eq!(clock.ticks_to_nanos(5000), 2000.0);
+    }
+
+    #[test]
+    fn detected_clock_measures_a_sleep() {
+        let clock = Clock::detect();
+        let start = clock.start();
+        std::thread::sleep(Duration::from_millis(20));
+        let nanos = clock.ticks_to_nanos(clock.stop() - start);
+        // The sleep takes at least as long as asked, and a loaded machine
+        // may stretch it, but not by whole seconds.
+        assert!(
+            nanos >= 19e6,
+            "{}: slept for only {}ns",
+            clock.source,
+            nanos
+        );
+        assert!(nanos < 2e9, "{}: slept for {}ns", clock.source, nanos);
+    }
+
+    #[test]
+    fn calibration_is_sane() {
+        if !tsc::is_invariant() {
+            println!("skipping: no invariant TSC");
+            return;
+        }
+        let hz = tsc::calibrate(CALIBRATION_PERIOD);
+        assert!(
+            (100_000_000..20_000_000_000).contains(&hz),
+            "calibrated to {}Hz",
+            hz
+        );
+        // Two calibrations agree with each other to within a few percent,
+        // and with CPUID, when it enumerates the frequency, to within a bit
+        // more, as virtual machines don't always report it exactly.
+        let again = tsc::calibrate(CALIBRATION_PERIOD);
+        let off = (hz as f64 - again as f64).abs() / hz as f64;
+        assert!(off < 0.05, "calibrated to {}Hz, then {}Hz", hz, again);
+        if let Some(cpuid) = tsc::cpuid_frequency() {
+            let off = (hz as f64 - cpuid as f64).abs() / cpuid as f64;
+            assert!(off < 0.1, "calibrated to {}Hz, cpuid says {}Hz", hz, cpuid);
+        }
+    }
+}
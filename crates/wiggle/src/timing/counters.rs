@@ -0,0 +1,297 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+/// An event which can be counted over each timed hostcall with the CPU's
+/// performance counters; see [`set_perf_counters`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PerfCounter {
+    /// Instructions retired.
+    Instructions,
+    /// Last-level cache misses.
+    CacheMisses,
+    /// Mispredicted branches.
+    BranchMisses,
+    /// Times the thread was switched out. Counted by the kernel rather than
+    /// the CPU, so only available when kernel events may be counted.
+    ContextSwitches,
+}
+
+impl PerfCounter {
+    pub const ALL: [PerfCounter; 4] = [
+        PerfCounter::Instructions,
+        PerfCounter::CacheMisses,
+        PerfCounter::BranchMisses,
+        PerfCounter::ContextSwitches,
+    ];
+
+    /// The name `perf` knows the event by, e.g. `cache-misses`.
+    pub fn name(self) -> &'static str {
+        match self {
+            PerfCounter::Instructions => "instructions",
+            PerfCounter::CacheMisses => "cache-misses",
+            PerfCounter::BranchMisses => "branch-misses",
+            PerfCounter::ContextSwitches => "context-switches",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// How many times each counted [`PerfCounter`] ticked over one hostcall.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PerfCounts {
+    counts: [Option<u64>; 4],
+}
+
+impl PerfCounts {
+    /// The count for `counter`, if it was counted.
+    pub fn get(&self, counter: PerfCounter) -> Option<u64> {
+        self.counts[counter as usize]
+    }
+
+    /// Every counted counter along with its count.
+    pub fn iter(&self) -> impl Iterator<Item = (PerfCounter, u64)> + '_ {
+        PerfCounter::ALL
+            .iter()
+            .filter_map(move |c| self.get(*c).map(|n| (*c, n)))
+    }
+
+    /// The counts between `start` and these.
+    pub(crate) fn since(&self, start: &PerfCounts) -> PerfCounts {
+        let mut counts = PerfCounts::default();
+        for (i, count) in counts.counts.iter_mut().enumerate() {
+            if let (Some(end), Some(start)) = (self.counts[i], start.counts[i]) {
+                *count = Some(end.saturating_sub(start));
+            }
+        }
+        counts
+    }
+}
+
+/// Which events the counters of [`set_perf_counters`] include.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PerfScope {
+    /// Everything the thread did, including the syscalls it made.
+    UserAndKernel,
+    /// Only what the thread did in user space, because
+    /// `/proc/sys/kernel/perf_event_paranoid` forbids counting in the kernel.
+    User,
+}
+
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+static USER_ONLY: AtomicBool = AtomicBool::new(false);
+/// Bumped whenever the counters change, so that threads reopen theirs.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Makes every timed hostcall from now on also count `counters` over its
+/// implementation, the [`Phase::Call`](super::Phase::Call), or stops
+/// counting if `counters` is empty.
+///
+/// This is only supported on Linux, where each thread which makes hostcalls
+/// opens a group of `perf_event_open` counters. Where the kernel allows it,
+/// and wiggle was built with Rust 1.59 or later, hardware counters are read
+/// with `rdpmc` without making a syscall, and otherwise all counters are read
+/// with a single `read`. Counters which had to take turns on the PMU because
+/// more were opened than it has room for are scaled up to estimate what they
+/// would have counted all along.
+///
+/// When `/proc/sys/kernel/perf_event_paranoid` forbids counting events in
+/// the kernel, counting falls back to user space only, as reported by
+/// [`perf_scope`]. An error is returned, and counting stays as it was, if
+/// the counters can't be opened at all, such as when the CPU has no
+/// performance counters, or when asking for
+/// [`PerfCounter::ContextSwitches`] without access to kernel events.
+///
+/// As with [`set_count_syscalls`](super::set_count_syscalls), a call whose
+/// implementation moves to another thread, as async hostcalls may, is not
+/// counted.
+pub fn set_perf_counters(counters: &[PerfCounter]) -> io::Result<()> {
+    let enabled = counters.iter().fold(0, |bits, c| bits | c.bit());
+    let user_only = if enabled == 0 {
+        false
+    } else {
+        imp::probe(&events(enabled))?
+    };
+    USER_ONLY.store(user_only, Ordering::Relaxed);
+    ENABLED.store(enabled, Ordering::Relaxed);
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The counters being counted; see [`set_perf_counters`].
+pub fn perf_counters() -> Vec<PerfCounter> {
+    events(ENABLED.load(Ordering::Relaxed))
+}
+
+/// Whether the counters include kernel events; see [`set_perf_counters`].
+pub fn perf_scope() -> PerfScope {
+    if USER_ONLY.load(Ordering::Relaxed) {
+        PerfScope::User
+    } else {
+        PerfScope::UserAndKernel
+    }
+}
+
+fn events(enabled: u8) -> Vec<PerfCounter> {
+    PerfCounter::ALL
+        .iter()
+        .copied()
+        .filter(|c| enabled & c.bit() != 0)
+        .collect()
+}
+
+/// The current thread's counts so far, if any counters are enabled and
+/// this thread could open them.
+#[inline]
+pub(crate) fn perf_counts() -> Option<PerfCounts> {
+    if ENABLED.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    imp::perf_counts()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::super::perf::{
+        Group, FLAGS_USER_ONLY, HW_BRANCH_MISSES, HW_CACHE_MISSES, HW_INSTRUCTIONS,
+        SW_CONTEXT_SWITCHES, TYPE_HARDWARE, TYPE_SOFTWARE,
+    };
+    use super::{PerfCounter, PerfCounts, ENABLED, GENERATION, USER_ONLY};
+    use std::cell::RefCell;
+    use std::io;
+    use std::sync::atomic::Ordering;
+
+    /// A thread's counters, along with the generation they were opened for.
+    struct ThreadGroup {
+        generation: u64,
+        counters: Vec<PerfCounter>,
+        /// `None` if they couldn't be opened, so that they aren't retried on
+        /// every call.
+        group: Option<Group>,
+    }
+
+    thread_local! {
+        static GROUP: RefCell<Option<ThreadGroup>> = RefCell::new(None);
+    }
+
+    fn event(counter: PerfCounter) -> (u32, u64) {
+        match counter {
+            PerfCounter::Instructions => (TYPE_HARDWARE, HW_INSTRUCTIONS),
+            PerfCounter::CacheMisses => (TYPE_HARDWARE, HW_CACHE_MISSES),
+            PerfCounter::BranchMisses => (TYPE_HARDWARE, HW_BRANCH_MISSES),
+            PerfCounter::ContextSwitches => (TYPE_SOFTWARE, SW_CONTEXT_SWITCHES),
+        }
+    }
+
+    fn open(counters: &[PerfCounter], user_only: bool) -> io::Result<Group> {
+        let events = counters.iter().map(|c| event(*c)).collect::<Vec<_>>();
+        let flags = if user_only { FLAGS_USER_ONLY } else { 0 };
+        Group::open(&events, flags)
+    }
+
+    /// Opens and reads `counters` on the current thread, returning whether
+    /// only user space could be counted.
+    pub(super) fn probe(counters: &[PerfCounter]) -> io::Result<bool> {
+        let names = counters.iter().map(|c| c.name()).collect::<Vec<_>>();
+        let context = |e: io::Error| {
+            // The kernel reports events the CPU can't count as missing.
+            let e = match e.raw_os_error() {
+                Some(libc::ENOENT) | Some(libc::EOPNOTSUPP) => {
+                    io::Error::new(e.kind(), "this CPU doesn't support them")
+                }
+                _ => e,
+            };
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "failed to open perf counters for {}: {}",
+                    names.join(", "),
+                    e
+                ),
+            )
+        };
+        let (mut group, user_only) = match open(counters, false) {
+            Ok(group) => (group, false),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                if counters.contains(&PerfCounter::ContextSwitches) {
+                    return Err(context(io::Error::new(
+                        e.kind(),
+                        "context switches can only be counted in the kernel, which \
+                         perf_event_paranoid forbids",
+                    )));
+                }
+                (open(counters, true).map_err(context)?, true)
+            }
+            Err(e) => return Err(context(e)),
+        };
+        let mut values = vec![0; counters.len()];
+        group.read(&mut values).map_err(context)?;
+        Ok(user_only)
+    }
+
+    pub(super) fn perf_counts() -> Option<PerfCounts> {
+        GROUP.with(|group| {
+            let mut group = group.borrow_mut();
+            let generation = GENERATION.load(Ordering::Relaxed);
+            if group.as_ref().map_or(true, |g| g.generation != generation) {
+                let counters = super::events(ENABLED.load(Ordering::Relaxed));
+                *group = Some(ThreadGroup {
+                    generation,
+                    group: open(&counters, USER_ONLY.load(Ordering::Relaxed)).ok(),
+                    counters,
+                });
+            }
+            let group = group.as_mut().unwrap();
+            let mut values = [0; 4];
+            let values = &mut values[..group.counters.len()];
+            group.group.as_mut()?.read(values).ok()?;
+            let mut counts = PerfCounts::default();
+            for (counter, value) in group.counters.iter().zip(values.iter()) {
+                counts.counts[*counter as usize] = Some(*value);
+            }
+            Some(counts)
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::{PerfCounter, PerfCounts};
+    use std::io;
+
+    pub(super) fn probe(_counters: &[PerfCounter]) -> io::Result<bool> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "performance counters are only supported on Linux",
+        ))
+    }
+
+    pub(super) fn perf_counts() -> Option<PerfCounts> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_since() {
+        let mut start = PerfCounts::default();
+        start.counts[PerfCounter::Instructions as usize] = Some(10);
+        start.counts[PerfCounter::ContextSwitches as usize] = Some(2);
+        let mut end = start;
+        end.counts[PerfCounter::Instructions as usize] = Some(25);
+        let counts = end.since(&start);
+        assert_eq!(
+            counts.iter().collect::<Vec<_>>(),
+            [
+                (PerfCounter::Instructions, 15),
+                (PerfCounter::ContextSwitches, 0)
+            ]
+        );
+        assert_eq!(counts.get(PerfCounter::CacheMisses), None);
+    }
+}
@@ -0,0 +1,321 @@
+/// Each power of two is split into `2^SUB_BUCKET_BITS` linear sub-buckets,
+/// which bounds the error of a reported percentile to under 1%.
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+/// The percentiles reported by [`Histogram::summary`].
+pub const PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+/// A log-linear histogram of durations in nanoseconds, in the style of
+/// HdrHistogram.
+///
+/// Durations below 128ns are counted exactly; above that every power of two
+/// is divided into 128 buckets. The bucket array only grows to cover the
+/// largest duration seen, so memory stays bounded (about 24KiB for durations
+/// up to one second) no matter how many calls are recorded. Count, minimum,
+/// maximum, mean and standard deviation are tracked exactly, as is how many
+/// durations were at most each power of two.
+///
+/// Individual samples are only kept when the histogram was created with
+/// [`Histogram::with_raw_samples`].
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    /// Entry `k` counts the durations in `(2^(k-1), 2^k]`, and entry 0 those
+    /// of at most 1ns.
+    powers: Vec<u64>,
+    count: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    m2: f64,
+    raw: Option<Vec<f64>>,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A histogram which also keeps every recorded duration.
+    pub fn with_raw_samples() -> Self {
+        Histogram {
+            raw: Some(Vec::new()),
+            ..Self::default()
+        }
+    }
+
+    pub fn record(&mut self, nanos: f64) {
+        let nanos = nanos.max(0.0);
+        let index = bucket_index(nanos.round() as u64);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        let power = power_index(nanos);
+        if power >= self.powers.len() {
+            self.powers.resize(power + 1, 0);
+        }
+        self.powers[power] += 1;
+
+        if self.count == 0 {
+            self.min = nanos;
+            self.max = nanos;
+        } else {
+            self.min = self.min.min(nanos);
+            self.max = self.max.max(nanos);
+        }
+        // Welford's online update, which stays accurate over long runs.
+        self.count += 1;
+        let delta = nanos - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (nanos - self.mean);
+
+        if let Some(raw) = &mut self.raw {
+            raw.push(nanos);
+        }
+    }
+
+    /// Adds everything recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.count == 0 {
+            return;
+        }
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (a, b) in self.counts.iter_mut().zip(&other.counts) {
+            *a += b;
+        }
+        if other.powers.len() > self.powers.len() {
+            self.powers.resize(other.powers.len(), 0);
+        }
+        for (a, b) in self.powers.iter_mut().zip(&other.powers) {
+            *a += b;
+        }
+        if self.count == 0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.m2 +=
+            other.m2 + delta * delta * (self.count as f64 * other.count as f64) / count as f64;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.count = count;
+        match (&mut self.raw, &other.raw) {
+            (Some(a), Some(b)) => a.extend_from_slice(b),
+            (None, _) => {}
+            // Raw samples are only meaningful if they are complete.
+            (Some(_), None) => self.raw = None,
+        }
+    }
+
+    /// How many of the recorded durations were at most `2^shift`
+    /// nanoseconds, exactly.
+    pub fn count_at_most_power_of_two(&self, shift: u32) -> u64 {
+        self.powers.iter().take(shift as usize + 1).sum()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The population standard deviation.
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.m2 / self.count as f64).sqrt()
+        }
+    }
+
+    /// The duration below which `percentile` percent of calls fall, e.g.
+    /// `value_at_percentile(99.0)` for the p99.
+    pub fn value_at_percentile(&self, percentile: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_midpoint(index).max(self.min).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Every recorded duration, if this histogram keeps raw samples.
+    pub fn raw_samples(&self) -> Option<&[f64]> {
+        self.raw.as_deref()
+    }
+
+    /// Non-empty buckets as `(lowest value, highest value, count)`, in
+    /// increasing order.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| {
+                let (low, width) = bucket_bounds(index);
+                (low, low + width - 1, *count)
+            })
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            stddev: self.stddev(),
+            percentiles: [
+                self.value_at_percentile(PERCENTILES[0]),
+                self.value_at_percentile(PERCENTILES[1]),
+                self.value_at_percentile(PERCENTILES[2]),
+                self.value_at_percentile(PERCENTILES[3]),
+            ],
+        }
+    }
+}
+
+/// The headline statistics of a [`Histogram`], in nanoseconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub stddev: f64,
+    /// The values at each of [`PERCENTILES`].
+    pub percentiles: [f64; 4],
+}
+
+/// The index into [`Histogram::powers`] counting `nanos`: the smallest `k`
+/// for which `nanos <= 2^k`.
+fn power_index(nanos: f64) -> usize {
+    match nanos.ceil() as u64 {
+        0 | 1 => 0,
+        value => (64 - (value - 1).leading_zeros()) as usize,
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let magnitude = 63 - value.leading_zeros();
+    let shift = magnitude - SUB_BUCKET_BITS;
+    let sub = (value >> shift) as usize - SUB_BUCKETS;
+    (shift as usize + 1) * SUB_BUCKETS + sub
+}
+
+/// The lowest value counted in bucket `index`, and how many values it covers.
+fn bucket_bounds(index: usize) -> (u64, u64) {
+    if index < SUB_BUCKETS {
+        return (index as u64, 1);
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub = (index % SUB_BUCKETS) as u64;
+    ((SUB_BUCKETS as u64 + sub) << shift, 1 << shift)
+}
+
+fn bucket_midpoint(index: usize) -> f64 {
+    let (low, width) = bucket_bounds(index);
+    low as f64 + (width - 1) as f64 / 2.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket_bounds_roundtrip() {
+        for value in (0..100_000).chain(vec![u32::MAX as u64, u64::MAX >> 1]) {
+            let (low, width) = bucket_bounds(bucket_index(value));
+            assert!(low <= value && value - low < width, "value {}", value);
+        }
+    }
+
+    #[test]
+    fn counts_at_most_powers_of_two() {
+        let mut h = Histogram::new();
+        for v in [0.0, 1.0, 1.5, 128.0, 129.0, 256.0, 256.5, 512.0].iter() {
+            h.record(*v);
+        }
+        assert_eq!(h.count_at_most_power_of_two(0), 2);
+        assert_eq!(h.count_at_most_power_of_two(1), 3);
+        assert_eq!(h.count_at_most_power_of_two(7), 4);
+        assert_eq!(h.count_at_most_power_of_two(8), 6);
+        assert_eq!(h.count_at_most_power_of_two(9), 8);
+        assert_eq!(h.count_at_most_power_of_two(63), 8);
+    }
+
+    #[test]
+    fn percentiles_are_within_one_percent() {
+        let mut h = Histogram::new();
+        for i in 1..=10_000 {
+            h.record(i as f64 * 10.0);
+        }
+        assert_eq!(h.count(), 10_000);
+        assert_eq!(h.min(), 10.0);
+        assert_eq!(h.max(), 100_000.0);
+        for (p, expected) in [(50.0, 50_000.0), (90.0, 90_000.0), (99.0, 99_000.0)].iter() {
+            let actual = h.value_at_percentile(*p);
+            assert!(
+                (actual - expected).abs() / expected < 0.01,
+                "p{}: {} vs {}",
+                p,
+                actual,
+                expected
+            );
+        }
+        assert!((h.mean() - 50_005.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn merge_matches_single_histogram() {
+        let mut all = Histogram::with_raw_samples();
+        let mut a = Histogram::with_raw_samples();
+        let mut b = Histogram::with_raw_samples();
+        for i in 0..1000 {
+            let v = (i * 37 % 1000) as f64;
+            all.record(v);
+            if i % 3 == 0 {
+                a.record(v);
+            } else {
+                b.record(v);
+            }
+        }
+        a.merge(&b);
+        assert_eq!(a.count(), all.count());
+        assert_eq!(a.min(), all.min());
+        assert_eq!(a.max(), all.max());
+        assert!((a.mean() - all.mean()).abs() < 1e-9);
+        assert!((a.stddev() - all.stddev()).abs() < 1e-9);
+        assert_eq!(a.value_at_percentile(99.0), all.value_at_percentile(99.0));
+        assert_eq!(
+            a.count_at_most_power_of_two(8),
+            all.count_at_most_power_of_two(8)
+        );
+        assert_eq!(a.raw_samples().unwrap().len(), 1000);
+    }
+}
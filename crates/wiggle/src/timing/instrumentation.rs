@@ -0,0 +1,124 @@
+use super::stack::guest_stack;
+use super::{
+    clock, record, record_unsampled, sampling, HostcallResult, HostcallSample, PhaseTimestamps,
+    Sampler, Sampling,
+};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+
+/// The `(module, name)` of every host function which `add_to_linker` defined
+/// with its own [`HostcallTimer`](super::HostcallTimer).
+static INSTRUMENTED: Lazy<Mutex<HashSet<(String, String)>>> =
+    Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Decides which calls to time, with a [`Sampler`] for each host function,
+/// by module and then name.
+static SAMPLERS: Lazy<RwLock<HashMap<String, HashMap<String, Sampler>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Returned by `enter` for calls which aren't timed. Clock readings never
+/// come close to it.
+const UNSAMPLED: u64 = u64::MAX;
+
+/// Notes that `module::name` already times itself, so that
+/// [`WasmtimeInstrumentation`] doesn't record it a second time.
+///
+/// Called by the `add_to_linker` functions generated for modules which are
+/// instrumented, before each function is defined.
+#[doc(hidden)]
+pub fn mark_instrumented(module: &str, name: &str) {
+    INSTRUMENTED
+        .lock()
+        .unwrap()
+        .insert((module.to_string(), name.to_string()));
+}
+
+/// Whether the call to `module::name` being made now should be timed.
+pub(super) fn sample(module: &str, name: &str) -> bool {
+    if sampling() == Sampling::All {
+        return true;
+    }
+    if let Some(sampler) = SAMPLERS
+        .read()
+        .unwrap()
+        .get(module)
+        .and_then(|names| names.get(name))
+    {
+        return sampler.sample();
+    }
+    SAMPLERS
+        .write()
+        .unwrap()
+        .entry(module.to_string())
+        .or_default()
+        .entry(name.to_string())
+        .or_default()
+        .sample()
+}
+
+/// Records calls into any Wasmtime host function to the process-wide
+/// [`recorder`](super::recorder), for use with
+/// `wasmtime::Config::hostcall_instrumentation`.
+///
+/// This covers host functions defined with `Linker::func_wrap`,
+/// `Linker::func_new` and the like, which don't go through `from_witx!`.
+/// Functions generated with `instrument` are skipped since they are already
+/// recorded, with more detail. Nothing is known about how the rest split
+/// their time, so the whole call is recorded as [`Phase::Call`](super::Phase::Call).
+///
+/// With `wasmtime::Config::instrument_libcalls`, the runtime's library calls
+/// are recorded the same way, under the module `runtime`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WasmtimeInstrumentation;
+
+impl wasmtime::HostcallInstrumentation for WasmtimeInstrumentation {
+    fn instrument(&self, module: &str, name: &str) -> bool {
+        !INSTRUMENTED
+            .lock()
+            .unwrap()
+            .contains(&(module.to_string(), name.to_string()))
+    }
+
+    fn enter(&self, module: &str, name: &str) -> u64 {
+        if sample(module, name) {
+            clock().start()
+        } else {
+            UNSAMPLED
+        }
+    }
+
+    fn exit(&self, module: &str, name: &str, entered: u64, trapped: bool) {
+        if entered == UNSAMPLED {
+            record_unsampled(None, module, name);
+            return;
+        }
+        let end = clock().stop();
+        let stack = guest_stack();
+        record(
+            None,
+            &HostcallSample {
+                module,
+                function: name,
+                start: entered,
+                end,
+                phases: PhaseTimestamps {
+                    lift: entered,
+                    call: entered,
+                    call_end: end,
+                    lowered: end,
+                },
+                poll: None,
+                payload: None,
+                stack: stack.as_deref(),
+                syscalls: None,
+                counters: None,
+                result: if trapped {
+                    HostcallResult::Trap
+                } else {
+                    HostcallResult::Ok
+                },
+            },
+        );
+    }
+}
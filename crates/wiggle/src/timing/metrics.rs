@@ -0,0 +1,573 @@
+use super::{HostcallHistograms, HostcallRecorder, HostcallResult, HostcallTable, PerfCounter};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// The upper bounds of the buckets of the `wasmtime_hostcall_duration_seconds`
+/// histogram, in nanoseconds: every power of two from 128ns to about four
+/// seconds. [`Histogram`] counts the durations up to each power of two, so the
+/// exported counts are exact.
+///
+/// [`Histogram`]: super::Histogram
+const DURATION_BOUNDS: std::ops::RangeInclusive<u32> = 7..=32;
+
+/// The `Content-Type` of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// How long a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before accepting connections again after failing to.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Requests are only read up to the end of their head, and never beyond this.
+const MAX_REQUEST: usize = 8192;
+
+/// Writes `hostcalls` in the Prometheus text exposition format.
+///
+/// Each hostcall is labelled with its `module` and `function`. The number of
+/// calls made, `wasmtime_hostcall_calls_total`, counts every call, while
+/// everything else only covers the calls which were timed, which are all of
+/// them unless [`set_sampling`](super::set_sampling) says otherwise:
+///
+/// * `wasmtime_hostcall_duration_seconds`, a histogram of their durations
+/// * `wasmtime_hostcall_results_total`, by `result` (`ok`, `errno` or
+///   `trap`) and for errors the `errno`
+/// * `wasmtime_hostcall_payload_bytes_total`, for I/O hostcalls
+/// * `wasmtime_hostcall_syscalls_total` and
+///   `wasmtime_hostcall_perf_events_total`, by `event`, when those are
+///   counted
+pub fn write_prometheus(
+    out: &mut dyn Write,
+    hostcalls: &HostcallTable<HostcallHistograms>,
+) -> io::Result<()> {
+    let mut hostcalls = hostcalls.iter().collect::<Vec<_>>();
+    hostcalls.sort_by_key(|(module, function, _)| (*module, *function));
+    let labels = |module: &str, function: &str| {
+        format!(
+            "module=\"{}\",function=\"{}\"",
+            escape(module),
+            escape(function)
+        )
+    };
+
+    family(
+        out,
+        "wasmtime_hostcall_calls_total",
+        "counter",
+        "Calls made to each hostcall, whether or not they were timed.",
+    )?;
+    for (module, function, h) in hostcalls.iter() {
+        writeln!(
+            out,
+            "wasmtime_hostcall_calls_total{{{}}} {}",
+            labels(module, function),
+            h.count()
+        )?;
+    }
+
+    family(
+        out,
+        "wasmtime_hostcall_duration_seconds",
+        "histogram",
+        "The duration of each timed call.",
+    )?;
+    for (module, function, h) in hostcalls.iter() {
+        let labels = labels(module, function);
+        for shift in DURATION_BOUNDS {
+            writeln!(
+                out,
+                "wasmtime_hostcall_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels,
+                (1u64 << shift) as f64 / 1e9,
+                h.total.count_at_most_power_of_two(shift)
+            )?;
+        }
+        writeln!(
+            out,
+            "wasmtime_hostcall_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            labels,
+            h.total.count()
+        )?;
+        writeln!(
+            out,
+            "wasmtime_hostcall_duration_seconds_sum{{{}}} {}",
+            labels,
+            sum(&h.total) / 1e9
+        )?;
+        writeln!(
+            out,
+            "wasmtime_hostcall_duration_seconds_count{{{}}} {}",
+            labels,
+            h.total.count()
+        )?;
+    }
+
+    family(
+        out,
+        "wasmtime_hostcall_results_total",
+        "counter",
+        "Timed calls by how they finished.",
+    )?;
+    for (module, function, h) in hostcalls.iter() {
+        for (result, histogram) in h.results.iter() {
+            let result = match result {
+                HostcallResult::Ok => "result=\"ok\"".to_string(),
+                HostcallResult::Errno(errno) => format!("result=\"errno\",errno=\"{}\"", errno),
+                HostcallResult::Trap => "result=\"trap\"".to_string(),
+            };
+            writeln!(
+                out,
+                "wasmtime_hostcall_results_total{{{},{}}} {}",
+                labels(module, function),
+                result,
+                histogram.count()
+            )?;
+        }
+    }
+
+    family(
+        out,
+        "wasmtime_hostcall_payload_bytes_total",
+        "counter",
+        "Bytes moved by timed I/O calls.",
+    )?;
+    for (module, function, h) in hostcalls.iter().filter(|(_, _, h)| h.payload.calls > 0) {
+        writeln!(
+            out,
+            "wasmtime_hostcall_payload_bytes_total{{{}}} {}",
+            labels(module, function),
+            h.payload.bytes
+        )?;
+    }
+
+    family(
+        out,
+        "wasmtime_hostcall_syscalls_total",
+        "counter",
+        "Host syscalls issued by timed calls which counted them.",
+    )?;
+    for (module, function, h) in hostcalls.iter().filter(|(_, _, h)| h.syscalls.count() > 0) {
+        writeln!(
+            out,
+            "wasmtime_hostcall_syscalls_total{{{}}} {}",
+            labels(module, function),
+            sum(&h.syscalls).round()
+        )?;
+    }
+
+    family(
+        out,
+        "wasmtime_hostcall_perf_events_total",
+        "counter",
+        "Performance counter events during timed calls which counted them.",
+    )?;
+    for (module, function, h) in hostcalls.iter() {
+        for counter in PerfCounter::ALL.iter() {
+            let histogram = h.counter(*counter);
+            if histogram.count() == 0 {
+                continue;
+            }
+            writeln!(
+                out,
+                "wasmtime_hostcall_perf_events_total{{{},event=\"{}\"}} {}",
+                labels(module, function),
+                counter.name(),
+                sum(histogram).round()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn family(out: &mut dyn Write, name: &str, kind: &str, help: &str) -> io::Result<()> {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+/// The total of the values recorded in `histogram`.
+fn sum(histogram: &super::Histogram) -> f64 {
+    histogram.mean() * histogram.count() as f64
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Where a [`MetricsServer`] listens.
+///
+/// Parsed from `unix:PATH` for a Unix domain socket, or `HOST:PORT` for a TCP
+/// port on a loopback address, where `HOST` may also be `localhost`. Metrics
+/// are not served to other machines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MetricsEndpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for MetricsEndpoint {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<MetricsEndpoint> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return match path {
+                "" => Err(invalid("missing path after `unix:`".to_string())),
+                path => Ok(MetricsEndpoint::Unix(path.into())),
+            };
+            #[cfg(not(unix))]
+            return Err(invalid(format!(
+                "can't serve metrics at `{}`: Unix sockets aren't supported on this platform",
+                path
+            )));
+        }
+        let addr = match s.strip_prefix("localhost:") {
+            Some(port) => port
+                .parse()
+                .map(|port| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
+                .map_err(|_| invalid(format!("invalid port in `{}`", s)))?,
+            None => s.parse::<SocketAddr>().map_err(|_| {
+                invalid(format!(
+                    "invalid metrics endpoint `{}`: expected `unix:PATH` or `HOST:PORT`",
+                    s
+                ))
+            })?,
+        };
+        if !addr.ip().is_loopback() {
+            return Err(invalid(format!(
+                "can't serve metrics on `{}`: only loopback addresses are allowed",
+                s
+            )));
+        }
+        Ok(MetricsEndpoint::Tcp(addr))
+    }
+}
+
+impl fmt::Display for MetricsEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsEndpoint::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            MetricsEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Serves the current hostcall metrics of a [`HostcallRecorder`] in the
+/// Prometheus text format, as written by [`write_prometheus`], so that they
+/// can be scraped while modules are running.
+///
+/// Every HTTP `GET` request gets the metrics as they are at that moment,
+/// whatever the path; a client which doesn't speak HTTP can instead connect,
+/// shut down its side of the connection and read the bare metrics. Requests
+/// are handled one at a time on a background thread, which is stopped when
+/// the server is dropped.
+///
+/// The metrics come from [`HostcallRecorder::histograms`], so recorders
+/// which don't keep histograms serve no samples.
+pub struct MetricsServer {
+    endpoint: MetricsEndpoint,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// A connection accepted by a [`Listener`].
+trait Connection: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(match self {
+            Listener::Tcp(listener) => Box::new(listener.accept()?.0),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Box::new(listener.accept()?.0),
+        })
+    }
+}
+
+impl MetricsServer {
+    /// Starts serving the metrics recorded by `recorder` at `endpoint`.
+    ///
+    /// A TCP port of 0 picks a free port, which [`MetricsServer::endpoint`]
+    /// reports. A Unix socket is created at the given path, which must not
+    /// exist yet, and removed when the server is dropped.
+    pub fn start(
+        endpoint: &MetricsEndpoint,
+        recorder: Arc<dyn HostcallRecorder>,
+    ) -> io::Result<MetricsServer> {
+        let context = |e: io::Error| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to serve metrics at `{}`: {}", endpoint, e),
+            )
+        };
+        let (listener, endpoint) = match endpoint {
+            MetricsEndpoint::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).map_err(context)?;
+                let addr = listener.local_addr().map_err(context)?;
+                (Listener::Tcp(listener), MetricsEndpoint::Tcp(addr))
+            }
+            #[cfg(unix)]
+            MetricsEndpoint::Unix(path) => {
+                let listener = UnixListener::bind(path).map_err(context)?;
+                (Listener::Unix(listener), endpoint.clone())
+            }
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name("hostcall-metrics".to_string())
+                .spawn(move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        let mut conn = match listener.accept() {
+                            Ok(conn) => conn,
+                            // Running out of file descriptors, or a client
+                            // which gave up before it was accepted, shouldn't
+                            // stop metrics from being served for good.
+                            Err(e) => {
+                                tracing::warn!("failed to accept a metrics connection: {}", e);
+                                std::thread::sleep(ACCEPT_RETRY);
+                                continue;
+                            }
+                        };
+                        if stop.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        // A client going away mid-request is its problem.
+                        let _ = serve(&mut *conn, &*recorder);
+                    }
+                })?
+        };
+        Ok(MetricsServer {
+            endpoint,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Where the server is listening.
+    pub fn endpoint(&self) -> &MetricsEndpoint {
+        &self.endpoint
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the thread up from `accept`; if that fails it's stuck there
+        // for good, so leave it be rather than hang.
+        let woken = match &self.endpoint {
+            MetricsEndpoint::Tcp(addr) => TcpStream::connect(addr).map(drop),
+            #[cfg(unix)]
+            MetricsEndpoint::Unix(path) => UnixStream::connect(path).map(drop),
+        };
+        if woken.is_ok() {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+        #[cfg(unix)]
+        if let MetricsEndpoint::Unix(path) = &self.endpoint {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl fmt::Debug for MetricsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsServer")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+/// Answers one request on `conn`.
+fn serve(conn: &mut dyn Connection, recorder: &dyn HostcallRecorder) -> io::Result<()> {
+    conn.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let n = conn.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let mut metrics = Vec::new();
+    write_prometheus(&mut metrics, &recorder.histograms().unwrap_or_default())?;
+    if request.is_empty() {
+        return conn.write_all(&metrics);
+    }
+    let (status, body) = if request.starts_with(b"GET ") {
+        ("200 OK", metrics)
+    } else {
+        (
+            "405 Method Not Allowed",
+            b"only GET requests are supported\n".to_vec(),
+        )
+    };
+    write!(
+        conn,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        CONTENT_TYPE,
+        body.len()
+    )?;
+    conn.write_all(&body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(hostcalls: &HostcallTable<HostcallHistograms>) -> String {
+        let mut out = Vec::new();
+        write_prometheus(&mut out, hostcalls).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn duration_buckets_include_their_bound() {
+        let mut hostcalls = HostcallTable::new();
+        let h: &mut HostcallHistograms = hostcalls.entry("wasi", "fd_read");
+        for nanos in [128.0, 300.0, 512.0].iter() {
+            h.total.record(*nanos);
+        }
+        let text = text(&hostcalls);
+        let bucket = |le: &str| {
+            format!(
+                "wasmtime_hostcall_duration_seconds_bucket{{module=\"wasi\",function=\"fd_read\",le=\"{}\"}}",
+                le
+            )
+        };
+        let count = |le: &str| {
+            let bucket = bucket(le);
+            text.lines()
+                .find_map(|l| l.strip_prefix(&bucket))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+        assert_eq!(count("0.000000128"), "1");
+        assert_eq!(count("0.000000256"), "1");
+        assert_eq!(count("0.000000512"), "3");
+        assert_eq!(count("0.000001024"), "3");
+    }
+
+    #[test]
+    fn prometheus_text() {
+        let mut hostcalls = HostcallTable::new();
+        let h: &mut HostcallHistograms = hostcalls.entry("wasi", "fd_write");
+        for nanos in [100.0, 200.0, 300.0, 1_000_000.0].iter() {
+            h.total.record(*nanos);
+        }
+        h.results
+            .entry(HostcallResult::Ok)
+            .or_default()
+            .record(100.0);
+        h.results
+            .entry(HostcallResult::Errno(8))
+            .or_default()
+            .record(200.0);
+        h.unsampled = 6;
+
+        let text = text(&hostcalls);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"# TYPE wasmtime_hostcall_calls_total counter"));
+        assert!(lines
+            .contains(&"wasmtime_hostcall_calls_total{module=\"wasi\",function=\"fd_write\"} 10"));
+        assert!(lines.contains(
+            &"wasmtime_hostcall_duration_seconds_bucket{module=\"wasi\",function=\"fd_write\",le=\"0.000000128\"} 1"
+        ));
+        assert!(lines.contains(
+            &"wasmtime_hostcall_duration_seconds_bucket{module=\"wasi\",function=\"fd_write\",le=\"0.000000512\"} 3"
+        ));
+        assert!(lines.contains(
+            &"wasmtime_hostcall_duration_seconds_bucket{module=\"wasi\",function=\"fd_write\",le=\"+Inf\"} 4"
+        ));
+        assert!(lines.contains(
+            &"wasmtime_hostcall_duration_seconds_count{module=\"wasi\",function=\"fd_write\"} 4"
+        ));
+        assert!(lines.contains(
+            &"wasmtime_hostcall_results_total{module=\"wasi\",function=\"fd_write\",result=\"errno\",errno=\"8\"} 1"
+        ));
+        // Nothing moved a payload or counted syscalls.
+        assert!(!text.contains("wasmtime_hostcall_payload_bytes_total{"));
+        assert!(!text.contains("wasmtime_hostcall_syscalls_total{"));
+
+        // Buckets are cumulative.
+        let buckets = lines
+            .iter()
+            .filter(|l| l.starts_with("wasmtime_hostcall_duration_seconds_bucket"))
+            .map(|l| l.rsplit(' ').next().unwrap().parse::<u64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(buckets.len(), DURATION_BOUNDS.count() + 1);
+        assert!(buckets.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn parse_endpoints() {
+        assert_eq!(
+            "127.0.0.1:9000".parse::<MetricsEndpoint>().unwrap(),
+            MetricsEndpoint::Tcp("127.0.0.1:9000".parse().unwrap())
+        );
+        assert_eq!(
+            "localhost:9000".parse::<MetricsEndpoint>().unwrap(),
+            MetricsEndpoint::Tcp("127.0.0.1:9000".parse().unwrap())
+        );
+        assert_eq!(
+            "[::1]:0".parse::<MetricsEndpoint>().unwrap(),
+            MetricsEndpoint::Tcp("[::1]:0".parse().unwrap())
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            "unix:/tmp/metrics.sock".parse::<MetricsEndpoint>().unwrap(),
+            MetricsEndpoint::Unix("/tmp/metrics.sock".into())
+        );
+        assert!("0.0.0.0:9000".parse::<MetricsEndpoint>().is_err());
+        assert!("9000".parse::<MetricsEndpoint>().is_err());
+        assert!("unix:".parse::<MetricsEndpoint>().is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! Hostcall instrumentation.
+//!
+//! Code generated by `from_witx!` timestamps every hostcall with the
+//! process-wide [`clock`], using a [`HostcallTimer`] to split the call into
+//! [`Phase`]s, and hands the result to a [`HostcallRecorder`]. Other Wasmtime
+//! host functions can be recorded too, by configuring the engine with a
+//! [`WasmtimeInstrumentation`]. To bound the overhead, only some calls need be
+//! timed; see [`set_sampling`]. On Linux, calls can also count the syscalls
+//! they make and the CPU events they cause; see [`set_count_syscalls`] and
+//! [`set_perf_counters`]. The histograms a recorder keeps can be served to
+//! Prometheus while the process runs with a [`MetricsServer`].
+
+mod clock;
+mod counters;
+mod histogram;
+#[cfg(feature = "wasmtime")]
+mod instrumentation;
+mod metrics;
+mod payload;
+#[cfg(target_os = "linux")]
+mod perf;
+mod recorder;
+mod sampling;
+mod shards;
+mod stack;
+mod syscalls;
+mod timer;
+
+pub use clock::{clock, set_clock, Clock, ClockSource, FrequencySource, TSC_FREQUENCY_ENV};
+pub use counters::{
+    perf_counters, perf_scope, set_perf_counters, PerfCounter, PerfCounts, PerfScope,
+};
+pub use histogram::{Histogram, Summary, PERCENTILES};
+#[cfg(feature = "wasmtime")]
+pub use instrumentation::{mark_instrumented, WasmtimeInstrumentation};
+pub use metrics::{write_prometheus, MetricsEndpoint, MetricsServer};
+pub use payload::{report_payload, size_class, size_class_bounds, Payload, PayloadHistograms};
+pub use recorder::{
+    record, record_unsampled, recorder, set_recorder, thread_id, Breakdown, HistogramRecorder,
+    HostcallHistograms, HostcallRecorder, HostcallResult, HostcallSample, HostcallTable,
+    NoopRecorder, Phase, PhaseTimestamps, PollStats, RawSamples, StackStats, StoreRecorder,
+    TraceEvent, TraceRecorder,
+};
+pub use sampling::{sampling, set_sampling, Sampler, Sampling};
+pub use stack::{guest_frames, set_guest_frames};
+pub use syscalls::{count_syscalls, set_count_syscalls};
+pub use timer::{HostcallTimer, Polled};
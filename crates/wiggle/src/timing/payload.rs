@@ -0,0 +1,134 @@
+use super::Histogram;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+/// How much data an I/O hostcall moved.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Payload {
+    /// The number of bytes actually transferred, which may be less than the
+    /// guest asked for.
+    pub bytes: u64,
+    /// The number of buffers the bytes were spread over: the length of the
+    /// iovec array, or 1 for calls which take a single buffer.
+    pub iovecs: u32,
+}
+
+thread_local! {
+    static PAYLOAD: Cell<Option<Payload>> = Cell::new(None);
+}
+
+/// Reports the payload of the hostcall currently being made on this thread,
+/// to be recorded alongside its timings.
+///
+/// Hostcall implementations call this just before returning successfully.
+/// It is picked up by the [`HostcallTimer`](super::HostcallTimer) as the
+/// implementation returns, and is cheap enough to call whether or not the
+/// hostcall is being timed.
+pub fn report_payload(bytes: u64, iovecs: u32) {
+    PAYLOAD.with(|p| p.set(Some(Payload { bytes, iovecs })));
+}
+
+/// Takes the payload reported on this thread since the last call, if any.
+pub(crate) fn take_payload() -> Option<Payload> {
+    PAYLOAD.with(|p| p.take())
+}
+
+/// The histograms kept for the calls to one hostcall which reported a
+/// [`Payload`].
+#[derive(Clone, Debug, Default)]
+pub struct PayloadHistograms {
+    /// How many calls reported a payload.
+    pub calls: u64,
+    /// The total number of bytes transferred.
+    pub bytes: u64,
+    /// The total number of buffers used.
+    pub iovecs: u64,
+    /// The total duration of the calls which reported a payload, in
+    /// nanoseconds.
+    pub nanos: f64,
+    /// The duration of the calls, keyed by the [`size_class`] of their
+    /// payload.
+    pub sizes: BTreeMap<u32, Histogram>,
+}
+
+impl PayloadHistograms {
+    pub fn record(&mut self, payload: Payload, nanos: f64) {
+        self.calls += 1;
+        self.bytes += payload.bytes;
+        self.iovecs += u64::from(payload.iovecs);
+        self.nanos += nanos;
+        self.sizes
+            .entry(size_class(payload.bytes))
+            .or_default()
+            .record(nanos);
+    }
+
+    /// Adds everything recorded in `other` to these histograms.
+    pub fn merge(&mut self, other: &PayloadHistograms) {
+        self.calls += other.calls;
+        self.bytes += other.bytes;
+        self.iovecs += other.iovecs;
+        self.nanos += other.nanos;
+        for (class, histogram) in other.sizes.iter() {
+            self.sizes.entry(*class).or_default().merge(histogram);
+        }
+    }
+
+    /// The mean rate the calls transferred data at, in bytes per nanosecond.
+    pub fn throughput(&self) -> f64 {
+        if self.nanos > 0.0 {
+            self.bytes as f64 / self.nanos
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The power-of-two bucket a payload of `bytes` falls into: 0 for an empty
+/// payload, and otherwise `n` for sizes in `2^(n-1) ..= 2^n - 1`.
+pub fn size_class(bytes: u64) -> u32 {
+    64 - bytes.leading_zeros()
+}
+
+/// The smallest and largest payload, in bytes, in the given [`size_class`].
+pub fn size_class_bounds(class: u32) -> (u64, u64) {
+    match class {
+        0 => (0, 0),
+        64 => (1 << 63, u64::MAX),
+        n => (1 << (n - 1), (1 << n) - 1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn size_classes() {
+        for bytes in [0, 1, 2, 3, 4, 4095, 4096, u64::MAX].iter() {
+            let (min, max) = size_class_bounds(size_class(*bytes));
+            assert!(
+                min <= *bytes && *bytes <= max,
+                "{} not in {}..={}",
+                bytes,
+                min,
+                max
+            );
+        }
+        assert_eq!(size_class_bounds(size_class(4096)), (4096, 8191));
+    }
+
+    #[test]
+    fn reports_are_taken_once() {
+        assert_eq!(take_payload(), None);
+        report_payload(10, 2);
+        assert_eq!(
+            take_payload(),
+            Some(Payload {
+                bytes: 10,
+                iovecs: 2
+            })
+        );
+        assert_eq!(take_payload(), None);
+    }
+}
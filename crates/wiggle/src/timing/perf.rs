@@ -0,0 +1,379 @@
+//! Just enough of `perf_event_open(2)` to count events on the current
+//! thread.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::ptr;
+
+/// `PERF_TYPE_HARDWARE`.
+pub(crate) const TYPE_HARDWARE: u32 = 0;
+/// `PERF_TYPE_SOFTWARE`.
+pub(crate) const TYPE_SOFTWARE: u32 = 1;
+/// `PERF_TYPE_TRACEPOINT`.
+pub(crate) const TYPE_TRACEPOINT: u32 = 2;
+
+/// `PERF_COUNT_HW_INSTRUCTIONS`.
+pub(crate) const HW_INSTRUCTIONS: u64 = 1;
+/// `PERF_COUNT_HW_CACHE_MISSES`.
+pub(crate) const HW_CACHE_MISSES: u64 = 3;
+/// `PERF_COUNT_HW_BRANCH_MISSES`.
+pub(crate) const HW_BRANCH_MISSES: u64 = 5;
+/// `PERF_COUNT_SW_CONTEXT_SWITCHES`.
+pub(crate) const SW_CONTEXT_SWITCHES: u64 = 3;
+
+/// The `exclude_kernel` and `exclude_hv` bits of `perf_event_attr`'s flags.
+pub(crate) const FLAGS_USER_ONLY: u64 = (1 << 5) | (1 << 6);
+
+/// `PERF_FORMAT_TOTAL_TIME_ENABLED`.
+const FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+/// `PERF_FORMAT_TOTAL_TIME_RUNNING`.
+const FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+/// `PERF_FORMAT_GROUP`.
+const FORMAT_GROUP: u64 = 1 << 3;
+
+/// `PERF_FLAG_FD_CLOEXEC`.
+const FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+/// `struct perf_event_attr`, as of `PERF_ATTR_SIZE_VER5`. The bitfield
+/// following `read_format` is `flags`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub(crate) struct EventAttr {
+    pub type_: u32,
+    pub size: u32,
+    pub config: u64,
+    pub sample_period: u64,
+    pub sample_type: u64,
+    pub read_format: u64,
+    pub flags: u64,
+    pub wakeup_events: u32,
+    pub bp_type: u32,
+    pub config1: u64,
+    pub config2: u64,
+    pub branch_sample_type: u64,
+    pub sample_regs_user: u64,
+    pub sample_stack_user: u32,
+    pub clockid: i32,
+    pub sample_regs_intr: u64,
+    pub aux_watermark: u32,
+    pub sample_max_stack: u16,
+    pub reserved: u16,
+}
+
+impl EventAttr {
+    pub(crate) fn new(type_: u32, config: u64) -> EventAttr {
+        EventAttr {
+            type_,
+            size: std::mem::size_of::<EventAttr>() as u32,
+            config,
+            ..EventAttr::default()
+        }
+    }
+}
+
+/// A counter for one event, counting while the thread which opened it runs.
+#[derive(Debug)]
+pub(crate) struct Counter {
+    fd: RawFd,
+}
+
+impl Counter {
+    pub(crate) fn open(attr: &EventAttr) -> io::Result<Counter> {
+        Counter::open_in_group(attr, None)
+    }
+
+    /// Opens a counter which is scheduled onto the PMU together with
+    /// `leader`, so that the counts of a group cover the same stretches of
+    /// time.
+    fn open_in_group(attr: &EventAttr, leader: Option<&Counter>) -> io::Result<Counter> {
+        // Safety: `attr` is a valid `perf_event_attr` of the size it claims.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                attr as *const EventAttr,
+                0 as libc::pid_t,
+                -1 as libc::c_int,
+                leader.map_or(-1, |l| l.fd) as libc::c_int,
+                FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Counter { fd: fd as RawFd })
+    }
+
+    /// The number of events counted so far. This is itself a syscall.
+    pub(crate) fn read(&self) -> io::Result<u64> {
+        let mut value = 0u64;
+        read_exact(self.fd, std::slice::from_mut(&mut value))?;
+        Ok(value)
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Reads exactly `values.len()` `u64`s from `fd`.
+fn read_exact(fd: RawFd, values: &mut [u64]) -> io::Result<()> {
+    let len = std::mem::size_of_val(values);
+    // Safety: `values` is valid for writes of `len` bytes.
+    let n = unsafe { libc::read(fd, values.as_mut_ptr() as *mut libc::c_void, len) };
+    if n != len as isize {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The start of `struct perf_event_mmap_page`, which the kernel keeps up to
+/// date for each counter so that it can be read without a syscall.
+#[repr(C)]
+#[allow(dead_code)]
+struct MmapPage {
+    version: u32,
+    compat_version: u32,
+    lock: u32,
+    index: u32,
+    offset: i64,
+    time_enabled: u64,
+    time_running: u64,
+    capabilities: u64,
+    pmc_width: u16,
+}
+
+/// A counter's `perf_event_mmap_page`, mapped read-only.
+#[derive(Debug)]
+struct Page {
+    page: *const MmapPage,
+    len: usize,
+}
+
+impl Page {
+    fn map(counter: &Counter) -> io::Result<Page> {
+        // Safety: a page is mapped fresh, and only ever read.
+        unsafe {
+            let len = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+            let page = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                counter.fd,
+                0,
+            );
+            if page == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Page {
+                page: page as *const MmapPage,
+                len,
+            })
+        }
+    }
+
+    /// Reads the counter with `rdpmc`, or returns `None` if the kernel
+    /// doesn't allow that right now, such as when the counter isn't
+    /// scheduled on the PMU. Counters which have had to share the PMU with
+    /// others aren't read this way either, since their counts need scaling.
+    #[cfg(all(target_arch = "x86_64", stable_asm))]
+    fn rdpmc(&self) -> Option<u64> {
+        use std::sync::atomic::{fence, Ordering};
+
+        /// The `cap_user_rdpmc` bit of `perf_event_mmap_page`'s capabilities.
+        const CAP_USER_RDPMC: u64 = 1 << 2;
+
+        // Safety: the page stays mapped while `self` lives, and the kernel
+        // updates it under the `lock` sequence count, which is re-read to
+        // retry torn reads.
+        unsafe {
+            let page = self.page;
+            loop {
+                let seq = ptr::read_volatile(&(*page).lock);
+                fence(Ordering::Acquire);
+                let index = ptr::read_volatile(&(*page).index);
+                let caps = ptr::read_volatile(&(*page).capabilities);
+                if caps & CAP_USER_RDPMC == 0 || index == 0 {
+                    return None;
+                }
+                let enabled = ptr::read_volatile(&(*page).time_enabled);
+                let running = ptr::read_volatile(&(*page).time_running);
+                if enabled != running {
+                    return None;
+                }
+                let offset = ptr::read_volatile(&(*page).offset);
+                let width = u32::from(ptr::read_volatile(&(*page).pmc_width));
+                let (lo, hi): (u32, u32);
+                std::arch::asm!(
+                    "rdpmc",
+                    in("ecx") index - 1,
+                    out("eax") lo,
+                    out("edx") hi,
+                    options(nostack, preserves_flags),
+                );
+                // The counter is only `pmc_width` bits wide; sign-extend it.
+                let shift = 64 - width;
+                let pmc = ((u64::from(hi) << 32 | u64::from(lo)) << shift) as i64 >> shift;
+                fence(Ordering::Acquire);
+                if ptr::read_volatile(&(*page).lock) == seq {
+                    return Some(offset.wrapping_add(pmc) as u64);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", stable_asm)))]
+    fn rdpmc(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.page as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Counters for several events which are scheduled together, read either
+/// all at once with a single `read`, or without any syscall at all with
+/// `rdpmc` when the kernel allows user space to.
+#[derive(Debug)]
+pub(crate) struct Group {
+    counters: Vec<Counter>,
+    /// A page per counter, if every counter could be mapped for `rdpmc`.
+    pages: Option<Vec<Page>>,
+    buf: Vec<u64>,
+}
+
+impl Group {
+    /// Opens a counter for each of `events`, a `(type, config)` pair, with
+    /// the first leading the group. `flags` is set on every counter.
+    pub(crate) fn open(events: &[(u32, u64)], flags: u64) -> io::Result<Group> {
+        let mut counters = Vec::with_capacity(events.len());
+        for (type_, config) in events.iter() {
+            let mut attr = EventAttr::new(*type_, *config);
+            attr.flags = flags;
+            attr.read_format = FORMAT_GROUP | FORMAT_TOTAL_TIME_ENABLED | FORMAT_TOTAL_TIME_RUNNING;
+            let counter = Counter::open_in_group(&attr, counters.first())?;
+            counters.push(counter);
+        }
+        // Only hardware events can be read with `rdpmc`, and with `rdpmc`
+        // disabled mapping may fail, so reads fall back to the syscall.
+        let pages = if cfg!(all(target_arch = "x86_64", stable_asm))
+            && events.iter().all(|(type_, _)| *type_ == TYPE_HARDWARE)
+        {
+            counters
+                .iter()
+                .map(Page::map)
+                .collect::<io::Result<_>>()
+                .ok()
+        } else {
+            None
+        };
+        Ok(Group {
+            buf: vec![0; counters.len() + 3],
+            counters,
+            pages,
+        })
+    }
+
+    /// Reads every counter into `values`, in the order the events were
+    /// opened in.
+    pub(crate) fn read(&mut self, values: &mut [u64]) -> io::Result<()> {
+        if let Some(pages) = &self.pages {
+            let mut all = true;
+            for (value, page) in values.iter_mut().zip(pages.iter()) {
+                match page.rdpmc() {
+                    Some(v) => *value = v,
+                    None => {
+                        all = false;
+                        break;
+                    }
+                }
+            }
+            if all {
+                return Ok(());
+            }
+        }
+        // The leader reads as the number of counters, how long the group
+        // was enabled and how long it was actually counting, and then each
+        // count.
+        read_exact(self.counters[0].fd, &mut self.buf)?;
+        let (enabled, running) = (self.buf[1], self.buf[2]);
+        for (value, count) in values.iter_mut().zip(&self.buf[3..]) {
+            *value = scale(*count, enabled, running);
+        }
+        Ok(())
+    }
+}
+
+/// Estimates what a counter which only counted for `running` out of the
+/// `enabled` nanoseconds it was enabled for, because it had to take turns on
+/// the PMU with other counters, would have counted all along.
+fn scale(count: u64, enabled: u64, running: u64) -> u64 {
+    if running == 0 {
+        return 0;
+    }
+    if running >= enabled {
+        return count;
+    }
+    (u128::from(count) * u128::from(enabled) / u128::from(running)) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn attr_matches_ver5_layout() {
+        assert_eq!(std::mem::size_of::<EventAttr>(), 112);
+    }
+
+    #[test]
+    fn mmap_page_layout() {
+        let page = std::mem::MaybeUninit::<MmapPage>::uninit();
+        // Safety: only the address of the field is taken.
+        let pmc_width = unsafe { ptr::addr_of!((*page.as_ptr()).pmc_width) };
+        assert_eq!(pmc_width as usize - page.as_ptr() as usize, 48);
+    }
+
+    #[test]
+    fn multiplexed_counts_are_scaled() {
+        assert_eq!(scale(100, 10, 10), 100);
+        assert_eq!(scale(100, 10, 5), 200);
+        assert_eq!(scale(100, 10, 0), 0);
+        assert_eq!(scale(u64::MAX / 2, 4, 2), u64::MAX - 1);
+    }
+
+    #[test]
+    fn group_reads_in_order() {
+        // Software events can be counted even without a hardware PMU, though
+        // `perf_event_paranoid` may still forbid it.
+        let events = [
+            (TYPE_SOFTWARE, SW_CONTEXT_SWITCHES),
+            (TYPE_SOFTWARE, SW_CONTEXT_SWITCHES),
+        ];
+        let mut group = match Group::open(&events, 0) {
+            Ok(group) => group,
+            Err(e) => {
+                println!("skipping: can't open perf counters: {}", e);
+                return;
+            }
+        };
+        // Software events can't be read with `rdpmc`.
+        assert!(group.pages.is_none());
+        let mut before = [0; 2];
+        group.read(&mut before).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let mut after = [0; 2];
+        group.read(&mut after).unwrap();
+        assert!(after[0] > before[0]);
+        assert_eq!(after[0] - before[0], after[1] - before[1]);
+    }
+}
@@ -0,0 +1,759 @@
+use super::shards::Shards;
+use super::{clock, Histogram, Payload, PayloadHistograms, PerfCounter, PerfCounts};
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// How an instrumented hostcall finished.
+///
+/// Results are ordered with success first and traps last.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HostcallResult {
+    /// The call succeeded, or it has no error result at all.
+    Ok,
+    /// The call returned this (lowered) error code.
+    Errno(i32),
+    /// The call trapped, including via `proc_exit`.
+    Trap,
+}
+
+/// The parts a hostcall's time is split into.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// From Wasmtime calling the host function to the start of the abi
+    /// function: looking up the memory export and borrowing the context.
+    Entry,
+    /// Reading and validating the arguments.
+    Lift,
+    /// The implementation, i.e. the generated trait's method.
+    Call,
+    /// Writing results to guest memory and lowering the error code.
+    Lower,
+    /// From the end of the abi function until control is handed back to
+    /// Wasmtime, including converting traps.
+    Return,
+}
+
+impl Phase {
+    /// Every phase, in the order they happen.
+    pub const ALL: [Phase; 5] = [
+        Phase::Entry,
+        Phase::Lift,
+        Phase::Call,
+        Phase::Lower,
+        Phase::Return,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Entry => "entry",
+            Phase::Lift => "lift",
+            Phase::Call => "call",
+            Phase::Lower => "lower",
+            Phase::Return => "return",
+        }
+    }
+}
+
+/// The boundaries between the phases of a hostcall, in ticks of the
+/// process-wide [`clock`](super::clock).
+///
+/// A phase which was not measured, such as [`Phase::Entry`] when the abi
+/// function is called directly rather than through Wasmtime, has a zero
+/// duration.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PhaseTimestamps {
+    /// The start of argument lifting.
+    pub lift: u64,
+    /// The call into the implementation.
+    pub call: u64,
+    /// The return from the implementation.
+    pub call_end: u64,
+    /// The end of result lowering.
+    pub lowered: u64,
+}
+
+impl PhaseTimestamps {
+    /// When `phase` began and ended, for a call spanning `start` to `end`.
+    pub fn bounds(&self, start: u64, end: u64, phase: Phase) -> (u64, u64) {
+        match phase {
+            Phase::Entry => (start, self.lift),
+            Phase::Lift => (self.lift, self.call),
+            Phase::Call => (self.call, self.call_end),
+            Phase::Lower => (self.call_end, self.lowered),
+            Phase::Return => (self.lowered, end),
+        }
+    }
+}
+
+/// How an async hostcall's future was polled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollStats {
+    /// Ticks spent inside `Future::poll`, i.e. actually running rather than
+    /// suspended waiting for a wakeup.
+    pub busy: u64,
+    /// How many times the future returned `Poll::Pending`.
+    pub yields: u32,
+}
+
+/// A single timed hostcall, as handed to a `HostcallRecorder`.
+///
+/// `start` and `end` are ticks of the process-wide [`clock`](super::clock),
+/// and cover every [`Phase`] of the call. For async hostcalls this is wall
+/// time, including any time the call spent suspended; `poll` says how much of
+/// it was spent running.
+#[derive(Clone, Debug)]
+pub struct HostcallSample<'a> {
+    pub module: &'a str,
+    pub function: &'a str,
+    pub start: u64,
+    pub end: u64,
+    pub phases: PhaseTimestamps,
+    /// Set for async hostcalls only.
+    pub poll: Option<PollStats>,
+    /// Set for I/O hostcalls which reported how much data they moved.
+    pub payload: Option<Payload>,
+    /// The WebAssembly functions which led to this call, innermost first,
+    /// when [`set_guest_frames`](super::set_guest_frames) asked for them.
+    pub stack: Option<&'a [String]>,
+    /// The number of syscalls the implementation issued, when
+    /// [`set_count_syscalls`](super::set_count_syscalls) asked for them.
+    pub syscalls: Option<u64>,
+    /// How many times each performance counter ticked during the
+    /// implementation, when [`set_perf_counters`](super::set_perf_counters)
+    /// asked for them.
+    pub counters: Option<PerfCounts>,
+    pub result: HostcallResult,
+}
+
+impl HostcallSample<'_> {
+    /// The duration of this call in ticks.
+    pub fn ticks(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+
+    /// The duration of this call in nanoseconds.
+    pub fn nanos(&self) -> f64 {
+        clock().ticks_to_nanos(self.ticks())
+    }
+
+    /// When `phase` began and ended, in ticks.
+    pub fn phase_bounds(&self, phase: Phase) -> (u64, u64) {
+        self.phases.bounds(self.start, self.end, phase)
+    }
+
+    /// The time an async call spent running in nanoseconds, or `None` for a
+    /// synchronous call.
+    pub fn busy_nanos(&self) -> Option<f64> {
+        self.poll.map(|p| clock().ticks_to_nanos(p.busy))
+    }
+
+    /// The duration of `phase` in nanoseconds.
+    pub fn phase_nanos(&self, phase: Phase) -> f64 {
+        let (start, end) = self.phase_bounds(phase);
+        clock().ticks_to_nanos(end.saturating_sub(start))
+    }
+}
+
+/// A sink for hostcall timings.
+///
+/// The code generated by `from_witx!` calls `record` once per hostcall, after
+/// the call has completed. A recorder can be installed for the whole process
+/// with [`set_recorder`], or for a single context by overriding the
+/// `hostcall_recorder` method of the generated module trait.
+///
+/// There is no per-`Store` setting, as the generated code only sees the
+/// context a call is made through. A `Store` holds its own contexts, so
+/// returning the same recorder from each of them, such as with
+/// `WasiCtx::set_hostcall_recorder` for WASI, covers every call made in that
+/// `Store`. Calls through contexts which don't return one still go to the
+/// process-wide recorder.
+pub trait HostcallRecorder: Send + Sync {
+    fn record(&self, sample: &HostcallSample<'_>);
+
+    /// Called instead of `record` for a call which was made but not timed,
+    /// because the current [`Sampling`](super::Sampling) skipped it. The
+    /// default ignores it.
+    fn record_unsampled(&self, module: &str, function: &str) {
+        let _ = (module, function);
+    }
+
+    /// The histograms recorded so far, merged across threads, for recorders
+    /// which keep them. This may be called at any time, including while calls
+    /// are being recorded on other threads, so that stats can be read from a
+    /// long-running process; see [`MetricsServer`](super::MetricsServer). The
+    /// default returns `None`.
+    fn histograms(&self) -> Option<HostcallTable<HostcallHistograms>> {
+        None
+    }
+}
+
+impl<R: HostcallRecorder + ?Sized> HostcallRecorder for Arc<R> {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        (**self).record(sample)
+    }
+
+    fn record_unsampled(&self, module: &str, function: &str) {
+        (**self).record_unsampled(module, function)
+    }
+
+    fn histograms(&self) -> Option<HostcallTable<HostcallHistograms>> {
+        (**self).histograms()
+    }
+}
+
+/// A recorder which discards every sample.
+#[derive(Default, Debug)]
+pub struct NoopRecorder;
+
+impl HostcallRecorder for NoopRecorder {
+    fn record(&self, _sample: &HostcallSample<'_>) {}
+}
+
+/// Per-hostcall data, keyed by module name and then function name.
+///
+/// Lookups take `&str` so that recording into an existing entry does not
+/// allocate.
+#[derive(Clone, Debug)]
+pub struct HostcallTable<V> {
+    modules: HashMap<String, HashMap<String, V>>,
+}
+
+impl<V> Default for HostcallTable<V> {
+    fn default() -> Self {
+        HostcallTable {
+            modules: HashMap::new(),
+        }
+    }
+}
+
+impl<V> HostcallTable<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, module: &str, function: &str) -> Option<&V> {
+        self.modules.get(module)?.get(function)
+    }
+
+    pub fn get_mut(&mut self, module: &str, function: &str) -> Option<&mut V> {
+        self.modules.get_mut(module)?.get_mut(function)
+    }
+
+    /// Returns the entry for `module::function`, creating it with `V::default`
+    /// if this is the first time the hostcall is seen.
+    pub fn entry(&mut self, module: &str, function: &str) -> &mut V
+    where
+        V: Default,
+    {
+        if !self.modules.contains_key(module) {
+            self.modules.insert(module.to_owned(), HashMap::new());
+        }
+        let functions = self.modules.get_mut(module).unwrap();
+        if !functions.contains_key(function) {
+            functions.insert(function.to_owned(), V::default());
+        }
+        functions.get_mut(function).unwrap()
+    }
+
+    /// Sets the entry for `module::function`, replacing any previous value.
+    pub fn insert(&mut self, module: &str, function: &str, value: V) {
+        match self.modules.get_mut(module) {
+            Some(functions) => {
+                functions.insert(function.to_owned(), value);
+            }
+            None => {
+                let mut functions = HashMap::new();
+                functions.insert(function.to_owned(), value);
+                self.modules.insert(module.to_owned(), functions);
+            }
+        }
+    }
+
+    /// Iterates over `(module, function, value)` triples in no particular
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, &V)> {
+        self.modules.iter().flat_map(|(module, functions)| {
+            functions
+                .iter()
+                .map(move |(function, v)| (module.as_str(), function.as_str(), v))
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.values().all(|f| f.is_empty())
+    }
+}
+
+/// A recorder which keeps the duration of every call, in nanoseconds.
+///
+/// Memory use grows with the number of calls, so this is best suited to short
+/// runs where the full distribution is wanted.
+#[derive(Default, Debug)]
+pub struct RawSamples {
+    samples: Mutex<HostcallTable<Vec<f64>>>,
+}
+
+impl RawSamples {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of all samples recorded so far.
+    pub fn snapshot(&self) -> HostcallTable<Vec<f64>> {
+        self.samples.lock().unwrap().clone()
+    }
+}
+
+impl HostcallRecorder for RawSamples {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        self.samples
+            .lock()
+            .unwrap()
+            .entry(sample.module, sample.function)
+            .push(sample.nanos());
+    }
+}
+
+/// The histograms kept for one hostcall by a [`HistogramRecorder`].
+#[derive(Clone, Debug, Default)]
+pub struct HostcallHistograms {
+    /// The duration of the whole call.
+    pub total: Histogram,
+    /// The duration of each phase, indexed in the order of [`Phase::ALL`].
+    pub phases: [Histogram; 5],
+    /// For async calls, the time spent inside `Future::poll`.
+    pub busy: Histogram,
+    /// For async calls, the total number of times the call yielded.
+    pub yields: u64,
+    /// The duration of the whole call, separately for each way it finished,
+    /// so that fast failures can be told apart from successful calls.
+    pub results: BTreeMap<HostcallResult, Histogram>,
+    /// For I/O hostcalls, how the duration relates to the amount of data
+    /// moved.
+    pub payload: PayloadHistograms,
+    /// The calls made from each guest stack, for calls which recorded one.
+    pub stacks: HashMap<Vec<String>, StackStats>,
+    /// The number of syscalls each call issued, for calls which counted
+    /// them.
+    pub syscalls: Histogram,
+    /// The counts of each performance counter per call, indexed in the order
+    /// of [`PerfCounter::ALL`], for calls which counted them.
+    pub counters: [Histogram; 4],
+    /// The number of calls which were made but not timed, because the
+    /// current [`Sampling`](super::Sampling) skipped them. Everything else
+    /// only covers the calls which were timed.
+    pub unsampled: u64,
+}
+
+/// The calls to a hostcall made from one guest stack.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct StackStats {
+    pub calls: u64,
+    /// The total duration of the calls, in nanoseconds.
+    pub nanos: f64,
+}
+
+impl StackStats {
+    fn merge(&mut self, other: &StackStats) {
+        self.calls += other.calls;
+        self.nanos += other.nanos;
+    }
+}
+
+impl HostcallHistograms {
+    fn with_raw_samples() -> Self {
+        HostcallHistograms {
+            total: Histogram::with_raw_samples(),
+            ..Default::default()
+        }
+    }
+
+    pub fn phase(&self, phase: Phase) -> &Histogram {
+        &self.phases[phase as usize]
+    }
+
+    pub fn counter(&self, counter: PerfCounter) -> &Histogram {
+        &self.counters[counter as usize]
+    }
+
+    /// The number of calls made, whether or not they were timed.
+    pub fn count(&self) -> u64 {
+        self.total.count() + self.unsampled
+    }
+
+    /// How many calls each timed call stands for: the factor to scale counts
+    /// and totals taken from the histograms by to estimate them for every
+    /// call made.
+    pub fn scale(&self) -> f64 {
+        match self.total.count() {
+            0 => 0.0,
+            sampled => self.count() as f64 / sampled as f64,
+        }
+    }
+
+    /// The durations of the calls which finished with `result`, if any did.
+    pub fn result(&self, result: HostcallResult) -> Option<&Histogram> {
+        self.results.get(&result)
+    }
+
+    /// Adds everything recorded in `other` to these histograms.
+    pub fn merge(&mut self, other: &HostcallHistograms) {
+        self.total.merge(&other.total);
+        for (a, b) in self.phases.iter_mut().zip(other.phases.iter()) {
+            a.merge(b);
+        }
+        self.busy.merge(&other.busy);
+        self.yields += other.yields;
+        for (result, histogram) in other.results.iter() {
+            self.results.entry(*result).or_default().merge(histogram);
+        }
+        self.payload.merge(&other.payload);
+        for (stack, stats) in other.stacks.iter() {
+            self.stacks.entry(stack.clone()).or_default().merge(stats);
+        }
+        self.syscalls.merge(&other.syscalls);
+        for (a, b) in self.counters.iter_mut().zip(other.counters.iter()) {
+            a.merge(b);
+        }
+        self.unsampled += other.unsampled;
+    }
+}
+
+type HistogramShards = Shards<HostcallTable<HostcallHistograms>>;
+
+/// A recorder which keeps a [`Histogram`] per hostcall, and per phase of
+/// each hostcall.
+///
+/// Every thread records into its own set of histograms, which are merged by
+/// [`HistogramRecorder::snapshot`], so calls made on any thread are counted
+/// without the threads contending with each other. Recorders for individual
+/// stores can be split off with [`HistogramRecorder::for_store`] to get a
+/// per-store [`breakdown`](HistogramRecorder::breakdown) as well as totals.
+#[derive(Debug)]
+pub struct HistogramRecorder {
+    raw_samples: bool,
+    shards: HistogramShards,
+    stores: Mutex<Vec<(String, Arc<HistogramShards>)>>,
+}
+
+impl Default for HistogramRecorder {
+    fn default() -> Self {
+        HistogramRecorder {
+            raw_samples: false,
+            shards: Shards::new(),
+            stores: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl HistogramRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A recorder whose histograms also keep every sample; see
+    /// [`Histogram::with_raw_samples`].
+    pub fn with_raw_samples() -> Self {
+        HistogramRecorder {
+            raw_samples: true,
+            ..Self::default()
+        }
+    }
+
+    /// A recorder for a single store, typically installed as that store's
+    /// context recorder. Its samples are included in this recorder's totals
+    /// and show up under `label` in the breakdown.
+    pub fn for_store(&self, label: &str) -> StoreRecorder {
+        let shards = Arc::new(Shards::new());
+        self.stores
+            .lock()
+            .unwrap()
+            .push((label.to_owned(), shards.clone()));
+        StoreRecorder {
+            raw_samples: self.raw_samples,
+            shards,
+        }
+    }
+
+    /// The histograms recorded so far, merged across all threads and stores.
+    pub fn snapshot(&self) -> HostcallTable<HostcallHistograms> {
+        let mut merged = HostcallTable::new();
+        for part in self.breakdown() {
+            merge_into(&mut merged, &part.histograms);
+        }
+        merged
+    }
+
+    /// The histograms recorded so far, separately for each thread and store.
+    pub fn breakdown(&self) -> Vec<Breakdown> {
+        let mut parts = Vec::new();
+        let mut collect = |store: Option<&str>, shards: &HistogramShards| {
+            shards.for_each(|thread, histograms| {
+                if !histograms.is_empty() {
+                    parts.push(Breakdown {
+                        thread,
+                        store: store.map(|s| s.to_owned()),
+                        histograms: histograms.clone(),
+                    });
+                }
+            })
+        };
+        collect(None, &self.shards);
+        for (label, shards) in self.stores.lock().unwrap().iter() {
+            collect(Some(label), shards);
+        }
+        parts
+    }
+}
+
+impl HostcallRecorder for HistogramRecorder {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        self.shards
+            .with(|histograms| record_histogram(histograms, self.raw_samples, sample))
+    }
+
+    fn record_unsampled(&self, module: &str, function: &str) {
+        self.shards.with(|histograms| {
+            histogram_entry(histograms, self.raw_samples, module, function).unsampled += 1
+        })
+    }
+
+    fn histograms(&self) -> Option<HostcallTable<HostcallHistograms>> {
+        Some(self.snapshot())
+    }
+}
+
+/// A recorder for one store's hostcalls, created by
+/// [`HistogramRecorder::for_store`].
+#[derive(Debug)]
+pub struct StoreRecorder {
+    raw_samples: bool,
+    shards: Arc<HistogramShards>,
+}
+
+impl StoreRecorder {
+    /// The histograms recorded so far for this store, merged across all
+    /// threads.
+    pub fn snapshot(&self) -> HostcallTable<HostcallHistograms> {
+        let mut merged = HostcallTable::new();
+        self.shards
+            .for_each(|_, histograms| merge_into(&mut merged, histograms));
+        merged
+    }
+}
+
+impl HostcallRecorder for StoreRecorder {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        self.shards
+            .with(|histograms| record_histogram(histograms, self.raw_samples, sample))
+    }
+
+    fn record_unsampled(&self, module: &str, function: &str) {
+        self.shards.with(|histograms| {
+            histogram_entry(histograms, self.raw_samples, module, function).unsampled += 1
+        })
+    }
+
+    fn histograms(&self) -> Option<HostcallTable<HostcallHistograms>> {
+        Some(self.snapshot())
+    }
+}
+
+/// The histograms recorded by one thread, for one store if the samples came
+/// through a [`StoreRecorder`].
+#[derive(Clone, Debug)]
+pub struct Breakdown {
+    /// The [`thread_id`] of the recording thread.
+    pub thread: u64,
+    /// The label passed to [`HistogramRecorder::for_store`], if any.
+    pub store: Option<String>,
+    pub histograms: HostcallTable<HostcallHistograms>,
+}
+
+fn histogram_entry<'a>(
+    histograms: &'a mut HostcallTable<HostcallHistograms>,
+    raw_samples: bool,
+    module: &str,
+    function: &str,
+) -> &'a mut HostcallHistograms {
+    if histograms.get(module, function).is_none() && raw_samples {
+        histograms.insert(module, function, HostcallHistograms::with_raw_samples());
+    }
+    histograms.entry(module, function)
+}
+
+fn record_histogram(
+    histograms: &mut HostcallTable<HostcallHistograms>,
+    raw_samples: bool,
+    sample: &HostcallSample<'_>,
+) {
+    let entry = histogram_entry(histograms, raw_samples, sample.module, sample.function);
+    let nanos = sample.nanos();
+    entry.total.record(nanos);
+    entry
+        .results
+        .entry(sample.result)
+        .or_default()
+        .record(nanos);
+    if let Some(payload) = sample.payload {
+        entry.payload.record(payload, nanos);
+    }
+    if let Some(stack) = sample.stack {
+        if !entry.stacks.contains_key(stack) {
+            entry.stacks.insert(stack.to_vec(), StackStats::default());
+        }
+        let stats = entry.stacks.get_mut(stack).unwrap();
+        stats.calls += 1;
+        stats.nanos += nanos;
+    }
+    if let Some(syscalls) = sample.syscalls {
+        entry.syscalls.record(syscalls as f64);
+    }
+    if let Some(counters) = sample.counters {
+        for (counter, count) in counters.iter() {
+            entry.counters[counter as usize].record(count as f64);
+        }
+    }
+    for phase in Phase::ALL.iter() {
+        entry.phases[*phase as usize].record(sample.phase_nanos(*phase));
+    }
+    if let (Some(busy), Some(poll)) = (sample.busy_nanos(), sample.poll) {
+        entry.busy.record(busy);
+        entry.yields += u64::from(poll.yields);
+    }
+}
+
+fn merge_into(
+    into: &mut HostcallTable<HostcallHistograms>,
+    from: &HostcallTable<HostcallHistograms>,
+) {
+    for (module, function, histogram) in from.iter() {
+        match into.get_mut(module, function) {
+            Some(h) => h.merge(histogram),
+            None => into.insert(module, function, histogram.clone()),
+        }
+    }
+}
+
+/// One hostcall on the timeline kept by a [`TraceRecorder`].
+#[derive(Clone, Debug)]
+pub struct TraceEvent {
+    pub module: String,
+    pub function: String,
+    /// Start and end in ticks of the process-wide [`clock`](super::clock).
+    pub start: u64,
+    pub end: u64,
+    pub phases: PhaseTimestamps,
+    pub poll: Option<PollStats>,
+    pub payload: Option<Payload>,
+    /// The guest stack, innermost first, if one was recorded.
+    pub stack: Option<Vec<String>>,
+    pub syscalls: Option<u64>,
+    pub counters: Option<PerfCounts>,
+    /// The [`thread_id`] of the thread which made the call.
+    pub thread: u64,
+    pub result: HostcallResult,
+}
+
+/// A recorder which keeps every call along with when and on which thread it
+/// happened, for rendering as a timeline. Like [`HistogramRecorder`], each
+/// thread appends to its own buffer.
+#[derive(Debug)]
+pub struct TraceRecorder {
+    events: Shards<Vec<TraceEvent>>,
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        TraceRecorder {
+            events: Shards::new(),
+        }
+    }
+}
+
+impl TraceEvent {
+    /// When `phase` began and ended, in ticks.
+    pub fn phase_bounds(&self, phase: Phase) -> (u64, u64) {
+        self.phases.bounds(self.start, self.end, phase)
+    }
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A copy of the events recorded so far on every thread, ordered by
+    /// start time.
+    pub fn snapshot(&self) -> Vec<TraceEvent> {
+        let mut events = Vec::new();
+        self.events
+            .for_each(|_, thread_events| events.extend_from_slice(thread_events));
+        events.sort_by_key(|e| e.start);
+        events
+    }
+}
+
+impl HostcallRecorder for TraceRecorder {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        let event = TraceEvent {
+            module: sample.module.to_owned(),
+            function: sample.function.to_owned(),
+            start: sample.start,
+            end: sample.end,
+            phases: sample.phases,
+            poll: sample.poll,
+            payload: sample.payload,
+            stack: sample.stack.map(|s| s.to_vec()),
+            syscalls: sample.syscalls,
+            counters: sample.counters,
+            thread: thread_id(),
+            result: sample.result,
+        };
+        self.events.with(|events| events.push(event));
+    }
+}
+
+/// A small integer identifying the current thread, assigned in the order
+/// threads first ask for one. `std::thread::ThreadId` has no stable numeric
+/// form, which trace formats need.
+pub fn thread_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local!(static ID: u64 = NEXT.fetch_add(1, Ordering::Relaxed));
+    ID.with(|id| *id)
+}
+
+static RECORDER: Lazy<RwLock<Arc<dyn HostcallRecorder>>> =
+    Lazy::new(|| RwLock::new(Arc::new(NoopRecorder)));
+
+/// Installs the process-wide recorder, used for every hostcall whose context
+/// does not provide its own. The default discards all samples.
+pub fn set_recorder(recorder: Arc<dyn HostcallRecorder>) {
+    *RECORDER.write().unwrap() = recorder;
+}
+
+/// The currently installed process-wide recorder.
+pub fn recorder() -> Arc<dyn HostcallRecorder> {
+    RECORDER.read().unwrap().clone()
+}
+
+/// Hands `sample` to `local` if the context provided a recorder, and to the
+/// process-wide recorder otherwise.
+///
+/// This is called from code generated by `from_witx!`.
+pub fn record(local: Option<&dyn HostcallRecorder>, sample: &HostcallSample<'_>) {
+    match local {
+        Some(r) => r.record(sample),
+        None => RECORDER.read().unwrap().record(sample),
+    }
+}
+
+/// Like [`record`], for a call which was not timed.
+pub fn record_unsampled(local: Option<&dyn HostcallRecorder>, module: &str, function: &str) {
+    match local {
+        Some(r) => r.record_unsampled(module, function),
+        None => RECORDER.read().unwrap().record_unsampled(module, function),
+    }
+}
@@ -0,0 +1,160 @@
+use once_cell::sync::Lazy;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// Which hostcalls are timed.
+///
+/// Timing a call reads the clock several times, which can cost more than a
+/// cheap hostcall itself. Sampling only times some calls, and merely counts
+/// the rest, so that instrumentation can be left on in long-running
+/// benchmarks. Sampling decisions are made separately for each hostcall, so
+/// a rarely made call is not starved by a frequent one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// Time every call. The default.
+    All,
+    /// Time one in every `n` calls to each hostcall.
+    OneIn(u32),
+    /// Time at most `calls` calls to each hostcall in each `period`, counting
+    /// the rest.
+    Budget { calls: u32, period: Duration },
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling::All
+    }
+}
+
+const ALL: u8 = 0;
+const ONE_IN: u8 = 1;
+const BUDGET: u8 = 2;
+
+static MODE: AtomicU8 = AtomicU8::new(ALL);
+static ONE_IN_N: AtomicU32 = AtomicU32::new(1);
+static BUDGET_CALLS: AtomicU32 = AtomicU32::new(0);
+static BUDGET_PERIOD: AtomicU64 = AtomicU64::new(1);
+
+/// The epoch budget periods are counted from.
+static EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Sets which hostcalls are timed from now on, process-wide.
+pub fn set_sampling(sampling: Sampling) {
+    match sampling {
+        Sampling::All => {}
+        Sampling::OneIn(n) => ONE_IN_N.store(n.max(1), Ordering::Relaxed),
+        Sampling::Budget { calls, period } => {
+            Lazy::force(&EPOCH);
+            BUDGET_CALLS.store(calls, Ordering::Relaxed);
+            let period = u64::try_from(period.as_nanos()).unwrap_or(u64::MAX);
+            BUDGET_PERIOD.store(period.max(1), Ordering::Relaxed);
+        }
+    }
+    let mode = match sampling {
+        Sampling::All => ALL,
+        Sampling::OneIn(_) => ONE_IN,
+        Sampling::Budget { .. } => BUDGET,
+    };
+    MODE.store(mode, Ordering::Relaxed);
+}
+
+/// The current [`Sampling`].
+pub fn sampling() -> Sampling {
+    match MODE.load(Ordering::Relaxed) {
+        ONE_IN => Sampling::OneIn(ONE_IN_N.load(Ordering::Relaxed)),
+        BUDGET => Sampling::Budget {
+            calls: BUDGET_CALLS.load(Ordering::Relaxed),
+            period: Duration::from_nanos(BUDGET_PERIOD.load(Ordering::Relaxed)),
+        },
+        _ => Sampling::All,
+    }
+}
+
+/// The sampling state of one hostcall.
+///
+/// Code generated by `from_witx!` keeps one of these in a `static` for each
+/// instrumented function, and asks it whether to time each call.
+#[derive(Debug)]
+pub struct Sampler {
+    calls: AtomicU64,
+    period: AtomicU64,
+    sampled: AtomicU32,
+}
+
+impl Sampler {
+    pub const fn new() -> Sampler {
+        Sampler {
+            calls: AtomicU64::new(0),
+            period: AtomicU64::new(0),
+            sampled: AtomicU32::new(0),
+        }
+    }
+
+    /// Whether the call being made now should be timed.
+    ///
+    /// Concurrent calls may occasionally push a budget slightly over, which
+    /// only costs a little more overhead.
+    #[inline]
+    pub fn sample(&self) -> bool {
+        match MODE.load(Ordering::Relaxed) {
+            ALL => true,
+            ONE_IN => {
+                let n = u64::from(ONE_IN_N.load(Ordering::Relaxed));
+                self.calls.fetch_add(1, Ordering::Relaxed) % n == 0
+            }
+            _ => {
+                let elapsed = u64::try_from(EPOCH.elapsed().as_nanos()).unwrap_or(u64::MAX);
+                let period = elapsed / BUDGET_PERIOD.load(Ordering::Relaxed);
+                if self.period.swap(period, Ordering::Relaxed) != period {
+                    self.sampled.store(0, Ordering::Relaxed);
+                }
+                self.sampled.fetch_add(1, Ordering::Relaxed) < BUDGET_CALLS.load(Ordering::Relaxed)
+            }
+        }
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The sampling mode is process-wide, so everything that changes it is
+    // tested together.
+    #[test]
+    fn sampling_modes() {
+        let sampler = Sampler::new();
+        assert!((0..10).all(|_| sampler.sample()));
+
+        set_sampling(Sampling::OneIn(4));
+        assert_eq!(sampling(), Sampling::OneIn(4));
+        let sampled = (0..100).filter(|_| sampler.sample()).count();
+        assert_eq!(sampled, 25);
+        // Calls to Wasmtime host functions are sampled per function too.
+        #[cfg(feature = "wasmtime")]
+        {
+            use super::super::instrumentation::sample;
+            let sampled = (0..8).filter(|_| sample("m", "a")).count();
+            assert_eq!(sampled, 2);
+            assert!(sample("m", "b"));
+            assert!(sample("n", "a"));
+        }
+
+        set_sampling(Sampling::Budget {
+            calls: 3,
+            period: Duration::from_secs(3600),
+        });
+        let sampler = Sampler::new();
+        let sampled = (0..100).filter(|_| sampler.sample()).count();
+        assert!(sampled >= 3 && sampled <= 6, "sampled {}", sampled);
+
+        set_sampling(Sampling::All);
+        assert!((0..10).all(|_| sampler.sample()));
+    }
+}
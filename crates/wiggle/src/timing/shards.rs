@@ -0,0 +1,117 @@
+use super::thread_id;
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+/// Per-thread buffers which are only merged when somebody asks for the
+/// totals.
+///
+/// Each thread records into its own shard, so the lock taken on the hot path
+/// is never contended except by a concurrent [`Shards::for_each`]. Shards are
+/// owned by the `Shards` rather than the thread, so nothing is lost when a
+/// thread exits before the results are read.
+pub(crate) struct Shards<T> {
+    id: u64,
+    shards: Mutex<Vec<Arc<Shard<T>>>>,
+}
+
+struct Shard<T> {
+    thread: u64,
+    value: Mutex<T>,
+}
+
+thread_local! {
+    /// The shards this thread has recorded into, keyed by `Shards::id`. These
+    /// are weak so that dropping a recorder frees its buffers.
+    static LOCAL: RefCell<Vec<(u64, Weak<dyn Any + Send + Sync>)>> = RefCell::new(Vec::new());
+}
+
+impl<T: Default + Send + 'static> Shards<T> {
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Shards {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            shards: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Runs `f` on the current thread's buffer, creating it first if needed.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let shard = self.local();
+        let mut value = shard.value.lock().unwrap();
+        f(&mut value)
+    }
+
+    /// Runs `f` on every thread's buffer along with that thread's
+    /// [`thread_id`].
+    pub fn for_each(&self, mut f: impl FnMut(u64, &T)) {
+        let shards = self.shards.lock().unwrap().clone();
+        for shard in shards {
+            f(shard.thread, &shard.value.lock().unwrap());
+        }
+    }
+
+    fn local(&self) -> Arc<Shard<T>> {
+        LOCAL.with(|local| {
+            let mut local = local.borrow_mut();
+            let found = local
+                .iter()
+                .find(|(id, _)| *id == self.id)
+                .and_then(|(_, shard)| shard.upgrade());
+            if let Some(shard) = found {
+                return shard.downcast::<Shard<T>>().ok().unwrap();
+            }
+            let shard = Arc::new(Shard {
+                thread: thread_id(),
+                value: Mutex::new(T::default()),
+            });
+            self.shards.lock().unwrap().push(shard.clone());
+            local.retain(|(_, shard)| shard.strong_count() > 0);
+            let weak: Weak<dyn Any + Send + Sync> = Arc::downgrade(&shard) as _;
+            local.push((self.id, weak));
+            shard
+        })
+    }
+}
+
+impl<T> std::fmt::Debug for Shards<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shards").field("id", &self.id).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merges_every_thread() {
+        let shards = Arc::new(Shards::<u64>::new());
+        let threads = (0..4)
+            .map(|_| {
+                let shards = shards.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        shards.with(|n| *n += 1);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        shards.with(|n| *n += 1);
+
+        let mut threads = Vec::new();
+        let mut total = 0;
+        shards.for_each(|thread, n| {
+            threads.push(thread);
+            total += n;
+        });
+        threads.sort();
+        threads.dedup();
+        assert_eq!(threads.len(), 5);
+        assert_eq!(total, 4001);
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static GUEST_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes every hostcall made from now on record the names of up to `frames`
+/// of the WebAssembly functions which led to it, innermost first. Zero, the
+/// default, records none.
+///
+/// The stack is walked with `wasmtime::wasm_backtrace`, so this has no effect
+/// unless wiggle is built with Wasmtime support. Walking the stack costs far
+/// more than most hostcalls, and is done outside of the timed part of the
+/// call.
+pub fn set_guest_frames(frames: usize) {
+    GUEST_FRAMES.store(frames, Ordering::Relaxed);
+}
+
+/// The number of frames set by [`set_guest_frames`].
+pub fn guest_frames() -> usize {
+    GUEST_FRAMES.load(Ordering::Relaxed)
+}
+
+/// The WebAssembly functions on the current thread's stack, if they are
+/// being recorded.
+pub(crate) fn guest_stack() -> Option<Vec<String>> {
+    match guest_frames() {
+        0 => None,
+        frames => capture(frames),
+    }
+}
+
+#[cfg(feature = "wasmtime")]
+fn capture(frames: usize) -> Option<Vec<String>> {
+    let stack = wasmtime::wasm_backtrace(frames)
+        .iter()
+        .map(|frame| {
+            let func = match frame.func_name() {
+                Some(name) => name.to_string(),
+                None => format!("<wasm function {}>", frame.func_index()),
+            };
+            match frame.module_name() {
+                Some(module) => format!("{}!{}", module, func),
+                None => func,
+            }
+        })
+        .collect::<Vec<_>>();
+    if stack.is_empty() {
+        None
+    } else {
+        Some(stack)
+    }
+}
+
+#[cfg(not(feature = "wasmtime"))]
+fn capture(_frames: usize) -> Option<Vec<String>> {
+    None
+}
@@ -0,0 +1,128 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes every timed hostcall from now on also count the syscalls its
+/// implementation issues, so that the fan-out of a call such as `path_open`
+/// into `openat`, `fstat` and the like can be studied.
+///
+/// This is only supported on Linux, where syscalls are counted with a
+/// `perf_event_open` counter on the `raw_syscalls:sys_enter` tracepoint for
+/// each thread which makes hostcalls. Reading the tracepoint's id takes
+/// access to tracefs, and opening the counter is subject to
+/// `/proc/sys/kernel/perf_event_paranoid`, so this usually needs elevated
+/// privileges. An error is returned if the current thread can't count
+/// syscalls, and counting stays disabled.
+///
+/// Each count covers only the hostcall's implementation, the
+/// [`Phase::Call`](super::Phase::Call). Syscalls made by other threads, such
+/// as a blocking pool running an async hostcall's work, are not counted.
+pub fn set_count_syscalls(enable: bool) -> io::Result<()> {
+    if enable {
+        imp::counting_supported()?;
+    }
+    ENABLED.store(enable, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether syscalls are being counted; see [`set_count_syscalls`].
+pub fn count_syscalls() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The number of syscalls the current thread has entered so far, if they are
+/// being counted. Taking the count itself enters one.
+#[inline]
+pub(crate) fn syscall_count() -> Option<u64> {
+    if count_syscalls() {
+        imp::syscall_count()
+    } else {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::super::perf::{Counter, EventAttr, TYPE_TRACEPOINT};
+    use once_cell::sync::OnceCell;
+    use std::cell::RefCell;
+    use std::io;
+
+    /// Where tracefs may be mounted.
+    const TRACEFS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+    static TRACEPOINT: OnceCell<u64> = OnceCell::new();
+
+    thread_local! {
+        /// This thread's counter, opened on first use. `Some(None)` if it
+        /// couldn't be opened, so that it isn't retried on every call.
+        static COUNTER: RefCell<Option<Option<Counter>>> = RefCell::new(None);
+    }
+
+    fn tracepoint() -> io::Result<u64> {
+        TRACEPOINT
+            .get_or_try_init(|| {
+                let mut error = None;
+                for tracefs in TRACEFS.iter() {
+                    let path = format!("{}/events/raw_syscalls/sys_enter/id", tracefs);
+                    match std::fs::read_to_string(&path) {
+                        Ok(id) => {
+                            return id.trim().parse().map_err(|_| {
+                                io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    format!("invalid tracepoint id in {}", path),
+                                )
+                            })
+                        }
+                        Err(e) => error = Some(e),
+                    }
+                }
+                let error = error.unwrap();
+                Err(io::Error::new(
+                    error.kind(),
+                    format!(
+                        "failed to read the id of the raw_syscalls:sys_enter tracepoint: {}",
+                        error
+                    ),
+                ))
+            })
+            .map(|id| *id)
+    }
+
+    fn open() -> io::Result<Counter> {
+        Counter::open(&EventAttr::new(TYPE_TRACEPOINT, tracepoint()?))
+    }
+
+    pub(super) fn counting_supported() -> io::Result<()> {
+        open()?.read()?;
+        Ok(())
+    }
+
+    pub(super) fn syscall_count() -> Option<u64> {
+        COUNTER.with(|counter| {
+            counter
+                .borrow_mut()
+                .get_or_insert_with(|| open().ok())
+                .as_ref()?
+                .read()
+                .ok()
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+
+    pub(super) fn counting_supported() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "counting syscalls is only supported on Linux",
+        ))
+    }
+
+    pub(super) fn syscall_count() -> Option<u64> {
+        None
+    }
+}
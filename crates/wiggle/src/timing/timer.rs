@@ -0,0 +1,220 @@
+use super::counters::perf_counts;
+use super::payload::take_payload;
+use super::stack::guest_stack;
+use super::syscalls::syscall_count;
+use super::{
+    clock, record, record_unsampled, thread_id, Clock, HostcallRecorder, HostcallResult,
+    HostcallSample, Payload, PerfCounts, PhaseTimestamps, PollStats, Sampler,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Timestamps one hostcall as it passes through each [`Phase`](super::Phase).
+///
+/// Code generated by `from_witx!` starts a timer on entry to the abi function
+/// and stamps the end of argument lifting, the implementation and result
+/// lowering as it goes. When the call comes in through the `wasmtime`
+/// integration the timer is instead started as soon as Wasmtime hands control
+/// to the host function, and finished just before returning to wasm, so that
+/// the transitions in and out of the host are measured as well.
+///
+/// Each call site passes its own [`Sampler`]. A call which is not sampled
+/// never reads the clock, and is only counted when the timer finishes.
+#[derive(Debug)]
+pub struct HostcallTimer {
+    clock: &'static Clock,
+    sampled: bool,
+    start: u64,
+    lift: Option<u64>,
+    call: Option<u64>,
+    call_end: Option<u64>,
+    lowered: Option<u64>,
+    poll: Option<PollStats>,
+    payload: Option<Payload>,
+    stack: Option<Vec<String>>,
+    syscalls: Option<u64>,
+    /// The counts at the start of the call, and the thread they were read
+    /// on, until the call ends.
+    counters_start: Option<(u64, PerfCounts)>,
+    counters: Option<PerfCounts>,
+    result: HostcallResult,
+}
+
+impl HostcallTimer {
+    pub fn start(sampler: &Sampler) -> HostcallTimer {
+        let sampled = sampler.sample();
+        // Walk the stack, if asked to, before the clock starts.
+        let stack = if sampled { guest_stack() } else { None };
+        let clock = clock();
+        HostcallTimer {
+            clock,
+            sampled,
+            start: if sampled { clock.start() } else { 0 },
+            lift: None,
+            call: None,
+            call_end: None,
+            lowered: None,
+            poll: None,
+            payload: None,
+            stack,
+            syscalls: None,
+            counters_start: None,
+            counters: None,
+            result: HostcallResult::Trap,
+        }
+    }
+
+    /// Whether this call is being timed.
+    pub fn sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// Reads the clock, unless this call is not being timed.
+    #[inline]
+    fn stamp(&self) -> Option<u64> {
+        if self.sampled {
+            Some(self.clock.stop())
+        } else {
+            None
+        }
+    }
+
+    /// Marks the start of argument lifting, and so the end of the
+    /// transition into the host.
+    pub fn lift(&mut self) {
+        self.lift = self.stamp();
+    }
+
+    /// Marks the end of argument lifting, just before the implementation is
+    /// called.
+    pub fn call(&mut self) {
+        // Drop anything left over from an earlier call which wasn't timed.
+        take_payload();
+        if self.sampled {
+            // Read the counters outside of the syscall count, which would
+            // otherwise include reading them.
+            self.counters_start = perf_counts().map(|c| (thread_id(), c));
+            self.syscalls = syscall_count();
+        }
+        self.call = self.stamp();
+    }
+
+    /// Marks the return of the implementation, along with any
+    /// [`report_payload`](super::report_payload) it made and, if they are
+    /// being counted, the syscalls it issued and its performance counters.
+    pub fn call_end(&mut self) {
+        self.call_end = self.stamp();
+        self.payload = take_payload();
+        if let Some(start) = self.syscalls {
+            // The second count includes the syscall taking it.
+            self.syscalls = syscall_count().map(|end| end.saturating_sub(start + 1));
+        }
+        if let Some((thread, start)) = self.counters_start.take() {
+            if thread == thread_id() {
+                self.counters = perf_counts().map(|end| end.since(&start));
+            }
+        }
+    }
+
+    /// Marks the end of result lowering, along with how the call finished.
+    pub fn lowered(&mut self, result: HostcallResult) {
+        self.lowered = self.stamp();
+        self.result = result;
+    }
+
+    /// Records how an async call spent its time, as measured by [`Polled`].
+    pub fn polled(&mut self, poll: PollStats) {
+        self.poll = Some(poll);
+    }
+
+    /// Stops the timer and hands the sample to `recorder`, or to the
+    /// process-wide recorder if that is `None`.
+    ///
+    /// Phases which were never reached, for example the implementation when
+    /// lifting an argument failed, are recorded as taking no time.
+    pub fn finish(self, recorder: Option<&dyn HostcallRecorder>, module: &str, function: &str) {
+        if !self.sampled {
+            record_unsampled(recorder, module, function);
+            return;
+        }
+        let end = self.clock.stop();
+        let lowered = self.lowered.unwrap_or(end);
+        let call_end = self.call_end.unwrap_or(lowered);
+        let call = self.call.unwrap_or(call_end);
+        let lift = self.lift.unwrap_or(call);
+        record(
+            recorder,
+            &HostcallSample {
+                module,
+                function,
+                start: self.start,
+                end,
+                phases: PhaseTimestamps {
+                    lift,
+                    call,
+                    call_end,
+                    lowered,
+                },
+                poll: self.poll,
+                payload: self.payload,
+                stack: self.stack.as_deref(),
+                syscalls: self.syscalls,
+                counters: self.counters,
+                result: self.result,
+            },
+        );
+    }
+}
+
+/// Wraps the future of an async hostcall to measure how long it spends
+/// running, as opposed to waiting to be woken.
+///
+/// Only the time spent inside the inner future's `poll` counts as busy. The
+/// future resolves to the inner future's output along with the
+/// [`PollStats`]. When `sampled` is false only yields are counted, and the
+/// clock is never read.
+#[derive(Debug)]
+pub struct Polled<F> {
+    inner: F,
+    clock: &'static Clock,
+    sampled: bool,
+    stats: PollStats,
+}
+
+impl<F: Future> Polled<F> {
+    pub fn new(inner: F, sampled: bool) -> Polled<F> {
+        Polled {
+            inner,
+            clock: clock(),
+            sampled,
+            stats: PollStats::default(),
+        }
+    }
+}
+
+impl<F: Future> Future for Polled<F> {
+    type Output = (F::Output, PollStats);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: `inner` is never moved out of `self`, and no other field is
+        // pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        let poll = if this.sampled {
+            let start = this.clock.start();
+            let poll = inner.poll(cx);
+            this.stats.busy += this.clock.stop().saturating_sub(start);
+            poll
+        } else {
+            inner.poll(cx)
+        };
+        match poll {
+            Poll::Ready(output) => Poll::Ready((output, this.stats)),
+            Poll::Pending => {
+                this.stats.yields += 1;
+                Poll::Pending
+            }
+        }
+    }
+}
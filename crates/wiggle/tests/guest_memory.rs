@@ -0,0 +1,140 @@
+use wiggle::{GuestMemory, GuestPtr};
+use wiggle_test::{impl_errno, HostMemory, WasiCtx};
+
+mod sync {
+    use super::*;
+
+    wiggle::from_witx!({
+        witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $invalid_arg))
+(typename $total u32)
+(module $bytes
+  (@interface func (export \"sum\")
+     (param $ptr u32)
+     (param $len u32)
+     (result $err (expected $total (error $errno))))
+  (@interface func (export \"unscoped_sum\")
+     (param $ptr u32)
+     (param $len u32)
+     (result $err (expected $total (error $errno)))))
+        ",
+        guest_memory: { bytes::sum: true },
+    });
+
+    impl_errno!(types::Errno);
+
+    impl<'a> bytes::Bytes for WasiCtx<'a> {
+        fn sum(&mut self, ptr: u32, len: u32) -> Result<types::Total, types::Errno> {
+            sum_in_scope(ptr, len)
+        }
+        fn unscoped_sum(&mut self, ptr: u32, len: u32) -> Result<types::Total, types::Errno> {
+            sum_in_scope(ptr, len)
+        }
+    }
+
+    fn sum_in_scope(ptr: u32, len: u32) -> Result<types::Total, types::Errno> {
+        wiggle::with_guest_memory(|mem| sum(mem, ptr, len)).unwrap_or(Err(types::Errno::InvalidArg))
+    }
+
+    fn sum(mem: &dyn GuestMemory, ptr: u32, len: u32) -> Result<types::Total, types::Errno> {
+        let bytes = GuestPtr::<u8>::new(mem, ptr).as_array(len);
+        let bytes = bytes.as_slice().map_err(|_| types::Errno::InvalidArg)?;
+        Ok(bytes.iter().map(|b| u32::from(*b)).sum())
+    }
+
+    #[test]
+    fn trait_methods_reach_the_memory_of_the_call() {
+        let mut ctx = WasiCtx::new();
+        let host_memory = HostMemory::new();
+        write_bytes(&host_memory, 8, &[1, 2, 3, 4]);
+
+        let r = bytes::sum(&mut ctx, &host_memory, 8, 4, 0).unwrap();
+        assert_eq!(r, types::Errno::Ok as i32);
+        assert_eq!(read_total(&host_memory, 0), 10);
+
+        let r = bytes::unscoped_sum(&mut ctx, &host_memory, 8, 4, 0).unwrap();
+        assert_eq!(r, types::Errno::InvalidArg as i32);
+
+        // Nothing leaks out of the scope of the call.
+        assert!(wiggle::with_guest_memory(|_| ()).is_none());
+    }
+}
+
+mod asynchronous {
+    use super::*;
+
+    wiggle::from_witx!({
+        witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $invalid_arg))
+(typename $total u32)
+(module $bytes
+  (@interface func (export \"sum\")
+     (param $ptr u32)
+     (param $len u32)
+     (result $err (expected $total (error $errno))))
+  (@interface func (export \"unscoped_sum\")
+     (param $ptr u32)
+     (param $len u32)
+     (result $err (expected $total (error $errno)))))
+        ",
+        async: *,
+        guest_memory: { bytes::sum: true },
+    });
+
+    impl_errno!(types::Errno);
+
+    #[wiggle::async_trait]
+    impl<'a> bytes::Bytes for WasiCtx<'a> {
+        async fn sum(&mut self, ptr: u32, len: u32) -> Result<types::Total, types::Errno> {
+            // The memory is only reachable while the future is being polled,
+            // including after it has been suspended.
+            tokio::task::yield_now().await;
+            let first = wiggle::with_guest_memory(|mem| read_byte(mem, ptr));
+            tokio::task::yield_now().await;
+            let rest = wiggle::with_guest_memory(|mem| {
+                (1..len).map(|i| read_byte(mem, ptr + i)).sum::<u32>()
+            });
+            match (first, rest) {
+                (Some(first), Some(rest)) => Ok(first + rest),
+                _ => Err(types::Errno::InvalidArg),
+            }
+        }
+        async fn unscoped_sum(
+            &mut self,
+            ptr: u32,
+            _len: u32,
+        ) -> Result<types::Total, types::Errno> {
+            wiggle::with_guest_memory(|mem| read_byte(mem, ptr)).ok_or(types::Errno::InvalidArg)
+        }
+    }
+
+    fn read_byte(mem: &dyn GuestMemory, ptr: u32) -> u32 {
+        u32::from(GuestPtr::<u8>::new(mem, ptr).read().unwrap())
+    }
+
+    #[tokio::test]
+    async fn async_trait_methods_reach_the_memory_of_the_call() {
+        let mut ctx = WasiCtx::new();
+        let host_memory = HostMemory::new();
+        write_bytes(&host_memory, 8, &[5, 6, 7]);
+
+        let r = bytes::sum(&mut ctx, &host_memory, 8, 3, 0).await.unwrap();
+        assert_eq!(r, types::Errno::Ok as i32);
+        assert_eq!(read_total(&host_memory, 0), 18);
+
+        let r = bytes::unscoped_sum(&mut ctx, &host_memory, 8, 3, 0)
+            .await
+            .unwrap();
+        assert_eq!(r, types::Errno::InvalidArg as i32);
+    }
+}
+
+fn write_bytes(mem: &HostMemory, offset: u32, bytes: &[u8]) {
+    GuestPtr::<[u8]>::new(mem, (offset, bytes.len() as u32))
+        .copy_from_slice(bytes)
+        .unwrap();
+}
+
+fn read_total(mem: &HostMemory, offset: u32) -> u32 {
+    GuestPtr::<u32>::new(mem, offset).read().unwrap()
+}
@@ -0,0 +1,99 @@
+use std::sync::Mutex;
+use wiggle::timing::{HostcallRecorder, HostcallSample};
+use wiggle_test::{impl_errno, HostMemory};
+
+// A function's own entry wins over its module's, whichever way round.
+mod by_name {
+    wiggle::from_witx!({
+        witx: ["$CARGO_MANIFEST_DIR/tests/instrument.witx"],
+        instrument: {
+            kept: true,
+            kept::untimed: false,
+            dropped: false,
+            dropped::timed: true,
+        },
+    });
+}
+
+mod off {
+    wiggle::from_witx!({
+        witx: ["$CARGO_MANIFEST_DIR/tests/instrument.witx"],
+        instrument: false,
+    });
+}
+
+impl_errno!(by_name::types::Errno);
+impl_errno!(off::types::Errno);
+
+/// Keeps the `module::function` of every sample it is handed.
+#[derive(Default)]
+struct Log(Mutex<Vec<String>>);
+
+impl HostcallRecorder for Log {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("{}::{}", sample.module, sample.function));
+    }
+}
+
+#[derive(Default)]
+struct Ctx {
+    log: Log,
+}
+
+macro_rules! impl_modules {
+    ($($m:ident),*) => {$(
+        impl $m::kept::Kept for Ctx {
+            fn timed(&mut self) -> Result<(), $m::types::Errno> {
+                Ok(())
+            }
+            fn untimed(&mut self) -> Result<(), $m::types::Errno> {
+                Ok(())
+            }
+            fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+                Some(&self.log)
+            }
+        }
+
+        impl $m::dropped::Dropped for Ctx {
+            fn timed(&mut self) -> Result<(), $m::types::Errno> {
+                Ok(())
+            }
+            fn untimed(&mut self) -> Result<(), $m::types::Errno> {
+                Ok(())
+            }
+            fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+                Some(&self.log)
+            }
+        }
+    )*};
+}
+
+impl_modules!(by_name, off);
+
+#[test]
+fn most_specific_entry_wins() {
+    let mut ctx = Ctx::default();
+    let host_memory = HostMemory::new();
+    by_name::kept::timed(&mut ctx, &host_memory).unwrap();
+    by_name::kept::untimed(&mut ctx, &host_memory).unwrap();
+    by_name::dropped::timed(&mut ctx, &host_memory).unwrap();
+    by_name::dropped::untimed(&mut ctx, &host_memory).unwrap();
+    assert_eq!(
+        *ctx.log.0.lock().unwrap(),
+        ["kept::timed", "dropped::timed"]
+    );
+}
+
+#[test]
+fn instrument_false_times_nothing() {
+    let mut ctx = Ctx::default();
+    let host_memory = HostMemory::new();
+    off::kept::timed(&mut ctx, &host_memory).unwrap();
+    off::kept::untimed(&mut ctx, &host_memory).unwrap();
+    off::dropped::timed(&mut ctx, &host_memory).unwrap();
+    off::dropped::untimed(&mut ctx, &host_memory).unwrap();
+    assert!(ctx.log.0.lock().unwrap().is_empty());
+}
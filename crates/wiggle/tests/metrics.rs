@@ -0,0 +1,121 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use wiggle::timing::{HistogramRecorder, HostcallRecorder, MetricsEndpoint, MetricsServer};
+use wiggle_test::{impl_errno, HostMemory};
+
+wiggle::from_witx!({
+    witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $io))
+(module $svc
+  (@interface func (export \"work\")
+     (param $fail u32)
+     (result $err (expected (error $errno)))))
+    ",
+    instrument: true,
+});
+
+impl_errno!(types::Errno);
+
+struct Ctx {
+    recorder: Arc<dyn HostcallRecorder>,
+}
+
+impl svc::Svc for Ctx {
+    fn work(&mut self, fail: u32) -> Result<(), types::Errno> {
+        if fail != 0 {
+            return Err(types::Errno::Io);
+        }
+        Ok(())
+    }
+    fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+        Some(&*self.recorder)
+    }
+}
+
+fn http_get(mut conn: impl Read + Write) -> String {
+    conn.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn calls(response: &str) -> u64 {
+    response
+        .lines()
+        .find(|l| l.starts_with("wasmtime_hostcall_calls_total{module=\"svc\",function=\"work\"}"))
+        .map_or(0, |l| l.rsplit(' ').next().unwrap().parse().unwrap())
+}
+
+#[test]
+fn metrics_are_served_over_tcp_while_recording() {
+    let histograms = Arc::new(HistogramRecorder::new());
+    let server = MetricsServer::start(&"127.0.0.1:0".parse().unwrap(), histograms.clone()).unwrap();
+    let addr = match server.endpoint() {
+        MetricsEndpoint::Tcp(addr) => *addr,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    };
+    assert_ne!(addr.port(), 0);
+
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+    };
+    let host_memory = HostMemory::new();
+    let response = http_get(TcpStream::connect(addr).unwrap());
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    assert_eq!(calls(&response), 0);
+
+    svc::work(&mut ctx, &host_memory, 0).unwrap();
+    assert_eq!(svc::work(&mut ctx, &host_memory, 1).unwrap(), 1);
+    let response = http_get(TcpStream::connect(addr).unwrap());
+    assert_eq!(calls(&response), 2);
+    assert!(response.contains(
+        "wasmtime_hostcall_results_total{module=\"svc\",function=\"work\",result=\"errno\",errno=\"1\"} 1"
+    ));
+
+    // Clients which don't speak HTTP get the bare metrics.
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("# HELP "));
+    assert_eq!(calls(&response), 2);
+
+    drop(server);
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn metrics_are_served_over_a_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("wiggle-metrics-{}.sock", std::process::id()));
+    let histograms = Arc::new(HistogramRecorder::new());
+    let server = MetricsServer::start(
+        &format!("unix:{}", path.display()).parse().unwrap(),
+        histograms.clone(),
+    )
+    .unwrap();
+
+    let mut ctx = Ctx {
+        recorder: Arc::new(histograms.for_store("one")),
+    };
+    let host_memory = HostMemory::new();
+    svc::work(&mut ctx, &host_memory, 0).unwrap();
+    let response = http_get(UnixStream::connect(&path).unwrap());
+    assert_eq!(calls(&response), 1);
+
+    // A non-GET request is refused.
+    let mut conn = UnixStream::connect(&path).unwrap();
+    conn.write_all(b"POST / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 405 "));
+
+    drop(server);
+    assert!(!path.exists());
+}
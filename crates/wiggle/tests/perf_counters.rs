@@ -0,0 +1,105 @@
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wiggle::timing::{set_perf_counters, HistogramRecorder, HostcallRecorder, PerfCounter};
+use wiggle_test::{impl_errno, HostMemory};
+
+// The counters are set process-wide, so the tests here take turns.
+static COUNTERS: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+wiggle::from_witx!({
+    witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $io))
+(module $work
+  (@interface func (export \"spin\")
+     (param $iterations u32)
+     (result $err (expected (error $errno))))
+  (@interface func (export \"nap\")
+     (param $millis u32)
+     (result $err (expected (error $errno)))))
+    ",
+    instrument: true,
+});
+
+impl_errno!(types::Errno);
+
+struct Ctx {
+    recorder: Arc<dyn HostcallRecorder>,
+    sink: u64,
+}
+
+impl work::Work for Ctx {
+    fn spin(&mut self, iterations: u32) -> Result<(), types::Errno> {
+        for i in 0..iterations {
+            self.sink = self.sink.wrapping_mul(31).wrapping_add(u64::from(i));
+        }
+        Ok(())
+    }
+    fn nap(&mut self, millis: u32) -> Result<(), types::Errno> {
+        std::thread::sleep(Duration::from_millis(u64::from(millis)));
+        Ok(())
+    }
+    fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+        Some(&*self.recorder)
+    }
+}
+
+/// Enables `counters`, or returns `false` if the machine running the tests
+/// doesn't allow counting them.
+fn enable(counters: &[PerfCounter]) -> bool {
+    match set_perf_counters(counters) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("skipping: can't count {:?}: {}", counters, e);
+            false
+        }
+    }
+}
+
+#[test]
+fn instructions_are_counted_per_call() {
+    let _guard = COUNTERS.lock().unwrap();
+    if !enable(&[PerfCounter::Instructions, PerfCounter::BranchMisses]) {
+        return;
+    }
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+        sink: 0,
+    };
+    let host_memory = HostMemory::new();
+    work::spin(&mut ctx, &host_memory, 0).unwrap();
+    work::spin(&mut ctx, &host_memory, 100_000).unwrap();
+    set_perf_counters(&[]).unwrap();
+
+    let snapshot = histograms.snapshot();
+    let spin = snapshot.get("work", "spin").unwrap();
+    let instructions = spin.counter(PerfCounter::Instructions);
+    assert_eq!(instructions.count(), 2);
+    assert!(instructions.max() >= 100_000.0);
+    assert!(instructions.min() < 100_000.0);
+    assert_eq!(spin.counter(PerfCounter::BranchMisses).count(), 2);
+    assert_eq!(spin.counter(PerfCounter::CacheMisses).count(), 0);
+}
+
+#[test]
+fn context_switches_are_counted_per_call() {
+    let _guard = COUNTERS.lock().unwrap();
+    if !enable(&[PerfCounter::ContextSwitches]) {
+        return;
+    }
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+        sink: 0,
+    };
+    let host_memory = HostMemory::new();
+    work::nap(&mut ctx, &host_memory, 10).unwrap();
+    set_perf_counters(&[]).unwrap();
+
+    let snapshot = histograms.snapshot();
+    let nap = snapshot.get("work", "nap").unwrap();
+    let switches = nap.counter(PerfCounter::ContextSwitches);
+    assert_eq!(switches.count(), 1);
+    assert!(switches.min() >= 1.0);
+}
@@ -0,0 +1,193 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use wiggle::replay::{HostcallLog, LogRecorder, LogReplayer};
+use wiggle::{GuestPtr, Trap};
+use wiggle_test::{impl_errno, HostMemory};
+
+wiggle::from_witx!({
+    witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $invalid_arg))
+(typename $timestamp u64)
+(typename $total u32)
+(module $host
+  (@interface func (export \"fill\")
+     (param $buf (@witx pointer u8))
+     (param $len u32)
+     (result $err (expected (error $errno))))
+  (@interface func (export \"now\")
+     (result $err (expected $timestamp (error $errno))))
+  (@interface func (export \"checksum\")
+     (param $buf (@witx const_pointer u8))
+     (param $len u32)
+     (result $err (expected $total (error $errno))))
+  (@interface func (export \"bail\")
+     (param $code u32)
+     (@witx noreturn)))
+    ",
+    hostcall_log: true,
+});
+
+impl_errno!(types::Errno);
+
+/// A host whose answers change from call to call, unless it is replaying,
+/// in which case it must never be reached.
+struct Ctx {
+    state: u8,
+    log: Option<Arc<dyn HostcallLog>>,
+    replaying: bool,
+}
+
+impl Ctx {
+    fn next(&mut self) -> u8 {
+        assert!(!self.replaying, "a replayed call reached the host");
+        self.state = self.state.wrapping_mul(31).wrapping_add(7);
+        self.state
+    }
+}
+
+impl host::Host for Ctx {
+    fn fill(&mut self, buf: &GuestPtr<u8>, len: u32) -> Result<(), types::Errno> {
+        for i in 0..len {
+            let b = self.next();
+            buf.add(i).unwrap().write(b).unwrap();
+        }
+        Ok(())
+    }
+    fn now(&mut self) -> Result<types::Timestamp, types::Errno> {
+        Ok(u64::from(self.next()) << 32)
+    }
+    fn checksum(&mut self, buf: &GuestPtr<u8>, len: u32) -> Result<types::Total, types::Errno> {
+        assert!(!self.replaying, "a replayed call reached the host");
+        let bytes = buf.as_array(len).as_slice().unwrap();
+        Ok(bytes.iter().map(|b| u32::from(*b)).sum())
+    }
+    fn bail(&mut self, code: u32) -> Trap {
+        assert!(!self.replaying, "a replayed call reached the host");
+        Trap::I32Exit(code as i32)
+    }
+    fn hostcall_log(&self) -> Option<Arc<dyn HostcallLog>> {
+        self.log.clone()
+    }
+}
+
+/// A log file shared with the test.
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What the guest sees from a run of calls.
+#[derive(Debug, PartialEq)]
+struct Run {
+    rets: Vec<Result<i32, Trap>>,
+    memory: Vec<u8>,
+}
+
+fn run(ctx: &mut Ctx) -> Run {
+    let memory = HostMemory::new();
+    let mut rets = vec![
+        host::fill(ctx, &memory, 8, 4),
+        host::now(ctx, &memory, 16),
+        host::checksum(ctx, &memory, 8, 4, 24),
+    ];
+    rets.push(host::bail(ctx, &memory, 3).map(|()| 0));
+    let memory = GuestPtr::<[u8]>::new(&memory, (0, 32))
+        .as_slice()
+        .unwrap()
+        .to_vec();
+    Run { rets, memory }
+}
+
+fn record() -> (Run, Vec<u8>) {
+    let buf = SharedBuf::default();
+    let recorder = Arc::new(LogRecorder::new(buf.clone()));
+    let mut ctx = Ctx {
+        state: 1,
+        log: Some(recorder.clone()),
+        replaying: false,
+    };
+    let run = run(&mut ctx);
+    recorder.flush().unwrap();
+    let log = buf.0.lock().unwrap().clone();
+    (run, log)
+}
+
+fn replayer(log: Vec<u8>) -> Ctx {
+    Ctx {
+        state: 0,
+        log: Some(Arc::new(LogReplayer::new(io::Cursor::new(log)).unwrap())),
+        replaying: true,
+    }
+}
+
+#[test]
+fn replay_reproduces_the_recorded_run() {
+    let (recorded, log) = record();
+    assert_eq!(recorded.rets[0], Ok(types::Errno::Ok as i32));
+    assert_eq!(recorded.rets[3], Err(Trap::I32Exit(3)));
+    assert_ne!(&recorded.memory[8..12], &[0; 4]);
+
+    // The host would answer differently now, but replay never asks it.
+    let mut unlogged = Ctx {
+        state: 2,
+        log: None,
+        replaying: false,
+    };
+    assert_ne!(run(&mut unlogged), recorded);
+
+    assert_eq!(run(&mut replayer(log)), recorded);
+}
+
+#[test]
+fn replay_reports_where_calls_diverge() {
+    let (_, log) = record();
+    let mut ctx = replayer(log.clone());
+    let memory = HostMemory::new();
+    assert!(host::fill(&mut ctx, &memory, 8, 4).is_ok());
+    let err = host::checksum(&mut ctx, &memory, 8, 4, 24).unwrap_err();
+    assert_eq!(
+        err,
+        Trap::String(
+            "hostcall replay diverged at call 1: expected a call to host::now, got host::checksum"
+                .to_owned()
+        )
+    );
+
+    // The same call, over different guest memory.
+    let mut ctx = replayer(log.clone());
+    let memory = HostMemory::new();
+    host::fill(&mut ctx, &memory, 8, 4).unwrap();
+    host::now(&mut ctx, &memory, 16).unwrap();
+    GuestPtr::<u8>::new(&memory, 9).write(0).unwrap();
+    let err = host::checksum(&mut ctx, &memory, 8, 4, 24).unwrap_err();
+    assert_eq!(
+        err,
+        Trap::String(
+            "hostcall replay diverged at call 2: host::checksum read different guest memory at 0x8"
+                .to_owned()
+        )
+    );
+
+    // More calls than were recorded.
+    let mut ctx = replayer(log);
+    let memory = HostMemory::new();
+    host::fill(&mut ctx, &memory, 8, 4).unwrap();
+    host::now(&mut ctx, &memory, 16).unwrap();
+    host::checksum(&mut ctx, &memory, 8, 4, 24).unwrap();
+    host::bail(&mut ctx, &memory, 3).unwrap_err();
+    let err = host::now(&mut ctx, &memory, 16).unwrap_err();
+    assert_eq!(
+        err,
+        Trap::String(
+            "hostcall replay diverged at call 4: the log ends before this call to host::now"
+                .to_owned()
+        )
+    );
+}
@@ -0,0 +1,104 @@
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
+use wiggle::timing::{set_sampling, HistogramRecorder, HostcallRecorder, Sampling};
+use wiggle_test::{impl_errno, HostMemory};
+
+// Sampling is set process-wide, so it is tested on its own rather than
+// alongside the tests in `timing.rs`, and the tests here take turns.
+static SAMPLING: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+wiggle::from_witx!({
+    witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $invalid_arg))
+(module $sampled
+  (@interface func (export \"maybe_fail\")
+     (param $fail u32)
+     (result $err (expected (error $errno))))
+  (@interface func (export \"copy\")
+     (param $len u32)
+     (result $err (expected (error $errno)))))
+    ",
+    instrument: true,
+});
+
+impl_errno!(types::Errno);
+
+struct Ctx {
+    recorder: Arc<dyn HostcallRecorder>,
+}
+
+impl sampled::Sampled for Ctx {
+    fn maybe_fail(&mut self, fail: u32) -> Result<(), types::Errno> {
+        if fail == 0 {
+            Ok(())
+        } else {
+            Err(types::Errno::InvalidArg)
+        }
+    }
+    fn copy(&mut self, len: u32) -> Result<(), types::Errno> {
+        wiggle::timing::report_payload(u64::from(len), 1);
+        Ok(())
+    }
+    fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+        Some(&*self.recorder)
+    }
+}
+
+#[test]
+fn counts_stay_exact_when_sampling() {
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+    };
+    let host_memory = HostMemory::new();
+
+    let _guard = SAMPLING.lock().unwrap();
+    set_sampling(Sampling::OneIn(10));
+    for i in 0..1000 {
+        sampled::maybe_fail(&mut ctx, &host_memory, i % 2).unwrap();
+    }
+    // Each hostcall is sampled on its own, so the one call to `copy` is
+    // timed even though `maybe_fail` was called many times before it.
+    sampled::copy(&mut ctx, &host_memory, 64).unwrap();
+    set_sampling(Sampling::All);
+
+    let snapshot = histograms.snapshot();
+    let maybe_fail = snapshot.get("sampled", "maybe_fail").unwrap();
+    assert_eq!(maybe_fail.count(), 1000);
+    assert_eq!(maybe_fail.total.count(), 100);
+    assert_eq!(maybe_fail.unsampled, 900);
+    assert_eq!(maybe_fail.scale(), 10.0);
+    // The sampled calls alternate results just like all the calls did.
+    let results = maybe_fail.results.values().map(|h| h.count()).sum::<u64>();
+    assert_eq!(results, 100);
+
+    let copy = snapshot.get("sampled", "copy").unwrap();
+    assert_eq!(copy.count(), 1);
+    assert_eq!(copy.payload.calls, 1);
+    assert_eq!(copy.payload.bytes, 64);
+}
+
+#[test]
+fn budgets_limit_timed_calls_per_period() {
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+    };
+    let host_memory = HostMemory::new();
+
+    let _guard = SAMPLING.lock().unwrap();
+    set_sampling(Sampling::Budget {
+        calls: 5,
+        period: std::time::Duration::from_secs(3600),
+    });
+    for _ in 0..100 {
+        sampled::copy(&mut ctx, &host_memory, 1).unwrap();
+    }
+    set_sampling(Sampling::All);
+
+    let snapshot = histograms.snapshot();
+    let copy = snapshot.get("sampled", "copy").unwrap();
+    assert_eq!(copy.count(), 100);
+    // The clock may have crossed into a new period mid-test.
+    assert!(copy.total.count() >= 5 && copy.total.count() <= 10);
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use wiggle::timing::{set_count_syscalls, HistogramRecorder, HostcallRecorder};
+use wiggle_test::{impl_errno, HostMemory};
+
+wiggle::from_witx!({
+    witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $io))
+(module $sys
+  (@interface func (export \"stat\")
+     (param $times u32)
+     (result $err (expected (error $errno)))))
+    ",
+    instrument: true,
+});
+
+impl_errno!(types::Errno);
+
+struct Ctx {
+    recorder: Arc<dyn HostcallRecorder>,
+}
+
+impl sys::Sys for Ctx {
+    fn stat(&mut self, times: u32) -> Result<(), types::Errno> {
+        for _ in 0..times {
+            std::fs::metadata("/").map_err(|_| types::Errno::Io)?;
+        }
+        Ok(())
+    }
+    fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+        Some(&*self.recorder)
+    }
+}
+
+#[test]
+fn syscalls_are_counted_per_call() {
+    // Counting needs access to tracefs and `perf_event_open`, which the
+    // machine running the tests may well not allow.
+    if let Err(e) = set_count_syscalls(true) {
+        println!("skipping: can't count syscalls: {}", e);
+        return;
+    }
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+    };
+    let host_memory = HostMemory::new();
+    sys::stat(&mut ctx, &host_memory, 0).unwrap();
+    sys::stat(&mut ctx, &host_memory, 3).unwrap();
+    set_count_syscalls(false).unwrap();
+
+    let snapshot = histograms.snapshot();
+    let stat = snapshot.get("sys", "stat").unwrap();
+    assert_eq!(stat.syscalls.count(), 2);
+    assert_eq!(stat.syscalls.min(), 0.0);
+    assert!(stat.syscalls.max() >= 3.0);
+}
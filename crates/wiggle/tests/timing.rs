@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex};
+use wiggle::timing::{
+    size_class, HistogramRecorder, HostcallRecorder, HostcallResult, HostcallSample, Phase,
+};
+use wiggle_test::{impl_errno, HostMemory};
+
+wiggle::from_witx!({
+    witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $invalid_arg))
+(module $timed
+  (@interface func (export \"maybe_fail\")
+     (param $fail u32)
+     (result $err (expected (error $errno))))
+  (@interface func (export \"copy\")
+     (param $len u32)
+     (result $err (expected (error $errno))))
+  (@interface func (export \"bail\")
+     (param $code u32)
+     (@witx noreturn)))
+    ",
+    instrument: true,
+});
+
+impl_errno!(types::Errno);
+
+/// Keeps the `(module, function, result)` of every sample it is handed.
+#[derive(Default)]
+struct Log(Mutex<Vec<(String, String, HostcallResult)>>);
+
+impl HostcallRecorder for Log {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        let p = &sample.phases;
+        assert!(sample.start <= p.lift);
+        assert!(p.lift <= p.call);
+        assert!(p.call <= p.call_end);
+        assert!(p.call_end <= p.lowered);
+        assert!(p.lowered <= sample.end);
+        self.0.lock().unwrap().push((
+            sample.module.to_owned(),
+            sample.function.to_owned(),
+            sample.result,
+        ));
+    }
+}
+
+struct Ctx {
+    recorder: Arc<dyn HostcallRecorder>,
+}
+
+impl timed::Timed for Ctx {
+    fn maybe_fail(&mut self, fail: u32) -> Result<(), types::Errno> {
+        if fail == 2 {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            Ok(())
+        } else if fail == 0 {
+            Ok(())
+        } else {
+            Err(types::Errno::InvalidArg)
+        }
+    }
+    fn copy(&mut self, len: u32) -> Result<(), types::Errno> {
+        wiggle::timing::report_payload(u64::from(len), 1);
+        Ok(())
+    }
+    fn bail(&mut self, code: u32) -> wiggle::Trap {
+        wiggle::Trap::I32Exit(code as i32)
+    }
+    fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+        Some(&*self.recorder)
+    }
+}
+
+#[test]
+fn records_every_call_with_its_result() {
+    let log = Arc::new(Log::default());
+    let mut ctx = Ctx {
+        recorder: log.clone(),
+    };
+    let host_memory = HostMemory::new();
+
+    assert_eq!(
+        timed::maybe_fail(&mut ctx, &host_memory, 0),
+        Ok(types::Errno::Ok as i32)
+    );
+    assert_eq!(
+        timed::maybe_fail(&mut ctx, &host_memory, 1),
+        Ok(types::Errno::InvalidArg as i32)
+    );
+    assert!(timed::bail(&mut ctx, &host_memory, 3).is_err());
+
+    let log = log.0.lock().unwrap();
+    let expected = [
+        ("maybe_fail", HostcallResult::Ok),
+        (
+            "maybe_fail",
+            HostcallResult::Errno(types::Errno::InvalidArg as i32),
+        ),
+        ("bail", HostcallResult::Trap),
+    ];
+    assert_eq!(log.len(), expected.len());
+    for ((module, function, result), (want_function, want_result)) in log.iter().zip(&expected) {
+        assert_eq!(module, "timed");
+        assert_eq!(function, want_function);
+        assert_eq!(result, want_result);
+    }
+}
+
+#[test]
+fn histograms_merge_threads_and_stores() {
+    let histograms = Arc::new(HistogramRecorder::new());
+    let threads = (0..4)
+        .map(|i| {
+            let recorder: Arc<dyn HostcallRecorder> = if i % 2 == 0 {
+                histograms.clone()
+            } else {
+                Arc::new(histograms.for_store(&format!("store{}", i)))
+            };
+            std::thread::spawn(move || {
+                let mut ctx = Ctx { recorder };
+                let host_memory = HostMemory::new();
+                for _ in 0..100 {
+                    timed::maybe_fail(&mut ctx, &host_memory, 0).unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    let total = histograms.snapshot();
+    assert_eq!(total.get("timed", "maybe_fail").unwrap().total.count(), 400);
+
+    let breakdown = histograms.breakdown();
+    assert_eq!(breakdown.len(), 4);
+    let mut stores = breakdown
+        .iter()
+        .filter_map(|part| part.store.clone())
+        .collect::<Vec<_>>();
+    stores.sort();
+    assert_eq!(stores, ["store1", "store3"]);
+    for part in breakdown.iter() {
+        assert_eq!(
+            part.histograms
+                .get("timed", "maybe_fail")
+                .unwrap()
+                .total
+                .count(),
+            100
+        );
+    }
+}
+
+#[test]
+fn implementation_time_is_its_own_phase() {
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+    };
+    let host_memory = HostMemory::new();
+    timed::maybe_fail(&mut ctx, &host_memory, 2).unwrap();
+
+    let snapshot = histograms.snapshot();
+    let h = snapshot.get("timed", "maybe_fail").unwrap();
+    let call = h.phase(Phase::Call).mean();
+    assert!(call >= 5_000_000.0, "call phase took {}ns", call);
+    let sum: f64 = Phase::ALL.iter().map(|p| h.phase(*p).mean()).sum();
+    assert!((sum - h.total.mean()).abs() / h.total.mean() < 0.01);
+    // Called directly rather than through Wasmtime, there is no transition
+    // in or out of the host to measure.
+    assert!(h.phase(Phase::Entry).max() < 1_000_000.0);
+    assert!(h.phase(Phase::Return).max() < 1_000_000.0);
+}
+
+#[test]
+fn failures_are_split_out_by_result() {
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+    };
+    let host_memory = HostMemory::new();
+    timed::maybe_fail(&mut ctx, &host_memory, 2).unwrap();
+    for _ in 0..3 {
+        timed::maybe_fail(&mut ctx, &host_memory, 1).unwrap();
+    }
+
+    let snapshot = histograms.snapshot();
+    let h = snapshot.get("timed", "maybe_fail").unwrap();
+    assert_eq!(h.total.count(), 4);
+    let ok = h.result(HostcallResult::Ok).unwrap();
+    assert_eq!(ok.count(), 1);
+    assert!(ok.min() >= 5_000_000.0);
+    let failed = h
+        .result(HostcallResult::Errno(types::Errno::InvalidArg as i32))
+        .unwrap();
+    assert_eq!(failed.count(), 3);
+    assert!(failed.max() < 5_000_000.0);
+    assert!(h.result(HostcallResult::Trap).is_none());
+    assert_eq!(
+        h.results.keys().copied().collect::<Vec<_>>(),
+        [
+            HostcallResult::Ok,
+            HostcallResult::Errno(types::Errno::InvalidArg as i32)
+        ]
+    );
+}
+
+#[test]
+fn payloads_are_recorded_by_size() {
+    let histograms = Arc::new(HistogramRecorder::new());
+    let mut ctx = Ctx {
+        recorder: histograms.clone(),
+    };
+    let host_memory = HostMemory::new();
+    for len in [10, 5000, 6000].iter() {
+        timed::copy(&mut ctx, &host_memory, *len).unwrap();
+    }
+    // A report made outside of a hostcall isn't attributed to the next one.
+    wiggle::timing::report_payload(100, 1);
+    timed::maybe_fail(&mut ctx, &host_memory, 0).unwrap();
+
+    let snapshot = histograms.snapshot();
+    let copy = &snapshot.get("timed", "copy").unwrap().payload;
+    assert_eq!(copy.calls, 3);
+    assert_eq!(copy.bytes, 11010);
+    assert_eq!(copy.iovecs, 3);
+    assert!(copy.throughput() > 0.0);
+    assert_eq!(copy.sizes[&size_class(10)].count(), 1);
+    assert_eq!(copy.sizes[&size_class(5000)].count(), 2);
+    let maybe_fail = &snapshot.get("timed", "maybe_fail").unwrap().payload;
+    assert_eq!(maybe_fail.calls, 0);
+}
@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wiggle::timing::{HostcallRecorder, HostcallSample, PollStats};
+use wiggle_test::{impl_errno, HostMemory};
+
+wiggle::from_witx!({
+    witx_literal: "
+(typename $errno (enum (@witx tag u8) $ok $invalid_arg))
+(module $sleepy
+  (@interface func (export \"nap\")
+     (param $millis u32)
+     (result $err (expected (error $errno)))))
+    ",
+    async: *,
+    instrument: true,
+});
+
+impl_errno!(types::Errno);
+
+/// Keeps the wall time and poll stats of every sample it is handed.
+#[derive(Default)]
+struct Log(Mutex<Vec<(f64, Option<PollStats>)>>);
+
+impl HostcallRecorder for Log {
+    fn record(&self, sample: &HostcallSample<'_>) {
+        self.0.lock().unwrap().push((sample.nanos(), sample.poll));
+    }
+}
+
+struct Ctx {
+    log: Arc<Log>,
+}
+
+#[wiggle::async_trait]
+impl sleepy::Sleepy for Ctx {
+    async fn nap(&mut self, millis: u32) -> Result<(), types::Errno> {
+        tokio::time::sleep(Duration::from_millis(millis.into())).await;
+        Ok(())
+    }
+    fn hostcall_recorder(&self) -> Option<&dyn HostcallRecorder> {
+        Some(&*self.log)
+    }
+}
+
+#[tokio::test]
+async fn suspended_time_is_not_busy_time() {
+    let log = Arc::new(Log::default());
+    let mut ctx = Ctx { log: log.clone() };
+    let host_memory = HostMemory::new();
+
+    let r = sleepy::nap(&mut ctx, &host_memory, 20).await;
+    assert_eq!(r, Ok(types::Errno::Ok as i32));
+
+    let log = log.0.lock().unwrap();
+    assert_eq!(log.len(), 1);
+    let (wall, poll) = log[0];
+    let poll = poll.expect("async calls report poll stats");
+    assert!(wall >= 20_000_000.0, "wall time was {}ns", wall);
+    assert!(poll.yields >= 1);
+    let busy = wiggle::timing::clock().ticks_to_nanos(poll.busy);
+    assert!(busy < wall / 2.0, "busy {}ns of {}ns", busy, wall);
+}
@@ -6,7 +6,8 @@
 use anyhow::Result;
 use structopt::{clap::AppSettings, clap::ErrorKind, StructOpt};
 use wasmtime_cli::commands::{
-    CompileCommand, ConfigCommand, RunCommand, SettingsCommand, WastCommand,
+    CompileCommand, ConfigCommand, HostcallBenchCommand, HostcallProfileCommand, RunCommand,
+    SettingsCommand, WastCommand,
 };
 
 /// Wasmtime WebAssembly Runtime
@@ -40,6 +41,10 @@ enum WasmtimeApp {
     Config(ConfigCommand),
     /// Compiles a WebAssembly module.
     Compile(CompileCommand),
+    /// Runs a suite of microbenchmarks of WASI hostcalls
+    HostcallBench(HostcallBenchCommand),
+    /// Works with hostcall profiles written by `run --hostcall-profile`
+    HostcallProfile(HostcallProfileCommand),
     /// Runs a WebAssembly module
     Run(RunCommand),
     /// Displays available Cranelift settings for a target.
@@ -54,6 +59,8 @@ impl WasmtimeApp {
         match self {
             Self::Config(c) => c.execute(),
             Self::Compile(c) => c.execute(),
+            Self::HostcallBench(c) => c.execute(),
+            Self::HostcallProfile(c) => c.execute(),
             Self::Run(c) => c.execute(),
             Self::Settings(c) => c.execute(),
             Self::Wast(c) => c.execute(),
@@ -62,7 +69,7 @@ impl WasmtimeApp {
 }
 
 fn main() -> Result<()> {
-    let res = WasmtimeApp::from_iter_safe(std::env::args())
+    WasmtimeApp::from_iter_safe(std::env::args())
         .unwrap_or_else(|e| match e.kind {
             ErrorKind::HelpDisplayed
             | ErrorKind::VersionDisplayed
@@ -71,22 +78,5 @@ fn main() -> Result<()> {
                 RunCommand::from_iter_safe(std::env::args()).unwrap_or_else(|_| e.exit()),
             ),
         })
-        .execute();
-    
-    use statistical::mean;
-    use wiggle::timing::results;
-    use std::fs::File;
-    use std::io::Write;
-    use statistical::univariate::geometric_mean;
-    let mut f = File::create("./wasmtime_results.txt").expect("Unable to open file");
-    results.with(|r| {
-            for (k,v) in r.borrow().iter(){
-                if !v.is_empty(){
-                    let mean = mean(v);
-                    let geomean = geometric_mean(v);
-                    writeln!(f, "{:?},{:?},{:?},{:?}", k, v.len(), mean, geomean);
-                }
-            }
-        });
-    return res;
+        .execute()
 }
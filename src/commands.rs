@@ -2,8 +2,12 @@
 
 mod compile;
 mod config;
+mod hostcall_bench;
+mod hostcall_profile;
 mod run;
 mod settings;
 mod wast;
 
-pub use self::{compile::*, config::*, run::*, settings::*, wast::*};
+pub use self::{
+    compile::*, config::*, hostcall_bench::*, hostcall_profile::*, run::*, settings::*, wast::*,
+};
@@ -0,0 +1,621 @@
+//! The module that implements the `wasmtime hostcall-bench` command.
+
+use crate::hostcall_profile::{ClockInfo, HostInfo};
+use crate::CommonOptions;
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use structopt::{clap::AppSettings, StructOpt};
+use tempfile::TempDir;
+use wasi_common::pipe::{ReadPipe, WritePipe};
+use wasmtime::{Engine, Linker, Module, Store};
+use wasmtime_wasi::sync::{ambient_authority, Dir, WasiCtxBuilder};
+use wasmtime_wasi::WasiCtx;
+use wiggle::timing::{HistogramRecorder, HostcallHistograms};
+
+lazy_static::lazy_static! {
+    static ref AFTER_HELP: String = {
+        crate::FLAG_EXPLANATIONS.to_string()
+    };
+}
+
+/// The version of the `--save-baseline` file layout.
+const BASELINE_VERSION: u32 = 1;
+
+/// The payload sizes used when `--size` isn't given.
+const DEFAULT_SIZES: [u32; 3] = [64, 4096, 65536];
+
+/// Runs a suite of microbenchmarks of WASI hostcalls
+#[derive(StructOpt)]
+#[structopt(
+    name = "hostcall-bench",
+    version = env!("CARGO_PKG_VERSION"),
+    setting = AppSettings::ColoredHelp,
+    after_help = AFTER_HELP.as_str(),
+)]
+pub struct HostcallBenchCommand {
+    #[structopt(flatten)]
+    common: CommonOptions,
+
+    /// Only run the benchmarks whose names contain this
+    #[structopt(long = "filter", number_of_values = 1, value_name = "NAME")]
+    filters: Vec<String>,
+
+    /// List the benchmarks instead of running them
+    #[structopt(long)]
+    list: bool,
+
+    /// The number of hostcalls each repetition of a benchmark makes
+    #[structopt(long, default_value = "10000", value_name = "N")]
+    iterations: u32,
+
+    /// The number of repetitions of each benchmark to run before measuring
+    #[structopt(long, default_value = "1", value_name = "N")]
+    warmup: u32,
+
+    /// The number of repetitions of each benchmark to measure
+    #[structopt(long, default_value = "5", value_name = "N")]
+    repeat: u32,
+
+    /// The payload sizes to run benchmarks which move data with, in bytes,
+    /// separated by commas [default: 64,4096,65536]
+    #[structopt(
+        long = "size",
+        number_of_values = 1,
+        use_delimiter = true,
+        value_name = "BYTES"
+    )]
+    sizes: Vec<u32>,
+
+    /// Compare the results against a file written by `--save-baseline`
+    #[structopt(long, value_name = "PATH")]
+    baseline: Option<PathBuf>,
+
+    /// Save the results to a file, to compare later runs against
+    #[structopt(long = "save-baseline", value_name = "PATH")]
+    save_baseline: Option<PathBuf>,
+}
+
+/// One microbenchmark: a loop making the same hostcall over and over.
+///
+/// The generated module lays out its memory as follows, and the snippets
+/// below refer to these addresses directly:
+///
+/// * 0: an iovec for the payload buffer
+/// * 16: where hostcalls write their results, such as a file descriptor
+/// * 64: the path `data`, a file in the preopened directory
+/// * 80: the path `bench-dir`
+/// * 256: a subscription to a relative timeout of zero on the monotonic
+///   clock
+/// * 512: where larger results, such as a filestat, are written
+/// * 1024: the payload buffer
+struct Benchmark {
+    name: &'static str,
+    /// The hostcall being measured.
+    hostcall: &'static str,
+    /// Whether the benchmark moves `SIZE` bytes per call.
+    sized: bool,
+    /// Whether to open `data` into `$fd` before the loop.
+    opens_data: bool,
+    /// The calls made on each iteration, each returning an errno.
+    calls: &'static [&'static str],
+}
+
+/// Opens `data` for reading and writing, leaving the new fd at 16.
+const OPEN_DATA: &str = "(call $path_open (i32.const 3) (i32.const 0) (i32.const 64) \
+    (i32.const 4) (i32.const 0) (i64.const 0x1fffffff) (i64.const 0x1fffffff) (i32.const 0) \
+    (i32.const 16))";
+
+const BENCHMARKS: &[Benchmark] = &[
+    Benchmark {
+        name: "args_sizes_get",
+        hostcall: "args_sizes_get",
+        sized: false,
+        opens_data: false,
+        calls: &["(call $args_sizes_get (i32.const 16) (i32.const 20))"],
+    },
+    Benchmark {
+        name: "environ_sizes_get",
+        hostcall: "environ_sizes_get",
+        sized: false,
+        opens_data: false,
+        calls: &["(call $environ_sizes_get (i32.const 16) (i32.const 20))"],
+    },
+    Benchmark {
+        name: "clock_res_get",
+        hostcall: "clock_res_get",
+        sized: false,
+        opens_data: false,
+        calls: &["(call $clock_res_get (i32.const 1) (i32.const 16))"],
+    },
+    Benchmark {
+        name: "clock_time_get",
+        hostcall: "clock_time_get",
+        sized: false,
+        opens_data: false,
+        calls: &["(call $clock_time_get (i32.const 1) (i64.const 0) (i32.const 16))"],
+    },
+    Benchmark {
+        name: "random_get",
+        hostcall: "random_get",
+        sized: true,
+        opens_data: false,
+        calls: &["(call $random_get (i32.const 1024) (i32.const SIZE))"],
+    },
+    Benchmark {
+        name: "sched_yield",
+        hostcall: "sched_yield",
+        sized: false,
+        opens_data: false,
+        calls: &["(call $sched_yield)"],
+    },
+    Benchmark {
+        name: "poll_oneoff/clock",
+        hostcall: "poll_oneoff",
+        sized: false,
+        opens_data: false,
+        calls: &[
+            "(call $poll_oneoff (i32.const 256) (i32.const 512) (i32.const 1) (i32.const 16))",
+        ],
+    },
+    Benchmark {
+        name: "fd_write/pipe",
+        hostcall: "fd_write",
+        sized: true,
+        opens_data: false,
+        calls: &["(call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16))"],
+    },
+    Benchmark {
+        name: "fd_read/pipe",
+        hostcall: "fd_read",
+        sized: true,
+        opens_data: false,
+        calls: &["(call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16))"],
+    },
+    Benchmark {
+        name: "fd_fdstat_get",
+        hostcall: "fd_fdstat_get",
+        sized: false,
+        opens_data: false,
+        calls: &["(call $fd_fdstat_get (i32.const 1) (i32.const 512))"],
+    },
+    Benchmark {
+        name: "fd_pread/file",
+        hostcall: "fd_pread",
+        sized: true,
+        opens_data: true,
+        calls: &[
+            "(call $fd_pread (local.get $fd) (i32.const 0) (i32.const 1) (i64.const 0) \
+                  (i32.const 16))",
+        ],
+    },
+    Benchmark {
+        name: "fd_pwrite/file",
+        hostcall: "fd_pwrite",
+        sized: true,
+        opens_data: true,
+        calls: &[
+            "(call $fd_pwrite (local.get $fd) (i32.const 0) (i32.const 1) (i64.const 0) \
+                  (i32.const 16))",
+        ],
+    },
+    Benchmark {
+        name: "fd_seek/file",
+        hostcall: "fd_seek",
+        sized: false,
+        opens_data: true,
+        calls: &["(call $fd_seek (local.get $fd) (i64.const 0) (i32.const 0) (i32.const 16))"],
+    },
+    Benchmark {
+        name: "fd_filestat_get/file",
+        hostcall: "fd_filestat_get",
+        sized: false,
+        opens_data: true,
+        calls: &["(call $fd_filestat_get (local.get $fd) (i32.const 512))"],
+    },
+    Benchmark {
+        name: "fd_readdir",
+        hostcall: "fd_readdir",
+        sized: true,
+        opens_data: false,
+        calls: &[
+            "(call $fd_readdir (i32.const 3) (i32.const 1024) (i32.const SIZE) \
+                  (i64.const 0) (i32.const 16))",
+        ],
+    },
+    Benchmark {
+        name: "path_filestat_get",
+        hostcall: "path_filestat_get",
+        sized: false,
+        opens_data: false,
+        calls: &[
+            "(call $path_filestat_get (i32.const 3) (i32.const 0) (i32.const 64) \
+                  (i32.const 4) (i32.const 512))",
+        ],
+    },
+    Benchmark {
+        name: "path_open+fd_close",
+        hostcall: "path_open",
+        sized: false,
+        opens_data: false,
+        calls: &[OPEN_DATA, "(call $fd_close (i32.load (i32.const 16)))"],
+    },
+    Benchmark {
+        name: "path_create_directory+path_remove_directory",
+        hostcall: "path_create_directory",
+        sized: false,
+        opens_data: false,
+        calls: &[
+            "(call $path_create_directory (i32.const 3) (i32.const 80) (i32.const 9))",
+            "(call $path_remove_directory (i32.const 3) (i32.const 80) (i32.const 9))",
+        ],
+    },
+];
+
+/// The parameters of each hostcall the benchmarks make. They all return an
+/// errno.
+const SIGNATURES: &[(&str, &str)] = &[
+    ("args_sizes_get", "i32 i32"),
+    ("clock_res_get", "i32 i32"),
+    ("clock_time_get", "i32 i64 i32"),
+    ("environ_sizes_get", "i32 i32"),
+    ("fd_close", "i32"),
+    ("fd_fdstat_get", "i32 i32"),
+    ("fd_filestat_get", "i32 i32"),
+    ("fd_pread", "i32 i32 i32 i64 i32"),
+    ("fd_pwrite", "i32 i32 i32 i64 i32"),
+    ("fd_read", "i32 i32 i32 i32"),
+    ("fd_readdir", "i32 i32 i32 i64 i32"),
+    ("fd_seek", "i32 i64 i32 i32"),
+    ("fd_write", "i32 i32 i32 i32"),
+    ("path_create_directory", "i32 i32 i32"),
+    ("path_filestat_get", "i32 i32 i32 i32 i32"),
+    ("path_open", "i32 i32 i32 i32 i32 i64 i64 i32 i32"),
+    ("path_remove_directory", "i32 i32 i32"),
+    ("poll_oneoff", "i32 i32 i32 i32"),
+    ("random_get", "i32 i32"),
+    ("sched_yield", ""),
+];
+
+impl Benchmark {
+    /// The text of a module exporting `run`, which takes the number of
+    /// iterations to make.
+    fn wat(&self, size: u32) -> String {
+        let mut calls = Vec::new();
+        if self.opens_data {
+            calls.push(OPEN_DATA);
+        }
+        calls.extend_from_slice(self.calls);
+        let hostcalls = calls
+            .iter()
+            .flat_map(|call| call.split("(call $").skip(1))
+            .map(|callee| {
+                callee
+                    .split(|c: char| c.is_whitespace() || c == ')')
+                    .next()
+                    .unwrap()
+            })
+            .collect::<BTreeSet<_>>();
+        let imports = hostcalls
+            .iter()
+            .map(|name| {
+                let params = SIGNATURES.iter().find(|(n, _)| n == name).unwrap().1;
+                format!(
+                    "(import \"wasi_snapshot_preview1\" \"{0}\" \
+                     (func ${0} (param {1}) (result i32)))\n",
+                    name, params
+                )
+            })
+            .collect::<String>();
+        // A failing hostcall traps, so that a broken benchmark isn't
+        // mistaken for a fast one.
+        let checked = |call: &str| format!("(if {} (then unreachable))\n", call);
+        let setup = if self.opens_data {
+            format!(
+                "{}(local.set $fd (i32.load (i32.const 16)))",
+                checked(OPEN_DATA)
+            )
+        } else {
+            String::new()
+        };
+        let body = self.calls.iter().map(|c| checked(c)).collect::<String>();
+        let pages = (1024 + size as u64 + 0xffff) / 0x10000;
+        format!(
+            r#"(module
+{imports}
+(memory (export "memory") {pages})
+(data (i32.const 64) "data")
+(data (i32.const 80) "bench-dir")
+(data (i32.const 272) "\01")
+(func (export "run") (param $n i32) (local $fd i32)
+  (i32.store (i32.const 0) (i32.const 1024))
+  (i32.store (i32.const 4) (i32.const {size}))
+  {setup}
+  (block $done
+    (loop $loop
+      (br_if $done (i32.eqz (local.get $n)))
+      {body}
+      (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+      (br $loop)))))
+"#,
+            imports = imports,
+            pages = pages,
+            size = size,
+            setup = setup,
+            body = body.replace("SIZE", &size.to_string()),
+        )
+    }
+}
+
+/// The results of a `hostcall-bench` run, as saved by `--save-baseline`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BenchReport {
+    /// The version of the layout of the file, currently 1.
+    pub schema_version: u32,
+    /// The machine the benchmarks ran on.
+    pub host: HostInfo,
+    /// The clock the hostcalls were timed with.
+    pub clock: ClockInfo,
+    /// The results of every benchmark run, in the order they ran.
+    pub results: Vec<BenchResult>,
+}
+
+/// The timings of the measured hostcall of one benchmark, in nanoseconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BenchResult {
+    /// The benchmark's name, e.g. `fd_write/pipe`.
+    pub benchmark: String,
+    /// The hostcall measured, e.g. `fd_write`.
+    pub hostcall: String,
+    /// The payload size in bytes, for benchmarks which move data.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u32>,
+    /// The number of calls measured.
+    pub calls: u64,
+    /// The mean duration of a call.
+    pub mean: f64,
+    /// The median duration of a call.
+    pub p50: f64,
+    /// The 99th percentile duration of a call.
+    pub p99: f64,
+    /// For benchmarks which move data, the bytes moved per nanosecond.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_per_ns: Option<f64>,
+}
+
+impl BenchResult {
+    fn new(benchmark: &Benchmark, size: Option<u32>, h: &HostcallHistograms) -> BenchResult {
+        let s = h.total.summary();
+        BenchResult {
+            benchmark: benchmark.name.to_string(),
+            hostcall: benchmark.hostcall.to_string(),
+            size,
+            calls: h.count(),
+            mean: s.mean,
+            p50: s.percentiles[0],
+            p99: s.percentiles[2],
+            bytes_per_ns: if h.payload.calls > 0 && h.payload.nanos > 0.0 {
+                Some(h.payload.bytes as f64 / h.payload.nanos)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// Creates a directory to preopen for the benchmarks, removed when dropped.
+fn scratch_dir(data_len: u32) -> Result<TempDir> {
+    let dir = tempfile::Builder::new()
+        .prefix("wasmtime-hostcall-bench-")
+        .tempdir()
+        .context("failed to create the benchmarks' scratch directory")?;
+    std::fs::write(dir.path().join("data"), vec![0; data_len as usize])
+        .context("failed to create the benchmarks' data file")?;
+    Ok(dir)
+}
+
+impl HostcallBenchCommand {
+    /// Executes the command.
+    pub fn execute(&self) -> Result<()> {
+        self.common.init_logging();
+
+        let benchmarks = BENCHMARKS
+            .iter()
+            .filter(|b| self.filters.is_empty() || self.filters.iter().any(|f| b.name.contains(f)))
+            .collect::<Vec<_>>();
+        if self.list {
+            for b in benchmarks {
+                println!("{}{}", b.name, if b.sized { " (sized)" } else { "" });
+            }
+            return Ok(());
+        }
+        if benchmarks.is_empty() {
+            bail!("no benchmarks match the given filters");
+        }
+        if self.iterations == 0 || self.repeat == 0 {
+            bail!("`--iterations` and `--repeat` must be greater than zero");
+        }
+        // The benchmarks count their iterations in an `i32`.
+        if self.iterations > i32::MAX as u32 {
+            bail!("`--iterations` must be at most {}", i32::MAX);
+        }
+        let sizes = if self.sizes.is_empty() {
+            &DEFAULT_SIZES[..]
+        } else {
+            &self.sizes[..]
+        };
+        let baseline = self.baseline.as_deref().map(read_baseline).transpose()?;
+
+        let engine = Engine::new(&self.common.config(None)?)?;
+        let mut linker = Linker::<WasiCtx>::new(&engine);
+        wasmtime_wasi::add_to_linker(&mut linker, |cx| cx)?;
+        let scratch = scratch_dir(sizes.iter().copied().max().unwrap_or(0))?;
+
+        let mut results = Vec::new();
+        for benchmark in benchmarks {
+            let sizes = if benchmark.sized {
+                sizes.iter().map(|s| Some(*s)).collect()
+            } else {
+                vec![None]
+            };
+            for size in sizes {
+                let histograms = self
+                    .run(&engine, &linker, scratch.path(), benchmark, size)
+                    .with_context(|| format!("failed to run benchmark `{}`", benchmark.name))?;
+                results.push(BenchResult::new(benchmark, size, &histograms));
+            }
+        }
+
+        print_table(&results, baseline.as_ref());
+        if let Some(path) = &self.save_baseline {
+            let report = BenchReport {
+                schema_version: BASELINE_VERSION,
+                host: HostInfo::current(),
+                clock: ClockInfo::current(),
+                results,
+            };
+            write_baseline(path, &report)?;
+        }
+        Ok(())
+    }
+
+    /// Runs every repetition of `benchmark`, returning the histograms of its
+    /// measured hostcall over the repetitions after the warmup.
+    fn run(
+        &self,
+        engine: &Engine,
+        linker: &Linker<WasiCtx>,
+        scratch: &Path,
+        benchmark: &Benchmark,
+        size: Option<u32>,
+    ) -> Result<HostcallHistograms> {
+        let wasm = wat::parse_str(benchmark.wat(size.unwrap_or(0)))?;
+        let module = Module::new(engine, &wasm)?;
+        let mut measured = HostcallHistograms::default();
+        for repetition in 0..self.warmup + self.repeat {
+            // Every repetition gets a fresh context, recording into its own
+            // histograms.
+            let recorder = Arc::new(HistogramRecorder::new());
+            let wasi = WasiCtxBuilder::new()
+                .stdin(Box::new(ReadPipe::new(std::io::repeat(0))))
+                .stdout(Box::new(WritePipe::new(std::io::sink())))
+                .preopened_dir(Dir::open_ambient_dir(scratch, ambient_authority())?, ".")?
+                .hostcall_recorder(recorder.clone())
+                .build();
+            let mut store = Store::new(engine, wasi);
+            let instance = linker.instantiate(&mut store, &module)?;
+            let run = instance.get_typed_func::<i32, (), _>(&mut store, "run")?;
+            run.call(&mut store, self.iterations as i32)?;
+            if repetition >= self.warmup {
+                if let Some(h) = recorder
+                    .snapshot()
+                    .get("wasi_snapshot_preview1", benchmark.hostcall)
+                {
+                    measured.merge(h);
+                }
+            }
+        }
+        Ok(measured)
+    }
+}
+
+fn read_baseline(path: &Path) -> Result<BenchReport> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open baseline `{}`", path.display()))?;
+    let report: BenchReport = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to parse baseline `{}`", path.display()))?;
+    if report.schema_version != BASELINE_VERSION {
+        bail!(
+            "baseline `{}` has version {}, but only version {} is supported",
+            path.display(),
+            report.schema_version,
+            BASELINE_VERSION
+        );
+    }
+    Ok(report)
+}
+
+fn write_baseline(path: &Path, report: &BenchReport) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("failed to create baseline `{}`", path.display()))?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, report)?;
+    writeln!(out)?;
+    out.flush()
+        .with_context(|| format!("failed to write `{}`", path.display()))?;
+    Ok(())
+}
+
+/// Prints a row per result, with the change in the median from the
+/// matching baseline result, if there is one.
+fn print_table(results: &[BenchResult], baseline: Option<&BenchReport>) {
+    let width = results
+        .iter()
+        .map(|r| r.benchmark.len())
+        .max()
+        .unwrap_or(0)
+        .max("benchmark".len());
+    print!(
+        "{:width$}  {:>7}  {:>9}  {:>10}  {:>10}  {:>10}  {:>9}",
+        "benchmark",
+        "size",
+        "calls",
+        "mean ns",
+        "p50 ns",
+        "p99 ns",
+        "MB/s",
+        width = width
+    );
+    if baseline.is_some() {
+        print!("  {:>12}", "p50 vs base");
+    }
+    println!();
+    for r in results {
+        print!(
+            "{:width$}  {:>7}  {:>9}  {:>10.1}  {:>10.1}  {:>10.1}  {:>9}",
+            r.benchmark,
+            r.size.map(|s| s.to_string()).unwrap_or_default(),
+            r.calls,
+            r.mean,
+            r.p50,
+            r.p99,
+            // Bytes per nanosecond are thousands of megabytes per second.
+            r.bytes_per_ns
+                .map(|b| format!("{:.1}", b * 1000.0))
+                .unwrap_or_default(),
+            width = width
+        );
+        if let Some(baseline) = baseline {
+            let base = baseline
+                .results
+                .iter()
+                .find(|b| b.benchmark == r.benchmark && b.size == r.size);
+            match base {
+                Some(base) if base.p50 > 0.0 => {
+                    print!("  {:>+11.1}%", (r.p50 / base.p50 - 1.0) * 100.0)
+                }
+                _ => print!("  {:>12}", "-"),
+            }
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn benchmarks_are_valid_wasm() {
+        for benchmark in BENCHMARKS {
+            let wat = benchmark.wat(4096);
+            if let Err(e) = wat::parse_str(&wat) {
+                panic!("benchmark `{}` is invalid: {}\n{}", benchmark.name, e, wat);
+            }
+        }
+    }
+}
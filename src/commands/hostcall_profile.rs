@@ -0,0 +1,323 @@
+//! The module that implements the `wasmtime hostcall-profile` command.
+
+use crate::hostcall_profile::{HostcallStats, Profile};
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+const DIFF_AFTER_HELP: &str =
+    "The profiles must be JSON, as written by `wasmtime run --hostcall-profile`.\n\
+     \n\
+     Changes are tested for significance with a Mann-Whitney U test on the \
+     individual durations when both profiles were recorded with \
+     `--hostcall-raw-samples`, and with a z-test on the mean durations \
+     otherwise.";
+
+/// Works with hostcall profiles written by `wasmtime run --hostcall-profile`
+#[derive(StructOpt)]
+#[structopt(name = "hostcall-profile")]
+pub enum HostcallProfileCommand {
+    /// Compares the hostcall timings of two profiles
+    #[structopt(after_help = DIFF_AFTER_HELP)]
+    Diff(HostcallProfileDiffCommand),
+}
+
+impl HostcallProfileCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        match self {
+            Self::Diff(c) => c.execute(),
+        }
+    }
+}
+
+/// Compares the hostcall timings of two profiles
+#[derive(StructOpt)]
+#[structopt(name = "diff", after_help = DIFF_AFTER_HELP)]
+pub struct HostcallProfileDiffCommand {
+    /// The profile to compare against
+    #[structopt(index = 1, value_name = "BEFORE", parse(from_os_str))]
+    before: PathBuf,
+
+    /// The profile to compare
+    #[structopt(index = 2, value_name = "AFTER", parse(from_os_str))]
+    after: PathBuf,
+
+    /// Fail if the median duration of any hostcall grows significantly by
+    /// more than this percentage
+    #[structopt(long, default_value = "10", value_name = "PERCENT")]
+    threshold: f64,
+
+    /// Also fail if the 99th percentile duration of any hostcall grows by
+    /// more than this percentage. Tails aren't tested for significance
+    #[structopt(long = "tail-threshold", value_name = "PERCENT")]
+    tail_threshold: Option<f64>,
+
+    /// The p-value below which a change counts as significant
+    #[structopt(long, default_value = "0.05", value_name = "P")]
+    alpha: f64,
+}
+
+/// How one hostcall changed between the two profiles.
+struct Change<'a> {
+    name: String,
+    before: Option<&'a HostcallStats>,
+    after: Option<&'a HostcallStats>,
+    /// The p-value of the change in durations, and the test it came from,
+    /// when both profiles timed enough calls to tell.
+    p: Option<(f64, &'static str)>,
+}
+
+impl HostcallProfileDiffCommand {
+    /// Executes the command.
+    pub fn execute(self) -> Result<()> {
+        let before = Profile::read(&self.before)?;
+        let after = Profile::read(&self.after)?;
+
+        let mut hostcalls = BTreeMap::new();
+        for h in before.hostcalls.iter() {
+            hostcalls.entry(name(h)).or_insert((None, None)).0 = Some(h);
+        }
+        for h in after.hostcalls.iter() {
+            hostcalls.entry(name(h)).or_insert((None, None)).1 = Some(h);
+        }
+        let changes = hostcalls
+            .into_iter()
+            .map(|(name, (before, after))| Change {
+                name,
+                before,
+                after,
+                p: match (before, after) {
+                    (Some(a), Some(b)) => p_value(a, b),
+                    _ => None,
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let width = changes
+            .iter()
+            .map(|c| c.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("hostcall".len());
+        println!(
+            "{:width$}  {:>9}  {:>9}  {:>10}  {:>10}  {:>8}  {:>10}  {:>10}  {:>8}  {:>9}",
+            "hostcall",
+            "count A",
+            "count B",
+            "p50 A ns",
+            "p50 B ns",
+            "p50",
+            "p99 A ns",
+            "p99 B ns",
+            "p99",
+            "p-value",
+            width = width
+        );
+        let mut regressions = Vec::new();
+        for c in changes.iter() {
+            let (before, after) = match (c.before, c.after) {
+                (Some(a), Some(b)) => (a, b),
+                (Some(a), None) => {
+                    println!(
+                        "{:width$}  {:>9}  {:>9}  removed",
+                        c.name,
+                        a.count,
+                        "-",
+                        width = width
+                    );
+                    continue;
+                }
+                (None, Some(b)) => {
+                    println!(
+                        "{:width$}  {:>9}  {:>9}  added",
+                        c.name,
+                        "-",
+                        b.count,
+                        width = width
+                    );
+                    continue;
+                }
+                (None, None) => unreachable!(),
+            };
+            let p50 = percent_change(before.p50, after.p50);
+            let p99 = percent_change(before.p99, after.p99);
+            let significant = c.p.map_or(false, |(p, _)| p < self.alpha);
+            let mut verdict = "";
+            if significant && p50 > self.threshold {
+                verdict = "regressed";
+            } else if significant && p50 < -self.threshold {
+                verdict = "improved";
+            }
+            if self.tail_threshold.map_or(false, |t| p99 > t) {
+                verdict = "regressed";
+            }
+            if verdict == "regressed" {
+                regressions.push(c.name.as_str());
+            }
+            println!(
+                "{:width$}  {:>9}  {:>9}  {:>10.1}  {:>10.1}  {:>+7.1}%  {:>10.1}  {:>10.1}  {:>+7.1}%  {:>9}  {}",
+                c.name,
+                before.count,
+                after.count,
+                before.p50,
+                after.p50,
+                p50,
+                before.p99,
+                after.p99,
+                p99,
+                match c.p {
+                    Some((p, test)) => format!("{:.4} {}", p, test),
+                    None => "-".to_string(),
+                },
+                verdict,
+                width = width
+            );
+        }
+        println!();
+        println!(
+            "p-values from a Mann-Whitney U test (U) on raw samples, or a z-test (z) on the means"
+        );
+
+        if !regressions.is_empty() {
+            bail!(
+                "{} hostcall(s) regressed beyond the threshold: {}",
+                regressions.len(),
+                regressions.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+fn name(h: &HostcallStats) -> String {
+    format!("{}::{}", h.module, h.function)
+}
+
+/// The change from `before` to `after` in percent.
+fn percent_change(before: f64, after: f64) -> f64 {
+    if before > 0.0 {
+        (after / before - 1.0) * 100.0
+    } else if after > 0.0 {
+        f64::INFINITY
+    } else {
+        0.0
+    }
+}
+
+/// The two-sided p-value of the durations of `a` and `b` differing, along
+/// with the test used, or `None` if either timed too few calls.
+fn p_value(a: &HostcallStats, b: &HostcallStats) -> Option<(f64, &'static str)> {
+    if let (Some(a), Some(b)) = (&a.samples, &b.samples) {
+        return mann_whitney(a, b).map(|p| (p, "U"));
+    }
+    if a.sampled < 2 || b.sampled < 2 {
+        return None;
+    }
+    let se = (a.stddev.powi(2) / a.sampled as f64 + b.stddev.powi(2) / b.sampled as f64).sqrt();
+    let p = if se > 0.0 {
+        2.0 * normal_sf(((b.mean - a.mean) / se).abs())
+    } else if a.mean == b.mean {
+        1.0
+    } else {
+        0.0
+    };
+    Some((p, "z"))
+}
+
+/// The two-sided p-value of the Mann-Whitney U test of `a` and `b`, with the
+/// normal approximation corrected for ties and continuity.
+fn mann_whitney(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let mut all = a
+        .iter()
+        .map(|x| (*x, true))
+        .chain(b.iter().map(|x| (*x, false)))
+        .collect::<Vec<_>>();
+    all.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    // Tied values share the mean of the ranks they span.
+    let n = all.len() as f64;
+    let mut rank_sum_a = 0.0;
+    let mut ties = 0.0;
+    let mut i = 0;
+    while i < all.len() {
+        let mut j = i;
+        while j < all.len() && all[j].0 == all[i].0 {
+            j += 1;
+        }
+        let rank = (i + j + 1) as f64 / 2.0;
+        rank_sum_a += rank * all[i..j].iter().filter(|x| x.1).count() as f64;
+        let t = (j - i) as f64;
+        ties += t * t * t - t;
+        i = j;
+    }
+
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+    let u = rank_sum_a - n1 * (n1 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        // Every value is the same.
+        return Some(1.0);
+    }
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    Some((2.0 * normal_sf(z)).min(1.0))
+}
+
+/// The probability of a standard normal variable exceeding `z`.
+fn normal_sf(z: f64) -> f64 {
+    erfc(z / std::f64::consts::SQRT_2) / 2.0
+}
+
+/// The complementary error function, to within a relative error of 1.2e-7
+/// (Numerical Recipes' `erfcc`).
+fn erfc(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 10] = [
+        -1.26551223,
+        1.00002368,
+        0.37409196,
+        0.09678418,
+        -0.18628806,
+        0.27886807,
+        -1.13520398,
+        1.48851587,
+        -0.82215223,
+        0.17087277,
+    ];
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = COEFFICIENTS.iter().rev().fold(0.0, |acc, c| acc * t + c);
+    let r = t * (poly - z * z).exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mann_whitney_p_values() {
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [6.0, 7.0, 8.0, 9.0, 10.0];
+        let p = mann_whitney(&a, &b).unwrap();
+        assert!((p - 0.01219).abs() < 1e-4, "p = {}", p);
+        assert_eq!(mann_whitney(&a, &a).unwrap(), 1.0);
+        assert_eq!(mann_whitney(&[3.0; 4], &[3.0; 6]).unwrap(), 1.0);
+        assert!(mann_whitney(&a, &[]).is_none());
+    }
+
+    #[test]
+    fn normal_tails() {
+        assert!((normal_sf(0.0) - 0.5).abs() < 1e-7);
+        assert!((normal_sf(1.96) - 0.025).abs() < 1e-4);
+        assert!((normal_sf(-1.96) - 0.975).abs() < 1e-4);
+    }
+}
@@ -1,7 +1,11 @@
 //! The module that implements the `wasmtime run` command.
 
+use crate::hostcall_profile::{
+    parse_perf_counter, parse_sampling, HostcallProfiler, ProfileFormat,
+};
 use crate::{CommonOptions, WasiModules};
 use anyhow::{anyhow, bail, Context as _, Result};
+use std::convert::TryFrom;
 use std::fs::File;
 use std::io::Read;
 use std::thread;
@@ -10,10 +14,16 @@ use std::{
     ffi::{OsStr, OsString},
     path::{Component, Path, PathBuf},
     process,
+    sync::Arc,
 };
 use structopt::{clap::AppSettings, StructOpt};
 use wasmtime::{Engine, Func, Linker, Module, Store, Trap, Val, ValType};
-use wasmtime_wasi::sync::{ambient_authority, Dir, WasiCtxBuilder};
+use wasmtime_wasi::sync::{ambient_authority, Dir, TcpListener, WasiCtxBuilder};
+use wiggle::replay::{HostcallLog, LogRecorder, LogReplayer};
+use wiggle::timing::{
+    self, HistogramRecorder, MetricsEndpoint, MetricsServer, PerfCounter, Sampling,
+    WasmtimeInstrumentation,
+};
 
 #[cfg(feature = "wasi-nn")]
 use wasmtime_wasi_nn::WasiNnCtx;
@@ -24,9 +34,13 @@ use wasmtime_wasi_crypto::WasiCryptoCtx;
 fn parse_module(s: &OsStr) -> Result<PathBuf, OsString> {
     // Do not accept wasmtime subcommand names as the module name
     match s.to_str() {
-        Some("help") | Some("config") | Some("run") | Some("wast") | Some("compile") => {
-            Err("module name cannot be the same as a subcommand".into())
-        }
+        Some("help")
+        | Some("config")
+        | Some("run")
+        | Some("wast")
+        | Some("compile")
+        | Some("hostcall-bench")
+        | Some("hostcall-profile") => Err("module name cannot be the same as a subcommand".into()),
         _ => Ok(s.into()),
     }
 }
@@ -107,6 +121,22 @@ pub struct RunCommand {
     #[structopt(long = "mapdir", number_of_values = 1, value_name = "GUEST_DIR::HOST_DIR", parse(try_from_str = parse_map_dirs))]
     map_dirs: Vec<(String, String)>,
 
+    /// Grant access to a copy-on-write overlay of a host directory, mapped
+    /// as a guest directory: the guest may change what's in it, but its
+    /// changes, up to 1 GiB of them, are kept in memory and never reach the
+    /// host directory
+    #[structopt(long = "dir-overlay", number_of_values = 1, value_name = "GUEST_DIR::HOST_DIR", parse(try_from_str = parse_map_dirs))]
+    overlay_dirs: Vec<(String, String)>,
+
+    /// Grant access to a TCP socket listening on the given address, which is
+    /// numbered after any preopened directories
+    #[structopt(
+        long = "tcplisten",
+        number_of_values = 1,
+        value_name = "SOCKET ADDRESS"
+    )]
+    tcplisten: Vec<String>,
+
     /// The path of the WebAssembly module to run
     #[structopt(
         index = 1,
@@ -133,6 +163,90 @@ pub struct RunCommand {
     )]
     wasm_timeout: Option<Duration>,
 
+    /// Time every WASI hostcall and write the results to the given file
+    #[structopt(long = "hostcall-profile", value_name = "PATH")]
+    hostcall_profile: Option<PathBuf>,
+
+    /// The format of the `--hostcall-profile` file
+    #[structopt(
+        long = "hostcall-profile-format",
+        value_name = "FORMAT",
+        default_value = "json",
+        possible_values = &["csv", "json", "chrome-trace", "folded"],
+    )]
+    hostcall_profile_format: ProfileFormat,
+
+    /// Include every individual hostcall duration in a JSON
+    /// `--hostcall-profile`, not just summary statistics
+    #[structopt(long = "hostcall-raw-samples")]
+    hostcall_raw_samples: bool,
+
+    /// Also break a JSON or CSV `--hostcall-profile` down by thread and store
+    #[structopt(long = "hostcall-profile-breakdown")]
+    hostcall_profile_breakdown: bool,
+
+    /// Attribute each hostcall in `--hostcall-profile` to up to this many of
+    /// the innermost WebAssembly functions that led to it [default: 64 for
+    /// the `folded` format, 0 otherwise]
+    #[structopt(long = "hostcall-profile-frames", value_name = "N")]
+    hostcall_profile_frames: Option<usize>,
+
+    /// Only time some hostcalls in `--hostcall-profile`, to bound the
+    /// overhead: one in every N calls to each hostcall, or at most N calls to
+    /// each hostcall per DURATION with N/DURATION. Every call is still
+    /// counted
+    #[structopt(
+        long = "hostcall-profile-sample",
+        value_name = "N[/DURATION]",
+        parse(try_from_str = parse_sampling),
+    )]
+    hostcall_profile_sample: Option<Sampling>,
+
+    /// Also time calls into the runtime's library functions, such as for
+    /// `memory.grow` and `memory.copy`, in `--hostcall-profile`, under the
+    /// `runtime` module
+    #[structopt(long = "hostcall-profile-libcalls")]
+    hostcall_profile_libcalls: bool,
+
+    /// Also count the host syscalls issued by each WASI hostcall in
+    /// `--hostcall-profile` (Linux only, usually needs root)
+    #[structopt(long = "hostcall-profile-syscalls")]
+    hostcall_profile_syscalls: bool,
+
+    /// Also count these hardware and kernel events over each WASI hostcall in
+    /// `--hostcall-profile`: any of `instructions`, `cache-misses`,
+    /// `branch-misses` and `context-switches`, separated by commas (Linux
+    /// only). Profiling carries on without them if they can't be counted
+    #[structopt(
+        long = "hostcall-profile-counters",
+        number_of_values = 1,
+        use_delimiter = true,
+        value_name = "COUNTER",
+        parse(try_from_str = parse_perf_counter),
+    )]
+    hostcall_profile_counters: Vec<PerfCounter>,
+
+    /// Serve live hostcall metrics in the Prometheus text format while the
+    /// module runs, over HTTP at `unix:PATH` or a loopback `HOST:PORT`. These
+    /// share the timings of a JSON, CSV or folded `--hostcall-profile`
+    #[structopt(long = "hostcall-metrics", value_name = "ENDPOINT")]
+    hostcall_metrics: Option<MetricsEndpoint>,
+
+    /// Record every WASI hostcall, with the guest memory it reads and
+    /// writes, to the given file
+    #[structopt(long = "hostcall-record", value_name = "PATH")]
+    hostcall_record: Option<PathBuf>,
+
+    /// Serve every WASI hostcall from a file written by `--hostcall-record`,
+    /// without touching the host, and fail at the first call which differs
+    /// from the recording
+    #[structopt(
+        long = "hostcall-replay",
+        value_name = "PATH",
+        conflicts_with = "hostcall-record"
+    )]
+    hostcall_replay: Option<PathBuf>,
+
     // NOTE: this must come last for trailing varargs
     /// The arguments to pass to the module
     #[structopt(value_name = "ARGS")]
@@ -144,15 +258,85 @@ impl RunCommand {
     pub fn execute(&self) -> Result<()> {
         self.common.init_logging();
 
+        let profiler = self.hostcall_profile.as_ref().map(|path| {
+            HostcallProfiler::install(
+                path,
+                self.hostcall_profile_format,
+                self.hostcall_raw_samples,
+                self.hostcall_profile_breakdown,
+                self.hostcall_profile_frames,
+                self.hostcall_profile_sample.unwrap_or(Sampling::All),
+            )
+        });
+
+        let metrics = match &self.hostcall_metrics {
+            Some(endpoint) => {
+                let histograms = match &profiler {
+                    Some(profiler) => profiler.histograms().ok_or_else(|| {
+                        anyhow!(
+                            "`--hostcall-metrics` can't be used with a `chrome-trace` \
+                             `--hostcall-profile`"
+                        )
+                    })?,
+                    None => {
+                        let histograms = Arc::new(HistogramRecorder::new());
+                        timing::set_recorder(histograms.clone());
+                        timing::set_sampling(self.hostcall_profile_sample.unwrap_or(Sampling::All));
+                        histograms
+                    }
+                };
+                Some(MetricsServer::start(endpoint, histograms)?)
+            }
+            None => None,
+        };
+
         let mut config = self.common.config(None)?;
+        if profiler.is_some() || metrics.is_some() {
+            // Time host functions that aren't instrumented by wiggle, too.
+            config.hostcall_instrumentation(Arc::new(WasmtimeInstrumentation));
+            config.instrument_libcalls(self.hostcall_profile_libcalls);
+            if self.hostcall_profile_syscalls {
+                timing::set_count_syscalls(true)
+                    .context("failed to start counting syscalls for `--hostcall-profile`")?;
+            }
+            if !self.hostcall_profile_counters.is_empty() {
+                if let Err(e) = timing::set_perf_counters(&self.hostcall_profile_counters) {
+                    eprintln!(
+                        "warning: not counting performance counters for `--hostcall-profile`: {}",
+                        e
+                    );
+                }
+            }
+        }
         if self.wasm_timeout.is_some() {
             config.interruptable(true);
         }
         let engine = Engine::new(&config)?;
         let mut store = Store::new(&engine, Host::default());
 
-        // Make wasi available by default.
-        let preopen_dirs = self.compute_preopen_dirs()?;
+        let recorder = match &self.hostcall_record {
+            Some(path) => Some(Arc::new(LogRecorder::create(path).with_context(|| {
+                format!("failed to create hostcall log '{}'", path.display())
+            })?)),
+            None => None,
+        };
+        let hostcall_log: Option<Arc<dyn HostcallLog>> = match &self.hostcall_replay {
+            Some(path) => Some(Arc::new(LogReplayer::open(path).with_context(|| {
+                format!("failed to open hostcall log '{}'", path.display())
+            })?)),
+            None => recorder.clone().map(|r| r as _),
+        };
+
+        // Make wasi available by default. Replayed hostcalls never reach the
+        // host, so there's nothing to preopen for them.
+        let (preopen_dirs, preopen_sockets) = if self.hostcall_replay.is_some() {
+            (Vec::new(), Vec::new())
+        } else {
+            (
+                self.compute_preopen_dirs()?,
+                self.compute_preopen_sockets()?,
+            )
+        };
         let argv = self.compute_argv();
 
         let mut linker = Linker::new(&engine);
@@ -162,10 +346,14 @@ impl RunCommand {
             &mut store,
             &mut linker,
             preopen_dirs,
+            preopen_sockets,
             &argv,
             &self.vars,
             &self.common.wasi_modules.unwrap_or(WasiModules::default()),
         )?;
+        if let (Some(log), Some(wasi)) = (hostcall_log, store.data_mut().wasi.as_mut()) {
+            wasi.set_hostcall_log(log);
+        }
 
         // Load the preload wasm modules.
         for (name, path) in self.preloads.iter() {
@@ -181,12 +369,37 @@ impl RunCommand {
         }
 
         // Load the main wasm module.
-        match self
+        let result = self
             .load_main_module(&mut store, &mut linker)
-            .with_context(|| format!("failed to run main module `{}`", self.module.display()))
-        {
-            Ok(()) => (),
+            .with_context(|| format!("failed to run main module `{}`", self.module.display()));
+
+        // Write the profile now, since the error handling below may exit the
+        // process. Failing to write it doesn't change how the guest exits, so
+        // it is only an error of its own if the guest succeeded.
+        let written = (|| -> Result<()> {
+            if let Some(profiler) = &profiler {
+                profiler.write()?;
+            }
+            if let Some(recorder) = &recorder {
+                recorder
+                    .flush()
+                    .context("failed to write the hostcall log")?;
+            }
+            Ok(())
+        })();
+        drop(metrics);
+
+        match result {
+            Ok(()) => written?,
             Err(e) => {
+                // Exiting with status zero is a success too.
+                let status = e.downcast_ref::<Trap>().and_then(|t| t.i32_exit_status());
+                if status == Some(0) {
+                    written?;
+                } else if let Err(written) = &written {
+                    eprintln!("warning: {:?}", written);
+                }
+
                 // If the program exited because of a non-zero exit status, print
                 // a message and exit.
                 if let Some(trap) = e.downcast_ref::<Trap>() {
@@ -221,28 +434,54 @@ impl RunCommand {
         Ok(())
     }
 
-    fn compute_preopen_dirs(&self) -> Result<Vec<(String, Dir)>> {
+    fn compute_preopen_dirs(&self) -> Result<Vec<(String, Preopen)>> {
         let mut preopen_dirs = Vec::new();
 
         for dir in self.dirs.iter() {
             preopen_dirs.push((
                 dir.clone(),
-                Dir::open_ambient_dir(dir, ambient_authority())
-                    .with_context(|| format!("failed to open directory '{}'", dir))?,
+                Preopen::Dir(
+                    Dir::open_ambient_dir(dir, ambient_authority())
+                        .with_context(|| format!("failed to open directory '{}'", dir))?,
+                ),
             ));
         }
 
         for (guest, host) in self.map_dirs.iter() {
             preopen_dirs.push((
                 guest.clone(),
-                Dir::open_ambient_dir(host, ambient_authority())
-                    .with_context(|| format!("failed to open directory '{}'", host))?,
+                Preopen::Dir(
+                    Dir::open_ambient_dir(host, ambient_authority())
+                        .with_context(|| format!("failed to open directory '{}'", host))?,
+                ),
+            ));
+        }
+
+        for (guest, host) in self.overlay_dirs.iter() {
+            preopen_dirs.push((
+                guest.clone(),
+                Preopen::Overlay(
+                    Dir::open_ambient_dir(host, ambient_authority())
+                        .with_context(|| format!("failed to open directory '{}'", host))?,
+                ),
             ));
         }
 
         Ok(preopen_dirs)
     }
 
+    fn compute_preopen_sockets(&self) -> Result<Vec<TcpListener>> {
+        let mut listeners = Vec::new();
+
+        for address in self.tcplisten.iter() {
+            let listener = std::net::TcpListener::bind(address)
+                .with_context(|| format!("failed to bind to address '{}'", address))?;
+            listeners.push(TcpListener::from_std(listener));
+        }
+
+        Ok(listeners)
+    }
+
     fn compute_argv(&self) -> Vec<String> {
         let mut result = Vec::new();
 
@@ -407,11 +646,20 @@ struct Host {
     wasi_crypto: Option<WasiCryptoCtx>,
 }
 
+/// A host directory to hand to the guest.
+enum Preopen {
+    Dir(Dir),
+    /// A copy-on-write overlay of the directory, which the guest's changes
+    /// never reach.
+    Overlay(Dir),
+}
+
 /// Populates the given `Linker` with WASI APIs.
 fn populate_with_wasi(
     store: &mut Store<Host>,
     linker: &mut Linker<Host>,
-    preopen_dirs: Vec<(String, Dir)>,
+    preopen_dirs: Vec<(String, Preopen)>,
+    preopen_sockets: Vec<TcpListener>,
     argv: &[String],
     vars: &[(String, String)],
     wasi_modules: &WasiModules,
@@ -422,8 +670,17 @@ fn populate_with_wasi(
         let mut builder = WasiCtxBuilder::new();
         builder = builder.inherit_stdio().args(argv)?.envs(vars)?;
 
+        // The sockets come after the directories, as guests stop looking for
+        // preopened directories at the first descriptor which isn't one.
+        let first_socket_fd = 3 + preopen_dirs.len();
         for (name, dir) in preopen_dirs.into_iter() {
-            builder = builder.preopened_dir(dir, name)?;
+            builder = match dir {
+                Preopen::Dir(dir) => builder.preopened_dir(dir, name)?,
+                Preopen::Overlay(dir) => builder.preopened_overlay_dir(dir, name)?,
+            };
+        }
+        for (i, listener) in preopen_sockets.into_iter().enumerate() {
+            builder = builder.preopened_socket(u32::try_from(first_socket_fd + i)?, listener)?;
         }
         store.data_mut().wasi = Some(builder.build());
     }
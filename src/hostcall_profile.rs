@@ -0,0 +1,1030 @@
+//! Writing the hostcall timings gathered by `wiggle::timing` to a file.
+//!
+//! Four formats are supported:
+//!
+//! * `json`: per-hostcall summary statistics along with a description of the
+//!   host and the clock the timings were taken with. The layout is described
+//!   by [`Profile`] and versioned with [`SCHEMA_VERSION`].
+//! * `csv`: the same per-hostcall statistics, one row per hostcall under a
+//!   header row, with the mean time spent in each phase of the call. Each is
+//!   followed by a row per result the hostcall finished with.
+//! * `chrome-trace`: every call as a complete ("X") event in the Chrome
+//!   trace-event format, which can be opened in Perfetto or `chrome://tracing`.
+//!   Each call has the phases it went through nested underneath it.
+//! * `folded`: the time spent in each hostcall under each WebAssembly stack
+//!   it was called from, as the folded stacks taken by flamegraph tools.
+//!
+//! For I/O hostcalls which report how much data they moved, such as
+//! `fd_read` and `fd_write`, the JSON and CSV formats also include the bytes
+//! transferred and the resulting throughput, and the JSON format breaks the
+//! duration down by power-of-two payload size.
+//!
+//! When the WebAssembly stack of each call is recorded, the JSON format also
+//! includes the time spent in each hostcall per calling guest function. When
+//! syscalls or performance counters are counted, the JSON and CSV formats
+//! include how many each call issued or caused, and the `chrome-trace` format
+//! has them for every call.
+//!
+//! When only some calls are timed, as set by [`parse_sampling`], call counts
+//! stay exact. Durations are those of the calls which were timed, and the
+//! counts, totals and payloads derived from them are scaled up to estimate
+//! them for every call. The `chrome-trace` format only has the timed calls.
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use wasi_common::snapshots::preview_1::types::Errno;
+use wiggle::timing::{
+    self, size_class_bounds, Histogram, HistogramRecorder, HostcallHistograms, HostcallRecorder,
+    HostcallResult, HostcallTable, PayloadHistograms, PerfCounter, PerfScope, Phase, Sampling,
+    StackStats, TraceRecorder, PERCENTILES,
+};
+
+/// The version of the JSON profile layout. Bump this whenever a field is
+/// removed or changes meaning.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The file formats a hostcall profile can be written in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// Per-hostcall statistics as CSV.
+    Csv,
+    /// Per-hostcall statistics and host information as JSON.
+    Json,
+    /// A timeline of every call in the Chrome trace-event format.
+    ChromeTrace,
+    /// Hostcall time per guest stack, as folded stacks.
+    Folded,
+}
+
+/// How many WebAssembly frames to record for the `folded` format when not
+/// told otherwise.
+pub const DEFAULT_FOLDED_FRAMES: usize = 64;
+
+impl FromStr for ProfileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(ProfileFormat::Csv),
+            "json" => Ok(ProfileFormat::Json),
+            "chrome-trace" => Ok(ProfileFormat::ChromeTrace),
+            "folded" => Ok(ProfileFormat::Folded),
+            _ => bail!("unknown hostcall profile format `{}`", s),
+        }
+    }
+}
+
+/// Parses which hostcalls to time: `N` to time one in every `N` calls to
+/// each hostcall, or `N/DURATION`, e.g. `100/1s`, to time at most `N` calls
+/// to each hostcall per `DURATION`.
+pub fn parse_sampling(s: &str) -> Result<Sampling> {
+    let mut parts = s.splitn(2, '/');
+    let calls = parts.next().unwrap();
+    let calls = calls
+        .parse::<u32>()
+        .with_context(|| format!("invalid number of calls `{}`", calls))?;
+    match parts.next() {
+        None if calls == 0 => bail!("cannot time one in every zero calls"),
+        None if calls == 1 => Ok(Sampling::All),
+        None => Ok(Sampling::OneIn(calls)),
+        Some(_) if calls == 0 => bail!("cannot time zero calls in each period"),
+        Some(period) => {
+            let period = humantime::parse_duration(period)?;
+            if period == Duration::from_secs(0) {
+                bail!("sampling period must not be zero");
+            }
+            Ok(Sampling::Budget { calls, period })
+        }
+    }
+}
+
+/// Parses the name of a performance counter as `perf` knows it, e.g.
+/// `cache-misses`.
+pub fn parse_perf_counter(s: &str) -> Result<PerfCounter> {
+    match PerfCounter::ALL.iter().find(|c| c.name() == s) {
+        Some(counter) => Ok(*counter),
+        None => {
+            let names = PerfCounter::ALL.iter().map(|c| c.name());
+            bail!(
+                "unknown performance counter `{}`, expected one of: {}",
+                s,
+                names.collect::<Vec<_>>().join(", ")
+            )
+        }
+    }
+}
+
+/// The inverse of [`parse_sampling`], or `all` when every call is timed.
+fn sampling_spec(sampling: Sampling) -> String {
+    match sampling {
+        Sampling::All => "all".to_string(),
+        Sampling::OneIn(n) => n.to_string(),
+        Sampling::Budget { calls, period } => {
+            format!("{}/{}", calls, humantime::format_duration(period))
+        }
+    }
+}
+
+/// A JSON hostcall profile.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    /// Always [`SCHEMA_VERSION`] when written by this version of Wasmtime.
+    pub schema_version: u32,
+    /// The machine the profile was recorded on.
+    pub host: HostInfo,
+    /// The clock the hostcalls were timed with.
+    pub clock: ClockInfo,
+    /// Which calls were timed, as taken by [`parse_sampling`], or `all`.
+    #[serde(default = "all_sampled")]
+    pub sampling: String,
+    /// Whether the performance counters, if any were counted, cover `user`
+    /// space only or `user-and-kernel`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perf_scope: Option<String>,
+    /// Statistics for every hostcall made at least once, across all threads
+    /// and stores. All durations are in nanoseconds.
+    pub hostcalls: Vec<HostcallStats>,
+    /// The same statistics split up by thread and store, when a breakdown was
+    /// requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<Vec<BreakdownStats>>,
+}
+
+impl Profile {
+    /// Reads a profile written in the `json` format.
+    pub fn read(path: &Path) -> Result<Profile> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open hostcall profile `{}`", path.display()))?;
+        let profile: Profile = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("failed to parse hostcall profile `{}`", path.display()))?;
+        if profile.schema_version != SCHEMA_VERSION {
+            bail!(
+                "hostcall profile `{}` has schema version {}, but only version {} is supported",
+                path.display(),
+                profile.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(profile)
+    }
+}
+
+fn all_sampled() -> String {
+    "all".to_string()
+}
+
+/// A description of the machine a profile was recorded on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostInfo {
+    /// The operating system, as in `std::env::consts::OS`.
+    pub os: String,
+    /// The CPU architecture, as in `std::env::consts::ARCH`.
+    pub arch: String,
+    /// The CPU model name, where the host reports one.
+    pub cpu: Option<String>,
+    /// The version of Wasmtime that recorded the profile.
+    pub wasmtime_version: String,
+}
+
+impl HostInfo {
+    /// Describes the current host.
+    pub fn current() -> HostInfo {
+        HostInfo {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpu: cpu_model(),
+            wasmtime_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// The clock used to time hostcalls.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClockInfo {
+    /// Where the ticks came from and how their frequency was determined.
+    pub source: String,
+    /// The clock frequency, e.g. the TSC frequency.
+    pub frequency_hz: u64,
+}
+
+impl ClockInfo {
+    /// Describes the process-wide `wiggle::timing` clock.
+    pub fn current() -> ClockInfo {
+        let clock = timing::clock();
+        ClockInfo {
+            source: clock.source().to_string(),
+            frequency_hz: clock.frequency_hz(),
+        }
+    }
+}
+
+/// Statistics for a single hostcall, in nanoseconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostcallStats {
+    /// The witx module, e.g. `wasi_snapshot_preview1`.
+    pub module: String,
+    /// The function within `module`, e.g. `fd_write`.
+    pub function: String,
+    /// How many times the hostcall was made.
+    pub count: u64,
+    /// How many of those calls were timed. The durations below only cover
+    /// these calls.
+    #[serde(default)]
+    pub sampled: u64,
+    /// The total duration of every call, estimated from the calls which were
+    /// timed.
+    #[serde(default)]
+    pub total: f64,
+    /// The mean duration.
+    pub mean: f64,
+    /// The shortest call.
+    pub min: f64,
+    /// The median duration.
+    pub p50: f64,
+    /// The 90th percentile duration.
+    pub p90: f64,
+    /// The 99th percentile duration.
+    pub p99: f64,
+    /// The 99.9th percentile duration.
+    pub p99_9: f64,
+    /// The longest call.
+    pub max: f64,
+    /// The standard deviation of the duration.
+    pub stddev: f64,
+    /// How the duration splits into the phases of a call, in order.
+    pub phases: Vec<PhaseStats>,
+    /// For async hostcalls, how much of the duration was spent running
+    /// rather than suspended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<PollSummary>,
+    /// The same statistics for each way the calls finished: successfully,
+    /// with each error code, or by trapping.
+    #[serde(default)]
+    pub results: Vec<ResultStats>,
+    /// For I/O hostcalls, how much data the calls moved and how their
+    /// duration depends on it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<PayloadStats>,
+    /// The time spent in this hostcall per calling WebAssembly function,
+    /// most first, when guest stacks were recorded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub callers: Vec<CallerStats>,
+    /// How many host syscalls the implementation issued per call, when they
+    /// were counted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub syscalls: Option<CountStats>,
+    /// How many times each performance counter ticked per call, by the
+    /// counter's name, when they were counted.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub counters: BTreeMap<String, CountStats>,
+    /// Every individual duration, when raw samples were requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples: Option<Vec<f64>>,
+}
+
+impl HostcallStats {
+    fn from_histograms(module: &str, function: &str, h: &HostcallHistograms) -> HostcallStats {
+        let s = h.total.summary();
+        let scale = h.scale();
+        HostcallStats {
+            module: module.to_string(),
+            function: function.to_string(),
+            count: h.count(),
+            sampled: s.count,
+            total: s.mean * h.count() as f64,
+            mean: s.mean,
+            min: s.min,
+            p50: s.percentiles[0],
+            p90: s.percentiles[1],
+            p99: s.percentiles[2],
+            p99_9: s.percentiles[3],
+            max: s.max,
+            stddev: s.stddev,
+            phases: Phase::ALL
+                .iter()
+                .map(|phase| {
+                    let p = h.phase(*phase).summary();
+                    PhaseStats {
+                        phase: phase.name().to_string(),
+                        mean: p.mean,
+                        p50: p.percentiles[0],
+                        p99: p.percentiles[2],
+                        max: p.max,
+                    }
+                })
+                .collect(),
+            poll: if h.busy.count() > 0 {
+                let b = h.busy.summary();
+                Some(PollSummary {
+                    busy_mean: b.mean,
+                    busy_p50: b.percentiles[0],
+                    busy_p99: b.percentiles[2],
+                    busy_max: b.max,
+                    yields: h.yields,
+                })
+            } else {
+                None
+            },
+            results: h
+                .results
+                .iter()
+                .map(|(result, h)| ResultStats::from_histogram(module, result, h, scale))
+                .collect(),
+            payload: if h.payload.calls > 0 {
+                Some(PayloadStats::from_histograms(&h.payload, scale))
+            } else {
+                None
+            },
+            callers: CallerStats::from_stacks(&h.stacks, scale),
+            syscalls: CountStats::from_histogram(&h.syscalls, scale),
+            counters: PerfCounter::ALL
+                .iter()
+                .filter_map(|c| {
+                    let stats = CountStats::from_histogram(h.counter(*c), scale)?;
+                    Some((c.name().to_string(), stats))
+                })
+                .collect(),
+            samples: h.total.raw_samples().map(|s| s.to_vec()),
+        }
+    }
+}
+
+/// Statistics for the calls to a hostcall which finished in one particular
+/// way, in nanoseconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResultStats {
+    /// `ok`, `trap`, or the name of the error code returned, e.g. `noent`.
+    pub result: String,
+    /// The (lowered) error code, for calls which returned one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<i32>,
+    /// How many calls finished this way, estimated from the calls which were
+    /// timed.
+    pub count: u64,
+    /// The mean duration.
+    pub mean: f64,
+    /// The shortest call.
+    pub min: f64,
+    /// The median duration.
+    pub p50: f64,
+    /// The 90th percentile duration.
+    pub p90: f64,
+    /// The 99th percentile duration.
+    pub p99: f64,
+    /// The 99.9th percentile duration.
+    pub p99_9: f64,
+    /// The longest call.
+    pub max: f64,
+    /// The standard deviation of the duration.
+    pub stddev: f64,
+}
+
+impl ResultStats {
+    fn from_histogram(
+        module: &str,
+        result: &HostcallResult,
+        h: &Histogram,
+        scale: f64,
+    ) -> ResultStats {
+        let s = h.summary();
+        ResultStats {
+            result: result_name(module, result),
+            errno: match result {
+                HostcallResult::Errno(e) => Some(*e),
+                HostcallResult::Ok | HostcallResult::Trap => None,
+            },
+            count: scaled(s.count, scale),
+            mean: s.mean,
+            min: s.min,
+            p50: s.percentiles[0],
+            p90: s.percentiles[1],
+            p99: s.percentiles[2],
+            p99_9: s.percentiles[3],
+            max: s.max,
+            stddev: s.stddev,
+        }
+    }
+}
+
+/// The calls made to a hostcall from one WebAssembly function.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CallerStats {
+    /// The calling function, as `module!function` where the module has a
+    /// name.
+    pub function: String,
+    /// How many calls the function made.
+    pub calls: u64,
+    /// The total duration of those calls.
+    pub total: f64,
+    /// The mean duration of those calls.
+    pub mean: f64,
+}
+
+impl CallerStats {
+    /// Groups the calls made from each stack by the innermost function.
+    fn from_stacks(stacks: &HashMap<Vec<String>, StackStats>, scale: f64) -> Vec<CallerStats> {
+        let mut callers = HashMap::<&str, StackStats>::new();
+        for (stack, stats) in stacks.iter() {
+            let caller = callers.entry(&stack[0]).or_default();
+            caller.calls += stats.calls;
+            caller.nanos += stats.nanos;
+        }
+        let mut callers = callers
+            .into_iter()
+            .map(|(function, stats)| CallerStats {
+                function: function.to_string(),
+                calls: scaled(stats.calls, scale),
+                total: stats.nanos * scale,
+                mean: stats.nanos / stats.calls as f64,
+            })
+            .collect::<Vec<_>>();
+        callers.sort_by(|a, b| {
+            b.total
+                .partial_cmp(&a.total)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.function.cmp(&b.function))
+        });
+        callers
+    }
+}
+
+/// The number of syscalls issued, or performance counter ticks, per call to
+/// a hostcall.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CountStats {
+    /// The total over all calls, estimated from the calls which were timed.
+    pub total: u64,
+    /// The mean number per call.
+    pub mean: f64,
+    /// The median number per call.
+    pub p50: f64,
+    /// The 99th percentile number per call.
+    pub p99: f64,
+    /// The most for a single call.
+    pub max: f64,
+}
+
+impl CountStats {
+    fn from_histogram(h: &Histogram, scale: f64) -> Option<CountStats> {
+        if h.count() == 0 {
+            return None;
+        }
+        let s = h.summary();
+        Some(CountStats {
+            total: scaled((s.mean * s.count as f64).round() as u64, scale),
+            mean: s.mean,
+            p50: s.percentiles[0],
+            p99: s.percentiles[2],
+            max: s.max,
+        })
+    }
+}
+
+/// The data moved by the calls to an I/O hostcall which reported it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayloadStats {
+    /// How many calls reported a payload. Failed calls don't.
+    pub calls: u64,
+    /// The total number of bytes transferred.
+    pub bytes: u64,
+    /// The total number of iovecs the bytes were spread over, counting calls
+    /// which take a single buffer as one.
+    pub iovecs: u64,
+    /// The mean throughput, in bytes per nanosecond.
+    pub bytes_per_ns: f64,
+    /// The duration of the calls, in nanoseconds, split by payload size into
+    /// power-of-two buckets. Only buckets with calls in them are included.
+    pub sizes: Vec<SizeStats>,
+}
+
+impl PayloadStats {
+    fn from_histograms(p: &PayloadHistograms, scale: f64) -> PayloadStats {
+        PayloadStats {
+            calls: scaled(p.calls, scale),
+            bytes: scaled(p.bytes, scale),
+            iovecs: scaled(p.iovecs, scale),
+            bytes_per_ns: p.throughput(),
+            sizes: p
+                .sizes
+                .iter()
+                .map(|(class, h)| {
+                    let (min_bytes, max_bytes) = size_class_bounds(*class);
+                    let s = h.summary();
+                    SizeStats {
+                        min_bytes,
+                        max_bytes,
+                        count: scaled(s.count, scale),
+                        mean: s.mean,
+                        p50: s.percentiles[0],
+                        p99: s.percentiles[2],
+                        max: s.max,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The duration of the calls whose payload was between `min_bytes` and
+/// `max_bytes` inclusive, in nanoseconds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SizeStats {
+    /// The smallest payload in this bucket.
+    pub min_bytes: u64,
+    /// The largest payload in this bucket.
+    pub max_bytes: u64,
+    /// How many calls fell into this bucket.
+    pub count: u64,
+    /// The mean duration.
+    pub mean: f64,
+    /// The median duration.
+    pub p50: f64,
+    /// The 99th percentile duration.
+    pub p99: f64,
+    /// The longest call.
+    pub max: f64,
+}
+
+/// How the futures of an async hostcall were polled. Busy time, in
+/// nanoseconds, only counts time spent inside `Future::poll`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollSummary {
+    /// The mean busy time.
+    pub busy_mean: f64,
+    /// The median busy time.
+    pub busy_p50: f64,
+    /// The 99th percentile busy time.
+    pub busy_p99: f64,
+    /// The longest busy time.
+    pub busy_max: f64,
+    /// How many times the calls yielded in total.
+    pub yields: u64,
+}
+
+/// Statistics for one phase of a hostcall, in nanoseconds. The phases are
+/// `entry` (from Wasmtime into the abi function), `lift` (reading the
+/// arguments), `call` (the implementation), `lower` (writing the results) and
+/// `return` (back to Wasmtime).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PhaseStats {
+    /// The name of the phase.
+    pub phase: String,
+    /// The mean duration.
+    pub mean: f64,
+    /// The median duration.
+    pub p50: f64,
+    /// The 99th percentile duration.
+    pub p99: f64,
+    /// The longest time spent in this phase.
+    pub max: f64,
+}
+
+/// The hostcalls made by a single thread, for a single store if the embedder
+/// labelled its stores.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BreakdownStats {
+    /// A small integer identifying the thread, unique within the process.
+    pub thread: u64,
+    /// The store's label, if it had its own recorder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    /// Statistics for every hostcall this thread made in this store.
+    pub hostcalls: Vec<HostcallStats>,
+}
+
+/// Collects hostcall timings for the lifetime of a `wasmtime run` and writes
+/// them out at the end.
+pub struct HostcallProfiler {
+    path: PathBuf,
+    format: ProfileFormat,
+    breakdown: bool,
+    sampling: Sampling,
+    histograms: Arc<HistogramRecorder>,
+    trace: Arc<TraceRecorder>,
+}
+
+impl HostcallProfiler {
+    /// Installs a process-wide recorder suited to `format`. `raw_samples`
+    /// keeps every duration in addition to the histograms, `breakdown` adds
+    /// per-thread and per-store statistics to the totals, and `frames` is how
+    /// many WebAssembly frames to record per call, defaulting to
+    /// [`DEFAULT_FOLDED_FRAMES`] for the `folded` format and none otherwise.
+    /// `sampling` sets which calls are timed.
+    pub fn install(
+        path: &Path,
+        format: ProfileFormat,
+        raw_samples: bool,
+        breakdown: bool,
+        frames: Option<usize>,
+        sampling: Sampling,
+    ) -> HostcallProfiler {
+        let histograms = Arc::new(if raw_samples {
+            HistogramRecorder::with_raw_samples()
+        } else {
+            HistogramRecorder::new()
+        });
+        let trace = Arc::new(TraceRecorder::new());
+        let recorder: Arc<dyn HostcallRecorder> = match format {
+            ProfileFormat::ChromeTrace => trace.clone(),
+            ProfileFormat::Csv | ProfileFormat::Json | ProfileFormat::Folded => histograms.clone(),
+        };
+        timing::set_recorder(recorder);
+        timing::set_guest_frames(frames.unwrap_or(match format {
+            ProfileFormat::Folded => DEFAULT_FOLDED_FRAMES,
+            _ => 0,
+        }));
+        timing::set_sampling(sampling);
+        HostcallProfiler {
+            path: path.to_path_buf(),
+            format,
+            breakdown,
+            sampling,
+            histograms,
+            trace,
+        }
+    }
+
+    /// The recorder keeping the profile's histograms, unless the format only
+    /// needs the timeline of calls.
+    pub fn histograms(&self) -> Option<Arc<HistogramRecorder>> {
+        match self.format {
+            ProfileFormat::ChromeTrace => None,
+            ProfileFormat::Csv | ProfileFormat::Json | ProfileFormat::Folded => {
+                Some(self.histograms.clone())
+            }
+        }
+    }
+
+    /// The profile as it stands.
+    pub fn profile(&self) -> Profile {
+        let breakdown = if self.breakdown {
+            let mut parts = self
+                .histograms
+                .breakdown()
+                .iter()
+                .map(|part| BreakdownStats {
+                    thread: part.thread,
+                    store: part.store.clone(),
+                    hostcalls: hostcall_stats(&part.histograms),
+                })
+                .collect::<Vec<_>>();
+            parts.sort_by(|a, b| (&a.store, a.thread).cmp(&(&b.store, b.thread)));
+            Some(parts)
+        } else {
+            None
+        };
+        Profile {
+            schema_version: SCHEMA_VERSION,
+            host: HostInfo::current(),
+            clock: ClockInfo::current(),
+            sampling: sampling_spec(self.sampling),
+            perf_scope: perf_scope(),
+            hostcalls: hostcall_stats(&self.histograms.snapshot()),
+            breakdown,
+        }
+    }
+
+    /// Writes everything recorded so far to the profile's path.
+    pub fn write(&self) -> Result<()> {
+        let file = File::create(&self.path).with_context(|| {
+            format!(
+                "failed to create hostcall profile `{}`",
+                self.path.display()
+            )
+        })?;
+        let mut out = BufWriter::new(file);
+        match self.format {
+            ProfileFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, &self.profile())?;
+                writeln!(out)?;
+            }
+            ProfileFormat::Csv => write_csv(&mut out, &self.profile())?,
+            ProfileFormat::ChromeTrace => self.write_chrome_trace(&mut out)?,
+            ProfileFormat::Folded => write_folded(&mut out, &self.histograms.snapshot())?,
+        }
+        out.flush()
+            .with_context(|| format!("failed to write `{}`", self.path.display()))?;
+        Ok(())
+    }
+
+    fn write_chrome_trace(&self, out: &mut impl Write) -> Result<()> {
+        let clock = timing::clock();
+        let events = self.trace.snapshot();
+        let epoch = events.iter().map(|e| e.start).min().unwrap_or(0);
+        let micros = |ticks: u64| clock.ticks_to_nanos(ticks) / 1000.0;
+        let pid = std::process::id();
+        // Each call is followed by an event per phase, which trace viewers
+        // nest underneath it since they share a thread and time range.
+        let mut trace_events = Vec::new();
+        for e in events.iter() {
+            let mut args = serde_json::json!({ "result": result_name(&e.module, &e.result) });
+            if let Some(poll) = e.poll {
+                args["busy_us"] = micros(poll.busy).into();
+                args["yields"] = poll.yields.into();
+            }
+            if let Some(payload) = e.payload {
+                args["bytes"] = payload.bytes.into();
+                args["iovecs"] = payload.iovecs.into();
+            }
+            if let Some(stack) = &e.stack {
+                args["stack"] = stack.clone().into();
+            }
+            if let Some(syscalls) = e.syscalls {
+                args["syscalls"] = syscalls.into();
+            }
+            for (counter, count) in e.counters.iter().flat_map(|c| c.iter()) {
+                args[counter.name()] = count.into();
+            }
+            trace_events.push(serde_json::json!({
+                "name": e.function,
+                "cat": e.module,
+                "ph": "X",
+                "ts": micros(e.start - epoch),
+                "dur": micros(e.end.saturating_sub(e.start)),
+                "pid": pid,
+                "tid": e.thread,
+                "args": args,
+            }));
+            for phase in Phase::ALL.iter() {
+                let (start, end) = e.phase_bounds(*phase);
+                if end <= start {
+                    continue;
+                }
+                trace_events.push(serde_json::json!({
+                    "name": phase.name(),
+                    "cat": "phase",
+                    "ph": "X",
+                    "ts": micros(start - epoch),
+                    "dur": micros(end - start),
+                    "pid": pid,
+                    "tid": e.thread,
+                }));
+            }
+        }
+        let mut other_data = serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "host": HostInfo::current(),
+            "clock": ClockInfo::current(),
+            "sampling": sampling_spec(self.sampling),
+        });
+        if let Some(scope) = perf_scope() {
+            other_data["perf_scope"] = scope.into();
+        }
+        let trace = serde_json::json!({
+            "traceEvents": trace_events,
+            "displayTimeUnit": "ns",
+            "otherData": other_data,
+        });
+        serde_json::to_writer(&mut *out, &trace)?;
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+/// Describes [`timing::perf_scope`] for the profile, if any performance
+/// counters are being counted.
+fn perf_scope() -> Option<String> {
+    if timing::perf_counters().is_empty() {
+        return None;
+    }
+    Some(match timing::perf_scope() {
+        PerfScope::User => "user".to_string(),
+        PerfScope::UserAndKernel => "user-and-kernel".to_string(),
+    })
+}
+
+fn hostcall_stats(histograms: &HostcallTable<HostcallHistograms>) -> Vec<HostcallStats> {
+    let mut stats = histograms
+        .iter()
+        .map(|(module, function, h)| HostcallStats::from_histograms(module, function, h))
+        .collect::<Vec<_>>();
+    stats.sort_by(|a, b| (&a.module, &a.function).cmp(&(&b.module, &b.function)));
+    stats
+}
+
+/// Writes one line per guest stack and hostcall called from it: the stack
+/// from the outermost frame in, then the hostcall, separated by `;`, followed
+/// by the total time in nanoseconds, scaled up for calls which weren't timed.
+/// Calls without a recorded stack are left out.
+fn write_folded(
+    out: &mut impl Write,
+    histograms: &HostcallTable<HostcallHistograms>,
+) -> Result<()> {
+    let mut lines = Vec::new();
+    for (module, function, h) in histograms.iter() {
+        let scale = h.scale();
+        for (stack, stats) in h.stacks.iter() {
+            let mut frames = stack.iter().rev().map(|f| f.as_str()).collect::<Vec<_>>();
+            let hostcall = format!("{}::{}", module, function);
+            frames.push(&hostcall);
+            lines.push((frames.join(";"), (stats.nanos * scale).round() as u64));
+        }
+    }
+    lines.sort();
+    for (stack, nanos) in lines {
+        writeln!(out, "{} {}", stack, nanos)?;
+    }
+    Ok(())
+}
+
+/// Writes the totals. With a breakdown, they are followed by one row per
+/// hostcall per thread and store, and every row has leading `thread` and
+/// `store` columns, which are both empty for the totals.
+///
+/// The trailing `result` column is `all` for the row covering every call,
+/// which is followed by a row per result with only the overall statistics
+/// filled in. `count` is the number of calls made, and `sampled` how many of
+/// them the durations were taken from. There is a mean column for every
+/// performance counter, such as `cache_misses_mean`, which is empty unless it
+/// was counted.
+fn write_csv(out: &mut impl Write, profile: &Profile) -> Result<()> {
+    if profile.breakdown.is_some() {
+        write!(out, "thread,store,")?;
+    }
+    write!(out, "module,function,count,mean_ns,min_ns")?;
+    for p in PERCENTILES.iter() {
+        write!(out, ",p{}_ns", p.to_string().replace('.', "_"))?;
+    }
+    write!(out, ",max_ns,stddev_ns")?;
+    for phase in Phase::ALL.iter() {
+        write!(out, ",{}_mean_ns", phase.name())?;
+    }
+    write!(
+        out,
+        ",busy_mean_ns,yields,bytes,iovecs,bytes_per_ns,syscalls_mean"
+    )?;
+    for counter in PerfCounter::ALL.iter() {
+        write!(out, ",{}_mean", counter.name().replace('-', "_"))?;
+    }
+    writeln!(out, ",sampled,total_ns,result")?;
+    match &profile.breakdown {
+        Some(parts) => {
+            write_csv_rows(out, ",,", &profile.hostcalls)?;
+            for part in parts {
+                let prefix = format!(
+                    "{},{},",
+                    part.thread,
+                    csv_field(part.store.as_deref().unwrap_or(""))
+                );
+                write_csv_rows(out, &prefix, &part.hostcalls)?;
+            }
+        }
+        None => write_csv_rows(out, "", &profile.hostcalls)?,
+    }
+    Ok(())
+}
+
+fn write_csv_rows(out: &mut impl Write, prefix: &str, hostcalls: &[HostcallStats]) -> Result<()> {
+    for h in hostcalls {
+        write!(
+            out,
+            "{}{},{},{},{},{},{},{},{},{},{},{}",
+            prefix,
+            csv_field(&h.module),
+            csv_field(&h.function),
+            h.count,
+            h.mean,
+            h.min,
+            h.p50,
+            h.p90,
+            h.p99,
+            h.p99_9,
+            h.max,
+            h.stddev,
+        )?;
+        for phase in h.phases.iter() {
+            write!(out, ",{}", phase.mean)?;
+        }
+        // Synchronous hostcalls leave the poll columns empty, those which
+        // don't report a payload the payload columns, and the syscall and
+        // counter columns are empty unless they were counted.
+        match &h.poll {
+            Some(poll) => write!(out, ",{},{}", poll.busy_mean, poll.yields)?,
+            None => write!(out, ",,")?,
+        }
+        match &h.payload {
+            Some(payload) => write!(
+                out,
+                ",{},{},{}",
+                payload.bytes, payload.iovecs, payload.bytes_per_ns
+            )?,
+            None => write!(out, ",,,")?,
+        }
+        match &h.syscalls {
+            Some(syscalls) => write!(out, ",{}", syscalls.mean)?,
+            None => write!(out, ",")?,
+        }
+        for counter in PerfCounter::ALL.iter() {
+            match h.counters.get(counter.name()) {
+                Some(stats) => write!(out, ",{}", stats.mean)?,
+                None => write!(out, ",")?,
+            }
+        }
+        writeln!(out, ",{},{},all", h.sampled, h.total)?;
+        for r in h.results.iter() {
+            write!(
+                out,
+                "{}{},{},{},{},{},{},{},{},{},{},{}",
+                prefix,
+                csv_field(&h.module),
+                csv_field(&h.function),
+                r.count,
+                r.mean,
+                r.min,
+                r.p50,
+                r.p90,
+                r.p99,
+                r.p99_9,
+                r.max,
+                r.stddev,
+            )?;
+            writeln!(
+                out,
+                "{},,,,,,,,,{}",
+                ",".repeat(h.phases.len() + PerfCounter::ALL.len()),
+                csv_field(&r.result)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Scales a count taken from the timed calls up to all calls.
+fn scaled(count: u64, scale: f64) -> u64 {
+    (count as f64 * scale).round() as u64
+}
+
+/// Quotes `s` if it contains anything CSV treats specially.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn result_name(module: &str, result: &HostcallResult) -> String {
+    match result {
+        HostcallResult::Ok => "ok".to_string(),
+        HostcallResult::Errno(e) => {
+            errno_name(module, *e).unwrap_or_else(|| format!("errno {}", e))
+        }
+        HostcallResult::Trap => "trap".to_string(),
+    }
+}
+
+/// The witx name of a WASI error code, e.g. `noent`.
+fn errno_name(module: &str, errno: i32) -> Option<String> {
+    match module {
+        // Both snapshots number their error codes the same way.
+        "wasi_snapshot_preview1" | "wasi_unstable" => {
+            let errno = Errno::try_from(errno).ok()?;
+            Some(format!("{:?}", errno).to_lowercase())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_model() -> Option<String> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo
+        .lines()
+        .find(|l| l.starts_with("model name"))
+        .and_then(|l| l.splitn(2, ':').nth(1))
+        .map(|m| m.trim().to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_model() -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sampling_specs() {
+        assert_eq!(parse_sampling("1").unwrap(), Sampling::All);
+        assert_eq!(parse_sampling("10").unwrap(), Sampling::OneIn(10));
+        assert_eq!(
+            parse_sampling("100/1s").unwrap(),
+            Sampling::Budget {
+                calls: 100,
+                period: Duration::from_secs(1),
+            }
+        );
+        for spec in &["0", "0/1s", "10/0s", "ten", "10/soon"] {
+            assert!(parse_sampling(spec).is_err(), "{}", spec);
+        }
+    }
+}
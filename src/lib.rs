@@ -94,6 +94,7 @@ lazy_static::lazy_static! {
 }
 
 pub mod commands;
+pub mod hostcall_profile;
 
 use anyhow::{bail, Result};
 use std::collections::HashMap;
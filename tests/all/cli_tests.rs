@@ -1,11 +1,11 @@
 use anyhow::{bail, Result};
-use std::io::Write;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
-use std::process::{Command, Output};
+use std::process::{Command, Output, Stdio};
 use tempfile::{NamedTempFile, TempDir};
 
-// Run the wasmtime CLI with the provided args and return the `Output`.
-fn run_wasmtime_for_output(args: &[&str]) -> Result<Output> {
+// A command to run the wasmtime CLI with the provided args.
+fn wasmtime_command(args: &[&str]) -> Result<Command> {
     let runner = std::env::vars()
         .filter(|(k, _v)| k.starts_with("CARGO_TARGET") && k.ends_with("RUNNER"))
         .next();
@@ -28,7 +28,13 @@ fn run_wasmtime_for_output(args: &[&str]) -> Result<Output> {
     } else {
         Command::new(&me)
     };
-    cmd.args(args).output().map_err(Into::into)
+    cmd.args(args);
+    Ok(cmd)
+}
+
+// Run the wasmtime CLI with the provided args and return the `Output`.
+fn run_wasmtime_for_output(args: &[&str]) -> Result<Output> {
+    wasmtime_command(args)?.output().map_err(Into::into)
 }
 
 // Run the wasmtime CLI with the provided args and, if it succeeds, return
@@ -397,3 +403,508 @@ fn run_cwasm() -> Result<()> {
     assert_eq!(stdout, "");
     Ok(())
 }
+
+#[test]
+fn hostcall_profile_json() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("profile.json");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    let stdout = run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        profile.to_str().unwrap(),
+    ])?;
+    assert_eq!(stdout, "Hello, world!\n");
+
+    let profile: serde_json::Value = serde_json::from_slice(&std::fs::read(&profile)?)?;
+    assert_eq!(profile["schema_version"], 1);
+    assert!(profile["clock"]["frequency_hz"].as_u64().unwrap() > 0);
+    let fd_write = profile["hostcalls"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["function"] == "fd_write")
+        .expect("no fd_write in profile");
+    assert_eq!(fd_write["module"], "wasi_snapshot_preview1");
+    assert_eq!(fd_write["count"], 1);
+    assert_eq!(fd_write["results"][0]["result"], "ok");
+    assert_eq!(fd_write["results"][0]["count"], 1);
+    assert_eq!(fd_write["payload"]["bytes"], 14);
+    assert_eq!(fd_write["payload"]["iovecs"], 1);
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_sampled() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("profile.json");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    let stdout = run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        profile.to_str().unwrap(),
+        "--hostcall-profile-sample",
+        "10",
+    ])?;
+    assert_eq!(stdout, "Hello, world!\n");
+
+    let profile: serde_json::Value = serde_json::from_slice(&std::fs::read(&profile)?)?;
+    assert_eq!(profile["sampling"], "10");
+    let fd_write = profile["hostcalls"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["function"] == "fd_write")
+        .expect("no fd_write in profile");
+    // The first call to each hostcall is always timed.
+    assert_eq!(fd_write["count"], 1);
+    assert_eq!(fd_write["sampled"], 1);
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_counters() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("profile.json");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    // Whether the counters can be opened depends on the machine, but the
+    // profile is written either way.
+    let stdout = run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        profile.to_str().unwrap(),
+        "--hostcall-profile-counters",
+        "instructions,context-switches",
+    ])?;
+    assert_eq!(stdout, "Hello, world!\n");
+
+    let profile: serde_json::Value = serde_json::from_slice(&std::fs::read(&profile)?)?;
+    let fd_write = profile["hostcalls"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["function"] == "fd_write")
+        .expect("no fd_write in profile");
+    if profile["perf_scope"].is_null() {
+        assert!(fd_write["counters"].is_null());
+    } else {
+        assert!(fd_write["counters"]["instructions"]["p50"].is_number());
+        assert!(fd_write["counters"]["context-switches"].is_object());
+    }
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_write_failure_keeps_exit_code() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("missing").join("profile.json");
+    let hostcall_profile = ["--hostcall-profile", profile.to_str().unwrap()];
+
+    // The guest's own exit code wins over failing to write the profile.
+    let wasm = build_wasm("tests/all/cli_tests/exit2_wasi_snapshot1.wat")?;
+    let mut args = vec![wasm.path().to_str().unwrap(), "--disable-cache"];
+    args.extend_from_slice(&hostcall_profile);
+    let output = run_wasmtime_for_output(&args)?;
+    assert_eq!(output.status.code().unwrap(), 2);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to create hostcall profile"));
+
+    // A guest which succeeded fails because of it.
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    let mut args = vec!["run", wasm.path().to_str().unwrap(), "--disable-cache"];
+    args.extend_from_slice(&hostcall_profile);
+    assert!(run_wasmtime(&args).is_err());
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_csv() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("profile.csv");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        profile.to_str().unwrap(),
+        "--hostcall-profile-format",
+        "csv",
+    ])?;
+
+    let profile = std::fs::read_to_string(&profile)?;
+    let mut lines = profile.lines();
+    assert!(lines.next().unwrap().starts_with("module,function,count,"));
+    let fd_write = lines
+        .filter(|l| l.starts_with("wasi_snapshot_preview1,fd_write,1,"))
+        .collect::<Vec<_>>();
+    assert_eq!(fd_write.len(), 2);
+    assert!(fd_write[0].ends_with(",all"));
+    assert!(fd_write[1].ends_with(",ok"));
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_csv_breakdown() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("profile.csv");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        profile.to_str().unwrap(),
+        "--hostcall-profile-format",
+        "csv",
+        "--hostcall-profile-breakdown",
+    ])?;
+
+    // The totals come first, followed by the same call on the only thread.
+    let profile = std::fs::read_to_string(&profile)?;
+    let mut lines = profile.lines();
+    assert!(lines.next().unwrap().starts_with("thread,store,module,"));
+    let fd_write = lines
+        .filter(|l| l.contains(",wasi_snapshot_preview1,fd_write,1,") && l.ends_with(",all"))
+        .collect::<Vec<_>>();
+    assert_eq!(fd_write.len(), 2);
+    assert!(fd_write[0].starts_with(",,"));
+    assert!(!fd_write[1].starts_with(','));
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_folded() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("profile.folded");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        profile.to_str().unwrap(),
+        "--hostcall-profile-format",
+        "folded",
+    ])?;
+
+    let profile = std::fs::read_to_string(&profile)?;
+    assert!(
+        profile
+            .lines()
+            .any(|l| l.contains("_start;wasi_snapshot_preview1::fd_write ")),
+        "{}",
+        profile
+    );
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_chrome_trace() -> Result<()> {
+    let td = TempDir::new()?;
+    let profile = td.path().join("trace.json");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        profile.to_str().unwrap(),
+        "--hostcall-profile-format",
+        "chrome-trace",
+    ])?;
+
+    let trace: serde_json::Value = serde_json::from_slice(&std::fs::read(&profile)?)?;
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert!(events
+        .iter()
+        .any(|e| e["name"] == "fd_write" && e["ph"] == "X"));
+    Ok(())
+}
+
+#[test]
+fn hostcall_bench_baseline() -> Result<()> {
+    let td = TempDir::new()?;
+    let baseline = td.path().join("baseline.json");
+    let bench = |extra: &[&str]| {
+        let mut args = vec![
+            "hostcall-bench",
+            "--disable-cache",
+            "--filter",
+            "clock_time_get",
+            "--filter",
+            "fd_write",
+            "--size",
+            "16,1024",
+            "--iterations",
+            "10",
+            "--warmup",
+            "0",
+            "--repeat",
+            "2",
+        ];
+        args.extend_from_slice(extra);
+        run_wasmtime(&args)
+    };
+
+    let stdout = bench(&["--save-baseline", baseline.to_str().unwrap()])?;
+    assert!(stdout.starts_with("benchmark "));
+    assert!(stdout.contains("clock_time_get"));
+    let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&baseline)?)?;
+    let results = report["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["benchmark"], "clock_time_get");
+    assert_eq!(results[0]["calls"], 20);
+    assert_eq!(results[1]["benchmark"], "fd_write/pipe");
+    assert_eq!(results[1]["size"], 16);
+    assert!(results[2]["bytes_per_ns"].as_f64().unwrap() > 0.0);
+
+    let stdout = bench(&["--baseline", baseline.to_str().unwrap()])?;
+    assert!(stdout.lines().next().unwrap().ends_with("p50 vs base"));
+    assert!(stdout.lines().skip(1).all(|l| l.ends_with('%')));
+
+    // Iteration counts which don't fit the benchmarks' `i32` are rejected
+    // rather than wrapping around.
+    assert!(run_wasmtime(&["hostcall-bench", "--iterations", "3000000000"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn hostcall_profile_diff() -> Result<()> {
+    let td = TempDir::new()?;
+    let before = td.path().join("before.json");
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-profile",
+        before.to_str().unwrap(),
+        "--hostcall-raw-samples",
+    ])?;
+
+    let stdout = run_wasmtime(&[
+        "hostcall-profile",
+        "diff",
+        before.to_str().unwrap(),
+        before.to_str().unwrap(),
+    ])?;
+    assert!(stdout.starts_with("hostcall "));
+    assert!(stdout.contains("wasi_snapshot_preview1::fd_write"));
+    assert!(!stdout.contains("regressed"));
+
+    // A single call isn't enough to tell a change apart from noise, so give
+    // `fd_write` plenty of samples, three times slower in the second profile.
+    let profile: serde_json::Value = serde_json::from_slice(&std::fs::read(&before)?)?;
+    let write_with_samples = |path: &Path, base: u64| -> Result<()> {
+        let mut profile = profile.clone();
+        let fd_write = profile["hostcalls"]
+            .as_array_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|h| h["function"] == "fd_write")
+            .expect("no fd_write in profile");
+        fd_write["p50"] = base.into();
+        fd_write["samples"] = (base..base + 50).collect::<Vec<_>>().into();
+        std::fs::write(path, serde_json::to_vec(&profile)?)?;
+        Ok(())
+    };
+    let after = td.path().join("after.json");
+    write_with_samples(&before, 100)?;
+    write_with_samples(&after, 300)?;
+
+    let output = run_wasmtime_for_output(&[
+        "hostcall-profile",
+        "diff",
+        before.to_str().unwrap(),
+        after.to_str().unwrap(),
+    ])?;
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    let fd_write = stdout
+        .lines()
+        .find(|l| l.starts_with("wasi_snapshot_preview1::fd_write "))
+        .unwrap();
+    assert!(fd_write.contains("+200.0%"));
+    assert!(fd_write.ends_with("regressed"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fd_write"));
+
+    // Changes within the threshold aren't regressions.
+    run_wasmtime(&[
+        "hostcall-profile",
+        "diff",
+        before.to_str().unwrap(),
+        after.to_str().unwrap(),
+        "--threshold",
+        "250",
+    ])?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn hostcall_metrics() -> Result<()> {
+    use std::os::unix::net::UnixStream;
+
+    let td = TempDir::new()?;
+    let socket = td.path().join("metrics.sock");
+    let endpoint = format!("unix:{}", socket.display());
+    let wasm = build_wasm("tests/all/cli_tests/hello_then_read_stdin.wat")?;
+    let mut child = wasmtime_command(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-metrics",
+        &endpoint,
+    ])?
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()?;
+
+    // The module blocks reading stdin once it has said hello, so metrics
+    // can be scraped while it's still running.
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line)?;
+    assert_eq!(line, "Hello, world!\n");
+
+    let mut conn = UnixStream::connect(&socket)?;
+    conn.write_all(b"GET /metrics HTTP/1.1\r\n\r\n")?;
+    let mut response = String::new();
+    conn.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains(
+        "wasmtime_hostcall_calls_total{module=\"wasi_snapshot_preview1\",function=\"fd_write\"} 1\n"
+    ));
+    assert!(response.contains(
+        "wasmtime_hostcall_payload_bytes_total{module=\"wasi_snapshot_preview1\",function=\"fd_write\"} 14\n"
+    ));
+
+    drop(child.stdin.take());
+    assert!(child.wait()?.success());
+    assert!(!socket.exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn tcplisten_echo() -> Result<()> {
+    use std::net::{TcpListener, TcpStream};
+
+    // Find a free port for the module to listen on.
+    let addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let wasm = build_wasm("tests/all/cli_tests/tcplisten_echo.wat")?;
+    let mut child = wasmtime_command(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--tcplisten",
+        &addr.to_string(),
+    ])?
+    .spawn()?;
+
+    // Wait for the module to start listening, but not forever: it may have
+    // failed to start, or never get there.
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+    let mut conn = loop {
+        match TcpStream::connect(addr) {
+            Ok(conn) => break conn,
+            Err(e) => {
+                if let Some(status) = child.try_wait()? {
+                    bail!("wasmtime exited with {} before accepting: {}", status, e);
+                }
+                if std::time::Instant::now() > deadline {
+                    child.kill()?;
+                    bail!("timed out connecting to wasmtime: {}", e);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        }
+    };
+    conn.set_read_timeout(Some(std::time::Duration::from_secs(30)))?;
+    conn.write_all(b"hello")?;
+    let mut echo = String::new();
+    conn.read_to_string(&mut echo)?;
+    assert_eq!(echo, "hello");
+
+    assert!(child.wait()?.success());
+    Ok(())
+}
+
+#[test]
+fn dir_overlay() -> Result<()> {
+    let td = TempDir::new()?;
+    std::fs::write(td.path().join("data.txt"), "original\n")?;
+    let wasm = build_wasm("tests/all/cli_tests/dir_overlay.wat")?;
+    let overlay = format!("/data::{}", td.path().display());
+    let stdout = run_wasmtime(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--dir-overlay",
+        &overlay,
+    ])?;
+
+    // The module sees its own change, but the host file is left alone.
+    assert_eq!(stdout, "changed\n");
+    assert_eq!(
+        std::fs::read_to_string(td.path().join("data.txt"))?,
+        "original\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn hostcall_record_and_replay() -> Result<()> {
+    let td = TempDir::new()?;
+    let log = td.path().join("hostcalls.log");
+    let wasm = build_wasm("tests/all/cli_tests/exit_random.wat")?;
+    let recorded = run_wasmtime_for_output(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-record",
+        log.to_str().unwrap(),
+    ])?;
+    let status = recorded.status.code().unwrap();
+    assert!((1..=64).contains(&status), "{:?}", recorded);
+
+    // The random byte comes from the log, so the exit status is the same.
+    for _ in 0..3 {
+        let replayed = run_wasmtime_for_output(&[
+            "run",
+            wasm.path().to_str().unwrap(),
+            "--disable-cache",
+            "--hostcall-replay",
+            log.to_str().unwrap(),
+        ])?;
+        assert_eq!(replayed.status.code(), Some(status), "{:?}", replayed);
+    }
+
+    // A different module makes different calls.
+    let wasm = build_wasm("tests/all/cli_tests/hello_wasi_snapshot1.wat")?;
+    let output = run_wasmtime_for_output(&[
+        "run",
+        wasm.path().to_str().unwrap(),
+        "--disable-cache",
+        "--hostcall-replay",
+        log.to_str().unwrap(),
+    ])?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "hostcall replay diverged at call 0: expected a call to \
+             wasi_snapshot_preview1::random_get, got wasi_snapshot_preview1::fd_write"
+        ),
+        "{}",
+        stderr
+    );
+    Ok(())
+}
@@ -0,0 +1,71 @@
+(module
+  (import "wasi_snapshot_preview1" "proc_exit"
+    (func $__wasi_proc_exit (param i32)))
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $__wasi_path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $__wasi_fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $__wasi_fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_close"
+    (func $__wasi_fd_close (param i32) (result i32)))
+  (func $_start
+    (block
+      ;; Truncate `data.txt` in the preopened directory, fd 3, and write to it.
+      (br_if 0
+        (call $__wasi_path_open
+          (i32.const 3)
+          (i32.const 0)
+          (i32.const 0)
+          (i32.const 8)
+          (i32.const 8)
+          (i64.const 64)
+          (i64.const 0)
+          (i32.const 0)
+          (i32.const 40)))
+      (i32.store (i32.const 32) (i32.const 16))
+      (i32.store (i32.const 36) (i32.const 8))
+      (br_if 0
+        (call $__wasi_fd_write
+          (i32.load (i32.const 40))
+          (i32.const 32)
+          (i32.const 1)
+          (i32.const 44)))
+      (br_if 0 (call $__wasi_fd_close (i32.load (i32.const 40))))
+      ;; Read it back, and print what it says now.
+      (br_if 0
+        (call $__wasi_path_open
+          (i32.const 3)
+          (i32.const 0)
+          (i32.const 0)
+          (i32.const 8)
+          (i32.const 0)
+          (i64.const 2)
+          (i64.const 0)
+          (i32.const 0)
+          (i32.const 40)))
+      (i32.store (i32.const 32) (i32.const 64))
+      (i32.store (i32.const 36) (i32.const 32))
+      (br_if 0
+        (call $__wasi_fd_read
+          (i32.load (i32.const 40))
+          (i32.const 32)
+          (i32.const 1)
+          (i32.const 44)))
+      (i32.store (i32.const 36) (i32.load (i32.const 44)))
+      (br_if 0
+        (call $__wasi_fd_write
+          (i32.const 1)
+          (i32.const 32)
+          (i32.const 1)
+          (i32.const 44)))
+      (return)
+    )
+    (call $__wasi_proc_exit (i32.const 1))
+  )
+  (memory 1)
+  (export "memory" (memory 0))
+  (export "_start" (func $_start))
+  (data (i32.const 0) "data.txt")
+  (data (i32.const 16) "changed\n")
+)
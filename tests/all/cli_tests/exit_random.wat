@@ -0,0 +1,18 @@
+(module
+  (import "wasi_snapshot_preview1" "proc_exit"
+    (func $__wasi_proc_exit (param i32)))
+  (import "wasi_snapshot_preview1" "random_get"
+    (func $__wasi_random_get (param i32 i32) (result i32)))
+  (func $_start
+    (if (call $__wasi_random_get (i32.const 0) (i32.const 1))
+      (then unreachable))
+    ;; Exit with a random status between 1 and 64.
+    (call $__wasi_proc_exit
+      (i32.add
+        (i32.and (i32.load8_u (i32.const 0)) (i32.const 63))
+        (i32.const 1)))
+  )
+  (memory 1)
+  (export "memory" (memory 0))
+  (export "_start" (func $_start))
+)
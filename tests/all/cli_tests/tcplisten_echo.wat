@@ -0,0 +1,55 @@
+(module
+  (import "wasi_snapshot_preview1" "proc_exit"
+    (func $__wasi_proc_exit (param i32)))
+  (import "wasi_snapshot_preview1" "poll_oneoff"
+    (func $__wasi_poll_oneoff (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "sock_accept"
+    (func $__wasi_sock_accept (param i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $__wasi_fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $__wasi_fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_close"
+    (func $__wasi_fd_close (param i32) (result i32)))
+  (func $_start
+    (block
+      ;; Wait for a connection on the preopened socket, fd 3.
+      (i32.store8 (i32.const 8) (i32.const 1))
+      (i32.store (i32.const 16) (i32.const 3))
+      (br_if 0
+        (call $__wasi_poll_oneoff
+          (i32.const 0)
+          (i32.const 64)
+          (i32.const 1)
+          (i32.const 96)))
+      (br_if 0 (i32.ne (i32.load8_u (i32.const 74)) (i32.const 1)))
+      (br_if 0
+        (call $__wasi_sock_accept
+          (i32.const 3)
+          (i32.const 0)
+          (i32.const 100)))
+      ;; Echo back whatever the first read gets.
+      (i32.store (i32.const 104) (i32.const 128))
+      (i32.store (i32.const 108) (i32.const 64))
+      (br_if 0
+        (call $__wasi_fd_read
+          (i32.load (i32.const 100))
+          (i32.const 104)
+          (i32.const 1)
+          (i32.const 112)))
+      (i32.store (i32.const 108) (i32.load (i32.const 112)))
+      (br_if 0
+        (call $__wasi_fd_write
+          (i32.load (i32.const 100))
+          (i32.const 104)
+          (i32.const 1)
+          (i32.const 112)))
+      (br_if 0 (call $__wasi_fd_close (i32.load (i32.const 100))))
+      (return)
+    )
+    (call $__wasi_proc_exit (i32.const 1))
+  )
+  (memory 1)
+  (export "memory" (memory 0))
+  (export "_start" (func $_start))
+)
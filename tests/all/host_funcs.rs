@@ -746,3 +746,44 @@ fn wasi_imports() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn wasi_imports_with_hostcall_instrumentation() -> Result<()> {
+    use wiggle::timing::{set_recorder, HistogramRecorder, WasmtimeInstrumentation};
+
+    let mut config = Config::new();
+    config.hostcall_instrumentation(std::sync::Arc::new(WasmtimeInstrumentation));
+    let engine = Engine::new(&config)?;
+    let mut linker = Linker::new(&engine);
+    wasmtime_wasi::add_to_linker(&mut linker, |s| s)?;
+    linker.func_wrap("host", "instrumented", || {})?;
+
+    let wasm = wat::parse_str(
+        r#"
+        (import "wasi_snapshot_preview1" "sched_yield" (func $sched_yield (result i32)))
+        (import "host" "instrumented" (func $host))
+        (memory (export "memory") 0)
+        (func (export "_start")
+            (drop (call $sched_yield))
+            (call $host)
+        )
+        "#,
+    )?;
+
+    let recorder = std::sync::Arc::new(HistogramRecorder::new());
+    set_recorder(recorder.clone());
+    let module = Module::new(&engine, wasm)?;
+    let mut store = Store::new(&engine, WasiCtxBuilder::new().build());
+    let instance = linker.instantiate(&mut store, &module)?;
+    let start = instance.get_typed_func::<(), (), _>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+
+    // WASI functions time themselves, so the engine's instrumentation must
+    // leave them alone rather than count them a second time.
+    let table = recorder.snapshot();
+    let count = |module, function| table.get(module, function).map(|h| h.count());
+    assert_eq!(count("wasi_snapshot_preview1", "sched_yield"), Some(1));
+    assert_eq!(count("host", "instrumented"), Some(1));
+
+    Ok(())
+}
@@ -368,3 +368,123 @@ fn instance_pre() -> Result<()> {
     instance_pre.instantiate(&mut store)?;
     Ok(())
 }
+
+/// Keeps the `(module, name, trapped)` of every call it is told about,
+/// except for calls into the module `skip`.
+#[derive(Default)]
+struct Calls(std::sync::Mutex<Vec<(String, String, bool)>>);
+
+impl HostcallInstrumentation for Calls {
+    fn instrument(&self, module: &str, _name: &str) -> bool {
+        module != "skip"
+    }
+
+    fn enter(&self, _module: &str, name: &str) -> u64 {
+        name.len() as u64
+    }
+
+    fn exit(&self, module: &str, name: &str, entered: u64, trapped: bool) {
+        assert_eq!(entered, name.len() as u64);
+        self.0
+            .lock()
+            .unwrap()
+            .push((module.to_string(), name.to_string(), trapped));
+    }
+}
+
+#[test]
+fn hostcall_instrumentation() -> Result<()> {
+    let calls = Arc::new(Calls::default());
+    let mut config = Config::new();
+    config.hostcall_instrumentation(calls.clone());
+    let engine = Engine::new(&config)?;
+    let mut linker = Linker::new(&engine);
+    linker.func_wrap("host", "wrap", |x: i32| x + 1)?;
+    linker.func_wrap("host", "trap", || -> Result<(), Trap> {
+        Err(Trap::new("nope"))
+    })?;
+    linker.func_new("host", "new", FuncType::new(None, None), |_, _, _| Ok(()))?;
+    linker.func_wrap("skip", "wrap", || {})?;
+
+    let module = Module::new(
+        &engine,
+        r#"(module
+            (import "host" "wrap" (func $wrap (param i32) (result i32)))
+            (import "host" "trap" (func $trap))
+            (import "host" "new" (func $new))
+            (import "skip" "wrap" (func $skip))
+            (func (export "run")
+                (drop (call $wrap (i32.const 1)))
+                call $new
+                call $skip
+                call $trap)
+        )"#,
+    )?;
+    let mut store = Store::new(&engine, ());
+    let instance = linker.instantiate(&mut store, &module)?;
+    let run = instance.get_typed_func::<(), (), _>(&mut store, "run")?;
+    assert!(run.call(&mut store, ()).is_err());
+
+    let anonymous = Func::wrap(&mut store, || {});
+    anonymous.call(&mut store, &[], &mut [])?;
+
+    let calls = calls.0.lock().unwrap();
+    let calls = calls
+        .iter()
+        .map(|(m, n, t)| (m.as_str(), n.as_str(), *t))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        calls,
+        [
+            ("host", "wrap", false),
+            ("host", "new", false),
+            ("host", "trap", true),
+            ("", "<anonymous>", false),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn libcall_instrumentation() -> Result<()> {
+    let module = r#"(module
+        (memory 1)
+        (func (export "run")
+            (drop (memory.grow (i32.const 1)))
+            (memory.fill (i32.const 0) (i32.const 0) (i32.const 16))
+            (memory.fill (i32.const 0) (i32.const 0) (i32.const 0x30000)))
+    )"#;
+
+    // Libcalls are only reported when asked for.
+    for instrument_libcalls in [false, true].iter() {
+        let calls = Arc::new(Calls::default());
+        let mut config = Config::new();
+        config.hostcall_instrumentation(calls.clone());
+        config.instrument_libcalls(*instrument_libcalls);
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, module)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[])?;
+        let run = instance.get_typed_func::<(), (), _>(&mut store, "run")?;
+        assert!(run.call(&mut store, ()).is_err());
+
+        let calls = calls.0.lock().unwrap();
+        let calls = calls
+            .iter()
+            .map(|(m, n, t)| (m.as_str(), n.as_str(), *t))
+            .collect::<Vec<_>>();
+        if *instrument_libcalls {
+            assert_eq!(
+                calls,
+                [
+                    ("runtime", "memory32_grow", false),
+                    ("runtime", "memory_fill", false),
+                    ("runtime", "memory_fill", true),
+                ]
+            );
+        } else {
+            assert!(calls.is_empty());
+        }
+    }
+    Ok(())
+}
@@ -105,6 +105,39 @@ fn test_trap_trace_cb() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[cfg_attr(all(target_os = "macos", target_arch = "aarch64"), ignore)] // TODO #2808 system libunwind is broken on aarch64
+fn wasm_backtrace_from_host() -> Result<()> {
+    let mut store = Store::<()>::default();
+    let wat = r#"
+        (module $hello_mod
+            (import "" "callers" (func $callers))
+            (func (export "run") (call $hello))
+            (func $hello (call $callers))
+        )
+    "#;
+
+    let fn_func = Func::wrap(&mut store, || {
+        let all = wasm_backtrace(usize::MAX);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].module_name().unwrap(), "hello_mod");
+        assert_eq!(all[0].func_name(), Some("hello"));
+        assert_eq!(all[1].func_index(), 1);
+
+        let innermost = wasm_backtrace(1);
+        assert_eq!(innermost.len(), 1);
+        assert_eq!(innermost[0].func_name(), Some("hello"));
+    });
+
+    let module = Module::new(store.engine(), wat)?;
+    let instance = Instance::new(&mut store, &module, &[fn_func.into()])?;
+    let run_func = instance.get_typed_func::<(), (), _>(&mut store, "run")?;
+    run_func.call(&mut store, ())?;
+
+    assert!(wasm_backtrace(usize::MAX).is_empty());
+    Ok(())
+}
+
 #[test]
 #[cfg_attr(all(target_os = "macos", target_arch = "aarch64"), ignore)] // TODO #2808 system libunwind is broken on aarch64
 fn test_trap_stack_overflow() -> Result<()> {